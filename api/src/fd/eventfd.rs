@@ -0,0 +1,151 @@
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use alloc::{sync::Arc, vec::Vec};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axprocess::Process;
+use axsync::Mutex;
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::general::{O_NONBLOCK, O_RDWR};
+use starry_core::task::ProcessData;
+
+use super::{FileLike, Kstat};
+use crate::has_pending_signal;
+
+/// The maximum value of the counter.
+const MAX_COUNT: u64 = u64::MAX - 1;
+
+/// An eventfd object, which is a 64-bit counter for event notification.
+pub struct EventFd {
+    count: Mutex<u64>,
+    semaphore: bool,
+    nonblocking: AtomicBool,
+    /// The processes of the threads waiting for the counter, which sleep on
+    /// their signal wait queues so that signals wake them.
+    waiters: Mutex<Vec<Arc<Process>>>,
+    /// The number of reads and writes done so far.
+    seq: AtomicU64,
+}
+
+impl EventFd {
+    pub fn new(initval: u64, semaphore: bool, nonblocking: bool) -> Self {
+        Self {
+            count: Mutex::new(initval),
+            semaphore,
+            nonblocking: AtomicBool::new(nonblocking),
+            waiters: Mutex::new(Vec::new()),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Run `f` on the counter once it returns `Some`, waiting in between
+    /// unless the eventfd is nonblocking, or until a signal is pending.
+    fn wait_for<T>(&self, f: impl Fn(&mut u64) -> Option<T>) -> LinuxResult<T> {
+        loop {
+            if let Some(res) = f(&mut self.count.lock()) {
+                self.seq.fetch_add(1, Ordering::AcqRel);
+                for process in self.waiters.lock().iter() {
+                    if let Some(data) = process.data::<ProcessData>() {
+                        data.signal_wq.notify_all(false);
+                    }
+                }
+                return Ok(res);
+            }
+            if self.nonblocking.load(Ordering::Acquire) {
+                return Err(LinuxError::EAGAIN);
+            }
+            if has_pending_signal() {
+                return Err(LinuxError::ERESTART);
+            }
+            let curr = current();
+            let process = curr.task_ext().thread.process();
+            self.waiters.lock().push(process.clone());
+            curr.task_ext().process_data().signal_wq.wait_until(|| {
+                let mut count = *self.count.lock();
+                f(&mut count).is_some() || has_pending_signal()
+            });
+            let mut waiters = self.waiters.lock();
+            if let Some(pos) = waiters.iter().position(|p| Arc::ptr_eq(p, process)) {
+                waiters.swap_remove(pos);
+            }
+        }
+    }
+}
+
+impl FileLike for EventFd {
+    /// Read the counter, resetting it to 0, or decrementing it by 1 in
+    /// semaphore mode.
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        if buf.len() < size_of::<u64>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let value = self.wait_for(|count| {
+            if *count == 0 {
+                None
+            } else if self.semaphore {
+                *count -= 1;
+                Some(1)
+            } else {
+                Some(core::mem::take(count))
+            }
+        })?;
+        buf[..size_of::<u64>()].copy_from_slice(&value.to_ne_bytes());
+        Ok(size_of::<u64>())
+    }
+
+    /// Add a value to the counter, waiting if it would exceed the maximum.
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        let value = buf
+            .get(..size_of::<u64>())
+            .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+            .ok_or(LinuxError::EINVAL)?;
+        if value == u64::MAX {
+            return Err(LinuxError::EINVAL);
+        }
+        self.wait_for(|count| {
+            (MAX_COUNT - *count >= value).then(|| {
+                *count += value;
+            })
+        })?;
+        Ok(size_of::<u64>())
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: 0o600u32, // rw-------
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        let count = *self.count.lock();
+        Ok(PollState {
+            readable: count > 0,
+            writable: count < MAX_COUNT,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn event_seq(&self) -> u64 {
+        self.seq.load(Ordering::Acquire)
+    }
+
+    fn status_flags(&self) -> u32 {
+        if self.nonblocking.load(Ordering::Acquire) {
+            O_RDWR | O_NONBLOCK
+        } else {
+            O_RDWR
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    string::String,
+};
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use axtask::WaitQueue;
+
+/// The kind of a `flock` lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlockKind {
+    Shared,
+    Exclusive,
+}
+
+/// The `flock` locks held on a file.
+///
+/// Locks belong to open file descriptions, which are identified by the
+/// address of the file object shared by all duplicated fds.
+#[derive(Default)]
+struct FlockState {
+    shared: BTreeSet<usize>,
+    exclusive: Option<usize>,
+}
+
+impl FlockState {
+    fn can_lock(&self, owner: usize, kind: FlockKind) -> bool {
+        let exclusive_free = self.exclusive.is_none_or(|o| o == owner);
+        match kind {
+            FlockKind::Shared => exclusive_free,
+            FlockKind::Exclusive => exclusive_free && self.shared.iter().all(|&o| o == owner),
+        }
+    }
+
+    fn unlock(&mut self, owner: usize) {
+        self.shared.remove(&owner);
+        if self.exclusive == Some(owner) {
+            self.exclusive = None;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shared.is_empty() && self.exclusive.is_none()
+    }
+}
+
+/// The `flock` locks of all files, keyed by path.
+static FLOCKS: Mutex<BTreeMap<String, FlockState>> = Mutex::new(BTreeMap::new());
+/// Tasks waiting for a `flock` lock to be released.
+static FLOCK_WQ: WaitQueue = WaitQueue::new();
+
+/// Place a `flock` lock of `kind` on the file at `path` for `owner`,
+/// converting any lock already held by `owner`.
+///
+/// Return `EAGAIN` if the lock is held by others and `nonblocking` is set.
+pub fn flock(path: &str, owner: usize, kind: FlockKind, nonblocking: bool) -> LinuxResult {
+    loop {
+        {
+            let mut flocks = FLOCKS.lock();
+            let state = flocks.entry(path.into()).or_default();
+            if state.can_lock(owner, kind) {
+                let downgraded = kind == FlockKind::Shared && state.exclusive == Some(owner);
+                state.unlock(owner);
+                match kind {
+                    FlockKind::Shared => {
+                        state.shared.insert(owner);
+                    }
+                    FlockKind::Exclusive => state.exclusive = Some(owner),
+                }
+                drop(flocks);
+                // Waiters for a shared lock can get it now.
+                if downgraded {
+                    FLOCK_WQ.notify_all(false);
+                }
+                return Ok(());
+            }
+        }
+        if nonblocking {
+            return Err(LinuxError::EAGAIN);
+        }
+        FLOCK_WQ.wait_until(|| {
+            FLOCKS
+                .lock()
+                .get(path)
+                .is_none_or(|state| state.can_lock(owner, kind))
+        });
+    }
+}
+
+/// Release the `flock` lock held by `owner` on the file at `path`.
+pub fn funlock(path: &str, owner: usize) {
+    let mut flocks = FLOCKS.lock();
+    let Some(state) = flocks.get_mut(path) else {
+        return;
+    };
+    state.unlock(owner);
+    if state.is_empty() {
+        flocks.remove(path);
+    }
+    drop(flocks);
+    FLOCK_WQ.notify_all(false);
+}
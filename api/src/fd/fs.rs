@@ -1,27 +1,81 @@
-use core::{any::Any, ffi::c_int};
+use core::{
+    any::Any,
+    ffi::c_int,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
 
 use alloc::{string::String, sync::Arc};
-use axerrno::{LinuxError, LinuxResult};
-use axio::PollState;
+use axerrno::{AxError, LinuxError, LinuxResult};
+use axhal::mem::PhysAddr;
+use axio::{PollState, SeekFrom};
 use axsync::{Mutex, MutexGuard};
-use linux_raw_sys::general::S_IFDIR;
+use linux_raw_sys::general::{
+    __O_TMPFILE, F_SEAL_FUTURE_WRITE, F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK, F_SEAL_WRITE,
+    FASYNC, IN_CLOSE_NOWRITE, IN_CLOSE_WRITE, IN_MODIFY, O_ACCMODE, O_APPEND, O_DIRECT,
+    O_DIRECTORY, O_NONBLOCK, O_RDONLY, O_RDWR, S_IFDIR,
+};
 
-use super::{FileLike, Kstat, get_file_like};
+use super::{FileLike, Kstat, add_file_like, fs_notify, funlock, get_file_like, page_cache};
+use crate::{
+    MountRoot, hold_mount,
+    path::{ATTR_MANAGER, FilePath, HARDLINK_MANAGER},
+};
+
+/// The file status flags kept by [`File`], where `FASYNC` is `O_ASYNC`.
+const FILE_STATUS_FLAGS: u32 = O_ACCMODE | O_APPEND | O_NONBLOCK | O_DIRECT | FASYNC;
+
+/// The name prefix of the hidden entries backing `O_TMPFILE` files.
+pub const TMPFILE_PREFIX: &str = ".tmpfile.";
 
 /// File wrapper for `axfs::fops::File`.
 pub struct File {
     inner: Mutex<axfs::fops::File>,
     path: String,
+    /// The identity of the file, which its cached pages are keyed by.
+    id: u64,
+    flags: AtomicU32,
+    /// Whether the file is opened by `O_TMPFILE`, whose entry at `path`
+    /// should be removed on close.
+    unnamed: bool,
+    /// Whether the file has been installed in the fd table, so that closing
+    /// it is reported to inotify.
+    installed: AtomicBool,
+    /// The `F_SEAL_*` seals of a memfd file, `None` for other files, which
+    /// cannot be sealed.
+    seals: Option<AtomicU32>,
+    /// The mounted file system the file lives on, kept while it is open.
+    _mount: Option<Arc<MountRoot>>,
 }
 
 impl File {
-    pub fn new(inner: axfs::fops::File, path: String) -> Self {
+    /// Create a file opened with the open `flags`.
+    pub fn new(inner: axfs::fops::File, path: String, flags: u32) -> Self {
         Self {
             inner: Mutex::new(inner),
+            flags: AtomicU32::new(flags & FILE_STATUS_FLAGS),
+            unnamed: flags & __O_TMPFILE != 0,
+            installed: AtomicBool::new(false),
+            seals: None,
+            _mount: hold_mount(&path),
+            id: FilePath::new(&path).map_or(0, |path| ATTR_MANAGER.file_id(&path)),
             path,
         }
     }
 
+    /// Create a memfd file backed by the hidden entry at `path`, which can
+    /// be sealed later if `allow_sealing` is set.
+    pub fn new_memfd(inner: axfs::fops::File, path: String, allow_sealing: bool) -> Self {
+        let mut file = Self::new(inner, path, O_RDWR | __O_TMPFILE);
+        file.seals = Some(AtomicU32::new(if allow_sealing { 0 } else { F_SEAL_SEAL }));
+        file
+    }
+
+    /// Get the identity of the file, shared by all the opens of it.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     /// Get the path of the file.
     pub fn path(&self) -> &str {
         &self.path
@@ -31,15 +85,174 @@ impl File {
     pub fn inner(&self) -> MutexGuard<axfs::fops::File> {
         self.inner.lock()
     }
+
+    /// Get the frame holding the page `index` of the file, which is shared
+    /// by the `MAP_SHARED` mappings of it, and count one more mapping of it.
+    pub fn map_shared_page(&self, index: u64) -> LinuxResult<PhysAddr> {
+        page_cache::map_cached_page(self.id, index, &self.inner())
+    }
+
+    /// Count one more mapping of the shared `pages` of the file.
+    pub fn share_shared_pages(&self, pages: Range<u64>) {
+        page_cache::share_cached_pages(self.id, pages);
+    }
+
+    /// Drop one mapping of the shared `pages` of the file.
+    pub fn unmap_shared_pages(&self, pages: Range<u64>) {
+        page_cache::unmap_cached_pages(self.id, pages);
+    }
+
+    /// Write the shared page `index` of the file back to it.
+    pub fn sync_shared_page(&self, index: u64) -> LinuxResult {
+        page_cache::sync_cached_page(self.id, index, &self.inner())
+    }
+
+    /// Read the shared `pages` of the file again from it.
+    pub fn reload_shared_pages(&self, pages: Range<u64>) -> LinuxResult {
+        page_cache::reload_cached_pages(self.id, pages, &self.inner())
+    }
+
+    /// Truncate or extend the file to `len` bytes, filling the extended part
+    /// with zeros.
+    ///
+    /// Return `EINVAL` if the file is not open for writing, or `EPERM` if it
+    /// is prohibited by the seals.
+    pub fn truncate(&self, len: u64) -> LinuxResult {
+        let inner = self.inner();
+        let seals = self.get_seals().unwrap_or(0);
+        if seals != 0 {
+            let size = inner.get_attr()?.size();
+            if (len < size && seals & F_SEAL_SHRINK != 0)
+                || (len > size && seals & F_SEAL_GROW != 0)
+            {
+                return Err(LinuxError::EPERM);
+            }
+        }
+        inner.truncate(len).map_err(|e| match e {
+            AxError::PermissionDenied => LinuxError::EINVAL,
+            e => e.into(),
+        })?;
+        page_cache::truncate_cached(self.id, len);
+        drop(inner);
+        fs_notify(&self.path, IN_MODIFY);
+        Ok(())
+    }
+
+    /// Read data from the file at `offset`, without changing the file position.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> LinuxResult<usize> {
+        let read = self.inner().read_at(offset, buf)?;
+        page_cache::read_cached(self.id, offset, &mut buf[..read]);
+        Ok(read)
+    }
+
+    /// Write data to the file at `offset`, without changing the file position.
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> LinuxResult<usize> {
+        let inner = self.inner();
+        self.check_write_seals(&inner, offset, buf.len())?;
+        let written = inner.write_at(offset, buf)?;
+        page_cache::write_cached(self.id, offset, &buf[..written]);
+        drop(inner);
+        if written > 0 {
+            fs_notify(&self.path, IN_MODIFY);
+        }
+        Ok(written)
+    }
+
+    /// Get the seals of the file, or `EINVAL` if it is not a memfd.
+    pub fn get_seals(&self) -> LinuxResult<u32> {
+        self.seals
+            .as_ref()
+            .map(|seals| seals.load(Ordering::Acquire))
+            .ok_or(LinuxError::EINVAL)
+    }
+
+    /// Add `seals` to the file.
+    ///
+    /// Return `EINVAL` if the file is not a memfd, `EPERM` if it is not open
+    /// for writing or has been sealed by `F_SEAL_SEAL`, or `EBUSY` if
+    /// `F_SEAL_WRITE` is added while the file is `mapped_writable`.
+    pub fn add_seals(&self, seals: u32, mapped_writable: bool) -> LinuxResult {
+        let current = self.seals.as_ref().ok_or(LinuxError::EINVAL)?;
+        if self.flags.load(Ordering::Acquire) & O_ACCMODE == O_RDONLY {
+            return Err(LinuxError::EPERM);
+        }
+        let mut result = Err(LinuxError::EPERM);
+        let _ = current.fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| {
+            result = if old & F_SEAL_SEAL != 0 {
+                Err(LinuxError::EPERM)
+            } else if seals & F_SEAL_WRITE != 0 && mapped_writable {
+                Err(LinuxError::EBUSY)
+            } else {
+                Ok(())
+            };
+            result.is_ok().then_some(old | seals)
+        });
+        result
+    }
+
+    /// Check that the seals allow writing `len` bytes at `offset`.
+    fn check_write_seals(&self, inner: &axfs::fops::File, offset: u64, len: usize) -> LinuxResult {
+        let seals = self.get_seals().unwrap_or(0);
+        if seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0 {
+            return Err(LinuxError::EPERM);
+        }
+        if seals & F_SEAL_GROW != 0 && offset + len as u64 > inner.get_attr()?.size() {
+            return Err(LinuxError::EPERM);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        funlock(&self.path, self as *const Self as usize);
+        if self.unnamed {
+            if let Ok(path) = FilePath::new(&self.path) {
+                if HARDLINK_MANAGER.link_count(&path) <= 1 {
+                    ATTR_MANAGER.remove(&self.path);
+                }
+                HARDLINK_MANAGER.remove_link(path.as_str());
+            }
+        } else if self.installed.load(Ordering::Acquire) {
+            let writable = self.flags.load(Ordering::Acquire) & O_ACCMODE != O_RDONLY;
+            fs_notify(
+                &self.path,
+                if writable {
+                    IN_CLOSE_WRITE
+                } else {
+                    IN_CLOSE_NOWRITE
+                },
+            );
+        }
+    }
 }
 
 impl FileLike for File {
     fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
-        Ok(self.inner().read(buf)?)
+        let mut inner = self.inner();
+        let pos = inner.seek(SeekFrom::Current(0))?;
+        let read = inner.read(buf)?;
+        // The pages mapped by `MAP_SHARED` may be newer than the file.
+        page_cache::read_cached(self.id, pos, &mut buf[..read]);
+        Ok(read)
     }
 
     fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
-        Ok(self.inner().write(buf)?)
+        // Holding the lock makes moving to the end of file and writing atomic.
+        let mut inner = self.inner();
+        let pos = if self.flags.load(Ordering::Acquire) & O_APPEND != 0 {
+            inner.seek(SeekFrom::End(0))?
+        } else {
+            inner.seek(SeekFrom::Current(0))?
+        };
+        self.check_write_seals(&inner, pos, buf.len())?;
+        let written = inner.write(buf)?;
+        page_cache::write_cached(self.id, pos, &buf[..written]);
+        drop(inner);
+        if written > 0 {
+            fs_notify(&self.path, IN_MODIFY);
+        }
+        Ok(written)
     }
 
     fn stat(&self) -> LinuxResult<Kstat> {
@@ -47,19 +260,29 @@ impl FileLike for File {
         let ty = metadata.file_type() as u8;
         let perm = metadata.perm().bits() as u32;
 
-        Ok(Kstat {
+        let mut kstat = Kstat {
+            nlink: HARDLINK_MANAGER.link_count(&FilePath::new(&self.path)?) as _,
             mode: ((ty as u32) << 12) | perm,
             size: metadata.size(),
             blocks: metadata.blocks(),
             blksize: 512,
             ..Default::default()
-        })
+        };
+        ATTR_MANAGER.apply(&self.path, &mut kstat);
+        Ok(kstat)
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
         self
     }
 
+    fn add_to_fd_table(self) -> LinuxResult<c_int> {
+        let file = Arc::new(self);
+        let fd = add_file_like(file.clone())?;
+        file.installed.store(true, Ordering::Release);
+        Ok(fd)
+    }
+
     fn poll(&self) -> LinuxResult<PollState> {
         Ok(PollState {
             readable: true,
@@ -67,7 +290,26 @@ impl FileLike for File {
         })
     }
 
-    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        if nonblocking {
+            self.flags.fetch_or(O_NONBLOCK, Ordering::AcqRel);
+        } else {
+            self.flags.fetch_and(!O_NONBLOCK, Ordering::AcqRel);
+        }
+        Ok(())
+    }
+
+    fn status_flags(&self) -> u32 {
+        self.flags.load(Ordering::Acquire)
+    }
+
+    fn set_status_flags(&self, flags: u32) -> LinuxResult {
+        let settable = FILE_STATUS_FLAGS & !O_ACCMODE;
+        let _ = self
+            .flags
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| {
+                Some((old & !settable) | (flags & settable))
+            });
         Ok(())
     }
 }
@@ -76,12 +318,15 @@ impl FileLike for File {
 pub struct Directory {
     inner: Mutex<axfs::fops::Directory>,
     path: String,
+    /// The mounted file system the directory lives on, kept while it is open.
+    _mount: Option<Arc<MountRoot>>,
 }
 
 impl Directory {
     pub fn new(inner: axfs::fops::Directory, path: String) -> Self {
         Self {
             inner: Mutex::new(inner),
+            _mount: hold_mount(&path),
             path,
         }
     }
@@ -97,6 +342,12 @@ impl Directory {
     }
 }
 
+impl Drop for Directory {
+    fn drop(&mut self) {
+        funlock(&self.path, self as *const Self as usize);
+    }
+}
+
 impl FileLike for Directory {
     fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
         Err(LinuxError::EBADF)
@@ -107,10 +358,12 @@ impl FileLike for Directory {
     }
 
     fn stat(&self) -> LinuxResult<Kstat> {
-        Ok(Kstat {
+        let mut kstat = Kstat {
             mode: S_IFDIR | 0o755u32, // rwxr-xr-x
             ..Default::default()
-        })
+        };
+        ATTR_MANAGER.apply(&self.path, &mut kstat);
+        Ok(kstat)
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
@@ -128,6 +381,10 @@ impl FileLike for Directory {
         Ok(())
     }
 
+    fn status_flags(&self) -> u32 {
+        O_RDONLY | O_DIRECTORY
+    }
+
     fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
         get_file_like(fd)?
             .into_any()
@@ -0,0 +1,293 @@
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use alloc::{
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+use axtask::WaitQueue;
+use linux_raw_sys::general::{
+    IN_IGNORED, IN_ISDIR, IN_MOVED_FROM, IN_MOVED_TO, IN_ONESHOT, IN_Q_OVERFLOW, O_NONBLOCK,
+    O_RDONLY, inotify_event,
+};
+
+use super::{FileLike, Kstat};
+
+/// The maximum number of events queued on an inotify instance.
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+struct Watch {
+    path: String,
+    mask: u32,
+}
+
+struct InotifyEvent {
+    wd: i32,
+    mask: u32,
+    cookie: u32,
+    name: String,
+}
+
+impl InotifyEvent {
+    /// The size of the event as read by user space, with the name padded
+    /// to the alignment of `inotify_event`.
+    fn size(&self) -> usize {
+        size_of::<inotify_event>() + self.name_len()
+    }
+
+    fn name_len(&self) -> usize {
+        if self.name.is_empty() {
+            0
+        } else {
+            (self.name.len() + 1).next_multiple_of(align_of::<inotify_event>())
+        }
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        let header = [
+            self.wd as u32,
+            self.mask,
+            self.cookie,
+            self.name_len() as u32,
+        ];
+        for (chunk, value) in buf.chunks_exact_mut(4).zip(header) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        let name = &mut buf[size_of::<inotify_event>()..self.size()];
+        name.fill(0);
+        name[..self.name.len()].copy_from_slice(self.name.as_bytes());
+    }
+}
+
+/// An inotify instance, which queues events of the watched paths.
+pub struct Inotify {
+    watches: Mutex<BTreeMap<i32, Watch>>,
+    next_wd: AtomicU32,
+    events: Mutex<VecDeque<InotifyEvent>>,
+    wait_queue: WaitQueue,
+    nonblocking: AtomicBool,
+}
+
+/// All living inotify instances.
+static INSTANCES: Mutex<Vec<Weak<Inotify>>> = Mutex::new(Vec::new());
+
+impl Inotify {
+    /// Create an inotify instance and register it to receive events.
+    pub fn new(nonblocking: bool) -> Arc<Self> {
+        let inotify = Arc::new(Self {
+            watches: Mutex::new(BTreeMap::new()),
+            next_wd: AtomicU32::new(1),
+            events: Mutex::new(VecDeque::new()),
+            wait_queue: WaitQueue::new(),
+            nonblocking: AtomicBool::new(nonblocking),
+        });
+        let mut instances = INSTANCES.lock();
+        instances.retain(|i| i.strong_count() > 0);
+        instances.push(Arc::downgrade(&inotify));
+        inotify
+    }
+
+    /// Watch `path` for the events in `mask`, returning the watch descriptor.
+    ///
+    /// Watching a path again replaces its mask, or extends it if `mask_add`
+    /// is set. Return `EEXIST` instead if `mask_create` is set.
+    pub fn add_watch(
+        &self,
+        path: &str,
+        mask: u32,
+        mask_add: bool,
+        mask_create: bool,
+    ) -> LinuxResult<i32> {
+        let path = path.trim_end_matches('/');
+        let mut watches = self.watches.lock();
+        if let Some((&wd, watch)) = watches.iter_mut().find(|(_, w)| w.path == path) {
+            if mask_create {
+                return Err(LinuxError::EEXIST);
+            }
+            watch.mask = if mask_add { watch.mask | mask } else { mask };
+            return Ok(wd);
+        }
+        let wd = self.next_wd.fetch_add(1, Ordering::Relaxed) as i32;
+        watches.insert(
+            wd,
+            Watch {
+                path: path.into(),
+                mask,
+            },
+        );
+        Ok(wd)
+    }
+
+    /// Remove the watch `wd`, queueing an `IN_IGNORED` event for it.
+    pub fn rm_watch(&self, wd: i32) -> LinuxResult {
+        self.watches.lock().remove(&wd).ok_or(LinuxError::EINVAL)?;
+        self.push_event(wd, IN_IGNORED, 0, String::new());
+        Ok(())
+    }
+
+    fn push_event(&self, wd: i32, mask: u32, cookie: u32, name: String) {
+        let mut events = self.events.lock();
+        if events.len() >= MAX_QUEUED_EVENTS {
+            if events.back().is_none_or(|e| e.mask != IN_Q_OVERFLOW) {
+                events.push_back(InotifyEvent {
+                    wd: -1,
+                    mask: IN_Q_OVERFLOW,
+                    cookie: 0,
+                    name: String::new(),
+                });
+            }
+        } else {
+            events.push_back(InotifyEvent {
+                wd,
+                mask,
+                cookie,
+                name,
+            });
+        }
+        drop(events);
+        self.wait_queue.notify_all(false);
+    }
+
+    /// Queue the event `mask` happening on `path` for the matching watches,
+    /// which either watch `path` itself or its parent directory.
+    fn notify(&self, path: &str, mask: u32, cookie: u32) {
+        // Paths are kept without the trailing '/', so the root is "".
+        let Some((parent, name)) = path.rsplit_once('/') else {
+            return;
+        };
+        let mut watches = self.watches.lock();
+        let mut oneshots = Vec::new();
+        for (&wd, watch) in watches.iter() {
+            let name = if watch.path == path {
+                ""
+            } else if watch.path == parent {
+                name
+            } else {
+                continue;
+            };
+            if watch.mask & mask & !IN_ISDIR == 0 {
+                continue;
+            }
+            self.push_event(wd, mask, cookie, name.into());
+            if watch.mask & IN_ONESHOT != 0 {
+                oneshots.push(wd);
+            }
+        }
+        for wd in oneshots {
+            watches.remove(&wd);
+            self.push_event(wd, IN_IGNORED, 0, String::new());
+        }
+    }
+}
+
+impl FileLike for Inotify {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        loop {
+            let mut events = self.events.lock();
+            if events.is_empty() {
+                drop(events);
+                if self.nonblocking.load(Ordering::Acquire) {
+                    return Err(LinuxError::EAGAIN);
+                }
+                self.wait_queue
+                    .wait_until(|| !self.events.lock().is_empty());
+                continue;
+            }
+            if events[0].size() > buf.len() {
+                return Err(LinuxError::EINVAL);
+            }
+            let mut read = 0;
+            while let Some(event) = events.front() {
+                let size = event.size();
+                if read + size > buf.len() {
+                    break;
+                }
+                event.write_to(&mut buf[read..read + size]);
+                read += size;
+                events.pop_front();
+            }
+            return Ok(read);
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EBADF)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: 0o600u32, // rw-------
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: !self.events.lock().is_empty(),
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn status_flags(&self) -> u32 {
+        if self.nonblocking.load(Ordering::Acquire) {
+            O_RDONLY | O_NONBLOCK
+        } else {
+            O_RDONLY
+        }
+    }
+}
+
+fn instances() -> Vec<Arc<Inotify>> {
+    INSTANCES.lock().iter().filter_map(Weak::upgrade).collect()
+}
+
+/// Report the file system event `mask` happening on the absolute `path` to
+/// all inotify instances.
+pub fn fs_notify(path: &str, mask: u32) {
+    let path = path.trim_end_matches('/');
+    for inotify in instances() {
+        inotify.notify(path, mask, 0);
+    }
+}
+
+/// Report that `old` has been renamed to `new`, as a pair of
+/// `IN_MOVED_FROM` and `IN_MOVED_TO` events sharing a cookie.
+///
+/// For `RENAME_EXCHANGE`, `exchanged_is_dir` tells whether the file moved
+/// the other way, from `new` to `old`, is a directory, and that move is
+/// reported as a second pair.
+pub fn fs_notify_move(old: &str, new: &str, is_dir: bool, exchanged_is_dir: Option<bool>) {
+    static NEXT_COOKIE: AtomicU32 = AtomicU32::new(1);
+
+    let old = old.trim_end_matches('/');
+    let new = new.trim_end_matches('/');
+    let instances = instances();
+    let report = |from: &str, to: &str, is_dir: bool| {
+        let cookie = NEXT_COOKIE.fetch_add(1, Ordering::Relaxed);
+        let dir_flag = if is_dir { IN_ISDIR } else { 0 };
+        for inotify in &instances {
+            inotify.notify(from, IN_MOVED_FROM | dir_flag, cookie);
+            inotify.notify(to, IN_MOVED_TO | dir_flag, cookie);
+        }
+    };
+    report(old, new, is_dir);
+    if let Some(is_dir) = exchanged_is_dir {
+        report(new, old, is_dir);
+    }
+}
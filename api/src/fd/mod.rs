@@ -1,36 +1,59 @@
+mod eventfd;
+mod flock;
 mod fs;
+mod inotify;
 mod net;
+mod page_cache;
 mod pipe;
+mod record_lock;
+mod signalfd;
 mod stdio;
+mod timerfd;
+mod unix;
 
 use core::{any::Any, ffi::c_int};
 
 use alloc::{sync::Arc, vec::Vec};
 use axerrno::{LinuxError, LinuxResult};
+use axhal::time::TimeValue;
 use axio::PollState;
 use axns::{ResArc, def_resource};
+use axtask::{TaskExtRef, current};
 use flatten_objects::FlattenObjects;
-use linux_raw_sys::general::{stat, statx};
+use linux_raw_sys::general::{O_NONBLOCK, O_RDWR, STATX_BASIC_STATS, stat, statx, statx_timestamp};
 use spin::RwLock;
 
+use self::record_lock::release_record_locks;
+
 pub use self::{
-    fs::{Directory, File},
+    eventfd::EventFd,
+    flock::{FlockKind, flock, funlock},
+    fs::{Directory, File, TMPFILE_PREFIX},
+    inotify::{Inotify, fs_notify, fs_notify_move},
     net::Socket,
     pipe::Pipe,
+    record_lock::{RecordLockKind, find_record_lock_conflict, set_record_lock},
+    signalfd::SignalFd,
+    timerfd::TimerFd,
+    unix::{UnixMessage, UnixSocket, UnixSocketType},
 };
 
 pub const AX_FILE_LIMIT: usize = 1024;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Kstat {
-    ino: u64,
-    nlink: u32,
-    uid: u32,
-    gid: u32,
-    mode: u32,
-    size: u64,
-    blocks: u64,
-    blksize: u32,
+    pub ino: u64,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub size: u64,
+    pub blocks: u64,
+    pub blksize: u32,
+    pub rdev: u64,
+    pub atime: TimeValue,
+    pub mtime: TimeValue,
+    pub ctime: TimeValue,
 }
 
 impl Default for Kstat {
@@ -44,6 +67,10 @@ impl Default for Kstat {
             size: 0,
             blocks: 0,
             blksize: 4096,
+            rdev: 0,
+            atime: TimeValue::ZERO,
+            mtime: TimeValue::ZERO,
+            ctime: TimeValue::ZERO,
         }
     }
 }
@@ -60,6 +87,13 @@ impl From<Kstat> for stat {
         stat.st_size = value.size as _;
         stat.st_blksize = value.blksize as _;
         stat.st_blocks = value.blocks as _;
+        stat.st_rdev = value.rdev as _;
+        stat.st_atime = value.atime.as_secs() as _;
+        stat.st_atime_nsec = value.atime.subsec_nanos() as _;
+        stat.st_mtime = value.mtime.as_secs() as _;
+        stat.st_mtime_nsec = value.mtime.subsec_nanos() as _;
+        stat.st_ctime = value.ctime.as_secs() as _;
+        stat.st_ctime_nsec = value.ctime.subsec_nanos() as _;
 
         stat
     }
@@ -69,8 +103,8 @@ impl From<Kstat> for statx {
     fn from(value: Kstat) -> Self {
         // SAFETY: valid for statx
         let mut statx: statx = unsafe { core::mem::zeroed() };
+        statx.stx_mask = STATX_BASIC_STATS;
         statx.stx_blksize = value.blksize as _;
-        statx.stx_attributes = value.mode as _;
         statx.stx_nlink = value.nlink as _;
         statx.stx_uid = value.uid as _;
         statx.stx_gid = value.gid as _;
@@ -78,11 +112,24 @@ impl From<Kstat> for statx {
         statx.stx_ino = value.ino as _;
         statx.stx_size = value.size as _;
         statx.stx_blocks = value.blocks as _;
+        statx.stx_rdev_major = (((value.rdev >> 8) & 0xfff) | ((value.rdev >> 32) & !0xfff)) as _;
+        statx.stx_rdev_minor = ((value.rdev & 0xff) | ((value.rdev >> 12) & !0xff)) as _;
+        statx.stx_atime = timevalue_to_statx_timestamp(value.atime);
+        statx.stx_mtime = timevalue_to_statx_timestamp(value.mtime);
+        statx.stx_ctime = timevalue_to_statx_timestamp(value.ctime);
 
         statx
     }
 }
 
+fn timevalue_to_statx_timestamp(tv: TimeValue) -> statx_timestamp {
+    statx_timestamp {
+        tv_sec: tv.as_secs() as _,
+        tv_nsec: tv.subsec_nanos() as _,
+        __reserved: 0,
+    }
+}
+
 #[allow(dead_code)]
 pub trait FileLike: Send + Sync {
     fn read(&self, buf: &mut [u8]) -> LinuxResult<usize>;
@@ -92,6 +139,26 @@ pub trait FileLike: Send + Sync {
     fn poll(&self) -> LinuxResult<PollState>;
     fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult;
 
+    /// Get the file status flags, i.e. the access mode and flags like
+    /// `O_APPEND` and `O_NONBLOCK`.
+    fn status_flags(&self) -> u32 {
+        O_RDWR
+    }
+
+    /// Set the file status flags that can be changed by `fcntl(F_SETFL)`.
+    fn set_status_flags(&self, flags: u32) -> LinuxResult {
+        self.set_nonblocking(flags & O_NONBLOCK != 0)
+    }
+
+    /// Get a number that changes whenever data arrives or space is freed,
+    /// telling an edge-triggered epoll that a file staying ready has a new
+    /// event.
+    ///
+    /// Files not tracking it only report the changes of their readiness.
+    fn event_seq(&self) -> u64 {
+        0
+    }
+
     fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>>
     where
         Self: Sized + 'static,
@@ -110,13 +177,29 @@ pub trait FileLike: Send + Sync {
     }
 }
 
+/// An entry of the file descriptor table.
+///
+/// Duplicated fds share the same file object, while the fd flags belong to
+/// each entry.
+#[derive(Clone)]
+pub struct FileDescriptor {
+    pub inner: Arc<dyn FileLike>,
+    pub cloexec: bool,
+}
+
+impl FileDescriptor {
+    pub fn new(inner: Arc<dyn FileLike>, cloexec: bool) -> Self {
+        Self { inner, cloexec }
+    }
+}
+
 def_resource! {
-    pub static FD_TABLE: ResArc<RwLock<FlattenObjects<Arc<dyn FileLike>, AX_FILE_LIMIT>>> = ResArc::new();
+    pub static FD_TABLE: ResArc<RwLock<FlattenObjects<FileDescriptor, AX_FILE_LIMIT>>> = ResArc::new();
 }
 
 impl FD_TABLE {
     /// Return a copy of the inner table.
-    pub fn copy_inner(&self) -> RwLock<FlattenObjects<Arc<dyn FileLike>, AX_FILE_LIMIT>> {
+    pub fn copy_inner(&self) -> RwLock<FlattenObjects<FileDescriptor, AX_FILE_LIMIT>> {
         let table = self.read();
         let mut new_table = FlattenObjects::new();
         for id in table.ids() {
@@ -129,7 +212,23 @@ impl FD_TABLE {
         let mut table = self.write();
         let ids = table.ids().collect::<Vec<_>>();
         for id in ids {
-            let _ = table.remove(id);
+            if let Some(fd) = table.remove(id) {
+                release_process_locks(fd.inner);
+            }
+        }
+    }
+
+    /// Close all fds with the close-on-exec flag set.
+    pub fn close_on_exec(&self) {
+        let mut table = self.write();
+        let ids = table
+            .ids()
+            .filter(|&id| table.get(id).is_some_and(|fd| fd.cloexec))
+            .collect::<Vec<_>>();
+        for id in ids {
+            if let Some(fd) = table.remove(id) {
+                release_process_locks(fd.inner);
+            }
         }
     }
 }
@@ -139,36 +238,66 @@ pub fn get_file_like(fd: c_int) -> LinuxResult<Arc<dyn FileLike>> {
     FD_TABLE
         .read()
         .get(fd as usize)
-        .cloned()
+        .map(|fd| fd.inner.clone())
         .ok_or(LinuxError::EBADF)
 }
 
 /// Add a file to the file descriptor table.
 pub fn add_file_like(f: Arc<dyn FileLike>) -> LinuxResult<c_int> {
-    Ok(FD_TABLE.write().add(f).map_err(|_| LinuxError::EMFILE)? as c_int)
+    Ok(FD_TABLE
+        .write()
+        .add(FileDescriptor::new(f, false))
+        .map_err(|_| LinuxError::EMFILE)? as c_int)
+}
+
+/// Add a file to the file descriptor table at the lowest free fd that is
+/// greater than or equal to `min_fd`.
+pub fn add_file_like_from(
+    f: Arc<dyn FileLike>,
+    min_fd: c_int,
+    cloexec: bool,
+) -> LinuxResult<c_int> {
+    if min_fd < 0 || min_fd as usize >= AX_FILE_LIMIT {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut table = FD_TABLE.write();
+    let fd = (min_fd as usize..AX_FILE_LIMIT)
+        .find(|&fd| !table.is_assigned(fd))
+        .ok_or(LinuxError::EMFILE)?;
+    let _ = table.add_at(fd, FileDescriptor::new(f, cloexec));
+    Ok(fd as c_int)
 }
 
 /// Close a file by `fd`.
 pub fn close_file_like(fd: c_int) -> LinuxResult {
-    let f = FD_TABLE
+    let fd = FD_TABLE
         .write()
         .remove(fd as usize)
         .ok_or(LinuxError::EBADF)?;
-    debug!("close_file_like <= count: {}", Arc::strong_count(&f));
+    debug!("close_file_like <= count: {}", Arc::strong_count(&fd.inner));
+    release_process_locks(fd.inner);
     Ok(())
 }
 
+/// Release the record locks held by the current process on the file behind
+/// `f`, as closing any fd of a file drops all of them.
+pub fn release_process_locks(f: Arc<dyn FileLike>) {
+    if let Ok(file) = f.into_any().downcast::<File>() {
+        release_record_locks(file.path(), current().task_ext().thread.process().pid());
+    }
+}
+
 #[ctor_bare::register_ctor]
 fn init_stdio() {
     let mut fd_table = flatten_objects::FlattenObjects::new();
     fd_table
-        .add_at(0, Arc::new(stdio::stdin()) as _)
+        .add_at(0, FileDescriptor::new(Arc::new(stdio::stdin()), false))
         .unwrap_or_else(|_| panic!()); // stdin
     fd_table
-        .add_at(1, Arc::new(stdio::stdout()) as _)
+        .add_at(1, FileDescriptor::new(Arc::new(stdio::stdout()), false))
         .unwrap_or_else(|_| panic!()); // stdout
     fd_table
-        .add_at(2, Arc::new(stdio::stdout()) as _)
+        .add_at(2, FileDescriptor::new(Arc::new(stdio::stdout()), false))
         .unwrap_or_else(|_| panic!()); // stderr
     FD_TABLE.init_new(spin::RwLock::new(fd_table));
 }
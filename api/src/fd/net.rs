@@ -5,7 +5,7 @@ use axerrno::{LinuxError, LinuxResult};
 use axio::PollState;
 use axnet::{TcpSocket, UdpSocket};
 use axsync::Mutex;
-use linux_raw_sys::general::S_IFSOCK;
+use linux_raw_sys::general::{O_NONBLOCK, O_RDWR, S_IFSOCK};
 
 use super::{FileLike, Kstat};
 
@@ -108,4 +108,16 @@ impl FileLike for Socket {
         }
         Ok(())
     }
+
+    fn status_flags(&self) -> u32 {
+        let nonblocking = match self {
+            Socket::Udp(udpsocket) => udpsocket.lock().is_nonblocking(),
+            Socket::Tcp(tcpsocket) => tcpsocket.lock().is_nonblocking(),
+        };
+        if nonblocking {
+            O_RDWR | O_NONBLOCK
+        } else {
+            O_RDWR
+        }
+    }
 }
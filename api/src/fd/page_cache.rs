@@ -0,0 +1,190 @@
+use core::{alloc::Layout, ops::Range};
+
+use alloc::collections::btree_map::BTreeMap;
+use axerrno::LinuxResult;
+use axhal::mem::{PhysAddr, VirtAddr, virt_to_phys};
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+
+const PAGE_LAYOUT: Layout = match Layout::from_size_align(PAGE_SIZE_4K, PAGE_SIZE_4K) {
+    Ok(layout) => layout,
+    Err(_) => panic!(),
+};
+
+/// A page of file contents shared by the `MAP_SHARED` mappings of the file.
+struct CachedPage {
+    /// The address of the page frame in the kernel address space.
+    vaddr: usize,
+    /// The number of mappings of the page.
+    mappers: usize,
+}
+
+impl CachedPage {
+    fn new() -> Self {
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { alloc::alloc::alloc_zeroed(PAGE_LAYOUT) };
+        if ptr.is_null() {
+            alloc::alloc::handle_alloc_error(PAGE_LAYOUT);
+        }
+        Self {
+            vaddr: ptr as usize,
+            mappers: 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        // SAFETY: the frame is owned by the page.
+        unsafe { core::slice::from_raw_parts(self.vaddr as *const u8, PAGE_SIZE_4K) }
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the frame is owned by the page.
+        unsafe { core::slice::from_raw_parts_mut(self.vaddr as *mut u8, PAGE_SIZE_4K) }
+    }
+
+    fn paddr(&self) -> PhysAddr {
+        virt_to_phys(VirtAddr::from(self.vaddr))
+    }
+}
+
+impl Drop for CachedPage {
+    fn drop(&mut self) {
+        // SAFETY: the frame is allocated with the same layout in `new`.
+        unsafe { alloc::alloc::dealloc(self.vaddr as *mut u8, PAGE_LAYOUT) };
+    }
+}
+
+/// The shared pages of all files, keyed by file identity and page index.
+static PAGE_CACHE: Mutex<BTreeMap<u64, BTreeMap<u64, CachedPage>>> = Mutex::new(BTreeMap::new());
+
+/// Get the frame holding page `index` of the file `id`, loading it from
+/// `file` if it is not cached, and count one more mapping of it.
+pub fn map_cached_page(id: u64, index: u64, file: &axfs::fops::File) -> LinuxResult<PhysAddr> {
+    let mut cache = PAGE_CACHE.lock();
+    let pages = cache.entry(id).or_default();
+    if let Some(page) = pages.get_mut(&index) {
+        page.mappers += 1;
+        return Ok(page.paddr());
+    }
+    let mut page = CachedPage::new();
+    // The part beyond the end of file stays zero.
+    file.read_at(index * PAGE_SIZE_4K as u64, page.data_mut())?;
+    page.mappers = 1;
+    let paddr = page.paddr();
+    pages.insert(index, page);
+    Ok(paddr)
+}
+
+/// Count one more mapping of the cached `pages` of the file `id`.
+pub fn share_cached_pages(id: u64, pages: Range<u64>) {
+    if let Some(cached) = PAGE_CACHE.lock().get_mut(&id) {
+        for (_, page) in cached.range_mut(pages) {
+            page.mappers += 1;
+        }
+    }
+}
+
+/// Write the cached page `index` of the file `id` back to `file`, without
+/// extending it.
+pub fn sync_cached_page(id: u64, index: u64, file: &axfs::fops::File) -> LinuxResult {
+    let cache = PAGE_CACHE.lock();
+    let Some(page) = cache.get(&id).and_then(|cached| cached.get(&index)) else {
+        return Ok(());
+    };
+    let size = file.get_attr()?.size();
+    let offset = index * PAGE_SIZE_4K as u64;
+    if offset < size {
+        let len = (size - offset).min(PAGE_SIZE_4K as u64) as usize;
+        file.write_at(offset, &page.data()[..len])?;
+    }
+    Ok(())
+}
+
+/// Read the cached `pages` of the file `id` again from `file`, discarding
+/// the changes not written back.
+pub fn reload_cached_pages(id: u64, pages: Range<u64>, file: &axfs::fops::File) -> LinuxResult {
+    let mut cache = PAGE_CACHE.lock();
+    let Some(cached) = cache.get_mut(&id) else {
+        return Ok(());
+    };
+    for (&index, page) in cached.range_mut(pages) {
+        let data = page.data_mut();
+        data.fill(0);
+        file.read_at(index * PAGE_SIZE_4K as u64, data)?;
+    }
+    Ok(())
+}
+
+/// Drop one mapping of the cached `pages` of the file `id`, freeing those no
+/// longer mapped.
+///
+/// The dirty pages must have been written back by their mappings.
+pub fn unmap_cached_pages(id: u64, pages: Range<u64>) {
+    let mut cache = PAGE_CACHE.lock();
+    let Some(cached) = cache.get_mut(&id) else {
+        return;
+    };
+    cached.retain(|index, page| {
+        if pages.contains(index) {
+            page.mappers -= 1;
+        }
+        page.mappers > 0
+    });
+    if cached.is_empty() {
+        cache.remove(&id);
+    }
+}
+
+/// Call `f` with each cached page of the file `id` overlapping the `len`
+/// bytes from `offset`, along with the part of it and of those bytes where
+/// they overlap.
+fn for_each_overlap(
+    id: u64,
+    offset: u64,
+    len: usize,
+    mut f: impl FnMut(&mut CachedPage, Range<usize>, Range<usize>),
+) {
+    let mut cache = PAGE_CACHE.lock();
+    let Some(cached) = cache.get_mut(&id) else {
+        return;
+    };
+    let end = offset + len as u64;
+    let pages = offset / PAGE_SIZE_4K as u64..end.div_ceil(PAGE_SIZE_4K as u64);
+    for (&index, page) in cached.range_mut(pages) {
+        let page_start = index * PAGE_SIZE_4K as u64;
+        let start = offset.max(page_start);
+        let stop = end.min(page_start + PAGE_SIZE_4K as u64);
+        let in_page = (start - page_start) as usize..(stop - page_start) as usize;
+        let in_buf = (start - offset) as usize..(stop - offset) as usize;
+        f(page, in_page, in_buf);
+    }
+}
+
+/// Overwrite the data read from the `buf.len()` bytes at `offset` of the
+/// file `id` with its cached pages, which hold the latest contents.
+pub fn read_cached(id: u64, offset: u64, buf: &mut [u8]) {
+    for_each_overlap(id, offset, buf.len(), |page, in_page, in_buf| {
+        buf[in_buf].copy_from_slice(&page.data()[in_page]);
+    });
+}
+
+/// Copy the data written to the `buf.len()` bytes at `offset` of the file
+/// `id` into its cached pages.
+pub fn write_cached(id: u64, offset: u64, buf: &[u8]) {
+    for_each_overlap(id, offset, buf.len(), |page, in_page, in_buf| {
+        page.data_mut()[in_page].copy_from_slice(&buf[in_buf]);
+    });
+}
+
+/// Zero the cached pages of the file `id` beyond `len` bytes, following a
+/// truncation.
+pub fn truncate_cached(id: u64, len: u64) {
+    let mut cache = PAGE_CACHE.lock();
+    let Some(cached) = cache.get_mut(&id) else {
+        return;
+    };
+    for (&index, page) in cached.range_mut(len / PAGE_SIZE_4K as u64..) {
+        let start = len.saturating_sub(index * PAGE_SIZE_4K as u64) as usize;
+        page.data_mut()[start..].fill(0);
+    }
+}
@@ -1,12 +1,16 @@
-use core::any::Any;
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use alloc::sync::Arc;
 use axerrno::{LinuxError, LinuxResult};
 use axio::PollState;
 use axsync::Mutex;
-use linux_raw_sys::general::S_IFIFO;
+use linux_raw_sys::general::{O_NONBLOCK, O_RDONLY, O_WRONLY, S_IFIFO};
 
 use super::{FileLike, Kstat};
+use crate::has_pending_signal;
 
 #[derive(Copy, Clone, PartialEq)]
 enum RingBufferStatus {
@@ -22,6 +26,8 @@ struct PipeRingBuffer {
     head: usize,
     tail: usize,
     status: RingBufferStatus,
+    /// The number of bytes written and read so far.
+    seq: u64,
 }
 
 impl PipeRingBuffer {
@@ -31,11 +37,13 @@ impl PipeRingBuffer {
             head: 0,
             tail: 0,
             status: RingBufferStatus::Empty,
+            seq: 0,
         }
     }
 
     fn write_byte(&mut self, byte: u8) {
         self.status = RingBufferStatus::Normal;
+        self.seq = self.seq.wrapping_add(1);
         self.arr[self.tail] = byte;
         self.tail = (self.tail + 1) % RING_BUFFER_SIZE;
         if self.tail == self.head {
@@ -45,6 +53,7 @@ impl PipeRingBuffer {
 
     fn read_byte(&mut self) -> u8 {
         self.status = RingBufferStatus::Normal;
+        self.seq = self.seq.wrapping_add(1);
         let c = self.arr[self.head];
         self.head = (self.head + 1) % RING_BUFFER_SIZE;
         if self.head == self.tail {
@@ -53,6 +62,11 @@ impl PipeRingBuffer {
         c
     }
 
+    /// Get the byte at `offset` from the head without consuming it.
+    const fn peek_byte(&self, offset: usize) -> u8 {
+        self.arr[(self.head + offset) % RING_BUFFER_SIZE]
+    }
+
     /// Get the length of remaining data in the buffer
     const fn available_read(&self) -> usize {
         if matches!(self.status, RingBufferStatus::Empty) {
@@ -77,6 +91,7 @@ impl PipeRingBuffer {
 pub struct Pipe {
     readable: bool,
     buffer: Arc<Mutex<PipeRingBuffer>>,
+    nonblocking: AtomicBool,
 }
 
 impl Pipe {
@@ -85,10 +100,12 @@ impl Pipe {
         let read_end = Pipe {
             readable: true,
             buffer: buffer.clone(),
+            nonblocking: AtomicBool::new(false),
         };
         let write_end = Pipe {
             readable: false,
             buffer,
+            nonblocking: AtomicBool::new(false),
         };
         (read_end, write_end)
     }
@@ -104,10 +121,32 @@ impl Pipe {
     pub fn closed(&self) -> bool {
         Arc::strong_count(&self.buffer) == 1
     }
-}
 
-impl FileLike for Pipe {
-    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+    /// Get the length of data that can be read without blocking.
+    pub fn available_read(&self) -> usize {
+        self.buffer.lock().available_read()
+    }
+
+    /// Get the length of data that can be written without blocking.
+    pub fn available_write(&self) -> usize {
+        self.buffer.lock().available_write()
+    }
+
+    /// Read from the pipe like [`FileLike::read`], but leave the data in the
+    /// pipe to be consumed later with [`Pipe::consume`].
+    pub fn peek(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        self.read_bytes(buf, false)
+    }
+
+    /// Drop `len` bytes of data from the pipe, which must have been peeked.
+    pub fn consume(&self, len: usize) {
+        let mut ring_buffer = self.buffer.lock();
+        for _ in 0..len.min(ring_buffer.available_read()) {
+            ring_buffer.read_byte();
+        }
+    }
+
+    fn read_bytes(&self, buf: &mut [u8], consume: bool) -> LinuxResult<usize> {
         if !self.readable() {
             return Err(LinuxError::EPERM);
         }
@@ -122,17 +161,33 @@ impl FileLike for Pipe {
                 if self.closed() {
                     return Ok(0);
                 }
+                if self.nonblocking.load(Ordering::Acquire) {
+                    return Err(LinuxError::EAGAIN);
+                }
+                if has_pending_signal() {
+                    return Err(LinuxError::ERESTART);
+                }
                 drop(ring_buffer);
                 // Buffer is empty, wait for write end to produce
                 axtask::yield_now(); // TODO: use synconize primitive
                 continue;
             }
-            for c in buf.iter_mut().take(read_size) {
-                *c = ring_buffer.read_byte();
+            for (i, c) in buf.iter_mut().take(read_size).enumerate() {
+                *c = if consume {
+                    ring_buffer.read_byte()
+                } else {
+                    ring_buffer.peek_byte(i)
+                };
             }
             return Ok(read_size);
         }
     }
+}
+
+impl FileLike for Pipe {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        self.read_bytes(buf, true)
+    }
 
     fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
         if !self.writable() {
@@ -154,6 +209,20 @@ impl FileLike for Pipe {
                 if self.closed() {
                     return Ok(write_size);
                 }
+                if self.nonblocking.load(Ordering::Acquire) {
+                    return if write_size > 0 {
+                        Ok(write_size)
+                    } else {
+                        Err(LinuxError::EAGAIN)
+                    };
+                }
+                if has_pending_signal() {
+                    return if write_size > 0 {
+                        Ok(write_size)
+                    } else {
+                        Err(LinuxError::ERESTART)
+                    };
+                }
                 drop(ring_buffer);
                 // Buffer is full, wait for read end to consume
                 axtask::yield_now(); // TODO: use synconize primitive
@@ -200,7 +269,21 @@ impl FileLike for Pipe {
         }
     }
 
-    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
         Ok(())
     }
+
+    fn event_seq(&self) -> u64 {
+        self.buffer.lock().seq
+    }
+
+    fn status_flags(&self) -> u32 {
+        let access = if self.readable { O_RDONLY } else { O_WRONLY };
+        if self.nonblocking.load(Ordering::Acquire) {
+            access | O_NONBLOCK
+        } else {
+            access
+        }
+    }
 }
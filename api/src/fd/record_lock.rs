@@ -0,0 +1,132 @@
+use alloc::{collections::btree_map::BTreeMap, string::String, vec::Vec};
+use axerrno::{LinuxError, LinuxResult};
+use axprocess::Pid;
+use axsync::Mutex;
+use axtask::WaitQueue;
+
+/// The kind of a POSIX record lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordLockKind {
+    Read,
+    Write,
+}
+
+/// A POSIX record lock on the byte range `start..end` of a file.
+///
+/// Record locks belong to processes, rather than open file descriptions.
+#[derive(Debug, Clone, Copy)]
+pub struct RecordLock {
+    pub owner: Pid,
+    pub kind: RecordLockKind,
+    pub start: u64,
+    /// The end of the range (exclusive), `u64::MAX` means the end of file.
+    pub end: u64,
+}
+
+impl RecordLock {
+    fn conflicts_with(&self, owner: Pid, kind: RecordLockKind, start: u64, end: u64) -> bool {
+        self.owner != owner
+            && self.start < end
+            && start < self.end
+            && (self.kind == RecordLockKind::Write || kind == RecordLockKind::Write)
+    }
+}
+
+/// The record locks of all files, keyed by path.
+static RECORD_LOCKS: Mutex<BTreeMap<String, Vec<RecordLock>>> = Mutex::new(BTreeMap::new());
+/// Tasks waiting for a record lock to be released.
+static RECORD_LOCK_WQ: WaitQueue = WaitQueue::new();
+
+/// Find a lock on the file at `path` that prevents `owner` from placing a
+/// lock of `kind` on `start..end`.
+pub fn find_record_lock_conflict(
+    path: &str,
+    owner: Pid,
+    kind: RecordLockKind,
+    start: u64,
+    end: u64,
+) -> Option<RecordLock> {
+    RECORD_LOCKS.lock().get(path).and_then(|locks| {
+        locks
+            .iter()
+            .find(|lock| lock.conflicts_with(owner, kind, start, end))
+            .copied()
+    })
+}
+
+/// Place a record lock of `kind` on `start..end` of the file at `path` for
+/// `owner`, or remove the locks of `owner` in that range if `kind` is `None`.
+///
+/// Locks already held by `owner` in the range are replaced. If the range is
+/// locked by others, wait for it if `wait` is set, otherwise return `EAGAIN`.
+pub fn set_record_lock(
+    path: &str,
+    owner: Pid,
+    kind: Option<RecordLockKind>,
+    start: u64,
+    end: u64,
+    wait: bool,
+) -> LinuxResult {
+    loop {
+        {
+            let mut all_locks = RECORD_LOCKS.lock();
+            let locks = all_locks.entry(path.into()).or_default();
+            if kind.is_none_or(|kind| {
+                !locks
+                    .iter()
+                    .any(|lock| lock.conflicts_with(owner, kind, start, end))
+            }) {
+                // Cut the range out of the locks held by `owner`.
+                let mut new_locks = Vec::with_capacity(locks.len() + 2);
+                for lock in locks.drain(..) {
+                    if lock.owner != owner || lock.end <= start || end <= lock.start {
+                        new_locks.push(lock);
+                        continue;
+                    }
+                    if lock.start < start {
+                        new_locks.push(RecordLock { end: start, ..lock });
+                    }
+                    if end < lock.end {
+                        new_locks.push(RecordLock { start: end, ..lock });
+                    }
+                }
+                if let Some(kind) = kind {
+                    new_locks.push(RecordLock {
+                        owner,
+                        kind,
+                        start,
+                        end,
+                    });
+                }
+                if new_locks.is_empty() {
+                    all_locks.remove(path);
+                } else {
+                    *locks = new_locks;
+                }
+                break;
+            }
+        }
+        if !wait {
+            return Err(LinuxError::EAGAIN);
+        }
+        let kind = kind.unwrap();
+        RECORD_LOCK_WQ
+            .wait_until(|| find_record_lock_conflict(path, owner, kind, start, end).is_none());
+    }
+    RECORD_LOCK_WQ.notify_all(false);
+    Ok(())
+}
+
+/// Release all record locks held by `owner` on the file at `path`.
+pub fn release_record_locks(path: &str, owner: Pid) {
+    let mut all_locks = RECORD_LOCKS.lock();
+    let Some(locks) = all_locks.get_mut(path) else {
+        return;
+    };
+    locks.retain(|lock| lock.owner != owner);
+    if locks.is_empty() {
+        all_locks.remove(path);
+    }
+    drop(all_locks);
+    RECORD_LOCK_WQ.notify_all(false);
+}
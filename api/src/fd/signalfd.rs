@@ -0,0 +1,170 @@
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsignal::ctypes::{SignalInfo, SignalSet};
+use axsync::Mutex;
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::general::{O_NONBLOCK, O_RDONLY, siginfo};
+
+use super::{FileLike, Kstat};
+use crate::has_pending_signal;
+
+/// The record read from a signalfd for each dequeued signal.
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct signalfd_siginfo {
+    ssi_signo: u32,
+    ssi_errno: i32,
+    ssi_code: i32,
+    ssi_pid: u32,
+    ssi_uid: u32,
+    ssi_fd: i32,
+    ssi_tid: u32,
+    ssi_band: u32,
+    ssi_overrun: u32,
+    ssi_trapno: u32,
+    ssi_status: i32,
+    ssi_int: i32,
+    ssi_ptr: u64,
+    ssi_utime: u64,
+    ssi_stime: u64,
+    ssi_addr: u64,
+    ssi_addr_lsb: u16,
+    __pad2: u16,
+    ssi_syscall: i32,
+    ssi_call_addr: u64,
+    ssi_arch: u32,
+    __pad: [u8; 28],
+}
+
+impl From<SignalInfo> for signalfd_siginfo {
+    fn from(sig: SignalInfo) -> Self {
+        let mut info: siginfo = unsafe { core::mem::zeroed() };
+        sig.to_ctype(&mut info);
+        // SAFETY: every kind of siginfo starts with the pid and uid of the
+        // sender, right after the signal number, errno and code.
+        let info = unsafe { info.__bindgen_anon_1.__bindgen_anon_1 };
+        let kill = unsafe { info._sifields._kill };
+        Self {
+            ssi_signo: info.si_signo as _,
+            ssi_errno: info.si_errno,
+            ssi_code: info.si_code,
+            ssi_pid: kill._pid as _,
+            ssi_uid: kill._uid,
+            ..Default::default()
+        }
+    }
+}
+
+/// A signalfd object, which reads the pending signals of the caller that
+/// are in its mask.
+pub struct SignalFd {
+    mask: Mutex<SignalSet>,
+    nonblocking: AtomicBool,
+}
+
+impl SignalFd {
+    pub fn new(mask: SignalSet, nonblocking: bool) -> Self {
+        Self {
+            mask: Mutex::new(mask),
+            nonblocking: AtomicBool::new(nonblocking),
+        }
+    }
+
+    /// Replace the set of signals read from the signalfd.
+    pub fn set_mask(&self, mask: SignalSet) {
+        *self.mask.lock() = mask;
+    }
+
+    fn has_pending(&self) -> bool {
+        let curr = current();
+        let task_ext = curr.task_ext();
+        let mut pending = task_ext.thread_data().pending.lock().pending
+            | task_ext.process_data().pending.lock().pending;
+        pending.remove_from(&!*self.mask.lock());
+        pending != SignalSet::default()
+    }
+}
+
+impl FileLike for SignalFd {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        const RECORD_SIZE: usize = size_of::<signalfd_siginfo>();
+        if buf.len() < RECORD_SIZE {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut read = 0;
+        loop {
+            let mask = *self.mask.lock();
+            while read + RECORD_SIZE <= buf.len() {
+                let Some(sig) = crate::dequeue_signal(&mask) else {
+                    break;
+                };
+                let record = signalfd_siginfo::from(sig);
+                // SAFETY: `signalfd_siginfo` is plain old data.
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(
+                        &record as *const signalfd_siginfo as *const u8,
+                        RECORD_SIZE,
+                    )
+                };
+                buf[read..read + RECORD_SIZE].copy_from_slice(bytes);
+                read += RECORD_SIZE;
+            }
+            if read > 0 {
+                return Ok(read);
+            }
+            if self.nonblocking.load(Ordering::Acquire) {
+                return Err(LinuxError::EAGAIN);
+            }
+            if has_pending_signal() {
+                return Err(LinuxError::ERESTART);
+            }
+            current()
+                .task_ext()
+                .process_data()
+                .signal_wq
+                .wait_until(|| self.has_pending() || has_pending_signal());
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: 0o600u32, // rw-------
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: self.has_pending(),
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn status_flags(&self) -> u32 {
+        if self.nonblocking.load(Ordering::Acquire) {
+            O_RDONLY | O_NONBLOCK
+        } else {
+            O_RDONLY
+        }
+    }
+}
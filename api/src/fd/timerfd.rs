@@ -0,0 +1,206 @@
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use alloc::{sync::Arc, vec::Vec};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::TimeValue;
+use axio::PollState;
+use axprocess::Process;
+use axsync::Mutex;
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::general::{O_NONBLOCK, O_RDWR};
+use starry_core::task::ProcessData;
+
+use super::{FileLike, Kstat};
+use crate::has_pending_signal;
+
+#[derive(Default)]
+struct TimerState {
+    /// The next expiration on the clock of the timer, `None` if disarmed.
+    deadline: Option<TimeValue>,
+    /// The period of the timer, zero for one-shot timers.
+    interval: TimeValue,
+    /// The expirations not read yet.
+    expirations: u64,
+}
+
+impl TimerState {
+    /// Account for the expirations up to `now`.
+    fn update(&mut self, now: TimeValue) {
+        let Some(deadline) = self.deadline else {
+            return;
+        };
+        if now < deadline {
+            return;
+        }
+        if self.interval.is_zero() {
+            self.expirations += 1;
+            self.deadline = None;
+        } else {
+            let interval = self.interval.as_nanos();
+            let count = (now - deadline).as_nanos() / interval + 1;
+            self.expirations = self.expirations.saturating_add(count as u64);
+            self.deadline = Some(deadline + Duration::from_nanos((count * interval) as u64));
+        }
+    }
+
+    /// Get the time until the next expiration and the interval.
+    fn setting(&self, now: TimeValue) -> (TimeValue, TimeValue) {
+        let remaining = self.deadline.map_or(TimeValue::ZERO, |d| {
+            // An armed timer never reports zero, as that means disarmed.
+            d.checked_sub(now).unwrap_or(TimeValue::from_nanos(1))
+        });
+        (remaining, self.interval)
+    }
+}
+
+/// A timerfd object, which counts the expirations of a timer.
+pub struct TimerFd {
+    clock: fn() -> TimeValue,
+    state: Mutex<TimerState>,
+    nonblocking: AtomicBool,
+    /// The processes of the threads waiting for the timer, which sleep on
+    /// their signal wait queues until the deadline so that signals wake
+    /// them.
+    waiters: Mutex<Vec<Arc<Process>>>,
+}
+
+impl TimerFd {
+    /// Create a disarmed timer measured by `clock`.
+    pub fn new(clock: fn() -> TimeValue, nonblocking: bool) -> Self {
+        Self {
+            clock,
+            state: Mutex::new(TimerState::default()),
+            nonblocking: AtomicBool::new(nonblocking),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Get the current time on the clock of the timer.
+    pub fn now(&self) -> TimeValue {
+        (self.clock)()
+    }
+
+    /// Get the time until the next expiration and the interval of the timer.
+    pub fn get_time(&self) -> (TimeValue, TimeValue) {
+        let now = self.now();
+        let mut state = self.state.lock();
+        state.update(now);
+        state.setting(now)
+    }
+
+    /// Arm the timer to expire at `deadline` and then every `interval`, or
+    /// disarm it if `deadline` is `None`. Return the previous setting.
+    pub fn set_time(
+        &self,
+        deadline: Option<TimeValue>,
+        interval: TimeValue,
+    ) -> (TimeValue, TimeValue) {
+        let now = self.now();
+        let mut state = self.state.lock();
+        state.update(now);
+        let old = state.setting(now);
+        *state = TimerState {
+            deadline,
+            interval,
+            expirations: 0,
+        };
+        drop(state);
+        for process in self.waiters.lock().iter() {
+            if let Some(data) = process.data::<ProcessData>() {
+                data.signal_wq.notify_all(false);
+            }
+        }
+        old
+    }
+}
+
+impl FileLike for TimerFd {
+    /// Read the number of expirations since the last read, resetting it.
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        if buf.len() < size_of::<u64>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let value = loop {
+            let now = self.now();
+            let mut state = self.state.lock();
+            state.update(now);
+            if state.expirations > 0 {
+                break core::mem::take(&mut state.expirations);
+            }
+            if self.nonblocking.load(Ordering::Acquire) {
+                return Err(LinuxError::EAGAIN);
+            }
+            if has_pending_signal() {
+                return Err(LinuxError::ERESTART);
+            }
+            // Wake up at the deadline, or when the timer is set again.
+            let deadline = state.deadline;
+            drop(state);
+            let changed = || {
+                has_pending_signal() || {
+                    let mut state = self.state.lock();
+                    state.update(self.now());
+                    state.expirations > 0 || state.deadline != deadline
+                }
+            };
+            let curr = current();
+            let process = curr.task_ext().thread.process();
+            let signal_wq = &curr.task_ext().process_data().signal_wq;
+            self.waiters.lock().push(process.clone());
+            match deadline {
+                Some(deadline) => {
+                    signal_wq.wait_timeout_until(deadline.saturating_sub(now), changed);
+                }
+                None => signal_wq.wait_until(changed),
+            }
+            let mut waiters = self.waiters.lock();
+            if let Some(pos) = waiters.iter().position(|p| Arc::ptr_eq(p, process)) {
+                waiters.swap_remove(pos);
+            }
+        };
+        buf[..size_of::<u64>()].copy_from_slice(&value.to_ne_bytes());
+        Ok(size_of::<u64>())
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: 0o600u32, // rw-------
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        let now = self.now();
+        let mut state = self.state.lock();
+        state.update(now);
+        Ok(PollState {
+            readable: state.expirations > 0,
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn status_flags(&self) -> u32 {
+        if self.nonblocking.load(Ordering::Acquire) {
+            O_RDWR | O_NONBLOCK
+        } else {
+            O_RDWR
+        }
+    }
+}
@@ -0,0 +1,663 @@
+use core::{
+    any::Any,
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use alloc::{
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsignal::ctypes::SignalInfo;
+use axsync::Mutex;
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::general::{O_NONBLOCK, O_RDWR, S_IFSOCK, SI_USER, SIGPIPE};
+
+use super::{FileLike, Kstat};
+use crate::{has_pending_signal, send_signal_thread, sockaddr::UnixAddr};
+
+/// The capacity of the buffer of each direction of a stream connection.
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The capacity of the queue of datagrams received by each socket, in bytes.
+const DATAGRAM_QUEUE_SIZE: usize = 64 * 1024;
+
+/// The type of a Unix domain socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixSocketType {
+    /// `SOCK_STREAM`
+    Stream,
+    /// `SOCK_DGRAM`
+    Datagram,
+}
+
+/// The bytes in a stream buffer, with the files passed along.
+#[derive(Default)]
+struct StreamData {
+    bytes: VecDeque<u8>,
+    /// The offset of the first byte in `bytes` from the start of the stream.
+    head: usize,
+    /// The files passed with `SCM_RIGHTS`, by the offset of the first byte
+    /// sent with them.
+    rights: VecDeque<(usize, Vec<Arc<dyn FileLike>>)>,
+}
+
+/// The data sent in one direction of a stream connection.
+#[derive(Default)]
+struct StreamBuffer {
+    data: Mutex<StreamData>,
+    /// Set when the writing end is closed, after which the data left is read
+    /// before the end of file.
+    write_closed: AtomicBool,
+    /// Set when the reading end is closed, after which writes fail with
+    /// `EPIPE`.
+    read_closed: AtomicBool,
+}
+
+/// A connected stream socket, which reads from `rx` and writes to `tx` of
+/// its peer.
+struct StreamConnection {
+    rx: Arc<StreamBuffer>,
+    tx: Arc<StreamBuffer>,
+    /// The address of the peer when it is connected.
+    peer_addr: UnixAddr,
+}
+
+impl StreamConnection {
+    /// Create the two ends of a connection, with the addresses of each other.
+    fn pair(addr: UnixAddr, peer_addr: UnixAddr) -> (Self, Self) {
+        let (a, b) = (
+            Arc::new(StreamBuffer::default()),
+            Arc::new(StreamBuffer::default()),
+        );
+        let this = Self {
+            rx: a.clone(),
+            tx: b.clone(),
+            peer_addr,
+        };
+        let peer = Self {
+            rx: b,
+            tx: a,
+            peer_addr: addr,
+        };
+        (this, peer)
+    }
+}
+
+enum UnixState {
+    Unconnected,
+    Listening {
+        backlog: usize,
+        /// The connected sockets to be accepted.
+        pending: VecDeque<Arc<UnixSocket>>,
+    },
+    Connected(StreamConnection),
+    /// A datagram socket, which sends to `peer` when it is connected.
+    Datagram {
+        peer: Option<Weak<UnixSocket>>,
+    },
+}
+
+/// A message received by a datagram socket.
+struct Datagram {
+    data: Vec<u8>,
+    rights: Vec<Arc<dyn FileLike>>,
+    /// The address of the sender when it is sent.
+    sender: UnixAddr,
+}
+
+/// A message received by [`UnixSocket::recv_msg`].
+pub struct UnixMessage {
+    /// The length of the data received into the buffer.
+    pub len: usize,
+    /// The length of the whole datagram, which is greater than `len` if it
+    /// is truncated.
+    pub full_len: usize,
+    /// The files passed with `SCM_RIGHTS`.
+    pub rights: Vec<Arc<dyn FileLike>>,
+    /// The address of the sender.
+    pub addr: UnixAddr,
+}
+
+struct UnixSocketInner {
+    /// The address bound, as given to `bind`.
+    addr: UnixAddr,
+    state: UnixState,
+}
+
+/// A Unix domain socket (`AF_UNIX`) of `SOCK_STREAM` or `SOCK_DGRAM`.
+pub struct UnixSocket {
+    ty: UnixSocketType,
+    inner: Mutex<UnixSocketInner>,
+    /// The datagrams received, if it is a datagram socket.
+    datagrams: Mutex<VecDeque<Datagram>>,
+    /// Set by `shutdown` on a datagram socket, whose peers are not told.
+    read_shutdown: AtomicBool,
+    write_shutdown: AtomicBool,
+    nonblocking: AtomicBool,
+}
+
+/// The bound sockets, by their addresses with the paths resolved.
+static UNIX_BINDINGS: Mutex<BTreeMap<UnixAddr, Weak<UnixSocket>>> = Mutex::new(BTreeMap::new());
+
+/// Raise `SIGPIPE` unless `nosignal` is set, and return `EPIPE`.
+fn broken_pipe(nosignal: bool) -> LinuxError {
+    if !nosignal {
+        send_signal_thread(
+            &current().task_ext().thread,
+            SignalInfo::new(SIGPIPE, SI_USER),
+        );
+    }
+    LinuxError::EPIPE
+}
+
+/// Retry `f` while it fails with `EAGAIN` unless `nonblocking` is set,
+/// until a signal arrives.
+fn block_on<T>(nonblocking: bool, mut f: impl FnMut() -> LinuxResult<T>) -> LinuxResult<T> {
+    loop {
+        match f() {
+            Err(LinuxError::EAGAIN) if !nonblocking => {
+                if has_pending_signal() {
+                    return Err(LinuxError::ERESTART);
+                }
+                axtask::yield_now(); // TODO: use synconize primitive
+            }
+            result => return result,
+        }
+    }
+}
+
+impl UnixSocket {
+    fn with_state(addr: UnixAddr, state: UnixState) -> Self {
+        let ty = match state {
+            UnixState::Datagram { .. } => UnixSocketType::Datagram,
+            _ => UnixSocketType::Stream,
+        };
+        Self {
+            ty,
+            inner: Mutex::new(UnixSocketInner { addr, state }),
+            datagrams: Mutex::new(VecDeque::new()),
+            read_shutdown: AtomicBool::new(false),
+            write_shutdown: AtomicBool::new(false),
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+
+    /// Create an unbound and unconnected socket of `ty`.
+    pub fn new(ty: UnixSocketType) -> Self {
+        let state = match ty {
+            UnixSocketType::Stream => UnixState::Unconnected,
+            UnixSocketType::Datagram => UnixState::Datagram { peer: None },
+        };
+        Self::with_state(UnixAddr::Unnamed, state)
+    }
+
+    /// Create a pair of unbound sockets of `ty` connected to each other.
+    pub fn pair(ty: UnixSocketType) -> (Arc<Self>, Arc<Self>) {
+        match ty {
+            UnixSocketType::Stream => {
+                let (a, b) = StreamConnection::pair(UnixAddr::Unnamed, UnixAddr::Unnamed);
+                (
+                    Arc::new(Self::with_state(UnixAddr::Unnamed, UnixState::Connected(a))),
+                    Arc::new(Self::with_state(UnixAddr::Unnamed, UnixState::Connected(b))),
+                )
+            }
+            UnixSocketType::Datagram => {
+                let (a, b) = (Arc::new(Self::new(ty)), Arc::new(Self::new(ty)));
+                a.inner.lock().state = UnixState::Datagram {
+                    peer: Some(Arc::downgrade(&b)),
+                };
+                b.inner.lock().state = UnixState::Datagram {
+                    peer: Some(Arc::downgrade(&a)),
+                };
+                (a, b)
+            }
+        }
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Acquire)
+    }
+
+    /// Get the address bound, which is unnamed if not bound.
+    pub fn local_addr(&self) -> UnixAddr {
+        self.inner.lock().addr.clone()
+    }
+
+    /// Bind the socket to `addr`, whose path is resolved to `key`.
+    ///
+    /// The file of a path must already be created.
+    pub fn bind(self: &Arc<Self>, addr: UnixAddr, key: UnixAddr) -> LinuxResult {
+        let mut inner = self.inner.lock();
+        if inner.addr != UnixAddr::Unnamed {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut bindings = UNIX_BINDINGS.lock();
+        if bindings
+            .get(&key)
+            .is_some_and(|socket| socket.strong_count() > 0)
+        {
+            return Err(LinuxError::EADDRINUSE);
+        }
+        bindings.insert(key, Arc::downgrade(self));
+        inner.addr = addr;
+        Ok(())
+    }
+
+    /// Get the socket bound to `key`, which is the address with the path
+    /// resolved.
+    fn lookup(key: &UnixAddr) -> LinuxResult<Arc<UnixSocket>> {
+        UNIX_BINDINGS
+            .lock()
+            .get(key)
+            .and_then(Weak::upgrade)
+            .ok_or(LinuxError::ECONNREFUSED)
+    }
+
+    /// Start accepting connections, at most `backlog` of which are pending.
+    pub fn listen(&self, backlog: usize) -> LinuxResult {
+        let mut inner = self.inner.lock();
+        match &mut inner.state {
+            UnixState::Listening { backlog: old, .. } => *old = backlog.max(1),
+            UnixState::Connected(_) => return Err(LinuxError::EINVAL),
+            UnixState::Datagram { .. } => return Err(LinuxError::EOPNOTSUPP),
+            UnixState::Unconnected => {
+                inner.state = UnixState::Listening {
+                    backlog: backlog.max(1),
+                    pending: VecDeque::new(),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Accept a pending connection, and return the socket connected to the
+    /// peer.
+    pub fn accept(&self) -> LinuxResult<Arc<UnixSocket>> {
+        block_on(self.is_nonblocking(), || {
+            let mut inner = self.inner.lock();
+            match &mut inner.state {
+                UnixState::Listening { pending, .. } => {
+                    pending.pop_front().ok_or(LinuxError::EAGAIN)
+                }
+                UnixState::Datagram { .. } => Err(LinuxError::EOPNOTSUPP),
+                _ => Err(LinuxError::EINVAL),
+            }
+        })
+    }
+
+    /// Get the socket of the same type as `self` bound to `key`.
+    fn lookup_peer(&self, key: &UnixAddr) -> LinuxResult<Arc<UnixSocket>> {
+        let peer = Self::lookup(key)?;
+        if peer.ty != self.ty {
+            return Err(LinuxError::EPROTOTYPE);
+        }
+        Ok(peer)
+    }
+
+    /// Connect to the listening socket bound to `key`, which is the address
+    /// with the path resolved.
+    ///
+    /// A datagram socket sends to the socket bound to `key` by default.
+    pub fn connect(&self, key: &UnixAddr) -> LinuxResult {
+        let listener = self.lookup_peer(key)?;
+        if self.ty == UnixSocketType::Datagram {
+            self.inner.lock().state = UnixState::Datagram {
+                peer: Some(Arc::downgrade(&listener)),
+            };
+            return Ok(());
+        }
+        if core::ptr::eq(self, &*listener) {
+            return Err(LinuxError::ECONNREFUSED);
+        }
+        block_on(self.is_nonblocking(), || {
+            let mut inner = self.inner.lock();
+            match inner.state {
+                UnixState::Unconnected => {}
+                UnixState::Listening { .. } => return Err(LinuxError::EINVAL),
+                UnixState::Connected(_) => return Err(LinuxError::EISCONN),
+                UnixState::Datagram { .. } => return Err(LinuxError::EOPNOTSUPP),
+            }
+            let mut listener_inner = listener.inner.lock();
+            let listener_addr = listener_inner.addr.clone();
+            let UnixState::Listening { backlog, pending } = &mut listener_inner.state else {
+                return Err(LinuxError::ECONNREFUSED);
+            };
+            if pending.len() >= *backlog {
+                return Err(LinuxError::EAGAIN);
+            }
+            let (this, peer) = StreamConnection::pair(inner.addr.clone(), listener_addr.clone());
+            pending.push_back(Arc::new(Self::with_state(
+                listener_addr,
+                UnixState::Connected(peer),
+            )));
+            inner.state = UnixState::Connected(this);
+            Ok(())
+        })
+    }
+
+    /// Get the address of the peer, which is what `accept` reports.
+    pub fn peer_addr(&self) -> LinuxResult<UnixAddr> {
+        let peer = match &self.inner.lock().state {
+            UnixState::Connected(conn) => return Ok(conn.peer_addr.clone()),
+            UnixState::Datagram { peer: Some(peer) } => peer.clone(),
+            _ => return Err(LinuxError::ENOTCONN),
+        };
+        // The peer is locked after `self` is unlocked, or the two would lock
+        // each other.
+        peer.upgrade()
+            .map(|peer| peer.local_addr())
+            .ok_or(LinuxError::ENOTCONN)
+    }
+
+    /// Get the peer a datagram socket is connected to.
+    fn datagram_peer(&self) -> LinuxResult<Arc<UnixSocket>> {
+        match &self.inner.lock().state {
+            UnixState::Datagram { peer: Some(peer) } => {
+                peer.upgrade().ok_or(LinuxError::ECONNREFUSED)
+            }
+            _ => Err(LinuxError::ENOTCONN),
+        }
+    }
+
+    /// Queue the datagram `buf` with the files `rights` from `sender` to be
+    /// received by `self` without blocking.
+    fn deliver(
+        &self,
+        sender: &UnixAddr,
+        buf: &[u8],
+        rights: &mut Vec<Arc<dyn FileLike>>,
+    ) -> LinuxResult<usize> {
+        if buf.len() > DATAGRAM_QUEUE_SIZE {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        let mut datagrams = self.datagrams.lock();
+        let queued: usize = datagrams.iter().map(|dgram| dgram.data.len()).sum();
+        if queued + buf.len() > DATAGRAM_QUEUE_SIZE {
+            return Err(LinuxError::EAGAIN);
+        }
+        datagrams.push_back(Datagram {
+            data: buf.to_vec(),
+            rights: mem::take(rights),
+            sender: sender.clone(),
+        });
+        Ok(buf.len())
+    }
+
+    /// Receive a datagram, the rest of which is discarded if it does not fit
+    /// in `buf`.
+    fn recv_datagram(&self, buf: &mut [u8], nonblocking: bool) -> LinuxResult<UnixMessage> {
+        block_on(nonblocking, || {
+            let Some(dgram) = self.datagrams.lock().pop_front() else {
+                if self.read_shutdown.load(Ordering::Acquire) {
+                    return Ok(UnixMessage {
+                        len: 0,
+                        full_len: 0,
+                        rights: Vec::new(),
+                        addr: UnixAddr::Unnamed,
+                    });
+                }
+                return Err(LinuxError::EAGAIN);
+            };
+            let len = dgram.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&dgram.data[..len]);
+            Ok(UnixMessage {
+                len,
+                full_len: dgram.data.len(),
+                rights: dgram.rights,
+                addr: dgram.sender,
+            })
+        })
+    }
+
+    /// Run `f` on the connection, or fail with `ENOTCONN`.
+    fn with_connection<R>(
+        &self,
+        f: impl FnOnce(&StreamConnection) -> LinuxResult<R>,
+    ) -> LinuxResult<R> {
+        match &self.inner.lock().state {
+            UnixState::Connected(conn) => f(conn),
+            _ => Err(LinuxError::ENOTCONN),
+        }
+    }
+
+    /// Receive the data sent by the peer of a stream socket.
+    fn recv_stream(&self, buf: &mut [u8], nonblocking: bool) -> LinuxResult<UnixMessage> {
+        block_on(nonblocking, || {
+            self.with_connection(|conn| {
+                let mut data = conn.rx.data.lock();
+                if data.bytes.is_empty() {
+                    if buf.is_empty() || conn.rx.write_closed.load(Ordering::Acquire) {
+                        return Ok(UnixMessage {
+                            len: 0,
+                            full_len: 0,
+                            rights: Vec::new(),
+                            addr: conn.peer_addr.clone(),
+                        });
+                    }
+                    return Err(LinuxError::EAGAIN);
+                }
+                // The files come with the first byte sent with them, and no
+                // bytes are received past the next files.
+                let head = data.head;
+                let rights = match data.rights.front() {
+                    Some((offset, _)) if *offset == head => data.rights.pop_front().unwrap().1,
+                    _ => Vec::new(),
+                };
+                let limit = data
+                    .rights
+                    .front()
+                    .map_or(usize::MAX, |(offset, _)| offset - head);
+                let len = data.bytes.len().min(buf.len()).min(limit);
+                for (dst, src) in buf.iter_mut().zip(data.bytes.drain(..len)) {
+                    *dst = src;
+                }
+                data.head += len;
+                Ok(UnixMessage {
+                    len,
+                    full_len: len,
+                    rights,
+                    addr: conn.peer_addr.clone(),
+                })
+            })
+        })
+    }
+
+    /// Receive the data sent by the peer with the files passed along,
+    /// blocking unless the socket is nonblocking or `dontwait` is set.
+    ///
+    /// A datagram socket receives one datagram from any sender.
+    pub fn recv_msg(&self, buf: &mut [u8], dontwait: bool) -> LinuxResult<UnixMessage> {
+        let nonblocking = dontwait || self.is_nonblocking();
+        if self.ty == UnixSocketType::Datagram {
+            self.recv_datagram(buf, nonblocking)
+        } else {
+            self.recv_stream(buf, nonblocking)
+        }
+    }
+
+    /// Send some of `buf` to the peer of a stream socket without blocking,
+    /// with the files `rights` if any byte is sent.
+    fn send_some(&self, buf: &[u8], rights: &mut Vec<Arc<dyn FileLike>>) -> LinuxResult<usize> {
+        self.with_connection(|conn| {
+            if conn.tx.read_closed.load(Ordering::Acquire)
+                || conn.tx.write_closed.load(Ordering::Acquire)
+            {
+                return Err(LinuxError::EPIPE);
+            }
+            let mut data = conn.tx.data.lock();
+            let len = (STREAM_BUFFER_SIZE - data.bytes.len()).min(buf.len());
+            if len == 0 && !buf.is_empty() {
+                return Err(LinuxError::EAGAIN);
+            }
+            if len > 0 && !rights.is_empty() {
+                let tail = data.head + data.bytes.len();
+                data.rights.push_back((tail, mem::take(rights)));
+            }
+            data.bytes.extend(&buf[..len]);
+            Ok(len)
+        })
+    }
+
+    /// Send `buf` to the peer with the files `rights`, blocking until all of
+    /// it is sent unless the socket is nonblocking or `dontwait` is set.
+    ///
+    /// Sending to a closed peer raises `SIGPIPE` unless `nosignal` is set.
+    ///
+    /// A datagram socket sends `buf` as one datagram to the socket bound to
+    /// `dest`, or the peer it is connected to if `dest` is `None`. A stream
+    /// socket cannot send to `dest`.
+    pub fn send_msg(
+        &self,
+        buf: &[u8],
+        mut rights: Vec<Arc<dyn FileLike>>,
+        dest: Option<&UnixAddr>,
+        dontwait: bool,
+        nosignal: bool,
+    ) -> LinuxResult<usize> {
+        let nonblocking = dontwait || self.is_nonblocking();
+        if self.ty == UnixSocketType::Datagram {
+            let peer = match dest {
+                Some(key) => self.lookup_peer(key)?,
+                None => self.datagram_peer()?,
+            };
+            if self.write_shutdown.load(Ordering::Acquire) {
+                return Err(broken_pipe(nosignal));
+            }
+            let sender = self.local_addr();
+            return block_on(nonblocking, || peer.deliver(&sender, buf, &mut rights));
+        }
+        if dest.is_some() {
+            return Err(match self.inner.lock().state {
+                UnixState::Connected(_) => LinuxError::EISCONN,
+                _ => LinuxError::EOPNOTSUPP,
+            });
+        }
+        let mut sent = 0;
+        loop {
+            match block_on(nonblocking, || self.send_some(&buf[sent..], &mut rights)) {
+                Ok(len) => sent += len,
+                Err(LinuxError::EAGAIN | LinuxError::ERESTART) if sent > 0 => break,
+                Err(LinuxError::EPIPE) if sent == 0 => return Err(broken_pipe(nosignal)),
+                Err(LinuxError::EPIPE) => break,
+                Err(err) => return Err(err),
+            }
+            if sent == buf.len() || nonblocking {
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Shut down receiving if `read` is set and sending if `write` is set.
+    ///
+    /// The peer of a stream socket reads the end of file after sending is
+    /// shut down, and fails to write after receiving is shut down.
+    pub fn shutdown(&self, read: bool, write: bool) -> LinuxResult {
+        if self.ty == UnixSocketType::Datagram {
+            self.read_shutdown.fetch_or(read, Ordering::AcqRel);
+            self.write_shutdown.fetch_or(write, Ordering::AcqRel);
+            return Ok(());
+        }
+        self.with_connection(|conn| {
+            if read {
+                conn.rx.read_closed.store(true, Ordering::Release);
+                conn.rx.write_closed.store(true, Ordering::Release);
+            }
+            if write {
+                conn.tx.write_closed.store(true, Ordering::Release);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let UnixState::Connected(conn) = &self.inner.lock().state {
+            conn.tx.write_closed.store(true, Ordering::Release);
+            conn.rx.read_closed.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl FileLike for UnixSocket {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        Ok(self.recv_msg(buf, false)?.len)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        self.send_msg(buf, Vec::new(), None, false, false)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFSOCK | 0o777u32, // rwxrwxrwx
+            blksize: 4096,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(match &self.inner.lock().state {
+            UnixState::Unconnected => PollState {
+                readable: false,
+                writable: false,
+            },
+            UnixState::Listening { pending, .. } => PollState {
+                readable: !pending.is_empty(),
+                writable: false,
+            },
+            UnixState::Connected(conn) => PollState {
+                readable: !conn.rx.data.lock().bytes.is_empty()
+                    || conn.rx.write_closed.load(Ordering::Acquire),
+                writable: conn.tx.data.lock().bytes.len() < STREAM_BUFFER_SIZE
+                    || conn.tx.read_closed.load(Ordering::Acquire),
+            },
+            UnixState::Datagram { peer } => PollState {
+                readable: !self.datagrams.lock().is_empty(),
+                writable: peer.as_ref().and_then(Weak::upgrade).is_none_or(|peer| {
+                    peer.datagrams
+                        .lock()
+                        .iter()
+                        .map(|dgram| dgram.data.len())
+                        .sum::<usize>()
+                        < DATAGRAM_QUEUE_SIZE
+                }),
+            },
+        })
+    }
+
+    fn event_seq(&self) -> u64 {
+        match &self.inner.lock().state {
+            // Every byte read or written moves the head or the end of the
+            // data of a buffer.
+            UnixState::Connected(conn) => {
+                let rx = conn.rx.data.lock();
+                let tx_head = conn.tx.data.lock().head;
+                (2 * rx.head + rx.bytes.len() + tx_head) as u64
+            }
+            _ => 0,
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn status_flags(&self) -> u32 {
+        if self.is_nonblocking() {
+            O_RDWR | O_NONBLOCK
+        } else {
+            O_RDWR
+        }
+    }
+}
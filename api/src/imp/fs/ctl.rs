@@ -1,13 +1,24 @@
 use core::ffi::{c_char, c_int, c_void};
 
-use alloc::ffi::CString;
+use alloc::{ffi::CString, format};
 use axerrno::{LinuxError, LinuxResult};
-use linux_raw_sys::general::{__kernel_ino_t, __kernel_off_t, AT_FDCWD, AT_REMOVEDIR};
+use axhal::time::wall_time;
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::general::{
+    __kernel_ino_t, __kernel_off_t, AT_EMPTY_PATH, AT_FDCWD, AT_REMOVEDIR, AT_SYMLINK_FOLLOW,
+    AT_SYMLINK_NOFOLLOW, IN_CREATE, IN_DELETE, IN_ISDIR, RENAME_EXCHANGE, RENAME_NOREPLACE,
+    S_IFBLK, S_IFCHR, S_IFIFO, S_IFMT, S_IFREG, S_IFSOCK, UTIME_NOW, UTIME_OMIT, timespec,
+};
 
+use super::{change_current_dir, check_writable, fs_info, visible_path};
 use crate::{
-    fd::{Directory, FileLike},
-    path::{HARDLINK_MANAGER, handle_file_path},
+    fd::{Directory, File, FileLike, TMPFILE_PREFIX, fs_notify, fs_notify_move, get_file_like},
+    path::{
+        ATTR_MANAGER, FilePath, HARDLINK_MANAGER, ROOT_DIR, SYMLINK_MANAGER, handle_file_path,
+        handle_link_path, resolve_symlinks, strip_root, user_path,
+    },
     ptr::{UserConstPtr, UserPtr, nullable},
+    time::timespec_to_timevalue,
 };
 
 /// The ioctl() system call manipulates the underlying device parameters
@@ -27,7 +38,61 @@ pub fn sys_chdir(path: UserConstPtr<c_char>) -> LinuxResult<isize> {
     let path = path.get_as_str()?;
     debug!("sys_chdir <= {:?}", path);
 
-    axfs::api::set_current_dir(path)?;
+    if path.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    let path = resolve_symlinks(&handle_file_path(AT_FDCWD, path)?, true)?;
+    if !path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    if !axfs::api::metadata(path.as_str())?.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+
+    change_current_dir(path.as_str())?;
+    Ok(0)
+}
+
+/// Change the root directory of the calling process to `path`.
+///
+/// The current directory is moved to the new root if it is outside of it.
+pub fn sys_chroot(path: UserConstPtr<c_char>) -> LinuxResult<isize> {
+    let path = path.get_as_str()?;
+    debug!("sys_chroot <= {:?}", path);
+
+    if path.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    let path = resolve_symlinks(&handle_file_path(AT_FDCWD, path)?, true)?;
+    if !path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    if !axfs::api::metadata(path.as_str())?.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+
+    // The root is kept as seen through the mount points, which the paths
+    // prefixed with it are redirected by again.
+    let root = visible_path(&path);
+    let root = match root.trim_end_matches('/') {
+        "" => "/",
+        root => root,
+    };
+    *ROOT_DIR.write() = root.into();
+    if strip_root(&visible_path(&axfs::api::current_dir()?)).is_none() {
+        change_current_dir(path.as_str())?;
+    }
+    Ok(0)
+}
+
+pub fn sys_fchdir(fd: c_int) -> LinuxResult<isize> {
+    debug!("sys_fchdir <= {}", fd);
+
+    let dir = Directory::from_fd(fd).map_err(|e| match e {
+        LinuxError::EINVAL => LinuxError::ENOTDIR,
+        e => e,
+    })?;
+    change_current_dir(dir.path())?;
     Ok(0)
 }
 
@@ -38,16 +103,80 @@ pub fn sys_mkdirat(dirfd: i32, path: UserConstPtr<c_char>, mode: u32) -> LinuxRe
         dirfd, path, mode
     );
 
-    if mode != 0 {
-        warn!("directory mode not supported.");
-    }
-
     let path = handle_file_path(dirfd, path)?;
+    check_writable(&path)?;
     axfs::api::create_dir(path.as_str())?;
+    set_mode(
+        &path,
+        mode & 0o777 & !current().task_ext().process_data().umask(),
+    );
+    fs_notify(path.as_str(), IN_CREATE | IN_ISDIR);
+
+    Ok(0)
+}
+
+pub fn sys_mkdir(path: UserConstPtr<c_char>, mode: u32) -> LinuxResult<isize> {
+    sys_mkdirat(AT_FDCWD, path, mode)
+}
+
+/// Set the file mode creation mask of the calling process.
+///
+/// Return the previous mask.
+pub fn sys_umask(mask: u32) -> LinuxResult<isize> {
+    debug!("sys_umask <= mask: {:o}", mask);
+    let old = current()
+        .task_ext()
+        .process_data()
+        .replace_umask(mask & 0o777);
+    Ok(old as _)
+}
+
+/// create a file system node at path
+/// mode: the file type and permission bits. FIFOs, sockets and device files
+/// are backed by empty regular files.
+/// dev: the device number of a character or block device file
+pub fn sys_mknodat(
+    dirfd: c_int,
+    path: UserConstPtr<c_char>,
+    mode: u32,
+    dev: u64,
+) -> LinuxResult<isize> {
+    let path = path.get_as_str()?;
+    debug!(
+        "sys_mknodat <= dirfd: {}, path: {}, mode: {:o}, dev: {:#x}",
+        dirfd, path, mode, dev
+    );
+
+    let file_type = match mode & S_IFMT {
+        0 | S_IFREG => None,
+        ty @ (S_IFIFO | S_IFSOCK | S_IFCHR | S_IFBLK) => Some(ty),
+        _ => return Err(LinuxError::EINVAL),
+    };
+
+    let path = resolve_symlinks(&handle_file_path(dirfd, path)?, false)?;
+    if path.exists() || SYMLINK_MANAGER.is_symlink(&path) {
+        return Err(LinuxError::EEXIST);
+    }
+    check_writable(&path)?;
+    if !FilePath::new(path.parent()?)?.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+
+    axfs::api::write(path.as_str(), b"")?;
+    ATTR_MANAGER.update(&path, |attr| {
+        attr.file_type = file_type;
+        attr.rdev = matches!(file_type, Some(S_IFCHR | S_IFBLK)).then_some(dev);
+        attr.mode = Some(mode & 0o7777 & !current().task_ext().process_data().umask());
+    });
+    fs_notify(path.as_str(), IN_CREATE);
 
     Ok(0)
 }
 
+pub fn sys_mknod(path: UserConstPtr<c_char>, mode: u32, dev: u64) -> LinuxResult<isize> {
+    sys_mknodat(AT_FDCWD, path, mode, dev)
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 struct DirEnt {
@@ -78,6 +207,11 @@ impl From<axfs::api::FileType> for FileType {
         match ft {
             ft if ft.is_dir() => FileType::Dir,
             ft if ft.is_file() => FileType::Reg,
+            ft if ft.is_symlink() => FileType::Lnk,
+            ft if ft.is_char_device() => FileType::Chr,
+            ft if ft.is_block_device() => FileType::Blk,
+            ft if ft.is_fifo() => FileType::Fifo,
+            ft if ft.is_socket() => FileType::Socket,
             _ => FileType::Unknown,
         }
     }
@@ -185,7 +319,11 @@ pub fn sys_getdents64(fd: i32, buf: UserPtr<u8>, len: usize) -> LinuxResult<isiz
     let mut total_size = initial_offset as usize;
     let mut current_offset = initial_offset;
 
-    for entry in axfs::api::read_dir(path)?.flatten().skip(count) {
+    for entry in axfs::api::read_dir(path)?
+        .flatten()
+        .filter(|entry| !entry.file_name().starts_with(TMPFILE_PREFIX))
+        .skip(count)
+    {
         let mut name = entry.file_name();
         name.push('\0');
         let name_bytes = name.as_bytes();
@@ -193,12 +331,16 @@ pub fn sys_getdents64(fd: i32, buf: UserPtr<u8>, len: usize) -> LinuxResult<isiz
         let entry_size = DirEnt::FIXED_SIZE + name_bytes.len();
         current_offset += entry_size as i64;
 
-        let dirent = DirEnt::new(
-            1,
-            current_offset,
-            entry_size,
-            FileType::from(entry.file_type()),
-        );
+        let file_type = if SYMLINK_MANAGER.is_symlink(&format!(
+            "{}/{}",
+            path.trim_end_matches('/'),
+            entry.file_name()
+        )) {
+            FileType::Lnk
+        } else {
+            FileType::from(entry.file_type())
+        };
+        let dirent = DirEnt::new(1, current_offset, entry_size, file_type);
 
         if buffer.write_entry(dirent, name_bytes).is_err() {
             break;
@@ -238,16 +380,42 @@ pub fn sys_linkat(
         old_dirfd, old_path, new_dirfd, new_path, flags
     );
 
-    if flags != 0 {
-        warn!("Unsupported flags: {flags}");
+    if flags & !(AT_EMPTY_PATH | AT_SYMLINK_FOLLOW) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if old_path.is_empty() && flags & AT_EMPTY_PATH == 0 {
+        return Err(LinuxError::ENOENT);
     }
 
     // handle old path
-    let old_path = handle_file_path(old_dirfd, old_path)?;
+    let old_path = resolve_symlinks(
+        &handle_file_path(old_dirfd, old_path)?,
+        flags & AT_SYMLINK_FOLLOW != 0,
+    )?;
     // handle new path
-    let new_path = handle_file_path(new_dirfd, new_path)?;
+    let new_path = resolve_symlinks(&handle_file_path(new_dirfd, new_path)?, false)?;
+
+    if !old_path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    check_writable(&new_path)?;
+    if new_path.exists() || SYMLINK_MANAGER.is_symlink(&new_path) {
+        return Err(LinuxError::EEXIST);
+    }
+    if axfs::api::metadata(old_path.as_str())?.is_dir() {
+        return Err(LinuxError::EPERM);
+    }
+    if fs_info(&old_path).mnt_dir != fs_info(&new_path).mnt_dir {
+        return Err(LinuxError::EXDEV);
+    }
 
-    HARDLINK_MANAGER.create_link(&new_path, &old_path)?;
+    // A hard link to a symbolic link behaves exactly like a copy of it.
+    if let Some(target) = SYMLINK_MANAGER.read_link(&old_path) {
+        SYMLINK_MANAGER.create_symlink(&new_path, &target)?;
+    } else {
+        HARDLINK_MANAGER.create_link(&new_path, &old_path)?;
+    }
+    fs_notify(new_path.as_str(), IN_CREATE);
 
     Ok(0)
 }
@@ -259,31 +427,76 @@ pub fn sys_link(
     sys_linkat(AT_FDCWD, old_path, AT_FDCWD, new_path, 0)
 }
 
+/// create a symbolic link at new_path pointing to target
+/// target: the content of the link, which is not resolved
+/// new_dirfd: the directory new_path is relative to
+/// new_path: the path of the link
+/// return value: return 0 when success
+pub fn sys_symlinkat(
+    target: UserConstPtr<c_char>,
+    new_dirfd: c_int,
+    new_path: UserConstPtr<c_char>,
+) -> LinuxResult<isize> {
+    let target = target.get_as_str()?;
+    let new_path = new_path.get_as_str()?;
+    debug!(
+        "sys_symlinkat <= target: {}, new_dirfd: {}, new_path: {}",
+        target, new_dirfd, new_path
+    );
+
+    if target.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+
+    let new_path = resolve_symlinks(&handle_file_path(new_dirfd, new_path)?, false)?;
+    check_writable(&new_path)?;
+    SYMLINK_MANAGER.create_symlink(&new_path, target)?;
+    fs_notify(new_path.as_str(), IN_CREATE);
+
+    Ok(0)
+}
+
+pub fn sys_symlink(
+    target: UserConstPtr<c_char>,
+    new_path: UserConstPtr<c_char>,
+) -> LinuxResult<isize> {
+    sys_symlinkat(target, AT_FDCWD, new_path)
+}
+
 /// remove link of specific file (can be used to delete file)
 /// dir_fd: the directory of link to be removed
 /// path: the name of link to be removed
 /// flags: can be 0 or AT_REMOVEDIR
 /// return 0 when success, else return -1
 pub fn sys_unlinkat(dirfd: c_int, path: UserConstPtr<c_char>, flags: u32) -> LinuxResult<isize> {
-    let path = path.get_as_str()?;
+    let name = path.get_as_str()?;
     debug!(
         "sys_unlinkat <= dirfd: {}, path: {}, flags: {}",
-        dirfd, path, flags
+        dirfd, name, flags
     );
 
-    let path = handle_file_path(dirfd, path)?;
+    let path = handle_file_path(dirfd, name)?;
+    check_writable(&path)?;
 
     if flags == AT_REMOVEDIR {
         axfs::api::remove_dir(path.as_str())?;
+        ATTR_MANAGER.remove(&path);
+        fs_notify(path.as_str(), IN_DELETE | IN_ISDIR);
     } else {
         let metadata = axfs::api::metadata(path.as_str())?;
         if metadata.is_dir() {
             return Err(LinuxError::EISDIR);
         } else {
             debug!("unlink file: {:?}", path);
+            let name = handle_link_path(dirfd, name)?;
             HARDLINK_MANAGER
-                .remove_link(&path)
+                .remove_link(&name)
                 .ok_or(LinuxError::ENOENT)?;
+            SYMLINK_MANAGER.remove_symlink(path.as_str());
+            if !path.exists() {
+                ATTR_MANAGER.remove(&path);
+            }
+            fs_notify(&name, IN_DELETE);
         }
     }
     Ok(0)
@@ -293,20 +506,377 @@ pub fn sys_unlink(path: UserConstPtr<c_char>) -> LinuxResult<isize> {
     sys_unlinkat(AT_FDCWD, path, 0)
 }
 
-pub fn sys_getcwd(buf: UserPtr<u8>, size: usize) -> LinuxResult<isize> {
-    let buf = nullable!(buf.get_as_mut_slice(size))?;
+/// rename old_path to new_path
+/// flags: RENAME_NOREPLACE fails if new_path exists, RENAME_EXCHANGE swaps
+/// the two existing paths
+/// return value: return 0 when success
+pub fn sys_renameat2(
+    old_dirfd: c_int,
+    old_path: UserConstPtr<c_char>,
+    new_dirfd: c_int,
+    new_path: UserConstPtr<c_char>,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let old_path = old_path.get_as_str()?;
+    let new_path = new_path.get_as_str()?;
+    debug!(
+        "sys_renameat2 <= old_dirfd: {}, old_path: {}, new_dirfd: {}, new_path: {}, flags: {}",
+        old_dirfd, old_path, new_dirfd, new_path, flags
+    );
+
+    if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0
+        || flags & (RENAME_NOREPLACE | RENAME_EXCHANGE) == RENAME_NOREPLACE | RENAME_EXCHANGE
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let old_path = resolve_symlinks(&handle_file_path(old_dirfd, old_path)?, false)?;
+    let new_path = resolve_symlinks(&handle_file_path(new_dirfd, new_path)?, false)?;
 
-    let Some(buf) = buf else {
+    if !old_path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    check_writable(&old_path)?;
+    check_writable(&new_path)?;
+    let new_exists = new_path.exists();
+    if flags & RENAME_NOREPLACE != 0 && new_exists {
+        return Err(LinuxError::EEXIST);
+    }
+    if flags & RENAME_EXCHANGE != 0 && !new_exists {
+        return Err(LinuxError::ENOENT);
+    }
+    if fs_info(&old_path).mnt_dir != fs_info(&new_path).mnt_dir {
+        return Err(LinuxError::EXDEV);
+    }
+    if old_path.trim_end_matches('/') == new_path.trim_end_matches('/') {
         return Ok(0);
+    }
+
+    let old_path = old_path.trim_end_matches('/');
+    let new_path = new_path.trim_end_matches('/');
+    if flags & RENAME_EXCHANGE != 0 {
+        // Swap through a temporary name next to the old path.
+        let mut tmp_path = format!("{}.exchange", old_path);
+        while axfs::api::absolute_path_exists(&tmp_path) {
+            tmp_path.push('~');
+        }
+        // Undo the steps already taken if a later one fails, so that both
+        // paths are left as they were.
+        rename_path(old_path, &tmp_path)?;
+        if let Err(err) = rename_path(new_path, old_path) {
+            rename_path(&tmp_path, old_path)?;
+            return Err(err);
+        }
+        if let Err(err) = rename_path(&tmp_path, new_path) {
+            rename_path(old_path, new_path)?;
+            rename_path(&tmp_path, old_path)?;
+            return Err(err);
+        }
+        fs_notify_move(
+            old_path,
+            new_path,
+            axfs::api::metadata(new_path)?.is_dir(),
+            Some(axfs::api::metadata(old_path)?.is_dir()),
+        );
+        return Ok(0);
+    }
+
+    let old_is_dir = axfs::api::metadata(old_path)?.is_dir();
+    if new_exists {
+        let new_is_dir = axfs::api::metadata(new_path)?.is_dir();
+        match (old_is_dir, new_is_dir) {
+            (true, false) => return Err(LinuxError::ENOTDIR),
+            (false, true) => return Err(LinuxError::EISDIR),
+            (true, true) => axfs::api::remove_dir(new_path)?,
+            (false, false) => {
+                SYMLINK_MANAGER.remove_symlink(new_path);
+                HARDLINK_MANAGER
+                    .remove_link(&FilePath::new(new_path)?)
+                    .ok_or(LinuxError::ENOENT)?;
+            }
+        }
+    }
+    rename_path(old_path, new_path)?;
+    fs_notify_move(old_path, new_path, old_is_dir, None);
+
+    Ok(0)
+}
+
+fn rename_path(old_path: &str, new_path: &str) -> LinuxResult {
+    axfs::api::rename(old_path, new_path)?;
+    SYMLINK_MANAGER.rename(old_path, new_path);
+    ATTR_MANAGER.rename(old_path, new_path);
+    Ok(())
+}
+
+pub fn sys_renameat(
+    old_dirfd: c_int,
+    old_path: UserConstPtr<c_char>,
+    new_dirfd: c_int,
+    new_path: UserConstPtr<c_char>,
+) -> LinuxResult<isize> {
+    sys_renameat2(old_dirfd, old_path, new_dirfd, new_path, 0)
+}
+
+pub fn sys_rename(
+    old_path: UserConstPtr<c_char>,
+    new_path: UserConstPtr<c_char>,
+) -> LinuxResult<isize> {
+    sys_renameat2(AT_FDCWD, old_path, AT_FDCWD, new_path, 0)
+}
+
+/// change the permission bits of the file referred to by fd
+pub fn sys_fchmod(fd: c_int, mode: u32) -> LinuxResult<isize> {
+    debug!("sys_fchmod <= fd: {}, mode: {:o}", fd, mode);
+
+    let path = handle_file_path(fd, "")?;
+    check_writable(&path)?;
+    set_mode(&path, mode);
+    Ok(0)
+}
+
+/// change the permission bits of the file at path
+/// flags: can be 0 or AT_SYMLINK_NOFOLLOW, the latter is not supported by
+/// symbolic links
+pub fn sys_fchmodat(
+    dirfd: c_int,
+    path: UserConstPtr<c_char>,
+    mode: u32,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let path = path.get_as_str()?;
+    debug!(
+        "sys_fchmodat <= dirfd: {}, path: {}, mode: {:o}, flags: {}",
+        dirfd, path, mode, flags
+    );
+
+    if flags & !AT_SYMLINK_NOFOLLOW != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let path = resolve_symlinks(
+        &handle_file_path(dirfd, path)?,
+        flags & AT_SYMLINK_NOFOLLOW == 0,
+    )?;
+    if SYMLINK_MANAGER.is_symlink(&path) {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    if !path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    check_writable(&path)?;
+
+    set_mode(&path, mode);
+    Ok(0)
+}
+
+pub fn sys_chmod(path: UserConstPtr<c_char>, mode: u32) -> LinuxResult<isize> {
+    sys_fchmodat(AT_FDCWD, path, mode, 0)
+}
+
+fn set_mode(path: &FilePath, mode: u32) {
+    ATTR_MANAGER.update(path, |attr| {
+        attr.mode = Some(mode & 0o7777);
+        attr.ctime = Some(wall_time());
+    });
+}
+
+/// change the owner and group of the file at path
+/// uid, gid: -1 means leaving the value unchanged
+/// flags: AT_EMPTY_PATH or AT_SYMLINK_NOFOLLOW
+pub fn sys_fchownat(
+    dirfd: c_int,
+    path: UserConstPtr<c_char>,
+    uid: u32,
+    gid: u32,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let path = nullable!(path.get_as_str())?.unwrap_or_default();
+    debug!(
+        "sys_fchownat <= dirfd: {}, path: {}, uid: {}, gid: {}, flags: {}",
+        dirfd, path, uid as i32, gid as i32, flags
+    );
+
+    if flags & !(AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if path.is_empty() && flags & AT_EMPTY_PATH == 0 {
+        return Err(LinuxError::ENOENT);
+    }
+    let path = resolve_symlinks(
+        &handle_file_path(dirfd, path)?,
+        flags & AT_SYMLINK_NOFOLLOW == 0,
+    )?;
+    if !path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    check_writable(&path)?;
+
+    ATTR_MANAGER.update(&path, |attr| {
+        if uid != u32::MAX {
+            attr.uid = Some(uid);
+        }
+        if gid != u32::MAX {
+            attr.gid = Some(gid);
+        }
+        attr.ctime = Some(wall_time());
+    });
+    Ok(0)
+}
+
+pub fn sys_fchown(fd: c_int, uid: u32, gid: u32) -> LinuxResult<isize> {
+    sys_fchownat(fd, UserConstPtr::default(), uid, gid, AT_EMPTY_PATH)
+}
+
+pub fn sys_chown(path: UserConstPtr<c_char>, uid: u32, gid: u32) -> LinuxResult<isize> {
+    sys_fchownat(AT_FDCWD, path, uid, gid, 0)
+}
+
+pub fn sys_lchown(path: UserConstPtr<c_char>, uid: u32, gid: u32) -> LinuxResult<isize> {
+    sys_fchownat(AT_FDCWD, path, uid, gid, AT_SYMLINK_NOFOLLOW)
+}
+
+/// change the access and modification times of the file at path
+/// times: NULL means setting both to the current time, a `tv_nsec` of
+/// UTIME_NOW or UTIME_OMIT sets the time to now or leaves it unchanged
+/// flags: can be 0 or AT_SYMLINK_NOFOLLOW
+pub fn sys_utimensat(
+    dirfd: c_int,
+    path: UserConstPtr<c_char>,
+    times: UserConstPtr<timespec>,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let path = nullable!(path.get_as_str())?;
+    let times = nullable!(times.get_as_slice(2))?;
+    debug!(
+        "sys_utimensat <= dirfd: {}, path: {:?}, times: {:?}, flags: {}",
+        dirfd, path, times, flags
+    );
+
+    if flags & !AT_SYMLINK_NOFOLLOW != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    // A NULL path refers to the file referred to by dirfd.
+    let path = resolve_symlinks(
+        &handle_file_path(dirfd, path.unwrap_or_default())?,
+        flags & AT_SYMLINK_NOFOLLOW == 0,
+    )?;
+    if !path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    check_writable(&path)?;
+
+    let now = wall_time();
+    let to_time = |ts: &timespec| match ts.tv_nsec as u32 {
+        UTIME_OMIT => Ok(None),
+        UTIME_NOW => Ok(Some(now)),
+        nsec if nsec < 1_000_000_000 => Ok(Some(timespec_to_timevalue(*ts))),
+        _ => Err(LinuxError::EINVAL),
+    };
+    let (atime, mtime) = match times {
+        Some(times) => (to_time(&times[0])?, to_time(&times[1])?),
+        None => (Some(now), Some(now)),
     };
 
-    let cwd = CString::new(axfs::api::current_dir()?).map_err(|_| LinuxError::EINVAL)?;
+    // Nothing changes if both times are omitted, not even the status change
+    // time.
+    if atime.is_none() && mtime.is_none() {
+        return Ok(0);
+    }
+    ATTR_MANAGER.update(&path, |attr| {
+        if atime.is_some() {
+            attr.atime = atime;
+        }
+        if mtime.is_some() {
+            attr.mtime = mtime;
+        }
+        attr.ctime = Some(now);
+    });
+    Ok(0)
+}
+
+/// Write the absolute path of the current working directory, terminated by
+/// NUL, into `buf`.
+///
+/// Return the length of the path including the NUL. Return `ERANGE` if it
+/// does not fit into `size` bytes.
+pub fn sys_getcwd(buf: UserPtr<u8>, size: usize) -> LinuxResult<isize> {
+    debug!("sys_getcwd <= buf: {:?}, size: {}", buf.address(), size);
+
+    let cwd = user_path(&axfs::api::current_dir()?);
+    let cwd = match cwd.trim_end_matches('/') {
+        "" => "/",
+        cwd => cwd,
+    };
+    let cwd = CString::new(cwd).map_err(|_| LinuxError::EINVAL)?;
     let cwd = cwd.as_bytes_with_nul();
+    if cwd.len() > size {
+        return Err(LinuxError::ERANGE);
+    }
 
-    if cwd.len() <= buf.len() {
-        buf[..cwd.len()].copy_from_slice(cwd);
-        Ok(buf.as_ptr() as _)
-    } else {
-        Err(LinuxError::ERANGE)
+    buf.get_as_mut_slice(cwd.len())?.copy_from_slice(cwd);
+    Ok(cwd.len() as _)
+}
+
+/// Read the target of the symbolic link `path` into `buf`.
+///
+/// The target is not NUL-terminated and is truncated to `size` bytes. Return
+/// the number of bytes written.
+///
+/// The links in `/proc/self/fd` lead to the paths the files were opened by.
+pub fn sys_readlinkat(
+    dirfd: c_int,
+    path: UserConstPtr<c_char>,
+    buf: UserPtr<u8>,
+    size: usize,
+) -> LinuxResult<isize> {
+    let path = path.get_as_str()?;
+    debug!(
+        "sys_readlinkat <= dirfd: {}, path: {}, size: {}",
+        dirfd, path, size
+    );
+
+    if size == 0 {
+        return Err(LinuxError::EINVAL);
     }
+    let buf = buf.get_as_mut_slice(size)?;
+
+    let path = resolve_symlinks(&handle_file_path(dirfd, path)?, false)?;
+    let target = if path.as_str() == "/proc/self/exe" {
+        user_path(&current().task_ext().process_data().exe_path.read())
+    } else if let Some(fd) = path.strip_prefix("/proc/self/fd/") {
+        let f = fd
+            .parse()
+            .ok()
+            .and_then(|fd| get_file_like(fd).ok())
+            .ok_or(LinuxError::ENOENT)?
+            .into_any();
+        if let Some(file) = f.downcast_ref::<File>() {
+            user_path(file.path())
+        } else if let Some(dir) = f.downcast_ref::<Directory>() {
+            match user_path(dir.path()).trim_end_matches('/') {
+                "" => "/".into(),
+                path => path.into(),
+            }
+        } else {
+            return Err(LinuxError::EINVAL);
+        }
+    } else if let Some(target) = SYMLINK_MANAGER.read_link(path.as_str()) {
+        target
+    } else if path.exists() {
+        return Err(LinuxError::EINVAL);
+    } else {
+        return Err(LinuxError::ENOENT);
+    };
+
+    let len = target.len().min(buf.len());
+    buf[..len].copy_from_slice(&target.as_bytes()[..len]);
+    Ok(len as isize)
+}
+
+pub fn sys_readlink(
+    path: UserConstPtr<c_char>,
+    buf: UserPtr<u8>,
+    size: usize,
+) -> LinuxResult<isize> {
+    sys_readlinkat(AT_FDCWD, path, buf, size)
 }
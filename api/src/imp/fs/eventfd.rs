@@ -0,0 +1,27 @@
+use core::ffi::c_int;
+
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use linux_raw_sys::general::{EFD_CLOEXEC, EFD_NONBLOCK, EFD_SEMAPHORE};
+
+use crate::fd::{EventFd, add_file_like_from};
+
+/// Create an eventfd object with the counter set to `initval`.
+pub fn sys_eventfd2(initval: u32, flags: c_int) -> LinuxResult<isize> {
+    debug!("sys_eventfd2 <= initval: {}, flags: {:#x}", initval, flags);
+    let flags = flags as u32;
+    if flags & !(EFD_CLOEXEC | EFD_NONBLOCK | EFD_SEMAPHORE) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let eventfd = EventFd::new(
+        initval as _,
+        flags & EFD_SEMAPHORE != 0,
+        flags & EFD_NONBLOCK != 0,
+    );
+    Ok(add_file_like_from(Arc::new(eventfd), 0, flags & EFD_CLOEXEC != 0)? as _)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_eventfd(initval: u32) -> LinuxResult<isize> {
+    sys_eventfd2(initval, 0)
+}
@@ -1,20 +1,37 @@
 use core::{
     ffi::{c_char, c_int},
     panic,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use crate::fd::{
-    Directory, FD_TABLE, File, FileLike, add_file_like, close_file_like, get_file_like,
+    AX_FILE_LIMIT, Directory, FD_TABLE, File, FileDescriptor, FileLike, FlockKind, Pipe,
+    RecordLockKind, TMPFILE_PREFIX, add_file_like, add_file_like_from, close_file_like,
+    find_record_lock_conflict, flock, fs_notify, funlock, get_file_like, release_process_locks,
+    set_record_lock,
 };
+use alloc::{format, string::ToString, sync::Arc};
 use axerrno::{AxError, LinuxError, LinuxResult};
 use axfs::fops::OpenOptions;
 use axio::SeekFrom;
+use axtask::{TaskExtRef, current};
 use linux_raw_sys::general::{
-    __kernel_mode_t, __kernel_off_t, AT_FDCWD, F_DUPFD, F_DUPFD_CLOEXEC, F_SETFL, O_APPEND,
-    O_CREAT, O_DIRECTORY, O_NONBLOCK, O_PATH, O_RDONLY, O_TRUNC, O_WRONLY,
+    __O_TMPFILE, __kernel_mode_t, __kernel_off_t, AT_FDCWD, F_ADD_SEALS, F_DUPFD, F_DUPFD_CLOEXEC,
+    F_GET_SEALS, F_GETFD, F_GETFL, F_GETLK, F_RDLCK, F_SEAL_FUTURE_WRITE, F_SEAL_GROW, F_SEAL_SEAL,
+    F_SEAL_SHRINK, F_SEAL_WRITE, F_SETFD, F_SETFL, F_SETLK, F_SETLKW, F_UNLCK, F_WRLCK,
+    FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FD_CLOEXEC, IN_CREATE, LOCK_EX, LOCK_NB, LOCK_SH,
+    LOCK_UN, MFD_ALLOW_SEALING, MFD_CLOEXEC, O_ACCMODE, O_CLOEXEC, O_CREAT, O_DIRECTORY,
+    O_NOFOLLOW, O_PATH, O_RDONLY, O_TRUNC, O_WRONLY, POSIX_FADV_DONTNEED, POSIX_FADV_NOREUSE,
+    POSIX_FADV_NORMAL, POSIX_FADV_RANDOM, POSIX_FADV_SEQUENTIAL, POSIX_FADV_WILLNEED, SEEK_CUR,
+    SEEK_DATA, SEEK_END, SEEK_HOLE, SEEK_SET,
 };
 
-use crate::ptr::UserConstPtr;
+use super::check_writable;
+use crate::{
+    is_mapped_writable,
+    path::{ATTR_MANAGER, FilePath, SYMLINK_MANAGER, handle_file_path, resolve_symlinks},
+    ptr::{UserConstPtr, UserPtr},
+};
 
 const O_EXEC: u32 = O_PATH;
 
@@ -30,9 +47,6 @@ fn flags_to_options(flags: c_int, _mode: __kernel_mode_t) -> OpenOptions {
             options.write(true);
         }
     };
-    if flags & O_APPEND != 0 {
-        options.append(true);
-    }
     if flags & O_TRUNC != 0 {
         options.truncate(true);
     }
@@ -65,36 +79,135 @@ pub fn sys_openat(
     let opts = flags_to_options(flags, mode);
     debug!("sys_openat <= {} {} {:?}", dirfd, path, opts);
 
-    let dir = if path.starts_with('/') || dirfd == AT_FDCWD {
-        None
-    } else {
-        Some(Directory::from_fd(dirfd)?)
-    };
+    // Unlike the `*at` calls with `AT_EMPTY_PATH`, an empty path does not
+    // refer to `dirfd`.
+    if path.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    // The path is made absolute instead of being opened relative to the
+    // directory of `dirfd`, so that the path kept by the new fd identifies
+    // the file in the attribute tables of `fchmod` and `fchown`, and the
+    // symlinks, which only `SYMLINK_MANAGER` knows, are followed.
+    let no_follow = flags as u32 & O_NOFOLLOW != 0;
+    let path = resolve_symlinks(&handle_file_path(dirfd, path)?, !no_follow)?;
+    if no_follow && SYMLINK_MANAGER.is_symlink(path.as_str()) {
+        return Err(LinuxError::ELOOP);
+    }
+    if flags as u32 & (O_ACCMODE | O_CREAT | O_TRUNC | __O_TMPFILE) != O_RDONLY {
+        check_writable(&path)?;
+    }
+    if flags as u32 & __O_TMPFILE != 0 {
+        return open_tmpfile(&path, flags as _, mode);
+    }
+    if opts.has_directory() && path.exists() && !axfs::api::metadata(path.as_str())?.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+    let created = flags as u32 & O_CREAT != 0 && !path.exists();
 
     if !opts.has_directory() {
-        match dir.as_ref().map_or_else(
-            || axfs::fops::File::open(path, &opts),
-            |dir| dir.inner().open_file_at(path, &opts),
-        ) {
+        match axfs::fops::File::open(path.as_str(), &opts) {
             Err(AxError::IsADirectory) => {}
             r => {
-                let fd = File::new(r?, path.into()).add_to_fd_table()?;
+                let file = r?;
+                if created {
+                    let umask = current().task_ext().process_data().umask();
+                    ATTR_MANAGER.update(&path, |attr| attr.mode = Some(mode & 0o7777 & !umask));
+                    fs_notify(path.as_str(), IN_CREATE);
+                }
+                let fd = File::new(file, path.to_string(), flags as _).add_to_fd_table()?;
                 return Ok(fd as _);
             }
         }
     }
 
     let fd = Directory::new(
-        dir.map_or_else(
-            || axfs::fops::Directory::open_dir(path, &opts),
-            |dir| dir.inner().open_dir_at(path, &opts),
-        )?,
-        path.into(),
+        axfs::fops::Directory::open_dir(path.as_str(), &opts)?,
+        path.to_string(),
     )
     .add_to_fd_table()?;
     Ok(fd as _)
 }
 
+/// Create an unnamed file in the directory `dir` for `O_TMPFILE`.
+///
+/// The file is backed by a hidden entry, which is removed when the file is
+/// closed unless the file has been linked into the namespace by `linkat`.
+fn open_tmpfile(dir: &FilePath, flags: u32, mode: __kernel_mode_t) -> LinuxResult<isize> {
+    if flags & O_ACCMODE == O_RDONLY {
+        return Err(LinuxError::EINVAL);
+    }
+    if !dir.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    if !axfs::api::metadata(dir.as_str())?.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+
+    let (file, path) = create_hidden_file(dir)?;
+    let umask = current().task_ext().process_data().umask();
+    ATTR_MANAGER.update(&path, |attr| attr.mode = Some(mode & 0o7777 & !umask));
+
+    let fd = File::new(file, path.to_string(), flags).add_to_fd_table()?;
+    Ok(fd as _)
+}
+
+/// Create a hidden entry in `dir` to back an unnamed file.
+fn create_hidden_file(dir: &FilePath) -> LinuxResult<(axfs::fops::File, FilePath)> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let path = loop {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{}{}", TMPFILE_PREFIX, id))?;
+        if !path.exists() {
+            break path;
+        }
+    };
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    opts.create_new(true);
+    let file = axfs::fops::File::open(path.as_str(), &opts)?;
+    Ok((file, path))
+}
+
+/// The maximum length of the name of a memfd, excluding the NUL.
+const MFD_NAME_MAX_LEN: usize = 249;
+
+/// Get the hidden directory that backs the memfds, creating it on first use.
+///
+/// The directory is in the RAM file system of the kernel at `/tmp`, found
+/// without the root directory and the mounts of the calling process, so that
+/// memfds work the same in every process and stay out of the way of its files.
+fn memfd_dir() -> LinuxResult<FilePath> {
+    let dir = FilePath::new(format!("/tmp/{}memfd/", TMPFILE_PREFIX))?;
+    match axfs::api::create_dir(dir.as_str()) {
+        Ok(()) | Err(AxError::AlreadyExists) => Ok(dir),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create an anonymous file living in memory.
+///
+/// The file is backed by a hidden entry in [`memfd_dir`], and `name` is only
+/// used for debugging.
+pub fn sys_memfd_create(name: UserConstPtr<c_char>, flags: u32) -> LinuxResult<isize> {
+    let name = name.get_as_str()?;
+    debug!("sys_memfd_create <= name: {:?}, flags: {:#x}", name, flags);
+
+    if name.len() > MFD_NAME_MAX_LEN {
+        return Err(LinuxError::EINVAL);
+    }
+    if flags & !(MFD_CLOEXEC | MFD_ALLOW_SEALING) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let (file, path) = create_hidden_file(&memfd_dir()?)?;
+    ATTR_MANAGER.update(&path, |attr| attr.mode = Some(0o777));
+    let file = File::new_memfd(file, path.to_string(), flags & MFD_ALLOW_SEALING != 0);
+    let fd = add_file_like_from(Arc::new(file), 0, flags & MFD_CLOEXEC != 0)?;
+    Ok(fd as _)
+}
+
 /// Open a file by `filename` and insert it into the file descriptor table.
 ///
 /// Return its index in the file table (`fd`). Return `EMFILE` if it already
@@ -119,6 +232,13 @@ fn dup_fd(old_fd: c_int) -> LinuxResult<isize> {
     Ok(new_fd as _)
 }
 
+/// Duplicate `old_fd` to the lowest free fd not less than `min_fd`.
+fn dup_fd_from(old_fd: c_int, min_fd: c_int, cloexec: bool) -> LinuxResult<isize> {
+    let f = get_file_like(old_fd)?;
+    let new_fd = add_file_like_from(f, min_fd, cloexec)?;
+    Ok(new_fd as _)
+}
+
 pub fn sys_dup(old_fd: c_int) -> LinuxResult<isize> {
     debug!("sys_dup <= {}", old_fd);
     dup_fd(old_fd)
@@ -126,17 +246,48 @@ pub fn sys_dup(old_fd: c_int) -> LinuxResult<isize> {
 
 pub fn sys_dup2(old_fd: c_int, new_fd: c_int) -> LinuxResult<isize> {
     debug!("sys_dup2 <= old_fd: {}, new_fd: {}", old_fd, new_fd);
+    dup_fd_to(old_fd, new_fd, false)
+}
+
+/// Duplicate `old_fd` to `new_fd`, setting the close-on-exec flag of `new_fd`
+/// if `O_CLOEXEC` is in `flags`.
+///
+/// Unlike `dup2`, it is an error if `old_fd` equals `new_fd`.
+pub fn sys_dup3(old_fd: c_int, new_fd: c_int, flags: c_int) -> LinuxResult<isize> {
+    debug!(
+        "sys_dup3 <= old_fd: {}, new_fd: {}, flags: {:#x}",
+        old_fd, new_fd, flags
+    );
+    if flags as u32 & !O_CLOEXEC != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if old_fd == new_fd {
+        return Err(LinuxError::EINVAL);
+    }
+    dup_fd_to(old_fd, new_fd, flags as u32 & O_CLOEXEC != 0)
+}
+
+/// Duplicate `old_fd` to `new_fd`, closing the file previously at `new_fd`.
+fn dup_fd_to(old_fd: c_int, new_fd: c_int, cloexec: bool) -> LinuxResult<isize> {
     let mut fd_table = FD_TABLE.write();
     let f = fd_table
         .get(old_fd as _)
-        .cloned()
+        .map(|fd| fd.inner.clone())
         .ok_or(LinuxError::EBADF)?;
+    if new_fd < 0 || new_fd as usize >= AX_FILE_LIMIT {
+        return Err(LinuxError::EBADF);
+    }
 
     if old_fd != new_fd {
-        fd_table.remove(new_fd as _);
+        let old = fd_table.remove(new_fd as _);
         fd_table
-            .add_at(new_fd as _, f)
+            .add_at(new_fd as _, FileDescriptor::new(f, cloexec))
             .unwrap_or_else(|_| panic!("new_fd should be valid"));
+        drop(fd_table);
+        // The file at `new_fd` is closed as by `close`.
+        if let Some(old) = old {
+            release_process_locks(old.inner);
+        }
     }
 
     Ok(new_fd as _)
@@ -146,18 +297,42 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> LinuxResult<isize> {
     debug!("sys_fcntl <= fd: {} cmd: {} arg: {}", fd, cmd, arg);
 
     match cmd as u32 {
-        F_DUPFD => dup_fd(fd),
-        F_DUPFD_CLOEXEC => {
-            // TODO: Change fd flags
-            dup_fd(fd)
+        F_DUPFD => dup_fd_from(fd, arg as _, false),
+        F_DUPFD_CLOEXEC => dup_fd_from(fd, arg as _, true),
+        F_GETFD => {
+            let fd_table = FD_TABLE.read();
+            let fd = fd_table.get(fd as _).ok_or(LinuxError::EBADF)?;
+            Ok(if fd.cloexec { FD_CLOEXEC as _ } else { 0 })
         }
+        F_SETFD => {
+            let mut fd_table = FD_TABLE.write();
+            let fd = fd_table.get_mut(fd as _).ok_or(LinuxError::EBADF)?;
+            fd.cloexec = arg as u32 & FD_CLOEXEC != 0;
+            Ok(0)
+        }
+        F_GETFL => Ok(get_file_like(fd)?.status_flags() as _),
         F_SETFL => {
-            if fd == 0 || fd == 1 || fd == 2 {
-                return Ok(0);
+            get_file_like(fd)?.set_status_flags(arg as _)?;
+            Ok(0)
+        }
+        F_GETLK | F_SETLK | F_SETLKW => {
+            let file = File::from_fd(fd)?;
+            let lock = UserPtr::<linux_raw_sys::general::flock>::from(arg).get_as_mut()?;
+            fcntl_record_lock(&file, cmd as u32, lock)
+        }
+        F_ADD_SEALS => {
+            let seals = arg as u32;
+            if seals
+                & !(F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE | F_SEAL_FUTURE_WRITE)
+                != 0
+            {
+                return Err(LinuxError::EINVAL);
             }
-            get_file_like(fd)?.set_nonblocking(arg & (O_NONBLOCK as usize) > 0)?;
+            let file = File::from_fd(fd)?;
+            file.add_seals(seals, is_mapped_writable(&file))?;
             Ok(0)
         }
+        F_GET_SEALS => Ok(File::from_fd(fd)?.get_seals()? as _),
         _ => {
             warn!("unsupported fcntl parameters: cmd: {}", cmd);
             Ok(0)
@@ -165,14 +340,241 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> LinuxResult<isize> {
     }
 }
 
+/// Handle the `F_GETLK`, `F_SETLK` and `F_SETLKW` commands of `fcntl`.
+///
+/// Record locks are owned by the calling process and cover the byte range
+/// described by `lock`, where a length of 0 extends to the end of file.
+fn fcntl_record_lock(
+    file: &File,
+    cmd: u32,
+    lock: &mut linux_raw_sys::general::flock,
+) -> LinuxResult<isize> {
+    let kind = match lock.l_type as u32 {
+        F_RDLCK => Some(RecordLockKind::Read),
+        F_WRLCK => Some(RecordLockKind::Write),
+        F_UNLCK => None,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let base = match lock.l_whence as u32 {
+        SEEK_SET => 0,
+        SEEK_CUR => file.inner().seek(SeekFrom::Current(0))? as i64,
+        SEEK_END => file.inner().get_attr()?.size() as i64,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let start = base
+        .checked_add(lock.l_start as i64)
+        .ok_or(LinuxError::EOVERFLOW)?;
+    let len = lock.l_len as i64;
+    let (start, end) = match len {
+        0 => (start, u64::MAX),
+        len if len > 0 => {
+            let end = start.checked_add(len).ok_or(LinuxError::EOVERFLOW)?;
+            (start, end as u64)
+        }
+        len => (start + len, start as u64),
+    };
+    if start < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let start = start as u64;
+    let pid = current().task_ext().thread.process().pid();
+
+    if cmd == F_GETLK {
+        let kind = kind.ok_or(LinuxError::EINVAL)?;
+        match find_record_lock_conflict(file.path(), pid, kind, start, end) {
+            Some(conflict) => {
+                lock.l_type = match conflict.kind {
+                    RecordLockKind::Read => F_RDLCK,
+                    RecordLockKind::Write => F_WRLCK,
+                } as _;
+                lock.l_whence = SEEK_SET as _;
+                lock.l_start = conflict.start as _;
+                lock.l_len = if conflict.end == u64::MAX {
+                    0
+                } else {
+                    (conflict.end - conflict.start) as _
+                };
+                lock.l_pid = conflict.owner as _;
+            }
+            None => lock.l_type = F_UNLCK as _,
+        }
+        return Ok(0);
+    }
+    set_record_lock(file.path(), pid, kind, start, end, cmd == F_SETLKW)?;
+    Ok(0)
+}
+
+/// Reposition the offset of the file indicated by `fd`.
+///
+/// Files are never sparse, so for `SEEK_DATA` and `SEEK_HOLE` the whole file
+/// is data followed by the implicit hole at the end of file.
 pub fn sys_lseek(fd: c_int, offset: __kernel_off_t, whence: c_int) -> LinuxResult<isize> {
     debug!("sys_lseek <= {} {} {}", fd, offset, whence);
-    let pos = match whence {
-        0 => SeekFrom::Start(offset as _),
-        1 => SeekFrom::Current(offset as _),
-        2 => SeekFrom::End(offset as _),
+    let file = File::from_fd(fd)?;
+    let mut inner = file.inner();
+    let pos = match whence as u32 {
+        SEEK_SET => SeekFrom::Start(offset as _),
+        SEEK_CUR => SeekFrom::Current(offset as _),
+        SEEK_END => SeekFrom::End(offset as _),
+        SEEK_DATA | SEEK_HOLE => {
+            let size = inner.get_attr()?.size();
+            if offset < 0 || offset as u64 >= size {
+                return Err(LinuxError::ENXIO);
+            }
+            if whence as u32 == SEEK_DATA {
+                SeekFrom::Start(offset as _)
+            } else {
+                SeekFrom::Start(size)
+            }
+        }
         _ => return Err(LinuxError::EINVAL),
     };
-    let off = File::from_fd(fd)?.inner().seek(pos)?;
+    let off = inner.seek(pos)?;
     Ok(off as _)
 }
+
+/// Truncate or extend the file referred to by `path` to `length` bytes.
+pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> LinuxResult<isize> {
+    let path = path.get_as_str()?;
+    debug!("sys_truncate <= path: {}, length: {}", path, length);
+
+    if length < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let path = resolve_symlinks(&handle_file_path(AT_FDCWD, path)?, true)?;
+    check_writable(&path)?;
+    let mut opts = OpenOptions::new();
+    opts.write(true);
+    let file = match axfs::fops::File::open(path.as_str(), &opts) {
+        Err(AxError::IsADirectory) => return Err(LinuxError::EISDIR),
+        r => File::new(r?, path.to_string(), O_WRONLY),
+    };
+    file.truncate(length as _)?;
+    Ok(0)
+}
+
+/// Truncate or extend the file referred to by `fd` to `length` bytes.
+pub fn sys_ftruncate(fd: c_int, length: __kernel_off_t) -> LinuxResult<isize> {
+    debug!("sys_ftruncate <= fd: {}, length: {}", fd, length);
+
+    if length < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    File::from_fd(fd)?.truncate(length as _)?;
+    Ok(0)
+}
+
+/// Manipulate the allocated disk space of the file referred to by `fd`.
+///
+/// The underlying file systems do not support preallocation, so the default
+/// mode only extends the file, and `FALLOC_FL_PUNCH_HOLE` zeroes the range.
+pub fn sys_fallocate(
+    fd: c_int,
+    mode: u32,
+    offset: __kernel_off_t,
+    len: __kernel_off_t,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_fallocate <= fd: {}, mode: {}, offset: {}, len: {}",
+        fd, mode, offset, len
+    );
+
+    if offset < 0 || len <= 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let file = match get_file_like(fd)?.into_any().downcast::<File>() {
+        Ok(file) => file,
+        Err(f) if f.is::<Pipe>() => return Err(LinuxError::ESPIPE),
+        Err(_) => return Err(LinuxError::ENODEV),
+    };
+    let file = file.inner();
+    let to_ebadf = |e| match e {
+        AxError::PermissionDenied => LinuxError::EBADF,
+        e => e.into(),
+    };
+    // Check that the file is open for writing.
+    file.write_at(0, &[]).map_err(to_ebadf)?;
+
+    let size = file.get_attr()?.size();
+    let end = (offset as u64).saturating_add(len as u64);
+    match mode {
+        0 => {
+            if end > size {
+                file.truncate(end).map_err(to_ebadf)?;
+            }
+        }
+        FALLOC_FL_KEEP_SIZE => {}
+        m if m == FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE => {
+            let zeros = [0u8; 512];
+            let mut pos = offset as u64;
+            let end = end.min(size);
+            while pos < end {
+                let n = ((end - pos) as usize).min(zeros.len());
+                pos += file.write_at(pos, &zeros[..n]).map_err(to_ebadf)? as u64;
+            }
+        }
+        _ => return Err(LinuxError::EOPNOTSUPP),
+    }
+    Ok(0)
+}
+
+/// Announce an access pattern for the file data.
+///
+/// The advice is only validated, as there is no page cache to tune.
+pub fn sys_fadvise64(
+    fd: c_int,
+    offset: __kernel_off_t,
+    len: __kernel_off_t,
+    advice: u32,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_fadvise64 <= fd: {}, offset: {}, len: {}, advice: {}",
+        fd, offset, len, advice
+    );
+
+    if let Err(f) = get_file_like(fd)?.into_any().downcast::<File>() {
+        if f.is::<Pipe>() {
+            return Err(LinuxError::ESPIPE);
+        }
+    }
+    if len < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    match advice {
+        POSIX_FADV_NORMAL
+        | POSIX_FADV_RANDOM
+        | POSIX_FADV_SEQUENTIAL
+        | POSIX_FADV_WILLNEED
+        | POSIX_FADV_DONTNEED
+        | POSIX_FADV_NOREUSE => Ok(0),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+/// Apply or remove an advisory lock on the file referred to by `fd`.
+///
+/// Locks are shared by duplicated fds, while separately opened fds contend
+/// for them.
+pub fn sys_flock(fd: c_int, operation: u32) -> LinuxResult<isize> {
+    debug!("sys_flock <= fd: {}, operation: {}", fd, operation);
+
+    let f = get_file_like(fd)?;
+    let owner = Arc::as_ptr(&f) as *const () as usize;
+    let f = f.into_any();
+    let path = if let Some(file) = f.downcast_ref::<File>() {
+        file.path()
+    } else if let Some(dir) = f.downcast_ref::<Directory>() {
+        dir.path()
+    } else {
+        return Err(LinuxError::EINVAL);
+    };
+
+    let nonblocking = operation & LOCK_NB != 0;
+    match operation & !LOCK_NB {
+        LOCK_SH => flock(path, owner, FlockKind::Shared, nonblocking)?,
+        LOCK_EX => flock(path, owner, FlockKind::Exclusive, nonblocking)?,
+        LOCK_UN => funlock(path, owner),
+        _ => return Err(LinuxError::EINVAL),
+    }
+    Ok(0)
+}
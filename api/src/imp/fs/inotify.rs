@@ -0,0 +1,88 @@
+use core::ffi::{c_char, c_int};
+
+use axerrno::{LinuxError, LinuxResult};
+use linux_raw_sys::general::{
+    AT_FDCWD, IN_ALL_EVENTS, IN_CLOEXEC, IN_DONT_FOLLOW, IN_EXCL_UNLINK, IN_MASK_ADD,
+    IN_MASK_CREATE, IN_NONBLOCK, IN_ONESHOT, IN_ONLYDIR,
+};
+
+use crate::{
+    fd::{FileLike, Inotify, add_file_like_from},
+    path::{handle_file_path, resolve_symlinks},
+    ptr::UserConstPtr,
+};
+
+/// Create an inotify instance, with `IN_NONBLOCK` and `IN_CLOEXEC` in
+/// `flags` applied to its fd.
+pub fn sys_inotify_init1(flags: c_int) -> LinuxResult<isize> {
+    debug!("sys_inotify_init1 <= flags: {:#x}", flags);
+    let flags = flags as u32;
+    if flags & !(IN_NONBLOCK | IN_CLOEXEC) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let inotify = Inotify::new(flags & IN_NONBLOCK != 0);
+    Ok(add_file_like_from(inotify, 0, flags & IN_CLOEXEC != 0)? as _)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_inotify_init() -> LinuxResult<isize> {
+    sys_inotify_init1(0)
+}
+
+/// Watch the file at `path` for the events in `mask` on the inotify
+/// instance `fd`, returning the watch descriptor.
+pub fn sys_inotify_add_watch(
+    fd: c_int,
+    path: UserConstPtr<c_char>,
+    mask: u32,
+) -> LinuxResult<isize> {
+    let path = path.get_as_str()?;
+    debug!(
+        "sys_inotify_add_watch <= fd: {}, path: {}, mask: {:#x}",
+        fd, path, mask
+    );
+
+    let inotify = Inotify::from_fd(fd)?;
+    if mask & IN_ALL_EVENTS == 0
+        || mask
+            & !(IN_ALL_EVENTS
+                | IN_DONT_FOLLOW
+                | IN_EXCL_UNLINK
+                | IN_MASK_ADD
+                | IN_MASK_CREATE
+                | IN_ONESHOT
+                | IN_ONLYDIR)
+            != 0
+        || (mask & IN_MASK_ADD != 0 && mask & IN_MASK_CREATE != 0)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let path = resolve_symlinks(
+        &handle_file_path(AT_FDCWD, path)?,
+        mask & IN_DONT_FOLLOW == 0,
+    )?;
+    if !path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    if mask & IN_ONLYDIR != 0 && !axfs::api::metadata(path.as_str())?.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+
+    let watch_mask = mask & (IN_ALL_EVENTS | IN_ONESHOT | IN_EXCL_UNLINK);
+    inotify
+        .add_watch(
+            path.as_str(),
+            watch_mask,
+            mask & IN_MASK_ADD != 0,
+            mask & IN_MASK_CREATE != 0,
+        )
+        .map(|wd| wd as _)
+}
+
+/// Remove the watch `wd` from the inotify instance `fd`.
+pub fn sys_inotify_rm_watch(fd: c_int, wd: c_int) -> LinuxResult<isize> {
+    debug!("sys_inotify_rm_watch <= fd: {}, wd: {}", fd, wd);
+    Inotify::from_fd(fd)?.rm_watch(wd)?;
+    Ok(0)
+}
@@ -1,11 +1,14 @@
 use core::ffi::c_int;
 
-use alloc::vec;
+use alloc::{sync::Arc, vec, vec::Vec};
 use axerrno::{LinuxError, LinuxResult};
-use linux_raw_sys::general::iovec;
+use axio::SeekFrom;
+use linux_raw_sys::general::{
+    __kernel_off_t, O_APPEND, SPLICE_F_GIFT, SPLICE_F_MORE, SPLICE_F_MOVE, SPLICE_F_NONBLOCK, iovec,
+};
 
 use crate::{
-    fd::{File, FileLike, get_file_like},
+    fd::{Directory, File, FileLike, Pipe, get_file_like},
     ptr::{UserConstPtr, UserPtr, nullable},
 };
 
@@ -54,7 +57,115 @@ pub fn sys_readv(fd: c_int, iov: UserPtr<iovec>, iocnt: usize) -> LinuxResult<is
     Ok(ret)
 }
 
-pub fn sys_pread64(fd: c_int, buf: UserPtr<u8>, len: usize, offset: u64) -> LinuxResult<isize> {
+/// Get the file indicated by `fd` for positioned I/O.
+///
+/// Return `ESPIPE` if the fd is not seekable.
+fn positioned_file(fd: c_int) -> LinuxResult<Arc<File>> {
+    as_positioned_file(get_file_like(fd)?)
+}
+
+fn as_positioned_file(f: Arc<dyn FileLike>) -> LinuxResult<Arc<File>> {
+    f.into_any().downcast::<File>().map_err(|f| {
+        if f.is::<Directory>() {
+            LinuxError::EISDIR
+        } else {
+            LinuxError::ESPIPE
+        }
+    })
+}
+
+/// Read data from the file indicated by `fd` at `offset` into the buffers
+/// described by `iov`, without changing the file position.
+pub fn sys_preadv(
+    fd: c_int,
+    iov: UserPtr<iovec>,
+    iocnt: usize,
+    offset: __kernel_off_t,
+) -> LinuxResult<isize> {
+    if !(0..=1024).contains(&iocnt) || offset < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let file = positioned_file(fd)?;
+    let iovs = iov.get_as_mut_slice(iocnt)?;
+    let mut offset = offset as u64;
+    let mut ret = 0;
+    for iov in iovs {
+        if iov.iov_len == 0 {
+            continue;
+        }
+        let buf = UserPtr::<u8>::from(iov.iov_base as usize);
+        let buf = buf.get_as_mut_slice(iov.iov_len as _)?;
+        debug!(
+            "sys_preadv <= fd: {}, buf: {:p}, len: {}, offset: {}",
+            fd,
+            buf.as_ptr(),
+            buf.len(),
+            offset
+        );
+
+        let read = file.read_at(offset, buf)?;
+        offset += read as u64;
+        ret += read as isize;
+
+        if read < buf.len() {
+            break;
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Write data from the buffers described by `iov` to the file indicated by
+/// `fd` at `offset`, without changing the file position.
+pub fn sys_pwritev(
+    fd: c_int,
+    iov: UserConstPtr<iovec>,
+    iocnt: usize,
+    offset: __kernel_off_t,
+) -> LinuxResult<isize> {
+    if !(0..=1024).contains(&iocnt) || offset < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let file = positioned_file(fd)?;
+    let iovs = iov.get_as_slice(iocnt)?;
+    let mut offset = offset as u64;
+    let mut ret = 0;
+    for iov in iovs {
+        if iov.iov_len == 0 {
+            continue;
+        }
+        let buf = UserConstPtr::<u8>::from(iov.iov_base as usize);
+        let buf = buf.get_as_slice(iov.iov_len as _)?;
+        debug!(
+            "sys_pwritev <= fd: {}, buf: {:p}, len: {}, offset: {}",
+            fd,
+            buf.as_ptr(),
+            buf.len(),
+            offset
+        );
+
+        let written = file.write_at(offset, buf)?;
+        offset += written as u64;
+        ret += written as isize;
+
+        if written < buf.len() {
+            break;
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Read data from the file indicated by `fd` at `offset`, without changing
+/// the file position.
+pub fn sys_pread64(
+    fd: c_int,
+    buf: UserPtr<u8>,
+    len: usize,
+    offset: __kernel_off_t,
+) -> LinuxResult<isize> {
     let buf = buf.get_as_mut_slice(len)?;
     debug!(
         "pread64 <= fd: {}, buf: {:p}, len: {}, offset: {}",
@@ -63,7 +174,32 @@ pub fn sys_pread64(fd: c_int, buf: UserPtr<u8>, len: usize, offset: u64) -> Linu
         buf.len(),
         offset
     );
-    Ok(File::from_fd(fd)?.inner().read_at(offset, buf)? as _)
+    if offset < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(positioned_file(fd)?.read_at(offset as _, buf)? as _)
+}
+
+/// Write data to the file indicated by `fd` at `offset`, without changing
+/// the file position.
+pub fn sys_pwrite64(
+    fd: c_int,
+    buf: UserConstPtr<u8>,
+    len: usize,
+    offset: __kernel_off_t,
+) -> LinuxResult<isize> {
+    let buf = buf.get_as_slice(len)?;
+    debug!(
+        "pwrite64 <= fd: {}, buf: {:p}, len: {}, offset: {}",
+        fd,
+        buf.as_ptr(),
+        buf.len(),
+        offset
+    );
+    if offset < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(positioned_file(fd)?.write_at(offset as _, buf)? as _)
 }
 
 /// Write data to the file indicated by `fd`.
@@ -86,6 +222,19 @@ pub fn sys_writev(fd: i32, iov: UserConstPtr<iovec>, iocnt: usize) -> LinuxResul
     }
 
     let iovs = iov.get_as_slice(iocnt)?;
+    let file = get_file_like(fd)?;
+    if file.status_flags() & O_APPEND != 0 {
+        // Appends must not be interleaved with other writes, so the buffers
+        // are gathered and written at once.
+        let mut data = Vec::new();
+        for iov in iovs {
+            let buf = UserConstPtr::<u8>::from(iov.iov_base as usize);
+            data.extend_from_slice(buf.get_as_slice(iov.iov_len as _)?);
+        }
+        debug!("sys_writev <= fd: {}, append len: {}", fd, data.len());
+        return Ok(file.write(&data)? as _);
+    }
+
     let mut ret = 0;
     for iov in iovs {
         if iov.iov_len == 0 {
@@ -100,7 +249,7 @@ pub fn sys_writev(fd: i32, iov: UserConstPtr<iovec>, iocnt: usize) -> LinuxResul
             buf.len()
         );
 
-        let written = get_file_like(fd)?.write(buf)?;
+        let written = file.write(buf)?;
         ret += written as isize;
 
         if written < buf.len() {
@@ -111,29 +260,35 @@ pub fn sys_writev(fd: i32, iov: UserConstPtr<iovec>, iocnt: usize) -> LinuxResul
     Ok(ret)
 }
 
-fn do_sendfile<F, D>(mut read: F, dest: &D) -> LinuxResult<usize>
+fn do_sendfile<F, D>(mut read: F, dest: &D, len: usize) -> LinuxResult<usize>
 where
     F: FnMut(&mut [u8]) -> LinuxResult<usize>,
     D: FileLike + ?Sized,
 {
     let mut buf = vec![0; 0x4000];
     let mut total_written = 0;
-    loop {
-        let bytes_read = read(&mut buf)?;
+    while total_written < len {
+        let chunk = (len - total_written).min(buf.len());
+        let bytes_read = read(&mut buf[..chunk])?;
         if bytes_read == 0 {
             break;
         }
 
         let bytes_written = dest.write(&buf[..bytes_read])?;
+        total_written += bytes_written;
         if bytes_written < bytes_read {
             break;
         }
-        total_written += bytes_written;
     }
 
     Ok(total_written)
 }
 
+/// Copy up to `len` bytes from `in_fd` to `out_fd`.
+///
+/// If `offset` is NULL, data is read from the current position of `in_fd`,
+/// which is advanced. Otherwise data is read from `*offset`, which is
+/// updated, and the position of `in_fd` is left unchanged.
 pub fn sys_sendfile(
     out_fd: c_int,
     in_fd: c_int,
@@ -148,26 +303,264 @@ pub fn sys_sendfile(
         len
     );
 
-    let src = get_file_like(in_fd)?;
+    let src = get_file_like(in_fd)?
+        .into_any()
+        .downcast::<File>()
+        .map_err(|_| LinuxError::EINVAL)?;
     let dest = get_file_like(out_fd)?;
     let offset = nullable!(offset.get_as_mut())?;
 
     if let Some(offset) = offset {
-        let src = src
-            .into_any()
-            .downcast::<File>()
-            .map_err(|_| LinuxError::ESPIPE)?;
-
-        do_sendfile(
+        if (*offset as i64) < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut pos = *offset;
+        let result = do_sendfile(
             |buf| {
-                let bytes_read = src.inner().read_at(*offset, buf)?;
-                *offset += bytes_read as u64;
+                let bytes_read = src.read_at(pos, buf)?;
+                pos += bytes_read as u64;
                 Ok(bytes_read)
             },
             dest.as_ref(),
-        )
+            len,
+        );
+        // Only the bytes actually written count as consumed.
+        if let Ok(n) = result {
+            *offset += n as u64;
+        }
+        result
     } else {
-        do_sendfile(|buf| src.read(buf), dest.as_ref())
+        let start = src.inner().seek(SeekFrom::Current(0))?;
+        let result = do_sendfile(|buf| src.read(buf), dest.as_ref(), len);
+        if let Ok(n) = result {
+            src.inner().seek(SeekFrom::Start(start + n as u64))?;
+        }
+        result
     }
     .map(|n| n as _)
 }
+
+/// Copy up to `len` bytes from `fd_in` to `fd_out`.
+///
+/// For each side, data is transferred at `*off` (which is updated) if the
+/// offset pointer is not NULL, otherwise at the current position of the file,
+/// which is advanced.
+pub fn sys_copy_file_range(
+    fd_in: c_int,
+    off_in: UserPtr<i64>,
+    fd_out: c_int,
+    off_out: UserPtr<i64>,
+    len: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_copy_file_range <= fd_in: {}, off_in: {}, fd_out: {}, off_out: {}, len: {}, flags: {}",
+        fd_in,
+        !off_in.is_null(),
+        fd_out,
+        !off_out.is_null(),
+        len,
+        flags
+    );
+
+    if flags != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let src = File::from_fd(fd_in)?;
+    let dest = File::from_fd(fd_out)?;
+    if dest.status_flags() & O_APPEND != 0 {
+        return Err(LinuxError::EBADF);
+    }
+    let mut off_in = nullable!(off_in.get_as_mut())?;
+    let mut off_out = nullable!(off_out.get_as_mut())?;
+
+    let mut pos_in = match off_in.as_deref() {
+        Some(&off) if off < 0 => return Err(LinuxError::EINVAL),
+        Some(&off) => off as u64,
+        None => src.inner().seek(SeekFrom::Current(0))?,
+    };
+    let mut pos_out = match off_out.as_deref() {
+        Some(&off) if off < 0 => return Err(LinuxError::EINVAL),
+        Some(&off) => off as u64,
+        None => dest.inner().seek(SeekFrom::Current(0))?,
+    };
+
+    if src.path() == dest.path()
+        && pos_in < pos_out.saturating_add(len as u64)
+        && pos_out < pos_in.saturating_add(len as u64)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let mut buf = vec![0; 0x4000];
+    let mut total_written = 0;
+    while total_written < len {
+        let chunk = (len - total_written).min(buf.len());
+        let bytes_read = src.read_at(pos_in, &mut buf[..chunk])?;
+        if bytes_read == 0 {
+            break;
+        }
+        let bytes_written = dest.write_at(pos_out, &buf[..bytes_read])?;
+        pos_in += bytes_written as u64;
+        pos_out += bytes_written as u64;
+        total_written += bytes_written;
+        if bytes_written < bytes_read {
+            break;
+        }
+    }
+
+    match off_in.as_deref_mut() {
+        Some(off) => *off = pos_in as _,
+        None => {
+            src.inner().seek(SeekFrom::Start(pos_in))?;
+        }
+    }
+    match off_out.as_deref_mut() {
+        Some(off) => *off = pos_out as _,
+        None => {
+            dest.inner().seek(SeekFrom::Start(pos_out))?;
+        }
+    }
+
+    Ok(total_written as _)
+}
+
+/// Read from `src` at `*offset` if given, or at the file position otherwise,
+/// leaving the data to be consumed by [`splice_consume`].
+///
+/// Files that are neither pipes nor regular files cannot be peeked, so the
+/// data is consumed right away.
+fn splice_peek(
+    src: &Arc<dyn FileLike>,
+    offset: Option<&i64>,
+    buf: &mut [u8],
+) -> LinuxResult<usize> {
+    if let Ok(pipe) = src.clone().into_any().downcast::<Pipe>() {
+        return pipe.peek(buf);
+    }
+    match offset {
+        Some(&off) => as_positioned_file(src.clone())?.read_at(off as _, buf),
+        None => match src.clone().into_any().downcast::<File>() {
+            Ok(file) => {
+                let pos = file.inner().seek(SeekFrom::Current(0))?;
+                file.read_at(pos, buf)
+            }
+            Err(_) => src.read(buf),
+        },
+    }
+}
+
+/// Consume the first `len` bytes of the data returned by [`splice_peek`].
+fn splice_consume(src: &Arc<dyn FileLike>, offset: Option<&mut i64>, len: usize) -> LinuxResult {
+    if let Ok(pipe) = src.clone().into_any().downcast::<Pipe>() {
+        pipe.consume(len);
+        return Ok(());
+    }
+    match offset {
+        Some(off) => *off += len as i64,
+        None => {
+            if let Ok(file) = src.clone().into_any().downcast::<File>() {
+                file.inner().seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write to `dest` at `*offset` if given, or at the file position otherwise.
+fn splice_write(
+    dest: &Arc<dyn FileLike>,
+    offset: Option<&mut i64>,
+    buf: &[u8],
+) -> LinuxResult<usize> {
+    match offset {
+        Some(off) => {
+            let written = as_positioned_file(dest.clone())?.write_at(*off as _, buf)?;
+            *off += written as i64;
+            Ok(written)
+        }
+        None => dest.write(buf),
+    }
+}
+
+/// Move up to `len` bytes between `fd_in` and `fd_out`, one of which must
+/// be a pipe.
+///
+/// The offset of the file side is used and updated if given, otherwise the
+/// file position is. Offsets must not be given for pipes.
+pub fn sys_splice(
+    fd_in: c_int,
+    off_in: UserPtr<i64>,
+    fd_out: c_int,
+    off_out: UserPtr<i64>,
+    len: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_splice <= fd_in: {}, off_in: {}, fd_out: {}, off_out: {}, len: {}, flags: {}",
+        fd_in,
+        !off_in.is_null(),
+        fd_out,
+        !off_out.is_null(),
+        len,
+        flags
+    );
+
+    if flags & !(SPLICE_F_MOVE | SPLICE_F_NONBLOCK | SPLICE_F_MORE | SPLICE_F_GIFT) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let src = get_file_like(fd_in)?;
+    let dest = get_file_like(fd_out)?;
+    let pipe_in = src.clone().into_any().downcast::<Pipe>().ok();
+    let pipe_out = dest.clone().into_any().downcast::<Pipe>().ok();
+    if pipe_in.is_none() && pipe_out.is_none() {
+        return Err(LinuxError::EINVAL);
+    }
+    if (pipe_in.is_some() && !off_in.is_null()) || (pipe_out.is_some() && !off_out.is_null()) {
+        return Err(LinuxError::ESPIPE);
+    }
+    let off_in = nullable!(off_in.get_as_mut())?;
+    let mut off_out = nullable!(off_out.get_as_mut())?;
+    if off_in.as_deref().is_some_and(|&off| off < 0)
+        || off_out.as_deref().is_some_and(|&off| off < 0)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let mut len = len.min(0x10000);
+    if flags & SPLICE_F_NONBLOCK != 0 {
+        if let Some(pipe) = &pipe_in {
+            if pipe.available_read() == 0 && !pipe.closed() {
+                return Err(LinuxError::EAGAIN);
+            }
+        }
+        if let Some(pipe) = &pipe_out {
+            len = len.min(pipe.available_write());
+            if len == 0 {
+                return Err(LinuxError::EAGAIN);
+            }
+        }
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let mut buf = vec![0; len];
+    let bytes_read = splice_peek(&src, off_in.as_deref(), &mut buf)?;
+    let mut total_written = 0;
+    while total_written < bytes_read {
+        match splice_write(
+            &dest,
+            off_out.as_deref_mut(),
+            &buf[total_written..bytes_read],
+        ) {
+            Ok(0) => break,
+            Ok(written) => total_written += written,
+            // Keep what is not written in the source.
+            Err(err) if total_written == 0 => return Err(err),
+            Err(_) => break,
+        }
+    }
+    splice_consume(&src, off_in, total_written)?;
+    Ok(total_written as _)
+}
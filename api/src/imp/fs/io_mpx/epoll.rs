@@ -0,0 +1,309 @@
+use core::{any::Any, ffi::c_int};
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::{
+    arch::TrapFrame,
+    time::{TimeValue, wall_time},
+};
+use axio::PollState;
+use axsignal::ctypes::SignalSet;
+use axsync::Mutex;
+use linux_raw_sys::general::{
+    EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, EPOLLERR, EPOLLET, EPOLLHUP,
+    EPOLLIN, EPOLLONESHOT, EPOLLOUT, epoll_event,
+};
+
+use crate::{
+    fd::{Directory, File, FileLike, Kstat, add_file_like_from, get_file_like},
+    has_pending_signal,
+    ptr::{UserConstPtr, UserPtr, nullable},
+    with_sigmask,
+};
+
+/// A file registered on an epoll instance.
+struct Interest {
+    file: Weak<dyn FileLike>,
+    events: u32,
+    data: u64,
+    /// The events ready and the [`FileLike::event_seq`] of the file last
+    /// time, used by edge-triggered interests.
+    last_ready: u32,
+    last_seq: u64,
+    /// Whether a one-shot interest has fired and waits for `EPOLL_CTL_MOD`.
+    disabled: bool,
+}
+
+/// Get the events of interest in `events` that are ready on `file`.
+///
+/// `EPOLLERR` and `EPOLLHUP` are always reported, whatever the interest is.
+fn ready_events(file: &dyn FileLike, events: u32) -> u32 {
+    match file.poll() {
+        Ok(PollState { readable, writable }) => {
+            let mut ready = 0;
+            if readable {
+                ready |= EPOLLIN;
+            }
+            if writable {
+                ready |= EPOLLOUT;
+            }
+            ready & events
+        }
+        Err(LinuxError::EPIPE) => EPOLLHUP | (events & EPOLLIN),
+        Err(_) => EPOLLERR,
+    }
+}
+
+/// An epoll instance, which is a set of files to wait for.
+pub struct Epoll {
+    interests: Mutex<BTreeMap<c_int, Interest>>,
+}
+
+impl Epoll {
+    fn new() -> Self {
+        Self {
+            interests: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn add(&self, fd: c_int, file: &Arc<dyn FileLike>, event: epoll_event) -> LinuxResult {
+        let mut interests = self.interests.lock();
+        if interests
+            .get(&fd)
+            .is_some_and(|i| i.file.strong_count() > 0)
+        {
+            return Err(LinuxError::EEXIST);
+        }
+        interests.insert(
+            fd,
+            Interest {
+                file: Arc::downgrade(file),
+                events: event.events,
+                data: event.data,
+                last_ready: 0,
+                last_seq: 0,
+                disabled: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn modify(&self, fd: c_int, event: epoll_event) -> LinuxResult {
+        let mut interests = self.interests.lock();
+        let interest = interests.get_mut(&fd).ok_or(LinuxError::ENOENT)?;
+        interest.events = event.events;
+        interest.data = event.data;
+        interest.last_ready = 0;
+        interest.last_seq = 0;
+        interest.disabled = false;
+        Ok(())
+    }
+
+    fn delete(&self, fd: c_int) -> LinuxResult {
+        self.interests
+            .lock()
+            .remove(&fd)
+            .map(|_| ())
+            .ok_or(LinuxError::ENOENT)
+    }
+
+    /// Collect the ready events into `events`, returning the number of them.
+    fn collect(&self, events: &mut [epoll_event]) -> usize {
+        let mut interests = self.interests.lock();
+        // Files closed since they were added are dropped from the set.
+        interests.retain(|_, i| i.file.strong_count() > 0);
+
+        let mut count = 0;
+        for interest in interests.values_mut() {
+            if count == events.len() {
+                break;
+            }
+            if interest.disabled {
+                continue;
+            }
+            let Some(file) = interest.file.upgrade() else {
+                continue;
+            };
+            let ready = ready_events(file.as_ref(), interest.events);
+            let seq = file.event_seq();
+            // An edge is a file becoming ready, or new data arriving or space
+            // being freed while it stays ready.
+            let edge = ready & !interest.last_ready != 0 || seq != interest.last_seq;
+            interest.last_ready = ready;
+            interest.last_seq = seq;
+            if ready == 0 || (interest.events & EPOLLET != 0 && !edge) {
+                continue;
+            }
+            events[count] = epoll_event {
+                events: ready,
+                data: interest.data,
+            };
+            count += 1;
+            if interest.events & EPOLLONESHOT != 0 {
+                interest.disabled = true;
+            }
+        }
+        count
+    }
+}
+
+impl FileLike for Epoll {
+    fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: 0o600u32, // rw-------
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        let readable = self.interests.lock().values().any(|i| {
+            !i.disabled
+                && i.file
+                    .upgrade()
+                    .is_some_and(|f| ready_events(f.as_ref(), i.events) != 0)
+        });
+        Ok(PollState {
+            readable,
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+/// Create an epoll instance, setting the close-on-exec flag of its fd if
+/// `EPOLL_CLOEXEC` is in `flags`.
+pub fn sys_epoll_create1(flags: c_int) -> LinuxResult<isize> {
+    debug!("sys_epoll_create1 <= flags: {:#x}", flags);
+    let flags = flags as u32;
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let fd = add_file_like_from(Arc::new(Epoll::new()), 0, flags & EPOLL_CLOEXEC != 0)?;
+    Ok(fd as _)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_epoll_create(size: c_int) -> LinuxResult<isize> {
+    if size <= 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    sys_epoll_create1(0)
+}
+
+/// Add, modify or delete the interest of the epoll instance `epfd` in `fd`.
+pub fn sys_epoll_ctl(
+    epfd: c_int,
+    op: u32,
+    fd: c_int,
+    event: UserConstPtr<epoll_event>,
+) -> LinuxResult<isize> {
+    debug!("sys_epoll_ctl <= epfd: {}, op: {}, fd: {}", epfd, op, fd);
+
+    let epoll = Epoll::from_fd(epfd)?;
+    let file = get_file_like(fd)?;
+    if epfd == fd {
+        return Err(LinuxError::EINVAL);
+    }
+    // Regular files and directories are always ready, so they cannot be
+    // waited for.
+    let any = file.clone().into_any();
+    if any.is::<File>() || any.is::<Directory>() {
+        return Err(LinuxError::EPERM);
+    }
+
+    match op {
+        EPOLL_CTL_ADD => epoll.add(fd, &file, *event.get_as_ref()?)?,
+        EPOLL_CTL_MOD => epoll.modify(fd, *event.get_as_ref()?)?,
+        EPOLL_CTL_DEL => epoll.delete(fd)?,
+        _ => return Err(LinuxError::EINVAL),
+    }
+    Ok(0)
+}
+
+/// Wait for the files of the epoll instance `epfd` to become ready.
+///
+/// A negative `timeout` waits indefinitely, otherwise it is in milliseconds.
+/// The signals blocked while waiting are replaced by `sigmask` unless it is
+/// null.
+pub fn sys_epoll_pwait(
+    tf: &mut TrapFrame,
+    epfd: c_int,
+    events: UserPtr<epoll_event>,
+    maxevents: c_int,
+    timeout: c_int,
+    sigmask: UserConstPtr<SignalSet>,
+    sigsetsize: usize,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_epoll_pwait <= epfd: {}, maxevents: {}, timeout: {}",
+        epfd, maxevents, timeout
+    );
+
+    if maxevents <= 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let epoll = Epoll::from_fd(epfd)?;
+    let events = events.get_as_mut_slice(maxevents as usize)?;
+    let sigmask = nullable!(sigmask.get_as_ref())?;
+    if sigmask.is_some() && sigsetsize != size_of::<SignalSet>() {
+        return Err(LinuxError::EINVAL);
+    }
+    let deadline = (timeout >= 0).then(|| wall_time() + TimeValue::from_millis(timeout as u64));
+
+    with_sigmask(tf, sigmask.copied(), || {
+        loop {
+            axnet::poll_interfaces();
+
+            let count = epoll.collect(events);
+            if count > 0 {
+                return Ok(count as _);
+            }
+
+            if deadline.is_some_and(|d| wall_time() >= d) {
+                return Ok(0);
+            }
+            if has_pending_signal() {
+                return Err(LinuxError::EINTR);
+            }
+
+            axtask::yield_now();
+        }
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_epoll_wait(
+    tf: &mut TrapFrame,
+    epfd: c_int,
+    events: UserPtr<epoll_event>,
+    maxevents: c_int,
+    timeout: c_int,
+) -> LinuxResult<isize> {
+    sys_epoll_pwait(
+        tf,
+        epfd,
+        events,
+        maxevents,
+        timeout,
+        UserConstPtr::default(),
+        0,
+    )
+}
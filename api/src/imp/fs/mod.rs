@@ -1,15 +1,21 @@
 mod ctl;
+mod eventfd;
 mod fd_ops;
+mod inotify;
 mod io;
 mod io_mpx;
 mod mount;
 mod pipe;
 mod stat;
+mod timerfd;
 
 pub use self::ctl::*;
+pub use self::eventfd::*;
 pub use self::fd_ops::*;
+pub use self::inotify::*;
 pub use self::io::*;
 pub use self::io_mpx::*;
 pub use self::mount::*;
 pub use self::pipe::*;
 pub use self::stat::*;
+pub use self::timerfd::*;
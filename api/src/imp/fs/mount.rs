@@ -1,14 +1,31 @@
-use core::ffi::{c_char, c_void};
+use core::{
+    ffi::{c_char, c_void},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use alloc::vec::Vec;
+use alloc::{format, string::String, sync::Arc, vec::Vec};
 use axerrno::{LinuxError, LinuxResult};
+use axfs::CURRENT_DIR_PATH;
+use axns::{ResArc, def_resource};
 use axsync::Mutex;
-use linux_raw_sys::general::AT_FDCWD;
+use linux_raw_sys::general::{
+    AT_FDCWD, MNT_DETACH, MNT_FORCE, MS_RDONLY, MS_REMOUNT, MSDOS_SUPER_MAGIC, PROC_SUPER_MAGIC,
+    RAMFS_MAGIC, SYSFS_MAGIC, TMPFS_MAGIC, UMOUNT_NOFOLLOW,
+};
 
-use crate::path::{FilePath, handle_file_path};
+use starry_core::task::{ProcessData, processes};
+
+use crate::fd::{Directory, FD_TABLE, File, FileLike, TMPFILE_PREFIX};
+use crate::path::{FilePath, handle_file_path, resolve_symlinks};
 
 use crate::ptr::UserConstPtr;
 
+/// Mount the file system `fs_type` at `target`.
+///
+/// axfs cannot attach file systems at runtime, so a `tmpfs` or `ramfs` is
+/// emulated by a hidden directory in the RAM file system at `/tmp`, which
+/// the paths under `target` are redirected to. A `devtmpfs` shares the
+/// nodes of `/dev`, like Linux does.
 pub fn sys_mount(
     source: UserConstPtr<c_char>,
     target: UserConstPtr<c_char>,
@@ -16,88 +33,206 @@ pub fn sys_mount(
     flags: i32,
     _data: UserConstPtr<c_void>,
 ) -> LinuxResult<isize> {
-    let source = source.get_as_str()?;
     let target = target.get_as_str()?;
-    let fs_type = fs_type.get_as_str()?;
-    info!(
-        "sys_mount <= source: {}, target: {}, fs_type: {}, flags: {}",
-        source, target, fs_type, flags
-    );
+    let flags = flags as u32;
+    info!("sys_mount <= target: {}, flags: {:#x}", target, flags);
 
-    let device_path = handle_file_path(AT_FDCWD, source)?;
-    let mount_path = handle_file_path(AT_FDCWD, target)?;
+    let mount_path = resolve_symlinks(&handle_file_path(AT_FDCWD, target)?, true)?;
+    if flags & MS_REMOUNT != 0 {
+        let mut mounted = MOUNTED.lock();
+        let m = mounted
+            .iter_mut()
+            .rev()
+            .find(|m| m.is_mounted_at(&mount_path))
+            .ok_or(LinuxError::EINVAL)?;
+        m.read_only = flags & MS_RDONLY != 0;
+        return Ok(0);
+    }
+
+    let source = source.get_as_str()?;
+    let fs_type = fs_type.get_as_str()?;
     info!(
         "mount {:?} to {:?} with fs_type={:?}",
-        device_path, mount_path, fs_type
+        source, mount_path, fs_type
     );
 
-    if fs_type != "vfat" {
-        debug!("fs_type can only be vfat.");
-        return Err(LinuxError::EPERM);
-    }
-
     if !mount_path.exists() {
         debug!("mount path not exist");
-        return Err(LinuxError::EPERM);
+        return Err(LinuxError::ENOENT);
     }
-
-    if check_mounted(&mount_path) {
-        debug!("mount path includes mounted fs");
-        return Err(LinuxError::EPERM);
+    if !axfs::api::metadata(mount_path.as_str())?.is_dir() {
+        return Err(LinuxError::ENOTDIR);
     }
 
-    if !mount_fat_fs(&device_path, &mount_path) {
-        debug!("mount error");
-        return Err(LinuxError::EPERM);
-    }
+    let (magic, backing) = match fs_type {
+        "vfat" => {
+            let device_path = handle_file_path(AT_FDCWD, source)?;
+            if check_mounted(&mount_path) {
+                debug!("mount path includes mounted fs");
+                return Err(LinuxError::EBUSY);
+            }
+            debug!("mounting {:?}", device_path);
+            (MSDOS_SUPER_MAGIC, None)
+        }
+        "tmpfs" => (TMPFS_MAGIC, Some(create_backing_dir()?)),
+        "ramfs" => (RAMFS_MAGIC, Some(create_backing_dir()?)),
+        "devtmpfs" => (
+            TMPFS_MAGIC,
+            Some(Arc::new(MountRoot {
+                path: FilePath::new("/dev/")?,
+                owned: false,
+            })),
+        ),
+        _ => {
+            debug!("unsupported fs_type {}", fs_type);
+            return Err(LinuxError::ENODEV);
+        }
+    };
+
+    MOUNTED.lock().push(MountedFs {
+        mnt_dir: mount_path,
+        magic,
+        backing,
+        read_only: flags & MS_RDONLY != 0,
+    });
     Ok(0)
 }
 
+/// Unmount the file system mounted at `target`.
+///
+/// Return `EBUSY` if files in it are still in use by the calling process,
+/// unless `MNT_DETACH` is set, in which case they keep working while the
+/// file system is no longer reachable by path. Its contents are removed once
+/// the last of them is closed.
 pub fn sys_umount2(target: UserConstPtr<c_char>, flags: i32) -> LinuxResult<isize> {
     let target = target.get_as_str()?;
     info!("sys_umount2 <= target: {}, flags: {}", target, flags);
 
-    let mount_path = handle_file_path(AT_FDCWD, target)?;
-    if flags != 0 {
-        debug!("flags unimplemented");
-        return Err(LinuxError::EPERM);
+    let flags = flags as u32;
+    if flags & !(MNT_FORCE | MNT_DETACH | UMOUNT_NOFOLLOW) != 0 {
+        return Err(LinuxError::EINVAL);
     }
+    let mount_path = resolve_symlinks(
+        &handle_file_path(AT_FDCWD, target)?,
+        flags & UMOUNT_NOFOLLOW == 0,
+    )?;
 
-    if !mount_path.exists() {
-        debug!("mount path not exist");
-        return Err(LinuxError::EPERM);
+    let mut mounted = MOUNTED.lock();
+    let index = mounted
+        .iter()
+        .rposition(|m| m.is_mounted_at(&mount_path))
+        .ok_or(LinuxError::EINVAL)?;
+    if flags & MNT_DETACH == 0 && mounted[index].is_busy() {
+        return Err(LinuxError::EBUSY);
     }
+    // The contents are removed outside of the lock, unless files under the
+    // mount still hold them.
+    let m = mounted.remove(index);
+    drop(mounted);
+    drop(m);
+    Ok(0)
+}
+
+/// The directory holding the contents of a mounted file system.
+///
+/// It is shared by the mount and by the files and current directories under
+/// it, and removed when the last of them goes away.
+pub struct MountRoot {
+    path: FilePath,
+    /// Whether the directory belongs to the mount, unlike `/dev`, whose nodes
+    /// are shared by all `devtmpfs` mounts.
+    owned: bool,
+}
 
-    if !umount_fat_fs(&mount_path) {
-        debug!("umount error");
-        return Err(LinuxError::EPERM);
+impl Drop for MountRoot {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = remove_dir_all(self.path.as_str());
+        }
     }
-    Ok(0)
 }
 
-/// Mounted File System
-/// "Mount" means read&write a file as a file system now
+/// Get the directory holding the mounted file system that the real `path`
+/// lives on, which keeps its contents around while it is held.
+pub fn hold_mount(path: &str) -> Option<Arc<MountRoot>> {
+    MOUNTED
+        .lock()
+        .iter()
+        .filter_map(|m| m.backing.as_ref())
+        .filter(|b| b.owned && is_under(path, &b.path))
+        .max_by_key(|b| b.path.len())
+        .cloned()
+}
+
+def_resource! {
+    /// The mounted file system holding the current directory of the process.
+    pub static CWD_MOUNT: ResArc<Mutex<Option<Arc<MountRoot>>>> = ResArc::new();
+}
+
+impl CWD_MOUNT {
+    /// Return a copy of the inner mount.
+    pub fn copy_inner(&self) -> Mutex<Option<Arc<MountRoot>>> {
+        Mutex::new(self.lock().clone())
+    }
+}
+
+#[ctor_bare::register_ctor]
+fn init_cwd_mount() {
+    CWD_MOUNT.init_new(Mutex::new(None));
+}
+
+/// Change the current directory of the process to the real `path`.
+pub fn change_current_dir(path: &str) -> LinuxResult {
+    axfs::api::set_current_dir(path)?;
+    *CWD_MOUNT.lock() = hold_mount(path);
+    Ok(())
+}
+
+/// A file system mounted by `sys_mount`.
 struct MountedFs {
-    //pub inner: Arc<Mutex<FATFileSystem>>,
-    pub device: FilePath,
-    pub mnt_dir: FilePath,
+    mnt_dir: FilePath,
+    magic: u32,
+    /// The directory holding the contents of the file system, which the
+    /// paths under `mnt_dir` are redirected to. `None` if the file system
+    /// is not backed.
+    backing: Option<Arc<MountRoot>>,
+    read_only: bool,
 }
 
 impl MountedFs {
-    pub fn new(device: &FilePath, mnt_dir: &FilePath) -> Self {
-        Self {
-            device: device.clone(),
-            mnt_dir: mnt_dir.clone(),
-        }
+    /// Get the path that the root of the file system is reached by.
+    fn root(&self) -> &FilePath {
+        self.backing.as_ref().map_or(&self.mnt_dir, |b| &b.path)
     }
 
-    #[allow(unused)]
-    pub fn device(&self) -> FilePath {
-        self.device.clone()
+    fn is_mounted_at(&self, path: &FilePath) -> bool {
+        self.root().trim_end_matches('/') == path.trim_end_matches('/')
     }
 
-    pub fn mnt_dir(&self) -> FilePath {
-        self.mnt_dir.clone()
+    /// Whether any file under the file system is open, or is the current
+    /// directory of a process.
+    fn is_busy(&self) -> bool {
+        let root = self.root().trim_end_matches('/');
+        processes()
+            .iter()
+            .filter_map(|process| process.data::<ProcessData>())
+            .any(|data| {
+                if is_under(&CURRENT_DIR_PATH.deref_from(&data.ns).lock(), root) {
+                    return true;
+                }
+                let table = FD_TABLE.deref_from(&data.ns).read();
+                table.ids().any(|id| {
+                    let file = table.get(id).unwrap().inner.clone().into_any();
+                    let path = if let Some(file) = file.downcast_ref::<File>() {
+                        file.path()
+                    } else if let Some(dir) = file.downcast_ref::<Directory>() {
+                        dir.path()
+                    } else {
+                        return false;
+                    };
+                    is_under(path, root)
+                })
+            })
     }
 }
 
@@ -105,39 +240,173 @@ impl MountedFs {
 /// Note that the startup file system is not in the vec, but in mod.rs
 static MOUNTED: Mutex<Vec<MountedFs>> = Mutex::new(Vec::new());
 
-/// Mount a fatfs device
-pub fn mount_fat_fs(device_path: &FilePath, mount_path: &FilePath) -> bool {
-    // device_path needs symlink lookup, but mount_path does not
-    // only opened files will be added to the symlink table for now, so do not convert now
-    // debug!("mounting {} to {}", device_path.path(), mount_path.path());
-    // if let Some(true_device_path) = real_path(device_path) {
-    if mount_path.exists() {
-        MOUNTED.lock().push(MountedFs::new(device_path, mount_path));
-        info!(
-            "mounted {} to {}",
-            device_path.as_str(),
-            mount_path.as_str()
-        );
-        return true;
+/// Create a hidden directory in `/tmp` to hold the contents of a mount.
+fn create_backing_dir() -> LinuxResult<Arc<MountRoot>> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = FilePath::new(format!("/tmp/{}mnt{}/", TMPFILE_PREFIX, id))?;
+        if !path.exists() {
+            axfs::api::create_dir(path.as_str())?;
+            return Ok(Arc::new(MountRoot { path, owned: true }));
+        }
     }
-    info!(
-        "mount failed: {} to {}",
-        device_path.as_str(),
-        mount_path.as_str()
-    );
-    false
 }
 
-/// unmount a fatfs device
-pub fn umount_fat_fs(mount_path: &FilePath) -> bool {
-    let mut mounted = MOUNTED.lock();
-    let length_before_deletion = mounted.len();
-    mounted.retain(|m| m.mnt_dir() != *mount_path);
-    length_before_deletion > mounted.len()
+fn remove_dir_all(path: &str) -> LinuxResult {
+    for entry in axfs::api::read_dir(path)? {
+        let entry = entry?;
+        let child = format!("{}/{}", path.trim_end_matches('/'), entry.file_name());
+        if entry.file_type().is_dir() {
+            remove_dir_all(&child)?;
+        } else {
+            axfs::api::remove_file(&child)?;
+        }
+    }
+    axfs::api::remove_dir(path)?;
+    Ok(())
+}
+
+/// Redirect `path` to where it lives if it is under a mounted file system.
+pub fn resolve_mounts(mut path: FilePath) -> FilePath {
+    let mounted = MOUNTED.lock();
+    let backings = mounted
+        .iter()
+        .filter_map(|m| m.backing.as_ref())
+        .map(|b| b.path.trim_end_matches('/'))
+        .collect::<Vec<_>>();
+    // Mounts stacked on earlier ones are redirected in turn.
+    for m in mounted.iter() {
+        let Some(backing) = &m.backing else {
+            continue;
+        };
+        let mnt_dir = m.mnt_dir.trim_end_matches('/');
+        // The backing directories hidden beneath the mount point belong to
+        // the covered file system, e.g. those in `/tmp` when it is mounted.
+        if backings
+            .iter()
+            .any(|b| *b != mnt_dir && is_under(b, mnt_dir) && is_under(&path, b))
+        {
+            continue;
+        }
+        if let Some(rest) = path.strip_prefix(mnt_dir) {
+            if rest.is_empty() || rest.starts_with('/') {
+                let new_path = format!("{}{}", backing.path.trim_end_matches('/'), rest);
+                if let Ok(new_path) = FilePath::new(new_path) {
+                    path = new_path;
+                }
+            }
+        }
+    }
+    path
+}
+
+/// Map the real `path` back to the path it is reached by through the mounted
+/// file systems, undoing [`resolve_mounts`].
+///
+/// Paths under `/dev` are kept, as they are shared by all `devtmpfs` mounts.
+pub fn visible_path(path: &str) -> String {
+    let mut path = String::from(path);
+    for m in MOUNTED.lock().iter().rev() {
+        let Some(backing) = m.backing.as_ref().filter(|b| b.owned) else {
+            continue;
+        };
+        if let Some(rest) = path.strip_prefix(backing.path.trim_end_matches('/')) {
+            if rest.is_empty() || rest.starts_with('/') {
+                path = format!("{}{}", m.mnt_dir.trim_end_matches('/'), rest);
+            }
+        }
+    }
+    path
+}
+
+/// Return `EROFS` if `path` is on a read-only file system.
+pub fn check_writable(path: &FilePath) -> LinuxResult {
+    if fs_info(path).read_only {
+        return Err(LinuxError::EROFS);
+    }
+    Ok(())
 }
 
 /// check if a path is mounted
 pub fn check_mounted(path: &FilePath) -> bool {
     let mounted = MOUNTED.lock();
-    mounted.iter().any(|m| path.starts_with(&m.mnt_dir()))
+    mounted.iter().any(|m| path.starts_with(&m.mnt_dir))
+}
+
+/// Information about the file system a path lives on
+pub struct FsInfo {
+    /// The mount point of the file system
+    pub mnt_dir: String,
+    /// The magic number of the file system
+    pub magic: u32,
+    /// Whether the file system is mounted read-only
+    pub read_only: bool,
+}
+
+impl FsInfo {
+    /// A deterministic id of the mounted file system, derived from the mount
+    /// point so that different mounts never share the same id.
+    pub fn fsid(&self) -> [i32; 2] {
+        // FNV-1a
+        let hash = self.mnt_dir.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        [hash as i32, (hash >> 32) as i32]
+    }
+}
+
+/// The file systems mounted by axfs at startup
+const BUILTIN_MOUNTS: &[(&str, u32)] = &[
+    ("/dev", TMPFS_MAGIC),
+    ("/tmp", RAMFS_MAGIC),
+    ("/proc", PROC_SUPER_MAGIC),
+    ("/sys", SYSFS_MAGIC),
+];
+
+#[cfg(feature = "lwext4_rs")]
+const ROOT_FS_MAGIC: u32 = linux_raw_sys::general::EXT4_SUPER_MAGIC;
+#[cfg(not(feature = "lwext4_rs"))]
+const ROOT_FS_MAGIC: u32 = MSDOS_SUPER_MAGIC;
+
+fn is_under(path: &str, mnt_dir: &str) -> bool {
+    let mnt_dir = mnt_dir.trim_end_matches('/');
+    path.strip_prefix(mnt_dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Find the file system containing `path`.
+///
+/// The mount point with the longest matching prefix wins.
+pub fn fs_info(path: &FilePath) -> FsInfo {
+    let mut info = FsInfo {
+        mnt_dir: "/".into(),
+        magic: ROOT_FS_MAGIC,
+        read_only: false,
+    };
+    let mut matched = 0;
+    for (mnt_dir, magic) in BUILTIN_MOUNTS {
+        if is_under(path, mnt_dir) && mnt_dir.len() > matched {
+            matched = mnt_dir.len();
+            info = FsInfo {
+                mnt_dir: (*mnt_dir).into(),
+                magic: *magic,
+                read_only: false,
+            };
+        }
+    }
+    // Paths under mounted file systems have been redirected to their roots.
+    for m in MOUNTED.lock().iter() {
+        let root = m.root().trim_end_matches('/');
+        if is_under(path, root) && root.len() >= matched {
+            matched = root.len();
+            info = FsInfo {
+                mnt_dir: m.mnt_dir.trim_end_matches('/').into(),
+                magic: m.magic,
+                read_only: m.read_only,
+            };
+        }
+    }
+    info
 }
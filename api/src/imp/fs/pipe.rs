@@ -1,24 +1,38 @@
 use core::ffi::c_int;
 
-use axerrno::LinuxResult;
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use linux_raw_sys::general::{O_CLOEXEC, O_NONBLOCK};
 
 use crate::{
-    fd::{FileLike, Pipe, close_file_like},
+    fd::{FileLike, Pipe, add_file_like_from, close_file_like},
     ptr::UserPtr,
 };
 
 pub fn sys_pipe(fds: UserPtr<[c_int; 2]>) -> LinuxResult<isize> {
+    sys_pipe2(fds, 0)
+}
+
+/// Create a pipe, with `O_NONBLOCK` and `O_CLOEXEC` in `flags` applied to
+/// both ends.
+pub fn sys_pipe2(fds: UserPtr<[c_int; 2]>, flags: c_int) -> LinuxResult<isize> {
+    let flags = flags as u32;
+    if flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
     let fds = fds.get_as_mut()?;
+    let cloexec = flags & O_CLOEXEC != 0;
 
     let (read_end, write_end) = Pipe::new();
-    let read_fd = read_end.add_to_fd_table()?;
-    let write_fd = write_end
-        .add_to_fd_table()
+    read_end.set_nonblocking(flags & O_NONBLOCK != 0)?;
+    write_end.set_nonblocking(flags & O_NONBLOCK != 0)?;
+    let read_fd = add_file_like_from(Arc::new(read_end), 0, cloexec)?;
+    let write_fd = add_file_like_from(Arc::new(write_end), 0, cloexec)
         .inspect_err(|_| close_file_like(read_fd).unwrap())?;
 
     fds[0] = read_fd;
     fds[1] = write_fd;
 
-    info!("sys_pipe2 <= fds: {:?}", fds);
+    info!("sys_pipe2 <= fds: {:?}, flags: {:#x}", fds, flags);
     Ok(0)
 }
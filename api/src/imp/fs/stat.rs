@@ -2,18 +2,41 @@ use core::ffi::{c_char, c_int};
 
 use axerrno::{AxError, LinuxError, LinuxResult};
 use axfs::fops::OpenOptions;
-use linux_raw_sys::general::{AT_EMPTY_PATH, stat, statfs, statx};
+use linux_raw_sys::general::{
+    AT_EACCESS, AT_EMPTY_PATH, AT_FDCWD, AT_NO_AUTOMOUNT, AT_SYMLINK_NOFOLLOW, F_OK, O_RDONLY,
+    R_OK, S_IFDIR, S_IFLNK, S_IFMT, STATX_ATIME, STATX_BLOCKS, STATX_CTIME, STATX_GID, STATX_INO,
+    STATX_MODE, STATX_MTIME, STATX_NLINK, STATX_SIZE, STATX_TYPE, STATX_UID, W_OK, X_OK, stat,
+    statfs, statx,
+};
 
+use super::fs_info;
 use crate::{
     fd::{Directory, File, FileLike, Kstat, get_file_like},
-    path::handle_file_path,
+    imp::{sys_getegid, sys_geteuid, sys_getgid, sys_getuid},
+    path::{ATTR_MANAGER, FilePath, SYMLINK_MANAGER, handle_file_path, resolve_symlinks},
     ptr::{UserConstPtr, UserPtr, nullable},
 };
 
-fn stat_at_path(path: &str) -> LinuxResult<Kstat> {
+/// Get the metadata of the file at `path`.
+///
+/// If `no_follow` is set and the final component is a symbolic link, the
+/// metadata of the link itself is returned.
+pub(crate) fn stat_at_path(path: &FilePath, no_follow: bool) -> LinuxResult<Kstat> {
+    let path = resolve_symlinks(path, !no_follow)?;
+    if let Some(target) = SYMLINK_MANAGER.read_link(path.as_str()) {
+        let mut kstat = Kstat {
+            mode: S_IFLNK | 0o777,
+            size: target.len() as _,
+            ..Default::default()
+        };
+        ATTR_MANAGER.apply(&path, &mut kstat);
+        return Ok(kstat);
+    }
+
+    let path = path.as_str();
     let opts = OpenOptions::new().set_read(true);
     match axfs::fops::File::open(path, &opts) {
-        Ok(file) => File::new(file, path.into()).stat(),
+        Ok(file) => File::new(file, path.into(), O_RDONLY).stat(),
         Err(AxError::IsADirectory) => {
             let dir = axfs::fops::Directory::open_dir(path, &opts)?;
             Directory::new(dir, path.into()).stat()
@@ -29,7 +52,8 @@ pub fn sys_stat(path: UserConstPtr<c_char>, statbuf: UserPtr<stat>) -> LinuxResu
     let path = path.get_as_str()?;
     debug!("sys_stat <= path: {}", path);
 
-    *statbuf.get_as_mut()? = stat_at_path(path)?.into();
+    let path = handle_file_path(AT_FDCWD, path)?;
+    *statbuf.get_as_mut()? = stat_at_path(&path, false)?.into();
 
     Ok(0)
 }
@@ -50,8 +74,8 @@ pub fn sys_lstat(path: UserConstPtr<c_char>, statbuf: UserPtr<stat>) -> LinuxRes
     let path = path.get_as_str()?;
     debug!("sys_lstat <= path: {}", path);
 
-    // TODO: symlink
-    *statbuf.get_as_mut()? = unsafe { core::mem::zeroed() };
+    let path = handle_file_path(AT_FDCWD, path)?;
+    *statbuf.get_as_mut()? = stat_at_path(&path, true)?.into();
 
     Ok(0)
 }
@@ -68,6 +92,10 @@ pub fn sys_fstatat(
         dirfd, path, flags
     );
 
+    if flags & !(AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW | AT_NO_AUTOMOUNT) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+
     *statbuf.get_as_mut()? = if path.is_none_or(|s| s.is_empty()) {
         if (flags & AT_EMPTY_PATH) == 0 {
             return Err(LinuxError::ENOENT);
@@ -76,7 +104,7 @@ pub fn sys_fstatat(
         f.stat()?.into()
     } else {
         let path = handle_file_path(dirfd, path.unwrap_or_default())?;
-        stat_at_path(path.as_str())?.into()
+        stat_at_path(&path, (flags & AT_SYMLINK_NOFOLLOW) != 0)?.into()
     };
 
     Ok(0)
@@ -86,7 +114,7 @@ pub fn sys_statx(
     dirfd: c_int,
     path: UserConstPtr<c_char>,
     flags: u32,
-    _mask: u32,
+    mask: u32,
     statxbuf: UserPtr<statx>,
 ) -> LinuxResult<isize> {
     // `statx()` uses pathname, dirfd, and flags to identify the target
@@ -118,39 +146,179 @@ pub fn sys_statx(
 
     let path = nullable!(path.get_as_str())?;
     debug!(
-        "sys_statx <= dirfd: {}, path: {:?}, flags: {}",
-        dirfd, path, flags
+        "sys_statx <= dirfd: {}, path: {:?}, flags: {}, mask: {:#x}",
+        dirfd, path, flags, mask
     );
 
-    *statxbuf.get_as_mut()? = if path.is_none_or(|s| s.is_empty()) {
+    let kstat = if path.is_none_or(|s| s.is_empty()) {
         if (flags & AT_EMPTY_PATH) == 0 {
             return Err(LinuxError::ENOENT);
         }
         let f = get_file_like(dirfd)?;
-        f.stat()?.into()
+        f.stat()?
     } else {
         let path = handle_file_path(dirfd, path.unwrap_or_default())?;
-        stat_at_path(path.as_str())?.into()
+        stat_at_path(&path, (flags & AT_SYMLINK_NOFOLLOW) != 0)?
     };
+    *statxbuf.get_as_mut()? = statx_with_mask(kstat, mask);
 
     Ok(0)
 }
 
+/// Convert `kstat` into a `statx`, only filling the fields requested by
+/// `mask`. `stx_mask` is set to the fields actually filled.
+fn statx_with_mask(kstat: Kstat, mask: u32) -> statx {
+    let full: statx = kstat.into();
+    let mask = mask & full.stx_mask;
+
+    // SAFETY: valid for statx
+    let mut statx: statx = unsafe { core::mem::zeroed() };
+    statx.stx_mask = mask;
+    statx.stx_blksize = full.stx_blksize;
+    if mask & STATX_TYPE != 0 {
+        statx.stx_mode |= full.stx_mode & S_IFMT as u16;
+    }
+    if mask & STATX_MODE != 0 {
+        statx.stx_mode |= full.stx_mode & !(S_IFMT as u16);
+    }
+    if mask & STATX_NLINK != 0 {
+        statx.stx_nlink = full.stx_nlink;
+    }
+    if mask & STATX_UID != 0 {
+        statx.stx_uid = full.stx_uid;
+    }
+    if mask & STATX_GID != 0 {
+        statx.stx_gid = full.stx_gid;
+    }
+    if mask & STATX_ATIME != 0 {
+        statx.stx_atime = full.stx_atime;
+    }
+    if mask & STATX_MTIME != 0 {
+        statx.stx_mtime = full.stx_mtime;
+    }
+    if mask & STATX_CTIME != 0 {
+        statx.stx_ctime = full.stx_ctime;
+    }
+    if mask & STATX_INO != 0 {
+        statx.stx_ino = full.stx_ino;
+    }
+    if mask & STATX_SIZE != 0 {
+        statx.stx_size = full.stx_size;
+    }
+    if mask & STATX_BLOCKS != 0 {
+        statx.stx_blocks = full.stx_blocks;
+    }
+
+    statx
+}
+
+/// The `f_flags` bit of a file system mounted read-only.
+const ST_RDONLY: u32 = 1;
+
+/// Get the statistics of the file system containing `path`.
+fn statfs_at_path(path: &FilePath) -> LinuxResult<statfs> {
+    let info = fs_info(path);
+
+    let mut statfs: statfs = unsafe { core::mem::zeroed() };
+    statfs.f_type = info.magic as _;
+    statfs.f_fsid.val = info.fsid();
+    statfs.f_namelen = 255;
+    statfs.f_bsize = 4096;
+    statfs.f_frsize = 4096;
+    // axfs does not expose the capacity of the underlying file systems, so
+    // the block and inode counts are left 0 as unknown.
+    if info.read_only {
+        statfs.f_flags = ST_RDONLY as _;
+    }
+
+    Ok(statfs)
+}
+
 pub fn sys_statfs(path: UserConstPtr<c_char>, statfsbuf: UserPtr<statfs>) -> LinuxResult<isize> {
     let path = path.get_as_str()?;
     debug!("sys_statfs <= path: {:?}", path);
 
-    let mut statfs: statfs = unsafe { core::mem::zeroed() };
-    // TODO: get real statfs
-    statfs.f_bsize = 4096;
-    statfs.f_blocks = 1024;
-    statfs.f_bfree = 512;
-    statfs.f_bavail = 256;
-    statfs.f_files = 1024;
-    statfs.f_ffree = 512;
-    statfs.f_namelen = 255;
+    let path = resolve_symlinks(&handle_file_path(AT_FDCWD, path)?, true)?;
+    if !path.exists() {
+        return Err(LinuxError::ENOENT);
+    }
+    *statfsbuf.get_as_mut()? = statfs_at_path(&path)?;
 
-    *statfsbuf.get_as_mut()? = statfs;
+    Ok(0)
+}
+
+pub fn sys_fstatfs(fd: i32, statfsbuf: UserPtr<statfs>) -> LinuxResult<isize> {
+    debug!("sys_fstatfs <= fd: {}", fd);
+
+    let path = handle_file_path(fd, "")?;
+    *statfsbuf.get_as_mut()? = statfs_at_path(&path)?;
+
+    Ok(0)
+}
+
+/// Check whether the calling process can access the file at `path`.
+///
+/// `mode` is either `F_OK` or a mask of `R_OK`, `W_OK` and `X_OK`. The check
+/// uses the real user and group ids, unless `AT_EACCESS` is given.
+pub fn sys_faccessat2(
+    dirfd: c_int,
+    path: UserConstPtr<c_char>,
+    mode: u32,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let path = nullable!(path.get_as_str())?;
+    debug!(
+        "sys_faccessat2 <= dirfd: {}, path: {:?}, mode: {}, flags: {}",
+        dirfd, path, mode, flags
+    );
+
+    if flags & !(AT_EACCESS | AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0
+        || mode & !(R_OK | W_OK | X_OK) != 0
+    {
+        return Err(LinuxError::EINVAL);
+    }
 
+    let kstat = if path.is_none_or(|s| s.is_empty()) {
+        if (flags & AT_EMPTY_PATH) == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+        get_file_like(dirfd)?.stat()?
+    } else {
+        let path = handle_file_path(dirfd, path.unwrap_or_default())?;
+        stat_at_path(&path, (flags & AT_SYMLINK_NOFOLLOW) != 0)?
+    };
+    if mode == F_OK {
+        return Ok(0);
+    }
+
+    let (uid, gid) = if flags & AT_EACCESS != 0 {
+        (sys_geteuid()?, sys_getegid()?)
+    } else {
+        (sys_getuid()?, sys_getgid()?)
+    };
+    let granted = if uid == 0 {
+        // root may read and write anything, and execute anything that is
+        // executable by someone
+        let exec = kstat.mode & S_IFMT == S_IFDIR || kstat.mode & 0o111 != 0;
+        R_OK | W_OK | if exec { X_OK } else { 0 }
+    } else if kstat.uid as isize == uid {
+        (kstat.mode >> 6) & 0o7
+    } else if kstat.gid as isize == gid {
+        (kstat.mode >> 3) & 0o7
+    } else {
+        kstat.mode & 0o7
+    };
+
+    if mode & !granted != 0 {
+        return Err(LinuxError::EACCES);
+    }
     Ok(0)
 }
+
+pub fn sys_faccessat(dirfd: c_int, path: UserConstPtr<c_char>, mode: u32) -> LinuxResult<isize> {
+    sys_faccessat2(dirfd, path, mode, 0)
+}
+
+pub fn sys_access(path: UserConstPtr<c_char>, mode: u32) -> LinuxResult<isize> {
+    sys_faccessat2(AT_FDCWD, path, mode, 0)
+}
@@ -0,0 +1,91 @@
+use core::ffi::c_int;
+
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::{TimeValue, monotonic_time, wall_time};
+use linux_raw_sys::general::{
+    __kernel_clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME, TFD_CLOEXEC, TFD_NONBLOCK,
+    TFD_TIMER_ABSTIME, itimerspec, timespec,
+};
+
+use crate::{
+    fd::{FileLike, TimerFd, add_file_like_from},
+    ptr::{UserConstPtr, UserPtr, nullable},
+    time::{timespec_to_timevalue, timevalue_to_timespec},
+};
+
+fn check_timespec(ts: &timespec) -> LinuxResult<TimeValue> {
+    if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(timespec_to_timevalue(*ts))
+}
+
+fn to_itimerspec((value, interval): (TimeValue, TimeValue)) -> itimerspec {
+    itimerspec {
+        it_interval: timevalue_to_timespec(interval),
+        it_value: timevalue_to_timespec(value),
+    }
+}
+
+/// Create a disarmed timerfd measured by the clock `clockid`.
+pub fn sys_timerfd_create(clockid: __kernel_clockid_t, flags: c_int) -> LinuxResult<isize> {
+    debug!(
+        "sys_timerfd_create <= clockid: {}, flags: {:#x}",
+        clockid, flags
+    );
+    let clock: fn() -> TimeValue = match clockid as u32 {
+        CLOCK_REALTIME => wall_time,
+        CLOCK_MONOTONIC => monotonic_time,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let flags = flags as u32;
+    if flags & !(TFD_CLOEXEC | TFD_NONBLOCK) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let timerfd = TimerFd::new(clock, flags & TFD_NONBLOCK != 0);
+    Ok(add_file_like_from(Arc::new(timerfd), 0, flags & TFD_CLOEXEC != 0)? as _)
+}
+
+/// Arm or disarm the timerfd `fd`, storing the previous setting to `old`.
+///
+/// The initial expiration in `new` is absolute if `TFD_TIMER_ABSTIME` is
+/// set, otherwise relative to the current time. A zero value disarms it.
+pub fn sys_timerfd_settime(
+    fd: c_int,
+    flags: c_int,
+    new: UserConstPtr<itimerspec>,
+    old: UserPtr<itimerspec>,
+) -> LinuxResult<isize> {
+    debug!("sys_timerfd_settime <= fd: {}, flags: {:#x}", fd, flags);
+    let flags = flags as u32;
+    if flags & !TFD_TIMER_ABSTIME != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let timerfd = TimerFd::from_fd(fd)?;
+    let new = new.get_as_ref()?;
+    let value = check_timespec(&new.it_value)?;
+    let interval = check_timespec(&new.it_interval)?;
+
+    let deadline = if value.is_zero() {
+        None
+    } else if flags & TFD_TIMER_ABSTIME != 0 {
+        Some(value)
+    } else {
+        Some(timerfd.now() + value)
+    };
+    // Check the buffer of the old setting before the timer is changed.
+    let old = nullable!(old.get_as_mut())?;
+    let prev = timerfd.set_time(deadline, interval);
+    if let Some(old) = old {
+        *old = to_itimerspec(prev);
+    }
+    Ok(0)
+}
+
+/// Get the time until the next expiration and the interval of the timerfd.
+pub fn sys_timerfd_gettime(fd: c_int, curr: UserPtr<itimerspec>) -> LinuxResult<isize> {
+    let timerfd = TimerFd::from_fd(fd)?;
+    *curr.get_as_mut()? = to_itimerspec(timerfd.get_time());
+    Ok(0)
+}
@@ -1,14 +1,68 @@
 use axerrno::LinuxResult;
+use axhal::paging::MappingFlags;
 use axtask::{TaskExtRef, current};
+use memory_addr::{VirtAddr, VirtAddrRange};
 
+use super::{add_vma, is_locked, lock_new_mapping, remove_cow_pages, remove_vmas};
+
+/// Set the program break to `addr`, returning the new program break.
+///
+/// The pages between the initial and the current program break are mapped
+/// on demand. If `addr` is below the initial program break or the heap
+/// cannot grow to it, the program break is left unchanged.
 pub fn sys_brk(addr: usize) -> LinuxResult<isize> {
     let task = current();
     let process_data = task.task_ext().process_data();
-    let mut return_val: isize = process_data.get_heap_top() as isize;
-    let heap_bottom = process_data.get_heap_bottom() as usize;
-    if addr != 0 && addr >= heap_bottom && addr <= heap_bottom + axconfig::plat::USER_HEAP_SIZE {
-        process_data.set_heap_top(addr);
-        return_val = addr as isize;
+    let heap_bottom = process_data.get_heap_bottom();
+    let heap_top = process_data.get_heap_top();
+    if addr < heap_bottom {
+        return Ok(heap_top as _);
+    }
+    let Some(new_end) = addr.checked_next_multiple_of(memory_addr::PAGE_SIZE_4K) else {
+        return Ok(heap_top as _);
+    };
+    let old_end = VirtAddr::from(memory_addr::align_up_4k(heap_top));
+    let new_end = VirtAddr::from(new_end);
+
+    let mut aspace = process_data.aspace.lock();
+    if new_end > old_end {
+        let size = new_end - old_end;
+        let is_free = new_end <= aspace.end()
+            && aspace.find_free_area(old_end, size, VirtAddrRange::new(old_end, new_end))
+                == Some(old_end);
+        if !is_free
+            || aspace
+                .map_alloc(
+                    old_end,
+                    size,
+                    MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
+                    false,
+                )
+                .is_err()
+        {
+            return Ok(heap_top as _);
+        }
+        let heap_start = VirtAddr::from(memory_addr::align_up_4k(heap_bottom));
+        let locked = is_locked(&process_data.aspace, heap_start);
+        add_vma(
+            &process_data.aspace,
+            heap_start,
+            new_end - heap_start,
+            MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
+        );
+        lock_new_mapping(
+            &process_data.aspace,
+            &mut aspace,
+            heap_start,
+            new_end - heap_start,
+            locked,
+        )?;
+    } else if new_end < old_end {
+        remove_cow_pages(&process_data.aspace, new_end, old_end - new_end);
+        remove_vmas(&process_data.aspace, new_end, old_end - new_end);
+        aspace.unmap(new_end, old_end - new_end)?;
+        axhal::arch::flush_tlb(None);
     }
-    Ok(return_val)
+    process_data.set_heap_top(addr);
+    Ok(addr as _)
 }
@@ -0,0 +1,302 @@
+use core::alloc::Layout;
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use axerrno::LinuxResult;
+use axhal::{
+    mem::{phys_to_virt, virt_to_phys},
+    paging::MappingFlags,
+};
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr, VirtAddrRange};
+
+use super::{file_page_at, find_vma, is_shm_attached, vmas};
+
+const PAGE_LAYOUT: Layout = match Layout::from_size_align(PAGE_SIZE_4K, PAGE_SIZE_4K) {
+    Ok(layout) => layout,
+    Err(_) => panic!(),
+};
+
+/// The pages of a user address space mapped to copy-on-write frames.
+struct CowPages {
+    aspace: Weak<Mutex<AddrSpace>>,
+    /// The frames mapped by the address space, keyed by the page addresses.
+    frames: BTreeMap<VirtAddr, PhysAddr>,
+}
+
+/// The frames shared copy-on-write by the private mappings of address
+/// spaces copied by `fork`.
+///
+/// The frames of axmm cannot be shared by its allocated mappings, so the
+/// shared frames are owned here and mapped linearly. A frame is mapped
+/// read-only while it is shared, and a write to it copies it for the writer,
+/// unless the writer is the last one mapping it, which takes it over.
+struct CowTable {
+    spaces: Vec<CowPages>,
+    /// The number of pages mapping each frame.
+    refs: BTreeMap<PhysAddr, usize>,
+}
+
+impl CowTable {
+    /// Get the index of the pages of `aspace` in `spaces`, dropping those of
+    /// the address spaces that are gone.
+    fn position(&mut self, aspace: &Arc<Mutex<AddrSpace>>) -> usize {
+        let mut dead = Vec::new();
+        self.spaces.retain_mut(|s| {
+            if s.aspace.strong_count() > 0 {
+                return true;
+            }
+            dead.extend(core::mem::take(&mut s.frames).into_values());
+            false
+        });
+        for frame in dead {
+            release(&mut self.refs, frame);
+        }
+        let weak = Arc::downgrade(aspace);
+        match self
+            .spaces
+            .iter()
+            .position(|s| Weak::ptr_eq(&s.aspace, &weak))
+        {
+            Some(pos) => pos,
+            None => {
+                self.spaces.push(CowPages {
+                    aspace: weak,
+                    frames: BTreeMap::new(),
+                });
+                self.spaces.len() - 1
+            }
+        }
+    }
+}
+
+static COW_TABLE: Mutex<CowTable> = Mutex::new(CowTable {
+    spaces: Vec::new(),
+    refs: BTreeMap::new(),
+});
+
+/// Allocate a frame holding a copy of the frame at `paddr`.
+fn copy_frame(paddr: PhysAddr) -> PhysAddr {
+    // SAFETY: the layout has a non-zero size.
+    let ptr = unsafe { alloc::alloc::alloc(PAGE_LAYOUT) };
+    if ptr.is_null() {
+        alloc::alloc::handle_alloc_error(PAGE_LAYOUT);
+    }
+    // SAFETY: both frames are valid for a page and do not overlap.
+    unsafe {
+        core::ptr::copy_nonoverlapping(phys_to_virt(paddr).as_ptr(), ptr, PAGE_SIZE_4K);
+    }
+    virt_to_phys(VirtAddr::from(ptr as usize))
+}
+
+/// Drop one reference to `frame`, freeing it if there are no more.
+fn release(refs: &mut BTreeMap<PhysAddr, usize>, frame: PhysAddr) {
+    let Some(count) = refs.get_mut(&frame) else {
+        return;
+    };
+    *count -= 1;
+    if *count == 0 {
+        refs.remove(&frame);
+        // SAFETY: the frame is allocated with the same layout in `copy_frame`.
+        unsafe { alloc::alloc::dealloc(phys_to_virt(frame).as_mut_ptr(), PAGE_LAYOUT) };
+    }
+}
+
+/// Map the copy-on-write page at `page` of `aspace` writable with `flags`,
+/// copying its frame first if it is shared.
+fn unshare_page(
+    refs: &mut BTreeMap<PhysAddr, usize>,
+    frames: &mut BTreeMap<VirtAddr, PhysAddr>,
+    aspace: &mut AddrSpace,
+    page: VirtAddr,
+    flags: MappingFlags,
+) -> LinuxResult {
+    let frame = frames[&page];
+    if refs.get(&frame).is_some_and(|&count| count > 1) {
+        let copy = copy_frame(frame);
+        release(refs, frame);
+        frames.insert(page, copy);
+        refs.insert(copy, 1);
+        aspace.unmap(page, PAGE_SIZE_4K)?;
+        aspace.map_linear(page, copy, PAGE_SIZE_4K, flags)?;
+    } else {
+        aspace.protect(page, PAGE_SIZE_4K, flags)?;
+    }
+    axhal::arch::flush_tlb(Some(page));
+    Ok(())
+}
+
+/// Move the populated private pages of `aspace` to copy-on-write frames
+/// mapped read-only, so that the address space can be copied by `fork`
+/// without copying them.
+///
+/// The shared file pages and the System V shared memory are shared by the
+/// copy anyway, and the pages not populated yet are populated on demand by
+/// both. This must be called with `aspace` locked as `aspace_ref`, before
+/// [`fork_cow_pages`].
+pub fn share_cow_pages(aspace_ref: &Arc<Mutex<AddrSpace>>, aspace: &mut AddrSpace) -> LinuxResult {
+    let private = vmas(aspace_ref)
+        .into_iter()
+        .filter(|vma| {
+            !is_shm_attached(aspace_ref, vma.range.start, vma.range.size())
+                && !file_page_at(aspace_ref, vma.range.start).is_some_and(|(.., shared)| shared)
+        })
+        .collect::<Vec<_>>();
+
+    let mut table = COW_TABLE.lock();
+    let pos = table.position(aspace_ref);
+    let CowTable { spaces, refs } = &mut *table;
+    let frames = &mut spaces[pos].frames;
+    for vma in private {
+        let flags = vma.flags - MappingFlags::WRITE;
+        for page in (vma.range.start.as_usize()..vma.range.end.as_usize()).step_by(PAGE_SIZE_4K) {
+            let page = VirtAddr::from(page);
+            if frames.contains_key(&page) {
+                aspace.protect(page, PAGE_SIZE_4K, flags)?;
+                continue;
+            }
+            let Ok((paddr, ..)) = aspace.page_table().query(page) else {
+                continue;
+            };
+            let frame = copy_frame(paddr);
+            frames.insert(page, frame);
+            refs.insert(frame, 1);
+            aspace.unmap(page, PAGE_SIZE_4K)?;
+            aspace.map_linear(page, frame, PAGE_SIZE_4K, flags)?;
+        }
+    }
+    axhal::arch::flush_tlb(None);
+    Ok(())
+}
+
+/// Share the copy-on-write frames of `parent` with its copy `child`, which
+/// maps them as the parent does.
+pub fn fork_cow_pages(parent: &Arc<Mutex<AddrSpace>>, child: &Arc<Mutex<AddrSpace>>) {
+    let mut table = COW_TABLE.lock();
+    let pos = table.position(parent);
+    let frames = table.spaces[pos].frames.clone();
+    for frame in frames.values() {
+        *table.refs.entry(*frame).or_default() += 1;
+    }
+    let pos = table.position(child);
+    table.spaces[pos].frames = frames;
+}
+
+/// Forget the copy-on-write pages in the `len` bytes from `start` of
+/// `aspace`, releasing their frames.
+///
+/// This must be called before the range is unmapped.
+pub fn remove_cow_pages(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) {
+    let mut table = COW_TABLE.lock();
+    let pos = table.position(aspace);
+    let CowTable { spaces, refs } = &mut *table;
+    let frames = &mut spaces[pos].frames;
+    let pages = frames
+        .range(start..start + len)
+        .map(|(&page, _)| page)
+        .collect::<Vec<_>>();
+    for page in pages {
+        release(refs, frames.remove(&page).unwrap());
+    }
+}
+
+/// Forget all the copy-on-write pages of `aspace`, whose user mappings are
+/// being removed.
+pub fn clear_cow_pages(aspace: &Arc<Mutex<AddrSpace>>) {
+    let mut table = COW_TABLE.lock();
+    let pos = table.position(aspace);
+    let space = table.spaces.swap_remove(pos);
+    for frame in space.frames.into_values() {
+        release(&mut table.refs, frame);
+    }
+}
+
+/// Check whether the page at `vaddr` of `aspace` is a copy-on-write page.
+pub fn is_cow_page(aspace: &Arc<Mutex<AddrSpace>>, vaddr: VirtAddr) -> bool {
+    let mut table = COW_TABLE.lock();
+    let pos = table.position(aspace);
+    table.spaces[pos]
+        .frames
+        .contains_key(&vaddr.align_down_4k())
+}
+
+/// Keep the shared copy-on-write pages in the `len` bytes from `start` of
+/// `aspace` read-only, after the range has been protected with `flags`.
+pub fn protect_cow_pages(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+    flags: MappingFlags,
+) -> LinuxResult {
+    if !flags.contains(MappingFlags::WRITE) {
+        return Ok(());
+    }
+    let mut table = COW_TABLE.lock();
+    let pos = table.position(aspace_ref);
+    let CowTable { spaces, refs } = &*table;
+    for (&page, frame) in spaces[pos].frames.range(start..start + len) {
+        if refs.get(frame).is_some_and(|&count| count > 1) {
+            aspace.protect(page, PAGE_SIZE_4K, flags - MappingFlags::WRITE)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the copy-on-write pages in the `len` bytes from `start` of `aspace`
+/// that are about to be written, where the mappings permit it.
+///
+/// This must be called before writing to the pages, with `aspace` locked as
+/// `aspace_ref`.
+pub fn unshare_cow_pages(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+) {
+    let pages = {
+        let mut table = COW_TABLE.lock();
+        let pos = table.position(aspace_ref);
+        table.spaces[pos]
+            .frames
+            .range(start.align_down_4k()..start + len)
+            .map(|(&page, _)| page)
+            .collect::<Vec<_>>()
+    };
+    for page in pages {
+        let Some(vma) =
+            find_vma(aspace_ref, page).filter(|vma| vma.flags.contains(MappingFlags::WRITE))
+        else {
+            continue;
+        };
+        let mut table = COW_TABLE.lock();
+        let pos = table.position(aspace_ref);
+        let CowTable { spaces, refs } = &mut *table;
+        if let Err(e) = unshare_page(refs, &mut spaces[pos].frames, aspace, page, vma.flags) {
+            warn!("failed to copy the page at {:#x}: {:?}", page, e);
+        }
+    }
+}
+
+/// Handle a page fault at `vaddr` of `aspace` caused by writing to a
+/// copy-on-write page, returning whether the access is now allowed.
+pub fn handle_cow_fault(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    if !access_flags.contains(MappingFlags::WRITE) {
+        return false;
+    }
+    let mut aspace = aspace_ref.lock();
+    let page = vaddr.align_down_4k();
+    unshare_cow_pages(aspace_ref, &mut aspace, page, PAGE_SIZE_4K);
+    aspace.check_region_access(
+        VirtAddrRange::from_start_size(page, PAGE_SIZE_4K),
+        access_flags,
+    )
+}
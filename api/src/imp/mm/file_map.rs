@@ -0,0 +1,462 @@
+use alloc::{
+    collections::btree_set::BTreeSet,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+
+use crate::fd::File;
+
+/// A mapping of a file in a user address space.
+struct FileMapping {
+    aspace: Weak<Mutex<AddrSpace>>,
+    start: VirtAddr,
+    /// The number of pages of the mapping.
+    pages: usize,
+    file: Arc<File>,
+    /// The index of the file page mapped at `start`.
+    first_page: u64,
+    /// The number of pages within the end of file when the file was mapped,
+    /// accesses beyond which raise `SIGBUS`.
+    file_pages: usize,
+    /// Whether the mapping uses the shared pages of the file.
+    shared: bool,
+    /// The access permitted to the mapping.
+    flags: MappingFlags,
+    /// The indexes of the shared file pages written through the mapping
+    /// since they were last written back.
+    ///
+    /// The shared pages are mapped read-only until they are written, so that
+    /// the first write to them faults and is recorded here.
+    dirty: BTreeSet<u64>,
+}
+
+impl FileMapping {
+    fn end(&self) -> VirtAddr {
+        self.start + self.pages * PAGE_SIZE_4K
+    }
+
+    /// Get the range of the cached file pages used by the pages `range` of
+    /// the mapping.
+    fn cached_pages(&self, range: core::ops::Range<usize>) -> core::ops::Range<u64> {
+        let end = range.end.min(self.file_pages);
+        let start = range.start.min(end);
+        self.first_page + start as u64..self.first_page + end as u64
+    }
+
+    /// Get the address the file page `index` is mapped at.
+    fn page_addr(&self, index: u64) -> VirtAddr {
+        self.start + (index - self.first_page) as usize * PAGE_SIZE_4K
+    }
+
+    /// Get the range of the pages of the mapping in the `len` bytes from
+    /// `start`.
+    fn page_range(&self, start: VirtAddr, len: usize) -> core::ops::Range<usize> {
+        let first = (start.max(self.start) - self.start) / PAGE_SIZE_4K;
+        let last = ((start + len).min(self.end()) - self.start).div_ceil(PAGE_SIZE_4K);
+        first..last
+    }
+
+    fn overlaps(&self, aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) -> bool {
+        Weak::ptr_eq(&self.aspace, &Arc::downgrade(aspace))
+            && self.start < start + len
+            && start < self.end()
+    }
+
+    /// Write back the dirty pages among the shared pages used by the pages
+    /// `range`, and drop the reference to those shared pages.
+    fn release(&self, range: core::ops::Range<usize>) {
+        if self.shared {
+            let pages = self.cached_pages(range);
+            for &index in self.dirty.range(pages.clone()) {
+                if let Err(e) = self.file.sync_shared_page(index) {
+                    warn!(
+                        "failed to write back the pages of {}: {:?}",
+                        self.file.path(),
+                        e
+                    );
+                }
+            }
+            self.file.unmap_shared_pages(pages);
+        }
+    }
+
+    /// Get the part of the mapping from its page `offset` with `pages` pages.
+    fn slice(&self, offset: usize, pages: usize) -> Self {
+        let first_page = self.first_page + offset as u64;
+        Self {
+            aspace: self.aspace.clone(),
+            start: self.start + offset * PAGE_SIZE_4K,
+            pages,
+            file: self.file.clone(),
+            first_page,
+            file_pages: self.file_pages.saturating_sub(offset).min(pages),
+            shared: self.shared,
+            flags: self.flags,
+            dirty: self
+                .dirty
+                .range(first_page..first_page + pages as u64)
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Map the shared pages `pages` of the mapping read-only unless they are
+    /// dirty, so that writing to them faults.
+    fn write_protect(
+        &self,
+        aspace: &mut AddrSpace,
+        pages: impl IntoIterator<Item = u64>,
+    ) -> LinuxResult {
+        if !self.shared || !self.flags.contains(MappingFlags::WRITE) {
+            return Ok(());
+        }
+        for index in pages
+            .into_iter()
+            .filter(|index| !self.dirty.contains(index))
+        {
+            aspace.protect(
+                self.page_addr(index),
+                PAGE_SIZE_4K,
+                self.flags - MappingFlags::WRITE,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Map the pages of the mapping at `to` of `aspace` as they are mapped
+    /// at its start, so that the old pages can be unmapped.
+    ///
+    /// The shared pages are mapped again, keeping their references, and the
+    /// private pages are copied.
+    fn move_to(&self, aspace: &mut AddrSpace, to: VirtAddr) -> LinuxResult {
+        let file_length = self.file_pages * PAGE_SIZE_4K;
+        if self.shared {
+            for index in self.cached_pages(0..self.file_pages) {
+                let (paddr, ..) = aspace
+                    .page_table()
+                    .query(self.page_addr(index))
+                    .map_err(|_| LinuxError::EFAULT)?;
+                let flags = if self.dirty.contains(&index) {
+                    self.flags
+                } else {
+                    self.flags - MappingFlags::WRITE
+                };
+                let offset = self.page_addr(index) - self.start;
+                aspace.map_linear(to + offset, paddr, PAGE_SIZE_4K, flags)?;
+            }
+        } else if file_length > 0 {
+            let mut buf = vec![0u8; file_length];
+            aspace.read(self.start, &mut buf)?;
+            aspace.map_alloc(to, file_length, self.flags, true)?;
+            aspace.write(to, &buf)?;
+        }
+        if self.file_pages < self.pages {
+            aspace.map_alloc(
+                to + file_length,
+                (self.pages - self.file_pages) * PAGE_SIZE_4K,
+                MappingFlags::USER,
+                false,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+static FILE_MAPPINGS: Mutex<Vec<FileMapping>> = Mutex::new(Vec::new());
+
+/// Drop the mappings of exited processes, releasing their shared pages.
+fn collect_dead(mappings: &mut Vec<FileMapping>) {
+    mappings.retain(|m| {
+        if m.aspace.strong_count() > 0 {
+            return true;
+        }
+        m.release(0..m.pages);
+        false
+    });
+}
+
+/// Record that `pages` pages of `file` starting from the page `first_page`
+/// are mapped at `start` of `aspace` with `flags`, of which `file_pages` are
+/// within the end of file.
+///
+/// The shared pages of a `shared` mapping must already be mapped with
+/// [`File::map_shared_page`], and read-only, so that writes to them are
+/// caught.
+#[allow(clippy::too_many_arguments)]
+pub fn add_file_mapping(
+    aspace: &Arc<Mutex<AddrSpace>>,
+    start: VirtAddr,
+    pages: usize,
+    file: Arc<File>,
+    first_page: u64,
+    file_pages: usize,
+    shared: bool,
+    flags: MappingFlags,
+) {
+    let mut mappings = FILE_MAPPINGS.lock();
+    collect_dead(&mut mappings);
+    mappings.push(FileMapping {
+        aspace: Arc::downgrade(aspace),
+        start,
+        pages,
+        file,
+        first_page,
+        file_pages: file_pages.min(pages),
+        shared,
+        flags,
+        dirty: BTreeSet::new(),
+    });
+}
+
+/// Forget the file mappings in the `len` bytes from `start` of `aspace`,
+/// writing back and releasing the shared pages they use.
+///
+/// This must be called before the range is unmapped.
+pub fn remove_file_mappings(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) {
+    let mut mappings = FILE_MAPPINGS.lock();
+    collect_dead(&mut mappings);
+    let mut kept = Vec::with_capacity(mappings.len());
+    for m in mappings.drain(..) {
+        if !m.overlaps(aspace, start, len) {
+            kept.push(m);
+            continue;
+        }
+        let range = m.page_range(start, len);
+        m.release(range.clone());
+        if range.start > 0 {
+            kept.push(m.slice(0, range.start));
+        }
+        if range.end < m.pages {
+            kept.push(m.slice(range.end, m.pages - range.end));
+        }
+    }
+    *mappings = kept;
+}
+
+/// Change the access permitted to the file mappings in the `len` bytes from
+/// `start` of `aspace` to `flags`, after the range has been protected.
+///
+/// The shared pages not written yet are kept read-only.
+pub fn protect_file_mappings(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+    flags: MappingFlags,
+) -> LinuxResult {
+    let mut mappings = FILE_MAPPINGS.lock();
+    let mut kept = Vec::with_capacity(mappings.len());
+    let mut result = Ok(());
+    for m in mappings.drain(..) {
+        if !m.overlaps(aspace_ref, start, len) {
+            kept.push(m);
+            continue;
+        }
+        let range = m.page_range(start, len);
+        let mut middle = m.slice(range.start, range.end - range.start);
+        middle.flags = flags;
+        if result.is_ok() {
+            result = middle.write_protect(aspace, middle.cached_pages(0..middle.pages));
+        }
+        if range.start > 0 {
+            kept.push(m.slice(0, range.start));
+        }
+        kept.push(middle);
+        if range.end < m.pages {
+            kept.push(m.slice(range.end, m.pages - range.end));
+        }
+    }
+    *mappings = kept;
+    result
+}
+
+/// Move the file mappings in the `len` bytes from `start` of `aspace` to
+/// `new_start`, mapping their pages there.
+///
+/// The old range must then be unmapped without removing its file mappings,
+/// whose shared pages are kept by the new range.
+pub fn move_file_mappings(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+    new_start: VirtAddr,
+) -> LinuxResult {
+    let mut mappings = FILE_MAPPINGS.lock();
+    let mut kept = Vec::with_capacity(mappings.len());
+    let mut result = Ok(());
+    for m in mappings.drain(..) {
+        if !m.overlaps(aspace_ref, start, len) {
+            kept.push(m);
+            continue;
+        }
+        let range = m.page_range(start, len);
+        let mut middle = m.slice(range.start, range.end - range.start);
+        let to = new_start + (middle.start - start);
+        if result.is_ok() {
+            result = middle.move_to(aspace, to);
+        }
+        middle.start = to;
+        if range.start > 0 {
+            kept.push(m.slice(0, range.start));
+        }
+        kept.push(middle);
+        if range.end < m.pages {
+            kept.push(m.slice(range.end, m.pages - range.end));
+        }
+    }
+    *mappings = kept;
+    result
+}
+
+/// Get the file, the index of the file page and whether the mapping is
+/// shared for the file mapping page at `vaddr` of `aspace`.
+pub fn file_page_at(
+    aspace: &Arc<Mutex<AddrSpace>>,
+    vaddr: VirtAddr,
+) -> Option<(Arc<File>, u64, bool)> {
+    FILE_MAPPINGS
+        .lock()
+        .iter()
+        .find(|m| m.overlaps(aspace, vaddr, 1))
+        .map(|m| {
+            let index = m.first_page + ((vaddr - m.start) / PAGE_SIZE_4K) as u64;
+            (m.file.clone(), index, m.shared)
+        })
+}
+
+/// Duplicate the file mappings of `parent` to its copy `child`, which shares
+/// the shared pages with it.
+pub fn fork_file_mappings(parent: &Arc<Mutex<AddrSpace>>, child: &Arc<Mutex<AddrSpace>>) {
+    let mut mappings = FILE_MAPPINGS.lock();
+    collect_dead(&mut mappings);
+    let mut forked = Vec::new();
+    for m in mappings
+        .iter()
+        .filter(|m| Weak::ptr_eq(&m.aspace, &Arc::downgrade(parent)))
+    {
+        if m.shared {
+            m.file.share_shared_pages(m.cached_pages(0..m.pages));
+        }
+        let mut copy = m.slice(0, m.pages);
+        copy.aspace = Arc::downgrade(child);
+        forked.push(copy);
+    }
+    mappings.extend(forked);
+}
+
+/// Write the dirty shared pages in the `len` bytes from `start` of `aspace`
+/// back to their files if `write_back` is set, then read them again from the
+/// files if `invalidate` is set.
+pub fn sync_file_mappings(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    start: VirtAddr,
+    len: usize,
+    write_back: bool,
+    invalidate: bool,
+) -> LinuxResult {
+    let mut aspace = aspace_ref.lock();
+    let mut mappings = FILE_MAPPINGS.lock();
+    for m in mappings
+        .iter_mut()
+        .filter(|m| m.shared && m.overlaps(aspace_ref, start, len))
+    {
+        let pages = m.cached_pages(m.page_range(start, len));
+        if write_back {
+            for &index in m.dirty.range(pages.clone()) {
+                m.file.sync_shared_page(index)?;
+            }
+        }
+        if invalidate {
+            m.file.reload_shared_pages(pages.clone())?;
+        }
+        // Later writes fault again to be recorded.
+        if write_back || invalidate {
+            let cleaned = m.dirty.range(pages).copied().collect::<Vec<_>>();
+            for index in &cleaned {
+                m.dirty.remove(index);
+            }
+            m.write_protect(&mut aspace, cleaned)?;
+        }
+    }
+    axhal::arch::flush_tlb(None);
+    Ok(())
+}
+
+/// Record the writes to the shared file pages in the `len` bytes from
+/// `start` of `aspace`, mapping them writable where the mappings permit it.
+///
+/// This must be called before writing to the pages, with `aspace` locked as
+/// `aspace_ref`.
+pub fn dirty_file_pages(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+) {
+    let mut mappings = FILE_MAPPINGS.lock();
+    for m in mappings.iter_mut().filter(|m| {
+        m.shared && m.flags.contains(MappingFlags::WRITE) && m.overlaps(aspace_ref, start, len)
+    }) {
+        for index in m.cached_pages(m.page_range(start, len)) {
+            let addr = m.page_addr(index);
+            if !m.dirty.contains(&index) && aspace.protect(addr, PAGE_SIZE_4K, m.flags).is_ok() {
+                m.dirty.insert(index);
+                axhal::arch::flush_tlb(Some(addr));
+            }
+        }
+    }
+}
+
+/// Handle a page fault at `vaddr` of `aspace` caused by the first write to a
+/// shared file page, returning whether the access is now allowed.
+pub fn handle_file_page_fault(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    if !access_flags.contains(MappingFlags::WRITE) {
+        return false;
+    }
+    let mut aspace = aspace_ref.lock();
+    let page = VirtAddr::from(vaddr.as_usize() & !(PAGE_SIZE_4K - 1));
+    dirty_file_pages(aspace_ref, &mut aspace, page, PAGE_SIZE_4K);
+    aspace.check_region_access(
+        VirtAddrRange::from_start_size(page, PAGE_SIZE_4K),
+        access_flags,
+    )
+}
+
+/// Check whether `vaddr` of `aspace` is in a file mapping but beyond the end
+/// of the file, where accesses raise `SIGBUS`.
+pub fn is_beyond_eof(aspace: &Arc<Mutex<AddrSpace>>, vaddr: VirtAddr) -> bool {
+    FILE_MAPPINGS.lock().iter().any(|m| {
+        Weak::ptr_eq(&m.aspace, &Arc::downgrade(aspace))
+            && m.start + m.file_pages * PAGE_SIZE_4K <= vaddr
+            && vaddr < m.end()
+    })
+}
+
+/// Check whether any page in the `len` bytes from `start` of `aspace` is in
+/// a file mapping.
+pub fn is_file_mapped(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) -> bool {
+    FILE_MAPPINGS.lock().iter().any(|m| {
+        Weak::ptr_eq(&m.aspace, &Arc::downgrade(aspace)) && m.start < start + len && start < m.end()
+    })
+}
+
+/// Check whether `file` is mapped shared and writable by any process, so
+/// that it can still be written through the mappings.
+pub fn is_mapped_writable(file: &File) -> bool {
+    let mut mappings = FILE_MAPPINGS.lock();
+    collect_dead(&mut mappings);
+    mappings
+        .iter()
+        .any(|m| m.shared && m.flags.contains(MappingFlags::WRITE) && m.file.id() == file.id())
+}
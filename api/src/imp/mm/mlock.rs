@@ -0,0 +1,122 @@
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use axmm::AddrSpace;
+use axsync::Mutex;
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::general::{MCL_CURRENT, MCL_FUTURE, MCL_ONFAULT};
+use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+
+use super::{find_vma, is_mapped, lock_future_vmas, lock_vmas, vmas};
+
+/// Check whether the page at `vaddr` of `aspace` is locked.
+pub fn is_locked(aspace: &Arc<Mutex<AddrSpace>>, vaddr: VirtAddr) -> bool {
+    find_vma(aspace, vaddr).is_some_and(|vma| vma.locked)
+}
+
+/// Lock the new mapping of `len` bytes at `start` of `aspace`, which has
+/// been recorded, if `locked` is set or `MCL_FUTURE` is in effect.
+///
+/// `aspace` is locked as `aspace_ref`.
+pub fn lock_new_mapping(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+    locked: bool,
+) -> LinuxResult {
+    if locked {
+        lock_vmas(aspace_ref, start, len, true);
+    }
+    if is_locked(aspace_ref, start) {
+        aspace.populate_area(start, len)?;
+    }
+    Ok(())
+}
+
+/// Get the page range covering the `len` bytes from `addr` of `aspace`,
+/// which must all be mapped.
+fn locked_range(
+    aspace: &Arc<Mutex<AddrSpace>>,
+    addr: usize,
+    len: usize,
+) -> LinuxResult<VirtAddrRange> {
+    let start = memory_addr::align_down_4k(addr);
+    let end = addr
+        .checked_add(len)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(LinuxError::ENOMEM)?;
+    let range = VirtAddrRange::new(start.into(), end.into());
+    if !range.is_empty() && !is_mapped(aspace, range.start, range.size()) {
+        return Err(LinuxError::ENOMEM);
+    }
+    Ok(range)
+}
+
+/// Lock the pages in the `len` bytes from `addr` in memory.
+pub fn sys_mlock(addr: usize, len: usize) -> LinuxResult<isize> {
+    debug!("sys_mlock <= addr: {:#x}, len: {:#x}", addr, len);
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let range = locked_range(&process_data.aspace, addr, len)?;
+    if range.is_empty() {
+        return Ok(0);
+    }
+    process_data
+        .aspace
+        .lock()
+        .populate_area(range.start, range.size())?;
+    lock_vmas(&process_data.aspace, range.start, range.size(), true);
+    Ok(0)
+}
+
+/// Unlock the pages in the `len` bytes from `addr`.
+pub fn sys_munlock(addr: usize, len: usize) -> LinuxResult<isize> {
+    debug!("sys_munlock <= addr: {:#x}, len: {:#x}", addr, len);
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let range = locked_range(&process_data.aspace, addr, len)?;
+    lock_vmas(&process_data.aspace, range.start, range.size(), false);
+    Ok(0)
+}
+
+/// Lock all the current mappings if `MCL_CURRENT` is set, and the mappings
+/// created later if `MCL_FUTURE` is set.
+///
+/// The locked mappings are populated unless `MCL_ONFAULT` is set.
+pub fn sys_mlockall(flags: u32) -> LinuxResult<isize> {
+    debug!("sys_mlockall <= flags: {:#x}", flags);
+    if flags & !(MCL_CURRENT | MCL_FUTURE | MCL_ONFAULT) != 0
+        || flags & (MCL_CURRENT | MCL_FUTURE) == 0
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    if flags & MCL_CURRENT != 0 {
+        let mut aspace = process_data.aspace.lock();
+        for vma in vmas(&process_data.aspace) {
+            if flags & MCL_ONFAULT == 0 {
+                aspace.populate_area(vma.range.start, vma.range.size())?;
+            }
+            lock_vmas(
+                &process_data.aspace,
+                vma.range.start,
+                vma.range.size(),
+                true,
+            );
+        }
+    }
+    lock_future_vmas(&process_data.aspace, flags & MCL_FUTURE != 0);
+    Ok(0)
+}
+
+/// Unlock all the mappings and stop locking the mappings created later.
+pub fn sys_munlockall() -> LinuxResult<isize> {
+    debug!("sys_munlockall");
+    let aspace = &current().task_ext().process_data().aspace;
+    for vma in vmas(aspace) {
+        lock_vmas(aspace, vma.range.start, vma.range.size(), false);
+    }
+    lock_future_vmas(aspace, false);
+    Ok(0)
+}
@@ -1,14 +1,28 @@
-use alloc::vec;
+use alloc::{sync::Arc, vec};
 use axerrno::{LinuxError, LinuxResult};
 use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axsync::Mutex;
 use axtask::{TaskExtRef, current};
 use linux_raw_sys::general::{
-    MAP_ANONYMOUS, MAP_FIXED, MAP_NORESERVE, MAP_PRIVATE, MAP_SHARED, MAP_STACK, PROT_EXEC,
+    F_SEAL_FUTURE_WRITE, F_SEAL_WRITE, MADV_DONTNEED, MADV_FREE, MADV_NORMAL, MADV_RANDOM,
+    MADV_SEQUENTIAL, MADV_WILLNEED, MAP_ANONYMOUS, MAP_FIXED, MAP_FIXED_NOREPLACE, MAP_LOCKED,
+    MAP_NORESERVE, MAP_PRIVATE, MAP_SHARED, MAP_SHARED_VALIDATE, MAP_STACK, MAP_TYPE, MREMAP_FIXED,
+    MREMAP_MAYMOVE, MS_ASYNC, MS_INVALIDATE, MS_SYNC, O_ACCMODE, O_RDWR, O_WRONLY, PROT_EXEC,
     PROT_GROWSDOWN, PROT_GROWSUP, PROT_READ, PROT_WRITE,
 };
-use memory_addr::{VirtAddr, VirtAddrRange};
+use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 
-use crate::fd::{File, FileLike};
+use super::{
+    add_file_mapping, add_vma, clear_cow_pages, clear_vmas, detach_all_shm, file_page_at, find_vma,
+    is_file_mapped, is_mapped, is_shm_attached, lock_new_mapping, move_file_mappings,
+    protect_cow_pages, protect_file_mappings, protect_vmas, remove_cow_pages, remove_file_mappings,
+    remove_vmas, sync_file_mappings, unmap_shm_attachments, vmas,
+};
+use crate::{
+    fd::{File, FileLike},
+    ptr::UserPtr,
+};
 
 bitflags::bitflags! {
     /// `PROT_*` flags for use with [`sys_mmap`].
@@ -57,12 +71,16 @@ bitflags::bitflags! {
         const PRIVATE = MAP_PRIVATE;
         /// Map address must be exactly as requested, no matter whether it is available.
         const FIXED = MAP_FIXED;
+        /// Like `FIXED`, but fail if the address range is already mapped.
+        const FIXED_NOREPLACE = MAP_FIXED_NOREPLACE;
         /// Don't use a file.
         const ANONYMOUS = MAP_ANONYMOUS;
         /// Don't check for reservations.
         const NORESERVE = MAP_NORESERVE;
         /// Allocation is for a stack.
         const STACK = MAP_STACK;
+        /// Lock the pages of the mapping.
+        const LOCKED = MAP_LOCKED;
     }
 }
 
@@ -78,8 +96,6 @@ pub fn sys_mmap(
     let process_data = curr.task_ext().process_data();
     let mut aspace = process_data.aspace.lock();
     let permission_flags = MmapProt::from_bits_truncate(prot);
-    // TODO: check illegal flags for mmap
-    // An example is the flags contained none of MAP_PRIVATE, MAP_SHARED, or MAP_SHARED_VALIDATE.
     let map_flags = MmapFlags::from_bits_truncate(flags);
 
     info!(
@@ -87,19 +103,79 @@ pub fn sys_mmap(
         addr, length, permission_flags, map_flags, fd, offset
     );
 
+    // Exactly one of MAP_PRIVATE, MAP_SHARED and MAP_SHARED_VALIDATE is required.
+    if !matches!(
+        flags & MAP_TYPE,
+        MAP_PRIVATE | MAP_SHARED | MAP_SHARED_VALIDATE
+    ) {
+        return Err(LinuxError::EINVAL);
+    }
+    if length == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if map_flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE)
+        && !memory_addr::is_aligned_4k(addr)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
     let start = memory_addr::align_down_4k(addr);
-    let end = memory_addr::align_up_4k(addr + length);
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(LinuxError::ENOMEM)?;
     let aligned_length = end - start;
     debug!(
         "start: {:x?}, end: {:x?}, aligned_length: {:x?}",
         start, end, aligned_length
     );
 
-    let start_addr = if map_flags.contains(MmapFlags::FIXED) {
+    let shared = flags & MAP_TYPE != MAP_PRIVATE;
+    // Anonymous mappings ignore `fd` and are filled with zeros on demand.
+    let file = if map_flags.contains(MmapFlags::ANONYMOUS) {
+        None
+    } else {
+        if offset < 0 || !memory_addr::is_aligned_4k(offset as usize) {
+            return Err(LinuxError::EINVAL);
+        }
+        let file = File::from_fd(fd)?;
+        let access = file.status_flags() & O_ACCMODE;
+        if access == O_WRONLY {
+            return Err(LinuxError::EACCES);
+        }
+        if shared && permission_flags.contains(MmapProt::WRITE) {
+            if access != O_RDWR {
+                return Err(LinuxError::EACCES);
+            }
+            if file.get_seals().unwrap_or(0) & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0 {
+                return Err(LinuxError::EPERM);
+            }
+        }
+        Some(file)
+    };
+
+    let start_addr = if map_flags.contains(MmapFlags::FIXED_NOREPLACE) {
+        let dst_addr = VirtAddr::from(start);
+        if start < aspace.base().as_usize() || end > aspace.end().as_usize() {
+            return Err(LinuxError::EINVAL);
+        }
+        let is_free = aspace.find_free_area(
+            dst_addr,
+            aligned_length,
+            VirtAddrRange::from_start_size(dst_addr, aligned_length),
+        ) == Some(dst_addr);
+        if !is_free {
+            return Err(LinuxError::EEXIST);
+        }
+        dst_addr
+    } else if map_flags.contains(MmapFlags::FIXED) {
         if start == 0 {
             return Err(LinuxError::EINVAL);
         }
         let dst_addr = VirtAddr::from(start);
+        remove_file_mappings(&process_data.aspace, dst_addr, aligned_length);
+        unmap_shm_attachments(&process_data.aspace, dst_addr, aligned_length);
+        remove_cow_pages(&process_data.aspace, dst_addr, aligned_length);
         aspace.unmap(dst_addr, aligned_length)?;
         dst_addr
     } else {
@@ -117,46 +193,157 @@ pub fn sys_mmap(
             .ok_or(LinuxError::ENOMEM)?
     };
 
-    let populate = if fd == -1 {
-        false
-    } else {
-        !map_flags.contains(MmapFlags::ANONYMOUS)
-    };
-
-    aspace.map_alloc(
+    let flags = permission_flags.into();
+    match file {
+        Some(file) => {
+            let first_page = (offset as usize / PAGE_SIZE_4K) as u64;
+            let pages = aligned_length / PAGE_SIZE_4K;
+            let file_pages = map_file(
+                &mut aspace,
+                start_addr,
+                pages,
+                &file,
+                first_page,
+                shared,
+                flags,
+            )?;
+            add_file_mapping(
+                &process_data.aspace,
+                start_addr,
+                pages,
+                file,
+                first_page,
+                file_pages,
+                shared,
+                flags,
+            );
+        }
+        None => {
+            aspace.map_alloc(start_addr, aligned_length, flags, false)?;
+        }
+    }
+    add_vma(&process_data.aspace, start_addr, aligned_length, flags);
+    lock_new_mapping(
+        &process_data.aspace,
+        &mut aspace,
         start_addr,
         aligned_length,
-        permission_flags.into(),
-        populate,
+        map_flags.contains(MmapFlags::LOCKED),
     )?;
+    Ok(start_addr.as_usize() as _)
+}
 
-    if populate {
-        let file = File::from_fd(fd)?;
-        let file = file.inner();
-        let file_size = file.get_attr()?.size() as usize;
-        if offset < 0 || offset as usize >= file_size {
-            return Err(LinuxError::EINVAL);
-        }
-        let offset = offset as usize;
-        let length = core::cmp::min(length, file_size - offset);
+/// Map `pages` pages of `file` from the page `first_page` at `start` with
+/// `flags`, returning the number of them within the end of file.
+fn map_file(
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    pages: usize,
+    file: &File,
+    first_page: u64,
+    shared: bool,
+    flags: MappingFlags,
+) -> LinuxResult<usize> {
+    let file_size = file.inner().get_attr()?.size();
+    let file_pages = (file_size
+        .div_ceil(PAGE_SIZE_4K as u64)
+        .saturating_sub(first_page) as usize)
+        .min(pages);
+    let file_length = file_pages * PAGE_SIZE_4K;
+    let offset = first_page * PAGE_SIZE_4K as u64;
+
+    if shared {
+        // Writes fault until they are recorded by the mapping.
+        map_shared_pages(
+            aspace,
+            start,
+            file,
+            first_page,
+            file_pages,
+            flags - MappingFlags::WRITE,
+        )?;
+    } else if file_pages > 0 {
+        aspace.map_alloc(start, file_length, flags, true)?;
+        let length = (file_size.saturating_sub(offset) as usize).min(file_length);
         let mut buf = vec![0u8; length];
-        file.read_at(offset as u64, &mut buf)?;
-        aspace.write(start_addr, &buf)?;
+        file.read_at(offset, &mut buf)?;
+        aspace.write(start, &buf)?;
     }
-    Ok(start_addr.as_usize() as _)
+    // The pages beyond the end of file are inaccessible, so that accesses to
+    // them fault and raise `SIGBUS`.
+    if file_pages < pages {
+        aspace.map_alloc(
+            start + file_length,
+            (pages - file_pages) * PAGE_SIZE_4K,
+            MappingFlags::USER,
+            false,
+        )?;
+    }
+    Ok(file_pages)
+}
+
+/// Map `count` shared pages of `file` from the page `first_page` at `start`.
+fn map_shared_pages(
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    file: &File,
+    first_page: u64,
+    count: usize,
+    flags: MappingFlags,
+) -> LinuxResult {
+    let mut mapped = 0;
+    let result = (0..count).try_for_each(|i| {
+        let paddr = file.map_shared_page(first_page + i as u64)?;
+        mapped += 1;
+        aspace.map_linear(start + i * PAGE_SIZE_4K, paddr, PAGE_SIZE_4K, flags)?;
+        Ok(())
+    });
+    if result.is_err() {
+        // Release the pages mapped so far.
+        file.unmap_shared_pages(first_page..first_page + mapped);
+        aspace.unmap(start, count * PAGE_SIZE_4K)?;
+    }
+    result
 }
 
+/// Unmap the pages in the `length` bytes from `addr`.
+///
+/// The mappings partially covered by the range are shrunk or split, and
+/// the pages in the range that are not mapped are ignored.
 pub fn sys_munmap(addr: usize, length: usize) -> LinuxResult<isize> {
+    debug!("sys_munmap <= addr: {:#x}, length: {:#x}", addr, length);
+    if !memory_addr::is_aligned_4k(addr) || length == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(LinuxError::EINVAL)?;
+
     let curr = current();
     let process_data = curr.task_ext().process_data();
     let mut aspace = process_data.aspace.lock();
-    let length = memory_addr::align_up_4k(length);
-    let start_addr = VirtAddr::from(addr);
+    // Nothing is mapped outside the user address space.
+    let start_addr = VirtAddr::from(addr).max(aspace.base());
+    let end_addr = VirtAddr::from(end).min(aspace.end());
+    if start_addr >= end_addr {
+        return Ok(0);
+    }
+    let length = end_addr - start_addr;
+    remove_file_mappings(&process_data.aspace, start_addr, length);
+    unmap_shm_attachments(&process_data.aspace, start_addr, length);
+    remove_cow_pages(&process_data.aspace, start_addr, length);
+    remove_vmas(&process_data.aspace, start_addr, length);
     aspace.unmap(start_addr, length)?;
     axhal::arch::flush_tlb(None);
     Ok(0)
 }
 
+/// Change the protection of the pages in the `length` bytes from `addr`,
+/// which must all be mapped.
+///
+/// The mappings partially covered by the range are split, so that only the
+/// pages in the range are changed.
 pub fn sys_mprotect(addr: usize, length: usize, prot: u32) -> LinuxResult<isize> {
     // TODO: implement PROT_GROWSUP & PROT_GROWSDOWN
     let Some(permission_flags) = MmapProt::from_bits(prot) else {
@@ -165,13 +352,365 @@ pub fn sys_mprotect(addr: usize, length: usize, prot: u32) -> LinuxResult<isize>
     if permission_flags.contains(MmapProt::GROWDOWN | MmapProt::GROWSUP) {
         return Err(LinuxError::EINVAL);
     }
+    if !memory_addr::is_aligned_4k(addr) {
+        return Err(LinuxError::EINVAL);
+    }
+    if length == 0 {
+        return Ok(0);
+    }
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(LinuxError::ENOMEM)?;
 
     let curr = current();
     let process_data = curr.task_ext().process_data();
     let mut aspace = process_data.aspace.lock();
-    let length = memory_addr::align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
-    aspace.protect(start_addr, length, permission_flags.into())?;
+    let length = end - addr;
+    if !aspace.check_region_access(
+        VirtAddrRange::from_start_size(start_addr, length),
+        MappingFlags::empty(),
+    ) {
+        return Err(LinuxError::ENOMEM);
+    }
+    let flags = permission_flags.into();
+    aspace.protect(start_addr, length, flags)?;
+    protect_file_mappings(&process_data.aspace, &mut aspace, start_addr, length, flags)?;
+    protect_cow_pages(&process_data.aspace, &mut aspace, start_addr, length, flags)?;
+    protect_vmas(&process_data.aspace, start_addr, length, flags);
+    axhal::arch::flush_tlb(None);
 
     Ok(0)
 }
+
+/// Resize the mapping of `old_size` bytes at `old_addr` to `new_size` bytes,
+/// moving it if `MREMAP_MAYMOVE` is set and it cannot grow in place.
+///
+/// With `MREMAP_FIXED`, the mapping is moved to `new_addr`, replacing the
+/// mappings there. The old range must lie within a single mapping, or
+/// `EFAULT` is returned. A file mapping grown or moved keeps mapping the
+/// following pages of the file.
+pub fn sys_mremap(
+    old_addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_addr: usize,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_mremap <= old_addr: {:#x}, old_size: {:#x}, new_size: {:#x}, flags: {:#x}, new_addr: {:#x}",
+        old_addr, old_size, new_size, flags, new_addr
+    );
+    if flags & !(MREMAP_MAYMOVE | MREMAP_FIXED) != 0
+        || (flags & MREMAP_FIXED != 0 && flags & MREMAP_MAYMOVE == 0)
+        || !memory_addr::is_aligned_4k(old_addr)
+        || old_size == 0
+        || new_size == 0
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    let old_size = memory_addr::align_up_4k(old_size);
+    let new_size = memory_addr::align_up_4k(new_size);
+
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let aspace_ref = &process_data.aspace;
+    let mut aspace = aspace_ref.lock();
+    let old_start = VirtAddr::from(old_addr);
+    let vma = find_vma(aspace_ref, old_start)
+        .filter(|vma| {
+            old_addr
+                .checked_add(old_size)
+                .is_some_and(|end| end <= vma.range.end.as_usize())
+        })
+        .ok_or(LinuxError::EFAULT)?;
+    let map_flags = vma.flags;
+
+    if flags & MREMAP_FIXED != 0 {
+        if !memory_addr::is_aligned_4k(new_addr) {
+            return Err(LinuxError::EINVAL);
+        }
+        let new_start = VirtAddr::from(new_addr);
+        if new_start < old_start + old_size && old_start < new_start + new_size {
+            return Err(LinuxError::EINVAL);
+        }
+        if new_start < aspace.base()
+            || new_addr
+                .checked_add(new_size)
+                .is_none_or(|end| end > aspace.end().as_usize())
+        {
+            return Err(LinuxError::ENOMEM);
+        }
+    } else if new_size <= old_size {
+        // Shrink the mapping in place.
+        let tail = old_start + new_size;
+        remove_file_mappings(aspace_ref, tail, old_size - new_size);
+        unmap_shm_attachments(aspace_ref, tail, old_size - new_size);
+        remove_cow_pages(aspace_ref, tail, old_size - new_size);
+        remove_vmas(aspace_ref, tail, old_size - new_size);
+        aspace.unmap(tail, old_size - new_size)?;
+        axhal::arch::flush_tlb(None);
+        return Ok(old_addr as _);
+    }
+
+    let old_end = old_start + old_size;
+    if flags & MREMAP_FIXED == 0 {
+        let extra = new_size - old_size;
+        let can_grow = old_end
+            .as_usize()
+            .checked_add(extra)
+            .is_some_and(|end| end <= aspace.end().as_usize())
+            && aspace.find_free_area(
+                old_end,
+                extra,
+                VirtAddrRange::from_start_size(old_end, extra),
+            ) == Some(old_end);
+        if can_grow {
+            extend_mapping(aspace_ref, &mut aspace, old_end, extra, map_flags)?;
+            add_vma(aspace_ref, old_start, new_size, map_flags);
+            lock_new_mapping(aspace_ref, &mut aspace, old_start, new_size, vma.locked)?;
+            return Ok(old_addr as _);
+        }
+        if flags & MREMAP_MAYMOVE == 0 {
+            return Err(LinuxError::ENOMEM);
+        }
+    }
+
+    let new_start = if flags & MREMAP_FIXED != 0 {
+        let new_start = VirtAddr::from(new_addr);
+        remove_file_mappings(aspace_ref, new_start, new_size);
+        unmap_shm_attachments(aspace_ref, new_start, new_size);
+        remove_cow_pages(aspace_ref, new_start, new_size);
+        remove_vmas(aspace_ref, new_start, new_size);
+        aspace.unmap(new_start, new_size)?;
+        new_start
+    } else {
+        aspace
+            .find_free_area(
+                aspace.base(),
+                new_size,
+                VirtAddrRange::new(aspace.base(), aspace.end()),
+            )
+            .ok_or(LinuxError::ENOMEM)?
+    };
+
+    // Move the contents to the new mapping.
+    let moved = old_size.min(new_size);
+    if is_file_mapped(aspace_ref, old_start, old_size) {
+        move_file_mappings(aspace_ref, &mut aspace, old_start, moved, new_start)?;
+        remove_file_mappings(aspace_ref, old_start + moved, old_size - moved);
+    } else {
+        let mut buf = vec![0u8; moved];
+        aspace.populate_area(old_start, moved)?;
+        aspace.read(old_start, &mut buf)?;
+        aspace.map_alloc(new_start, moved, map_flags, true)?;
+        aspace.write(new_start, &buf)?;
+    }
+    unmap_shm_attachments(aspace_ref, old_start, old_size);
+    remove_cow_pages(aspace_ref, old_start, old_size);
+    aspace.unmap(old_start, old_size)?;
+    if new_size > old_size {
+        extend_mapping(
+            aspace_ref,
+            &mut aspace,
+            new_start + old_size,
+            new_size - old_size,
+            map_flags,
+        )?;
+    }
+    axhal::arch::flush_tlb(None);
+    remove_vmas(aspace_ref, old_start, old_size);
+    add_vma(aspace_ref, new_start, new_size, map_flags);
+    lock_new_mapping(aspace_ref, &mut aspace, new_start, new_size, vma.locked)?;
+    Ok(new_start.as_usize() as _)
+}
+
+/// Map `len` bytes at `start` of `aspace` with `flags`, continuing the
+/// mapping that ends there, which maps the following pages of its file if
+/// it is a file mapping.
+fn extend_mapping(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+    flags: MappingFlags,
+) -> LinuxResult {
+    let Some((file, last_page, shared)) = file_page_at(aspace_ref, start - PAGE_SIZE_4K) else {
+        aspace.map_alloc(start, len, flags, false)?;
+        return Ok(());
+    };
+    let pages = len / PAGE_SIZE_4K;
+    let file_pages = map_file(aspace, start, pages, &file, last_page + 1, shared, flags)?;
+    add_file_mapping(
+        aspace_ref,
+        start,
+        pages,
+        file,
+        last_page + 1,
+        file_pages,
+        shared,
+        flags,
+    );
+    Ok(())
+}
+
+/// Give advice about the use of the `length` bytes from `addr`.
+///
+/// `MADV_DONTNEED` drops the pages of anonymous mappings, which are filled
+/// with zeros on the next access. The other advice is accepted without
+/// effect, including `MADV_FREE`, as the pages are never reclaimed.
+pub fn sys_madvise(addr: usize, length: usize, advice: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_madvise <= addr: {:#x}, length: {:#x}, advice: {}",
+        addr, length, advice
+    );
+    if !matches!(
+        advice,
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_WILLNEED | MADV_DONTNEED | MADV_FREE
+    ) {
+        return Err(LinuxError::EINVAL);
+    }
+    if !memory_addr::is_aligned_4k(addr) {
+        return Err(LinuxError::EINVAL);
+    }
+    if length == 0 {
+        return Ok(0);
+    }
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(LinuxError::EINVAL)?;
+
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let mut aspace = process_data.aspace.lock();
+    let start = VirtAddr::from(addr);
+    let length = end - addr;
+    if !aspace.check_region_access(
+        VirtAddrRange::from_start_size(start, length),
+        MappingFlags::empty(),
+    ) {
+        return Err(LinuxError::ENOMEM);
+    }
+    if advice != MADV_DONTNEED {
+        return Ok(0);
+    }
+
+    // Remap the part of each private anonymous mapping in the range lazily,
+    // while the pages of files and System V shared memory are kept.
+    let end = start + length;
+    for vma in vmas(&process_data.aspace)
+        .into_iter()
+        .filter(|vma| vma.range.start < end && start < vma.range.end)
+    {
+        let run_start = vma.range.start.max(start);
+        let run_len = vma.range.end.min(end) - run_start;
+        if !is_file_mapped(&process_data.aspace, run_start, run_len)
+            && !is_shm_attached(&process_data.aspace, run_start, run_len)
+        {
+            remove_cow_pages(&process_data.aspace, run_start, run_len);
+            aspace.unmap(run_start, run_len)?;
+            aspace.map_alloc(run_start, run_len, vma.flags, false)?;
+        }
+    }
+    axhal::arch::flush_tlb(None);
+    Ok(0)
+}
+
+/// Write the changes to the shared file mappings in the `length` bytes from
+/// `addr` back to the files.
+///
+/// The changes are written back at once for both `MS_SYNC` and `MS_ASYNC`.
+/// With `MS_INVALIDATE`, the pages are then read again from the files.
+pub fn sys_msync(addr: usize, length: usize, flags: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_msync <= addr: {:#x}, length: {:#x}, flags: {:#x}",
+        addr, length, flags
+    );
+    if flags & !(MS_SYNC | MS_ASYNC | MS_INVALIDATE) != 0
+        || flags & (MS_SYNC | MS_ASYNC) == MS_SYNC | MS_ASYNC
+        || !memory_addr::is_aligned_4k(addr)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    if length == 0 {
+        return Ok(0);
+    }
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(LinuxError::ENOMEM)?;
+
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let aspace = process_data.aspace.lock();
+    let start = VirtAddr::from(addr);
+    let length = end - addr;
+    if !aspace.check_region_access(
+        VirtAddrRange::from_start_size(start, length),
+        MappingFlags::empty(),
+    ) {
+        return Err(LinuxError::ENOMEM);
+    }
+    drop(aspace);
+    sync_file_mappings(
+        &process_data.aspace,
+        start,
+        length,
+        flags != MS_INVALIDATE,
+        flags & MS_INVALIDATE != 0,
+    )?;
+    Ok(0)
+}
+
+/// Report whether each page in the `length` bytes from `addr` is resident
+/// in memory, setting bit 0 of the byte of the page in `vec` if it is.
+///
+/// A page is resident once it has been populated, as the locked pages are.
+pub fn sys_mincore(addr: usize, length: usize, vec: UserPtr<u8>) -> LinuxResult<isize> {
+    debug!("sys_mincore <= addr: {:#x}, length: {:#x}", addr, length);
+    if !memory_addr::is_aligned_4k(addr) {
+        return Err(LinuxError::EINVAL);
+    }
+    if length == 0 {
+        return Ok(0);
+    }
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(LinuxError::ENOMEM)?;
+    let pages = (end - addr) / PAGE_SIZE_4K;
+    let vec = vec.get_as_mut_slice(pages)?;
+
+    let curr = current();
+    let aspace_ref = &curr.task_ext().process_data().aspace;
+    let start = VirtAddr::from(addr);
+    if !is_mapped(aspace_ref, start, end - addr) {
+        return Err(LinuxError::ENOMEM);
+    }
+    let aspace = aspace_ref.lock();
+    for (i, resident) in vec.iter_mut().enumerate() {
+        let vaddr = start + i * PAGE_SIZE_4K;
+        *resident = aspace.page_table().query(vaddr).is_ok() as u8;
+    }
+    Ok(0)
+}
+
+/// Remove all the user mappings of `aspace`, locked as `aspace_ref`, writing
+/// back the shared file pages and detaching the System V shared memory.
+///
+/// This is done when the address space is given up by `execve` or by the
+/// exit of the last process using it.
+pub fn clear_user_mappings(
+    aspace_ref: &Arc<Mutex<AddrSpace>>,
+    aspace: &mut AddrSpace,
+) -> LinuxResult {
+    remove_file_mappings(aspace_ref, aspace.base(), aspace.end() - aspace.base());
+    detach_all_shm(aspace_ref);
+    clear_cow_pages(aspace_ref);
+    clear_vmas(aspace_ref);
+    aspace.unmap_user_areas()?;
+    axhal::arch::flush_tlb(None);
+    Ok(())
+}
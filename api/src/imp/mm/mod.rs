@@ -1,5 +1,17 @@
 mod brk;
+mod cow;
+mod file_map;
+mod mlock;
 mod mmap;
+mod process_vm;
+mod shm;
+mod vma;
 
 pub use self::brk::*;
+pub use self::cow::*;
+pub use self::file_map::*;
+pub use self::mlock::*;
 pub use self::mmap::*;
+pub use self::process_vm::*;
+pub use self::shm::*;
+pub use self::vma::*;
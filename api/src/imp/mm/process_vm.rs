@@ -0,0 +1,168 @@
+use core::ops::Range;
+
+use alloc::vec::Vec;
+use axerrno::{AxResult, LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axprocess::{Pid, Process};
+use linux_raw_sys::general::iovec;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+use starry_core::task::{ProcessData, get_process};
+
+use crate::{
+    dirty_file_pages,
+    imp::sys_getuid,
+    ptr::{UserConstPtr, UserPtr},
+    unshare_cow_pages,
+};
+
+const IOV_MAX: usize = 1024;
+
+/// Check that the current process may access the memory of `proc`, as
+/// `ptrace` permits with `PTRACE_MODE_ATTACH_REALCREDS`: the real user ID of
+/// the caller must be root or that of the target.
+///
+/// The memory of an exited process is gone, for which `ESRCH` is returned.
+fn check_access(proc: &Process) -> LinuxResult {
+    if proc.is_zombie() {
+        return Err(LinuxError::ESRCH);
+    }
+    // Every process runs with the real user ID of root.
+    if sys_getuid()? != 0 {
+        return Err(LinuxError::EPERM);
+    }
+    Ok(())
+}
+
+/// Copy between the buffers `local` of the caller and the ranges `remote`
+/// of the process `pid`, which must be mapped with `access`.
+///
+/// `copy` is called on the pieces of the ranges within a page, with the
+/// local buffer and the part of it to copy. Return the number of bytes
+/// copied before the first piece that cannot be accessed.
+fn transfer<B: AsRef<[u8]>>(
+    pid: Pid,
+    mut local: Vec<B>,
+    remote: &[iovec],
+    access: MappingFlags,
+    mut copy: impl FnMut(&AddrSpace, VirtAddr, &mut B, Range<usize>) -> AxResult,
+) -> LinuxResult<isize> {
+    remote.iter().try_fold(0usize, |total, iov| {
+        (iov.iov_base as usize)
+            .checked_add(iov.iov_len as usize)
+            .and_then(|_| total.checked_add(iov.iov_len as usize))
+            .filter(|&total| total <= isize::MAX as usize)
+            .ok_or(LinuxError::EINVAL)
+    })?;
+
+    let proc = get_process(pid)?;
+    check_access(&proc)?;
+    let proc_data: &ProcessData = proc.data().unwrap();
+    let mut aspace = proc_data.aspace.lock();
+
+    let mut copied = 0;
+    let (mut buf, mut pos) = (0, 0);
+    'outer: for iov in remote {
+        let mut addr = iov.iov_base as usize;
+        let end = addr + iov.iov_len as usize;
+        while addr < end {
+            while buf < local.len() && pos == local[buf].as_ref().len() {
+                buf += 1;
+                pos = 0;
+            }
+            if buf == local.len() {
+                break 'outer;
+            }
+            let len = (end - addr)
+                .min(PAGE_SIZE_4K - addr % PAGE_SIZE_4K)
+                .min(local[buf].as_ref().len() - pos);
+            let vaddr = VirtAddr::from(addr);
+            if access.contains(MappingFlags::WRITE) {
+                dirty_file_pages(&proc_data.aspace, &mut aspace, vaddr, len);
+                unshare_cow_pages(&proc_data.aspace, &mut aspace, vaddr, len);
+            }
+            let accessible = aspace
+                .check_region_access(VirtAddrRange::from_start_size(vaddr, len), access)
+                && aspace
+                    .populate_area(vaddr.align_down_4k(), PAGE_SIZE_4K)
+                    .is_ok()
+                && copy(&aspace, vaddr, &mut local[buf], pos..pos + len).is_ok();
+            if !accessible {
+                if copied == 0 {
+                    return Err(LinuxError::EFAULT);
+                }
+                break 'outer;
+            }
+            copied += len;
+            addr += len;
+            pos += len;
+        }
+    }
+    Ok(copied as _)
+}
+
+/// Read the memory of the process `pid` at the ranges `remote_iov` into the
+/// buffers `local_iov` of the caller.
+pub fn sys_process_vm_readv(
+    pid: Pid,
+    local_iov: UserConstPtr<iovec>,
+    liovcnt: usize,
+    remote_iov: UserConstPtr<iovec>,
+    riovcnt: usize,
+    flags: usize,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_process_vm_readv <= pid: {}, liovcnt: {}, riovcnt: {}, flags: {:#x}",
+        pid, liovcnt, riovcnt, flags
+    );
+    if flags != 0 || liovcnt > IOV_MAX || riovcnt > IOV_MAX {
+        return Err(LinuxError::EINVAL);
+    }
+    let local = local_iov
+        .get_as_slice(liovcnt)?
+        .iter()
+        .filter(|iov| iov.iov_len > 0)
+        .map(|iov| UserPtr::<u8>::from(iov.iov_base as usize).get_as_mut_slice(iov.iov_len as _))
+        .collect::<LinuxResult<Vec<_>>>()?;
+    let remote = remote_iov.get_as_slice(riovcnt)?;
+    transfer(
+        pid,
+        local,
+        remote,
+        MappingFlags::READ,
+        |aspace, vaddr, buf, range| aspace.read(vaddr, &mut buf[range]),
+    )
+}
+
+/// Write the buffers `local_iov` of the caller to the memory of the process
+/// `pid` at the ranges `remote_iov`.
+pub fn sys_process_vm_writev(
+    pid: Pid,
+    local_iov: UserConstPtr<iovec>,
+    liovcnt: usize,
+    remote_iov: UserConstPtr<iovec>,
+    riovcnt: usize,
+    flags: usize,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_process_vm_writev <= pid: {}, liovcnt: {}, riovcnt: {}, flags: {:#x}",
+        pid, liovcnt, riovcnt, flags
+    );
+    if flags != 0 || liovcnt > IOV_MAX || riovcnt > IOV_MAX {
+        return Err(LinuxError::EINVAL);
+    }
+    let local = local_iov
+        .get_as_slice(liovcnt)?
+        .iter()
+        .filter(|iov| iov.iov_len > 0)
+        .map(|iov| UserConstPtr::<u8>::from(iov.iov_base as usize).get_as_slice(iov.iov_len as _))
+        .collect::<LinuxResult<Vec<_>>>()?;
+    let remote = remote_iov.get_as_slice(riovcnt)?;
+    transfer(
+        pid,
+        local,
+        remote,
+        MappingFlags::WRITE,
+        |aspace, vaddr, buf, range| aspace.write(vaddr, &buf[range]),
+    )
+}
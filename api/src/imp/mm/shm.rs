@@ -0,0 +1,483 @@
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::{
+    mem::{VirtAddr, virt_to_phys},
+    paging::MappingFlags,
+    time::wall_time,
+};
+use axmm::AddrSpace;
+use axprocess::Pid;
+use axsync::Mutex;
+use axtask::{TaskExtRef, current};
+use memory_addr::{PAGE_SIZE_4K, VirtAddrRange};
+
+use super::{add_vma, remove_cow_pages, remove_file_mappings, remove_vmas};
+use crate::ptr::{UserConstPtr, UserPtr};
+
+const IPC_PRIVATE: i32 = 0;
+const IPC_CREAT: i32 = 0o1000;
+const IPC_EXCL: i32 = 0o2000;
+
+const IPC_RMID: i32 = 0;
+const IPC_SET: i32 = 1;
+const IPC_STAT: i32 = 2;
+/// The flag of `shmctl` commands using the 64-bit structures, which are the
+/// only ones supported.
+const IPC_64: i32 = 0x100;
+
+const SHM_RDONLY: i32 = 0o10000;
+const SHM_RND: i32 = 0o20000;
+const SHM_REMAP: i32 = 0o40000;
+const SHM_EXEC: i32 = 0o100000;
+
+/// The mode bit reported by `IPC_STAT` for segments removed by `IPC_RMID`.
+const SHM_DEST: u32 = 0o1000;
+
+const SHMMAX: usize = 0x4000_0000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+struct ipc64_perm {
+    key: i32,
+    uid: u32,
+    gid: u32,
+    cuid: u32,
+    cgid: u32,
+    mode: u32,
+    seq: u16,
+    __pad2: u16,
+    __unused1: u64,
+    __unused2: u64,
+}
+
+/// The `struct shmid_ds` of `shmctl`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub struct shmid64_ds {
+    shm_perm: ipc64_perm,
+    shm_segsz: usize,
+    shm_atime: i64,
+    shm_dtime: i64,
+    shm_ctime: i64,
+    shm_cpid: i32,
+    shm_lpid: i32,
+    shm_nattch: u64,
+    __unused4: u64,
+    __unused5: u64,
+}
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE_4K]);
+
+/// A System V shared memory segment.
+struct ShmSegment {
+    /// The key of the segment, `IPC_PRIVATE` after it is removed.
+    key: i32,
+    size: usize,
+    pages: Vec<Box<Page>>,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    cpid: Pid,
+    lpid: Pid,
+    atime: i64,
+    dtime: i64,
+    ctime: i64,
+    nattch: u64,
+    /// Whether the segment is removed by `IPC_RMID`, so that it is freed
+    /// once it is no longer attached.
+    removed: bool,
+}
+
+/// An attachment of a segment to a user address space.
+struct ShmAttachment {
+    aspace: Weak<Mutex<AddrSpace>>,
+    start: VirtAddr,
+    size: usize,
+    shmid: i32,
+}
+
+struct ShmTable {
+    segments: BTreeMap<i32, ShmSegment>,
+    attachments: Vec<ShmAttachment>,
+    next_id: i32,
+}
+
+impl ShmTable {
+    /// Count one detachment of the segment `shmid`, freeing it if it is
+    /// removed and no longer attached.
+    fn detached(&mut self, shmid: i32, pid: Option<Pid>) {
+        let Some(seg) = self.segments.get_mut(&shmid) else {
+            return;
+        };
+        seg.nattch -= 1;
+        seg.dtime = now();
+        if let Some(pid) = pid {
+            seg.lpid = pid;
+        }
+        if seg.removed && seg.nattch == 0 {
+            self.segments.remove(&shmid);
+        }
+    }
+
+    /// Drop the attachments of exited processes.
+    fn collect_dead(&mut self) {
+        let mut dead = Vec::new();
+        self.attachments.retain(|a| {
+            if a.aspace.strong_count() > 0 {
+                return true;
+            }
+            dead.push(a.shmid);
+            false
+        });
+        for shmid in dead {
+            self.detached(shmid, None);
+        }
+    }
+
+    /// Trim the attachments to `aspace` in the `len` bytes from `start`,
+    /// which are being unmapped, detaching those entirely covered.
+    ///
+    /// An attachment split in two counts as two attachments of its segment.
+    fn unmap(&mut self, aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) {
+        let aspace = Arc::downgrade(aspace);
+        let end = start + len;
+        let mut kept = Vec::with_capacity(self.attachments.len() + 1);
+        let mut detached = Vec::new();
+        for a in self.attachments.drain(..) {
+            let a_end = a.start + a.size;
+            if !Weak::ptr_eq(&a.aspace, &aspace) || a_end <= start || end <= a.start {
+                kept.push(a);
+                continue;
+            }
+            let mut pieces = 0;
+            if a.start < start {
+                kept.push(ShmAttachment {
+                    aspace: a.aspace.clone(),
+                    start: a.start,
+                    size: start - a.start,
+                    shmid: a.shmid,
+                });
+                pieces += 1;
+            }
+            if end < a_end {
+                kept.push(ShmAttachment {
+                    aspace: a.aspace.clone(),
+                    start: end,
+                    size: a_end - end,
+                    shmid: a.shmid,
+                });
+                pieces += 1;
+            }
+            match pieces {
+                0 => detached.push(a.shmid),
+                2 => {
+                    if let Some(seg) = self.segments.get_mut(&a.shmid) {
+                        seg.nattch += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.attachments = kept;
+        for shmid in detached {
+            self.detached(shmid, Some(current_pid()));
+        }
+    }
+}
+
+static SHM_TABLE: Mutex<ShmTable> = Mutex::new(ShmTable {
+    segments: BTreeMap::new(),
+    attachments: Vec::new(),
+    next_id: 0,
+});
+
+fn now() -> i64 {
+    wall_time().as_secs() as _
+}
+
+fn current_pid() -> Pid {
+    current().task_ext().thread.process().pid()
+}
+
+/// Get the shared memory segment with `key`, creating it with at least
+/// `size` bytes if `key` is `IPC_PRIVATE` or `IPC_CREAT` is set.
+pub fn sys_shmget(key: i32, size: usize, shmflg: i32) -> LinuxResult<isize> {
+    debug!(
+        "sys_shmget <= key: {}, size: {:#x}, shmflg: {:#o}",
+        key, size, shmflg
+    );
+    let mut table = SHM_TABLE.lock();
+    table.collect_dead();
+    if key != IPC_PRIVATE {
+        if let Some((&shmid, seg)) = table.segments.iter().find(|(_, s)| s.key == key) {
+            if shmflg & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                return Err(LinuxError::EEXIST);
+            }
+            if size > seg.size {
+                return Err(LinuxError::EINVAL);
+            }
+            return Ok(shmid as _);
+        }
+        if shmflg & IPC_CREAT == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+    }
+    if size == 0 || size > SHMMAX {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let pages = (0..size.div_ceil(PAGE_SIZE_4K))
+        .map(|_| Box::new(Page([0; PAGE_SIZE_4K])))
+        .collect();
+    let shmid = table.next_id;
+    table.next_id += 1;
+    table.segments.insert(
+        shmid,
+        ShmSegment {
+            key,
+            size,
+            pages,
+            uid: 0,
+            gid: 0,
+            mode: shmflg as u32 & 0o777,
+            cpid: current_pid(),
+            lpid: 0,
+            atime: 0,
+            dtime: 0,
+            ctime: now(),
+            nattch: 0,
+            removed: false,
+        },
+    );
+    Ok(shmid as _)
+}
+
+/// Attach the shared memory segment `shmid` at `shmaddr`, or at an address
+/// chosen by the kernel if it is null.
+pub fn sys_shmat(shmid: i32, shmaddr: usize, shmflg: i32) -> LinuxResult<isize> {
+    debug!(
+        "sys_shmat <= shmid: {}, shmaddr: {:#x}, shmflg: {:#o}",
+        shmid, shmaddr, shmflg
+    );
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let mut aspace = process_data.aspace.lock();
+    let mut table = SHM_TABLE.lock();
+    table.collect_dead();
+    let seg = table.segments.get(&shmid).ok_or(LinuxError::EINVAL)?;
+    let size = seg.pages.len() * PAGE_SIZE_4K;
+
+    let mut flags = MappingFlags::READ | MappingFlags::USER;
+    if shmflg & SHM_RDONLY == 0 {
+        flags |= MappingFlags::WRITE;
+    }
+    if shmflg & SHM_EXEC != 0 {
+        flags |= MappingFlags::EXECUTE;
+    }
+
+    let start = if shmaddr == 0 {
+        aspace
+            .find_free_area(
+                aspace.base(),
+                size,
+                VirtAddrRange::new(aspace.base(), aspace.end()),
+            )
+            .ok_or(LinuxError::ENOMEM)?
+    } else {
+        let addr = if shmflg & SHM_RND != 0 {
+            memory_addr::align_down_4k(shmaddr)
+        } else if memory_addr::is_aligned_4k(shmaddr) {
+            shmaddr
+        } else {
+            return Err(LinuxError::EINVAL);
+        };
+        let start = VirtAddr::from(addr);
+        if start < aspace.base()
+            || addr
+                .checked_add(size)
+                .is_none_or(|end| end > aspace.end().as_usize())
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        if shmflg & SHM_REMAP != 0 {
+            remove_file_mappings(&process_data.aspace, start, size);
+            table.unmap(&process_data.aspace, start, size);
+            remove_cow_pages(&process_data.aspace, start, size);
+            remove_vmas(&process_data.aspace, start, size);
+            aspace.unmap(start, size)?;
+        } else if aspace.find_free_area(start, size, VirtAddrRange::from_start_size(start, size))
+            != Some(start)
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        start
+    };
+
+    for (i, page) in seg.pages.iter().enumerate() {
+        let paddr = virt_to_phys(VirtAddr::from(page.0.as_ptr() as usize));
+        if let Err(e) = aspace.map_linear(start + i * PAGE_SIZE_4K, paddr, PAGE_SIZE_4K, flags) {
+            aspace.unmap(start, size)?;
+            return Err(e.into());
+        }
+    }
+    drop(aspace);
+    add_vma(&process_data.aspace, start, size, flags);
+
+    let seg = table.segments.get_mut(&shmid).unwrap();
+    seg.nattch += 1;
+    seg.atime = now();
+    seg.lpid = current_pid();
+    table.attachments.push(ShmAttachment {
+        aspace: Arc::downgrade(&process_data.aspace),
+        start,
+        size,
+        shmid,
+    });
+    Ok(start.as_usize() as _)
+}
+
+/// Detach the shared memory segment attached at `shmaddr`.
+pub fn sys_shmdt(shmaddr: usize) -> LinuxResult<isize> {
+    debug!("sys_shmdt <= shmaddr: {:#x}", shmaddr);
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let mut aspace = process_data.aspace.lock();
+    let mut table = SHM_TABLE.lock();
+    table.collect_dead();
+    let aspace_weak = Arc::downgrade(&process_data.aspace);
+    let pos = table
+        .attachments
+        .iter()
+        .position(|a| Weak::ptr_eq(&a.aspace, &aspace_weak) && a.start.as_usize() == shmaddr)
+        .ok_or(LinuxError::EINVAL)?;
+    let attachment = table.attachments.remove(pos);
+    remove_vmas(&process_data.aspace, attachment.start, attachment.size);
+    aspace.unmap(attachment.start, attachment.size)?;
+    axhal::arch::flush_tlb(None);
+    table.detached(attachment.shmid, Some(current_pid()));
+    Ok(0)
+}
+
+/// Get or set the status of the shared memory segment `shmid`, or remove
+/// it.
+pub fn sys_shmctl(shmid: i32, cmd: i32, buf: UserPtr<shmid64_ds>) -> LinuxResult<isize> {
+    debug!("sys_shmctl <= shmid: {}, cmd: {}", shmid, cmd);
+    let mut table = SHM_TABLE.lock();
+    table.collect_dead();
+    let seg = table.segments.get_mut(&shmid).ok_or(LinuxError::EINVAL)?;
+    match cmd & !IPC_64 {
+        IPC_STAT => {
+            let mode = if seg.removed {
+                seg.mode | SHM_DEST
+            } else {
+                seg.mode
+            };
+            *buf.get_as_mut()? = shmid64_ds {
+                shm_perm: ipc64_perm {
+                    key: seg.key,
+                    uid: seg.uid,
+                    gid: seg.gid,
+                    cuid: 0,
+                    cgid: 0,
+                    mode,
+                    seq: 0,
+                    __pad2: 0,
+                    __unused1: 0,
+                    __unused2: 0,
+                },
+                shm_segsz: seg.size,
+                shm_atime: seg.atime,
+                shm_dtime: seg.dtime,
+                shm_ctime: seg.ctime,
+                shm_cpid: seg.cpid as _,
+                shm_lpid: seg.lpid as _,
+                shm_nattch: seg.nattch,
+                __unused4: 0,
+                __unused5: 0,
+            };
+        }
+        IPC_SET => {
+            let ds = UserConstPtr::<shmid64_ds>::from(buf.address().as_usize()).get_as_ref()?;
+            seg.uid = ds.shm_perm.uid;
+            seg.gid = ds.shm_perm.gid;
+            seg.mode = ds.shm_perm.mode & 0o777;
+            seg.ctime = now();
+        }
+        IPC_RMID => {
+            seg.key = IPC_PRIVATE;
+            seg.removed = true;
+            if seg.nattch == 0 {
+                table.segments.remove(&shmid);
+            }
+        }
+        _ => return Err(LinuxError::EINVAL),
+    }
+    Ok(0)
+}
+
+/// Duplicate the attachments of `parent` to its copy `child`, which maps
+/// the same segments.
+pub fn fork_shm_attachments(parent: &Arc<Mutex<AddrSpace>>, child: &Arc<Mutex<AddrSpace>>) {
+    let mut table = SHM_TABLE.lock();
+    table.collect_dead();
+    let parent = Arc::downgrade(parent);
+    let forked = table
+        .attachments
+        .iter()
+        .filter(|a| Weak::ptr_eq(&a.aspace, &parent))
+        .map(|a| ShmAttachment {
+            aspace: Arc::downgrade(child),
+            start: a.start,
+            size: a.size,
+            shmid: a.shmid,
+        })
+        .collect::<Vec<_>>();
+    for a in &forked {
+        if let Some(seg) = table.segments.get_mut(&a.shmid) {
+            seg.nattch += 1;
+        }
+    }
+    table.attachments.extend(forked);
+}
+
+/// Trim or detach the attachments in the `len` bytes from `start` of
+/// `aspace`, which are being unmapped.
+pub fn unmap_shm_attachments(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) {
+    let mut table = SHM_TABLE.lock();
+    table.collect_dead();
+    table.unmap(aspace, start, len);
+}
+
+/// Check whether any page in the `len` bytes from `start` of `aspace` is in
+/// an attachment.
+pub fn is_shm_attached(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) -> bool {
+    let aspace = Arc::downgrade(aspace);
+    SHM_TABLE.lock().attachments.iter().any(|a| {
+        Weak::ptr_eq(&a.aspace, &aspace) && a.start < start + len && start < a.start + a.size
+    })
+}
+
+/// Detach all the segments attached to `aspace`, which is being cleared.
+pub fn detach_all_shm(aspace: &Arc<Mutex<AddrSpace>>) {
+    let mut table = SHM_TABLE.lock();
+    let aspace = Arc::downgrade(aspace);
+    let mut detached = Vec::new();
+    table.attachments.retain(|a| {
+        if Weak::ptr_eq(&a.aspace, &aspace) {
+            detached.push(a.shmid);
+            return false;
+        }
+        true
+    });
+    for shmid in detached {
+        table.detached(shmid, Some(current_pid()));
+    }
+}
@@ -0,0 +1,206 @@
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{VirtAddr, VirtAddrRange};
+
+/// A virtual memory area, which is mapped at once with the same access.
+///
+/// Areas are not merged with their neighbours, so a mapping never spans the
+/// areas mapped by different calls.
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub range: VirtAddrRange,
+    /// The access permitted to the area.
+    pub flags: MappingFlags,
+    /// Whether the area is locked in memory.
+    ///
+    /// As there is no swap, locking memory only populates it and records
+    /// that it is locked.
+    pub locked: bool,
+}
+
+/// The areas of an address space.
+///
+/// The mappings of axmm cannot be enumerated, so the areas are recorded
+/// when they are mapped, unmapped or protected.
+struct AreaList {
+    aspace: Weak<Mutex<AddrSpace>>,
+    /// The sorted and disjoint areas.
+    areas: Vec<Vma>,
+    /// Whether the areas mapped later are locked (`MCL_FUTURE`).
+    lock_future: bool,
+}
+
+impl AreaList {
+    /// Apply `f` to the parts of the areas in `range`, keeping those outside
+    /// of it, and drop the parts for which `f` returns `None`.
+    fn update(&mut self, range: VirtAddrRange, mut f: impl FnMut(Vma) -> Option<Vma>) {
+        let mut kept = Vec::with_capacity(self.areas.len() + 2);
+        for vma in self.areas.drain(..) {
+            if vma.range.end <= range.start || range.end <= vma.range.start {
+                kept.push(vma);
+                continue;
+            }
+            if vma.range.start < range.start {
+                kept.push(Vma {
+                    range: VirtAddrRange::new(vma.range.start, range.start),
+                    ..vma
+                });
+            }
+            let inner = VirtAddrRange::new(
+                vma.range.start.max(range.start),
+                vma.range.end.min(range.end),
+            );
+            kept.extend(f(Vma {
+                range: inner,
+                ..vma
+            }));
+            if range.end < vma.range.end {
+                kept.push(Vma {
+                    range: VirtAddrRange::new(range.end, vma.range.end),
+                    ..vma
+                });
+            }
+        }
+        self.areas = kept;
+    }
+
+    fn insert(&mut self, vma: Vma) {
+        self.update(vma.range, |_| None);
+        let pos = self
+            .areas
+            .partition_point(|v| v.range.end <= vma.range.start);
+        self.areas.insert(pos, vma);
+    }
+}
+
+static AREAS: Mutex<Vec<AreaList>> = Mutex::new(Vec::new());
+
+/// Run `f` on the areas of `aspace`.
+fn with_areas<R>(aspace: &Arc<Mutex<AddrSpace>>, f: impl FnOnce(&mut AreaList) -> R) -> R {
+    let mut lists = AREAS.lock();
+    lists.retain(|l| l.aspace.strong_count() > 0);
+    let pos = match lists
+        .iter()
+        .position(|l| Weak::ptr_eq(&l.aspace, &Arc::downgrade(aspace)))
+    {
+        Some(pos) => pos,
+        None => {
+            lists.push(AreaList {
+                aspace: Arc::downgrade(aspace),
+                areas: Vec::new(),
+                lock_future: false,
+            });
+            lists.len() - 1
+        }
+    };
+    f(&mut lists[pos])
+}
+
+/// Record the area of `len` bytes at `start` of `aspace` mapped with `flags`,
+/// replacing the areas there.
+///
+/// The area is locked if `MCL_FUTURE` is in effect.
+pub fn add_vma(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize, flags: MappingFlags) {
+    let range = VirtAddrRange::from_start_size(start, len);
+    with_areas(aspace, |list| {
+        let locked = list.lock_future;
+        list.insert(Vma {
+            range,
+            flags,
+            locked,
+        })
+    });
+}
+
+/// Forget the areas in the `len` bytes from `start` of `aspace`, which are
+/// being unmapped.
+pub fn remove_vmas(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) {
+    let range = VirtAddrRange::from_start_size(start, len);
+    with_areas(aspace, |list| list.update(range, |_| None));
+}
+
+/// Record that the areas in the `len` bytes from `start` of `aspace` are
+/// protected with `flags`, splitting those partially covered.
+pub fn protect_vmas(
+    aspace: &Arc<Mutex<AddrSpace>>,
+    start: VirtAddr,
+    len: usize,
+    flags: MappingFlags,
+) {
+    let range = VirtAddrRange::from_start_size(start, len);
+    with_areas(aspace, |list| {
+        list.update(range, |vma| Some(Vma { flags, ..vma }))
+    });
+}
+
+/// Record that the areas in the `len` bytes from `start` of `aspace` are
+/// locked if `locked` is set, or unlocked otherwise.
+pub fn lock_vmas(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize, locked: bool) {
+    let range = VirtAddrRange::from_start_size(start, len);
+    with_areas(aspace, |list| {
+        list.update(range, |vma| Some(Vma { locked, ..vma }))
+    });
+}
+
+/// Set whether the areas mapped later in `aspace` are locked.
+pub fn lock_future_vmas(aspace: &Arc<Mutex<AddrSpace>>, locked: bool) {
+    with_areas(aspace, |list| list.lock_future = locked);
+}
+
+/// Check whether all the pages in the `len` bytes from `start` of `aspace`
+/// are in some areas.
+pub fn is_mapped(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, len: usize) -> bool {
+    let end = start + len;
+    with_areas(aspace, |list| {
+        let mut next = start;
+        for vma in list
+            .areas
+            .iter()
+            .skip_while(|v| v.range.end <= start)
+            .take_while(|v| v.range.start < end)
+        {
+            if vma.range.start > next {
+                return false;
+            }
+            next = vma.range.end;
+        }
+        next >= end
+    })
+}
+
+/// Find the area of `aspace` containing `vaddr`.
+pub fn find_vma(aspace: &Arc<Mutex<AddrSpace>>, vaddr: VirtAddr) -> Option<Vma> {
+    with_areas(aspace, |list| {
+        list.areas.iter().find(|v| v.range.contains(vaddr)).copied()
+    })
+}
+
+/// Get the areas of `aspace`.
+pub fn vmas(aspace: &Arc<Mutex<AddrSpace>>) -> Vec<Vma> {
+    with_areas(aspace, |list| list.areas.clone())
+}
+
+/// Duplicate the areas of `parent` to its copy `child`.
+///
+/// The memory locks are not inherited by the child.
+pub fn fork_vmas(parent: &Arc<Mutex<AddrSpace>>, child: &Arc<Mutex<AddrSpace>>) {
+    let mut areas = vmas(parent);
+    for vma in &mut areas {
+        vma.locked = false;
+    }
+    with_areas(child, |list| list.areas = areas);
+}
+
+/// Forget all the areas of `aspace`, whose user mappings are being removed,
+/// including `MCL_FUTURE`.
+pub fn clear_vmas(aspace: &Arc<Mutex<AddrSpace>>) {
+    with_areas(aspace, |list| {
+        list.areas.clear();
+        list.lock_future = false;
+    });
+}
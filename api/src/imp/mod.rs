@@ -1,9 +1,10 @@
 mod fs;
 mod mm;
+mod net;
 mod resources;
 mod signal;
 mod sys;
 mod task;
 mod time;
 
-pub use self::{fs::*, mm::*, resources::*, signal::*, sys::*, task::*, time::*};
+pub use self::{fs::*, mm::*, net::*, resources::*, signal::*, sys::*, task::*, time::*};
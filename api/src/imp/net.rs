@@ -0,0 +1,557 @@
+use core::ffi::c_int;
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::{
+    general::{AT_FDCWD, IN_CREATE, O_CLOEXEC, O_NONBLOCK, S_IFSOCK, iovec},
+    net::{
+        AF_UNIX, MSG_CMSG_CLOEXEC, MSG_CTRUNC, MSG_DONTWAIT, MSG_NOSIGNAL, MSG_TRUNC, SCM_RIGHTS,
+        SHUT_RD, SHUT_RDWR, SHUT_WR, SOCK_DGRAM, SOCK_STREAM, SOL_SOCKET, cmsghdr, msghdr,
+        sockaddr, socklen_t,
+    },
+};
+
+use crate::{
+    check_writable,
+    fd::{
+        FileLike, UnixMessage, UnixSocket, UnixSocketType, add_file_like_from, close_file_like,
+        fs_notify, get_file_like,
+    },
+    path::{ATTR_MANAGER, FilePath, SYMLINK_MANAGER, handle_file_path, resolve_symlinks},
+    ptr::{UserConstPtr, UserPtr},
+    sockaddr::{SockAddr, UnixAddr},
+};
+
+/// The flags that may be in the type of a socket, which are the same as the
+/// file status flags.
+const SOCK_NONBLOCK: u32 = O_NONBLOCK;
+const SOCK_CLOEXEC: u32 = O_CLOEXEC;
+
+const IOV_MAX: usize = 1024;
+
+/// The most files that may be passed in one message.
+const SCM_MAX_FD: usize = 253;
+
+/// Parse the type of a socket of `domain`, and return whether
+/// `SOCK_NONBLOCK` is set.
+fn socket_type(domain: u32, ty: u32, protocol: u32) -> LinuxResult<(UnixSocketType, bool)> {
+    if domain != AF_UNIX {
+        return Err(LinuxError::EAFNOSUPPORT);
+    }
+    if ty & !(0xf | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let unix_ty = match ty & 0xf {
+        SOCK_STREAM => UnixSocketType::Stream,
+        SOCK_DGRAM => UnixSocketType::Datagram,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    if protocol != 0 {
+        return Err(LinuxError::EPROTONOSUPPORT);
+    }
+    Ok((unix_ty, ty & SOCK_NONBLOCK != 0))
+}
+
+/// Get the Unix socket of `fd`.
+fn unix_socket(fd: c_int) -> LinuxResult<Arc<UnixSocket>> {
+    get_file_like(fd)?
+        .into_any()
+        .downcast::<UnixSocket>()
+        .map_err(|_| LinuxError::ENOTSOCK)
+}
+
+/// Read the socket address of `addrlen` bytes at `addr`.
+fn read_sockaddr(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<SockAddr> {
+    let bytes = UserConstPtr::<u8>::from(addr.address().as_usize()).get_as_slice(addrlen as _)?;
+    // SAFETY: The bytes are initialized.
+    unsafe { SockAddr::read(bytes.as_ptr().cast(), addrlen) }
+}
+
+/// Store `addr` to the buffer `buf` of `*addrlen` bytes unless it is null,
+/// truncating it, and then its real length to `addrlen`.
+fn write_sockaddr(
+    addr: &SockAddr,
+    buf: UserPtr<sockaddr>,
+    addrlen: UserPtr<socklen_t>,
+) -> LinuxResult {
+    if buf.is_null() {
+        return Ok(());
+    }
+    fill_sockaddr(addr, buf, addrlen.get_as_mut()?)
+}
+
+/// Store `addr` to the buffer `buf` of `*addrlen` bytes, truncating it, and
+/// then its real length to `addrlen`.
+fn fill_sockaddr(addr: &SockAddr, buf: UserPtr<sockaddr>, addrlen: &mut socklen_t) -> LinuxResult {
+    if (*addrlen as i32) < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let len = (*addrlen as usize).min(addr.addr_len() as usize);
+    UserPtr::<u8>::from(buf.address().as_usize())
+        .get_as_mut_slice(len)?
+        .copy_from_slice(&addr.bytes()[..len]);
+    *addrlen = addr.addr_len();
+    Ok(())
+}
+
+/// Resolve the path of `addr` to the key of the binding table, following the
+/// last symbolic link if `follow_last` is set.
+fn unix_key(addr: &UnixAddr, follow_last: bool) -> LinuxResult<(UnixAddr, Option<FilePath>)> {
+    match addr {
+        UnixAddr::Path(path) => {
+            let path = resolve_symlinks(&handle_file_path(AT_FDCWD, path)?, follow_last)?;
+            Ok((UnixAddr::Path(String::from(path.as_str())), Some(path)))
+        }
+        addr => Ok((addr.clone(), None)),
+    }
+}
+
+/// Read the address `addr` of `addrlen` bytes to connect or send to, and
+/// resolve it to the key of the binding table.
+fn read_dest(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<UnixAddr> {
+    let addr = UnixAddr::try_from(read_sockaddr(addr, addrlen)?)?;
+    let (key, path) = unix_key(&addr, true)?;
+    if path.is_some_and(|path| !path.exists()) {
+        return Err(LinuxError::ENOENT);
+    }
+    Ok(key)
+}
+
+/// Create a socket of `domain` and `ty`, in which `SOCK_NONBLOCK` and
+/// `SOCK_CLOEXEC` may be set.
+///
+/// Only `AF_UNIX` sockets of `SOCK_STREAM` and `SOCK_DGRAM` are supported.
+pub fn sys_socket(domain: u32, ty: u32, protocol: u32) -> LinuxResult<isize> {
+    debug!(
+        "sys_socket <= domain: {}, ty: {:#x}, protocol: {}",
+        domain, ty, protocol
+    );
+    let (unix_ty, nonblocking) = socket_type(domain, ty, protocol)?;
+    let socket = UnixSocket::new(unix_ty);
+    socket.set_nonblocking(nonblocking)?;
+    Ok(add_file_like_from(Arc::new(socket), 0, ty & SOCK_CLOEXEC != 0)? as _)
+}
+
+/// Bind the socket `fd` to the address `addr` of `addrlen` bytes.
+///
+/// A path is created as a socket file, which must not exist.
+pub fn sys_bind(fd: c_int, addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    let addr = UnixAddr::try_from(read_sockaddr(addr, addrlen)?)?;
+    debug!("sys_bind <= fd: {}, addr: {:?}", fd, addr);
+    if addr == UnixAddr::Unnamed || socket.local_addr() != UnixAddr::Unnamed {
+        return Err(LinuxError::EINVAL);
+    }
+    let (key, path) = unix_key(&addr, false)?;
+    if let Some(path) = path {
+        if path.exists() || SYMLINK_MANAGER.is_symlink(&path) {
+            return Err(LinuxError::EADDRINUSE);
+        }
+        check_writable(&path)?;
+        if !FilePath::new(path.parent()?)?.exists() {
+            return Err(LinuxError::ENOENT);
+        }
+        axfs::api::write(path.as_str(), b"")?;
+        ATTR_MANAGER.update(&path, |attr| {
+            attr.file_type = Some(S_IFSOCK);
+            attr.mode = Some(0o777 & !current().task_ext().process_data().umask());
+        });
+        fs_notify(path.as_str(), IN_CREATE);
+    }
+    socket.bind(addr, key)?;
+    Ok(0)
+}
+
+/// Start accepting connections on the socket `fd`, at most `backlog` of
+/// which are pending.
+pub fn sys_listen(fd: c_int, backlog: c_int) -> LinuxResult<isize> {
+    debug!("sys_listen <= fd: {}, backlog: {}", fd, backlog);
+    unix_socket(fd)?.listen(backlog.max(0) as _)?;
+    Ok(0)
+}
+
+/// Connect the socket `fd` to the listening socket at the address `addr` of
+/// `addrlen` bytes.
+///
+/// A datagram socket sends to the address by default instead.
+pub fn sys_connect(
+    fd: c_int,
+    addr: UserConstPtr<sockaddr>,
+    addrlen: socklen_t,
+) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    let key = read_dest(addr, addrlen)?;
+    debug!("sys_connect <= fd: {}, addr: {:?}", fd, key);
+    socket.connect(&key)?;
+    Ok(0)
+}
+
+pub fn sys_accept(
+    fd: c_int,
+    addr: UserPtr<sockaddr>,
+    addrlen: UserPtr<socklen_t>,
+) -> LinuxResult<isize> {
+    sys_accept4(fd, addr, addrlen, 0)
+}
+
+/// Accept a connection on the listening socket `fd`, storing the address of
+/// the peer to `addr` unless it is null.
+///
+/// `SOCK_NONBLOCK` and `SOCK_CLOEXEC` in `flags` are applied to the new
+/// socket.
+pub fn sys_accept4(
+    fd: c_int,
+    addr: UserPtr<sockaddr>,
+    addrlen: UserPtr<socklen_t>,
+    flags: u32,
+) -> LinuxResult<isize> {
+    debug!("sys_accept4 <= fd: {}, flags: {:#x}", fd, flags);
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let socket = unix_socket(fd)?.accept()?;
+    socket.set_nonblocking(flags & SOCK_NONBLOCK != 0)?;
+    write_sockaddr(&SockAddr::from(&socket.peer_addr()?), addr, addrlen)?;
+    Ok(add_file_like_from(socket, 0, flags & SOCK_CLOEXEC != 0)? as _)
+}
+
+/// Create a pair of connected sockets of `domain` and `ty`, with
+/// `SOCK_NONBLOCK` and `SOCK_CLOEXEC` in `ty` applied to both, and store
+/// their fds to `sv`.
+///
+/// Only `AF_UNIX` sockets of `SOCK_STREAM` and `SOCK_DGRAM` are supported.
+pub fn sys_socketpair(
+    domain: u32,
+    ty: u32,
+    protocol: u32,
+    sv: UserPtr<[c_int; 2]>,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_socketpair <= domain: {}, ty: {:#x}, protocol: {}",
+        domain, ty, protocol
+    );
+    let (unix_ty, nonblocking) = socket_type(domain, ty, protocol)?;
+    let sv = sv.get_as_mut()?;
+    let cloexec = ty & SOCK_CLOEXEC != 0;
+
+    let (a, b) = UnixSocket::pair(unix_ty);
+    a.set_nonblocking(nonblocking)?;
+    b.set_nonblocking(nonblocking)?;
+    let fd_a = add_file_like_from(a, 0, cloexec)?;
+    let fd_b = add_file_like_from(b, 0, cloexec).inspect_err(|_| {
+        // Another thread may have closed `fd_a` already.
+        let _ = close_file_like(fd_a);
+    })?;
+
+    sv[0] = fd_a;
+    sv[1] = fd_b;
+    Ok(0)
+}
+
+/// Get the `iovlen` buffers at `iov` of a message.
+fn msg_iovecs(iov: *mut iovec, iovlen: usize) -> LinuxResult<&'static [iovec]> {
+    if iovlen > IOV_MAX {
+        return Err(LinuxError::EMSGSIZE);
+    }
+    if iovlen == 0 {
+        return Ok(&[]);
+    }
+    UserConstPtr::<iovec>::from(iov as usize).get_as_slice(iovlen)
+}
+
+/// Round `len` up to the alignment of ancillary data.
+fn cmsg_align(len: usize) -> usize {
+    len.next_multiple_of(size_of::<usize>())
+}
+
+/// Parse the ancillary data `control` to send, and get the files of the fds
+/// passed with `SCM_RIGHTS`.
+fn parse_rights(control: &[u8]) -> LinuxResult<Vec<Arc<dyn FileLike>>> {
+    let header = cmsg_align(size_of::<cmsghdr>());
+    let mut rights = Vec::new();
+    let mut offset = 0;
+    while control.len() - offset >= size_of::<cmsghdr>() {
+        // SAFETY: The header is within `control`.
+        let cmsg = unsafe {
+            control
+                .as_ptr()
+                .add(offset)
+                .cast::<cmsghdr>()
+                .read_unaligned()
+        };
+        let len = cmsg.cmsg_len as usize;
+        if len < header || len > control.len() - offset {
+            return Err(LinuxError::EINVAL);
+        }
+        if cmsg.cmsg_level as u32 != SOL_SOCKET || cmsg.cmsg_type as u32 != SCM_RIGHTS {
+            return Err(LinuxError::EINVAL);
+        }
+        let fds = control[offset + header..offset + len].chunks_exact(size_of::<c_int>());
+        if rights.len() + fds.len() > SCM_MAX_FD {
+            return Err(LinuxError::EINVAL);
+        }
+        for fd in fds {
+            rights.push(get_file_like(c_int::from_ne_bytes(fd.try_into().unwrap()))?);
+        }
+        offset = (offset + cmsg_align(len)).min(control.len());
+    }
+    Ok(rights)
+}
+
+/// Install the files `rights` received at the lowest free fds, and store
+/// them to the ancillary data buffer `control` as `SCM_RIGHTS`.
+///
+/// Return the length of the ancillary data and whether it is truncated, in
+/// which case the files not installed are closed.
+fn write_rights(
+    control: &mut [u8],
+    rights: Vec<Arc<dyn FileLike>>,
+    cloexec: bool,
+) -> LinuxResult<(usize, bool)> {
+    if rights.is_empty() {
+        return Ok((0, false));
+    }
+    let header = cmsg_align(size_of::<cmsghdr>());
+    if control.len() < header {
+        return Ok((0, true));
+    }
+    let total = rights.len();
+    let mut fds = Vec::new();
+    for file in rights
+        .into_iter()
+        .take((control.len() - header) / size_of::<c_int>())
+    {
+        match add_file_like_from(file, 0, cloexec) {
+            Ok(fd) => fds.push(fd),
+            Err(_) => break,
+        }
+    }
+    if fds.is_empty() {
+        return Ok((0, true));
+    }
+
+    let len = header + fds.len() * size_of::<c_int>();
+    let cmsg = cmsghdr {
+        cmsg_len: len as _,
+        cmsg_level: SOL_SOCKET as _,
+        cmsg_type: SCM_RIGHTS as _,
+    };
+    // SAFETY: The header fits in `control`.
+    unsafe { control.as_mut_ptr().cast::<cmsghdr>().write_unaligned(cmsg) };
+    for (dst, fd) in control[header..len]
+        .chunks_exact_mut(size_of::<c_int>())
+        .zip(&fds)
+    {
+        dst.copy_from_slice(&fd.to_ne_bytes());
+    }
+    Ok((cmsg_align(len).min(control.len()), fds.len() < total))
+}
+
+/// Send the data in the buffers of `msg` on the socket `fd` to the address
+/// in `msg` if any, with the fds passed with `SCM_RIGHTS` in the ancillary
+/// data.
+pub fn sys_sendmsg(fd: c_int, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    let msg = msg.get_as_ref()?;
+    debug!(
+        "sys_sendmsg <= fd: {}, iovlen: {}, controllen: {}, flags: {:#x}",
+        fd, msg.msg_iovlen, msg.msg_controllen, flags
+    );
+
+    let mut data = Vec::new();
+    for iov in msg_iovecs(msg.msg_iov, msg.msg_iovlen as _)? {
+        let buf = UserConstPtr::<u8>::from(iov.iov_base as usize);
+        data.extend_from_slice(buf.get_as_slice(iov.iov_len as _)?);
+    }
+    let rights = if msg.msg_controllen > 0 {
+        let control = UserConstPtr::<u8>::from(msg.msg_control as usize);
+        parse_rights(control.get_as_slice(msg.msg_controllen as _)?)?
+    } else {
+        Vec::new()
+    };
+    let dest = if msg.msg_name.is_null() {
+        None
+    } else {
+        Some(read_dest(
+            UserConstPtr::from(msg.msg_name as usize),
+            msg.msg_namelen as _,
+        )?)
+    };
+
+    let sent = socket.send_msg(
+        &data,
+        rights,
+        dest.as_ref(),
+        flags & MSG_DONTWAIT != 0,
+        flags & MSG_NOSIGNAL != 0,
+    )?;
+    Ok(sent as _)
+}
+
+/// Receive data on the socket `fd` into the buffers of `msg`, with the fds
+/// passed along stored to the ancillary data.
+///
+/// The fds are close-on-exec if `MSG_CMSG_CLOEXEC` is set in `flags`, and
+/// `MSG_CTRUNC` is reported if the ancillary data buffer is too small. The
+/// length of the whole datagram is returned if `MSG_TRUNC` is set.
+pub fn sys_recvmsg(fd: c_int, msg: UserPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    let msg = msg.get_as_mut()?;
+    debug!(
+        "sys_recvmsg <= fd: {}, iovlen: {}, controllen: {}, flags: {:#x}",
+        fd, msg.msg_iovlen, msg.msg_controllen, flags
+    );
+
+    let bufs = msg_iovecs(msg.msg_iov, msg.msg_iovlen as _)?
+        .iter()
+        .filter(|iov| iov.iov_len > 0)
+        .map(|iov| UserPtr::<u8>::from(iov.iov_base as usize).get_as_mut_slice(iov.iov_len as _))
+        .collect::<LinuxResult<Vec<_>>>()?;
+    let control: &mut [u8] = if msg.msg_controllen > 0 {
+        UserPtr::<u8>::from(msg.msg_control as usize).get_as_mut_slice(msg.msg_controllen as _)?
+    } else {
+        &mut []
+    };
+
+    let mut data = vec![0; bufs.iter().map(|buf| buf.len()).sum()];
+    let received = socket.recv_msg(&mut data, flags & MSG_DONTWAIT != 0)?;
+    let mut rest = &data[..received.len];
+    for buf in bufs {
+        let len = buf.len().min(rest.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    if !msg.msg_name.is_null() {
+        let mut namelen = msg.msg_namelen as socklen_t;
+        fill_sockaddr(
+            &SockAddr::from(&received.addr),
+            UserPtr::from(msg.msg_name as usize),
+            &mut namelen,
+        )?;
+        msg.msg_namelen = namelen as _;
+    }
+    let (controllen, ctrunc) =
+        write_rights(control, received.rights, flags & MSG_CMSG_CLOEXEC != 0)?;
+    msg.msg_controllen = controllen as _;
+    msg.msg_flags = 0;
+    if ctrunc {
+        msg.msg_flags |= MSG_CTRUNC;
+    }
+    if received.full_len > received.len {
+        msg.msg_flags |= MSG_TRUNC;
+    }
+    Ok(recv_len(&received, flags) as _)
+}
+
+/// Get the length of `received` to return, which is that of the whole
+/// datagram if `MSG_TRUNC` is set in `flags`.
+fn recv_len(received: &UnixMessage, flags: u32) -> usize {
+    if flags & MSG_TRUNC != 0 {
+        received.full_len
+    } else {
+        received.len
+    }
+}
+
+/// Send `len` bytes at `buf` on the socket `fd` to the address `dest_addr`
+/// of `addrlen` bytes unless it is null.
+///
+/// A datagram to an address no socket is bound to is refused.
+pub fn sys_sendto(
+    fd: c_int,
+    buf: UserConstPtr<u8>,
+    len: usize,
+    flags: u32,
+    dest_addr: UserConstPtr<sockaddr>,
+    addrlen: socklen_t,
+) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    let dest = if dest_addr.is_null() {
+        None
+    } else {
+        Some(read_dest(dest_addr, addrlen)?)
+    };
+    debug!(
+        "sys_sendto <= fd: {}, len: {}, flags: {:#x}, dest: {:?}",
+        fd, len, flags, dest
+    );
+    let sent = socket.send_msg(
+        buf.get_as_slice(len)?,
+        Vec::new(),
+        dest.as_ref(),
+        flags & MSG_DONTWAIT != 0,
+        flags & MSG_NOSIGNAL != 0,
+    )?;
+    Ok(sent as _)
+}
+
+/// Receive at most `len` bytes into `buf` on the socket `fd`, storing the
+/// address of the sender to `src_addr` unless it is null.
+///
+/// The rest of a datagram longer than `len` is discarded, and its whole
+/// length is returned if `MSG_TRUNC` is set in `flags`.
+pub fn sys_recvfrom(
+    fd: c_int,
+    buf: UserPtr<u8>,
+    len: usize,
+    flags: u32,
+    src_addr: UserPtr<sockaddr>,
+    addrlen: UserPtr<socklen_t>,
+) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    debug!(
+        "sys_recvfrom <= fd: {}, len: {}, flags: {:#x}",
+        fd, len, flags
+    );
+    let received = socket.recv_msg(buf.get_as_mut_slice(len)?, flags & MSG_DONTWAIT != 0)?;
+    write_sockaddr(&SockAddr::from(&received.addr), src_addr, addrlen)?;
+    Ok(recv_len(&received, flags) as _)
+}
+
+/// Store the address the socket `fd` is bound to to `addr`.
+pub fn sys_getsockname(
+    fd: c_int,
+    addr: UserPtr<sockaddr>,
+    addrlen: UserPtr<socklen_t>,
+) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    debug!("sys_getsockname <= fd: {}", fd);
+    fill_sockaddr(
+        &SockAddr::from(&socket.local_addr()),
+        addr,
+        addrlen.get_as_mut()?,
+    )?;
+    Ok(0)
+}
+
+/// Store the address of the peer the socket `fd` is connected to to `addr`.
+pub fn sys_getpeername(
+    fd: c_int,
+    addr: UserPtr<sockaddr>,
+    addrlen: UserPtr<socklen_t>,
+) -> LinuxResult<isize> {
+    let socket = unix_socket(fd)?;
+    debug!("sys_getpeername <= fd: {}", fd);
+    fill_sockaddr(
+        &SockAddr::from(&socket.peer_addr()?),
+        addr,
+        addrlen.get_as_mut()?,
+    )?;
+    Ok(0)
+}
+
+/// Shut down receiving, sending or both on the socket `fd`.
+pub fn sys_shutdown(fd: c_int, how: u32) -> LinuxResult<isize> {
+    debug!("sys_shutdown <= fd: {}, how: {}", fd, how);
+    let socket = unix_socket(fd)?;
+    let (read, write) = match how {
+        SHUT_RD => (true, false),
+        SHUT_WR => (false, true),
+        SHUT_RDWR => (true, true),
+        _ => return Err(LinuxError::EINVAL),
+    };
+    socket.shutdown(read, write)?;
+    Ok(0)
+}
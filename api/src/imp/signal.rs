@@ -1,37 +1,161 @@
-use core::{mem, time::Duration};
+use core::{mem, sync::atomic::Ordering, time::Duration};
 
+use alloc::{sync::Arc, vec};
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
     arch::TrapFrame,
+    time::TimeValue,
     trap::{POST_TRAP, register_trap_handler},
 };
 use axprocess::{Pid, Process, ProcessGroup, Thread};
 use axsignal::{
-    ctypes::{k_sigaction, SignalAction, SignalActionFlags, SignalInfo, SignalSet}, handle_signal, SignalOSAction
+    ctypes::{k_sigaction, SignalAction, SignalActionFlags, SignalDisposition, SignalInfo, SignalSet}, handle_signal, SignalOSAction
 };
 use axtask::{TaskExtRef, current};
-use linux_raw_sys::general::{SI_TKILL, SI_USER, siginfo, timespec};
+use linux_raw_sys::general::{
+    MINSIGSTKSZ, O_CLOEXEC, O_NONBLOCK, RLIMIT_SIGPENDING, SI_TKILL, SI_USER, SIG_BLOCK,
+    SIG_SETMASK, SIG_UNBLOCK, SIGCHLD, SIGCONT, SIGRTMIN, SIGURG, SIGWINCH, SS_DISABLE, SS_ONSTACK,
+    siginfo, stack_t, timespec,
+};
 use starry_core::task::{
-    ProcessData, ThreadData, get_process, get_process_group, get_thread, processes,
+    NSIG, ProcessData, SignalStack, ThreadData, get_process, get_process_group, get_thread,
+    processes,
+};
+
+use crate::{
+    fd::{FileLike, SignalFd, add_file_like_from},
+    ptr::{UserConstPtr, UserPtr, nullable},
 };
 
-use crate::ptr::{UserConstPtr, UserPtr, nullable};
+use super::{apply_sched_changes, do_exit};
 
-use super::do_exit;
+/// The flag in the exit status of a process killed by a signal, set if a
+/// core dump is produced.
+const WCOREFLAG: i32 = 0x80;
 
 const SIGKILL: u32 = 9;
 const SIGSTOP: u32 = 19;
 
-fn dequeue_signal(mask: &SignalSet) -> Option<SignalInfo> {
+/// The size of the syscall instruction.
+#[cfg(target_arch = "x86_64")]
+const SYSCALL_INSN_LEN: usize = 2;
+#[cfg(not(target_arch = "x86_64"))]
+const SYSCALL_INSN_LEN: usize = 4;
+
+/// The offset of `uc_stack` in `ucontext_t`.
+const UC_STACK_OFFSET: usize = 16;
+
+/// The offset of the stack pointer saved in the `uc_mcontext` of
+/// `ucontext_t`.
+#[cfg(target_arch = "x86_64")]
+const UC_SP_OFFSET: usize = 160;
+#[cfg(target_arch = "riscv64")]
+const UC_SP_OFFSET: usize = 192;
+#[cfg(target_arch = "aarch64")]
+const UC_SP_OFFSET: usize = 432;
+#[cfg(target_arch = "loongarch64")]
+const UC_SP_OFFSET: usize = 208;
+
+const SFD_CLOEXEC: u32 = O_CLOEXEC;
+const SFD_NONBLOCK: u32 = O_NONBLOCK;
+
+/// Dequeue a pending signal of the current thread in `mask`, preferring the
+/// thread-directed ones over those sent to the whole process.
+pub fn dequeue_signal(mask: &SignalSet) -> Option<SignalInfo> {
     let curr = current();
     let task_ext = curr.task_ext();
-    task_ext
+    let proc_data = task_ext.process_data();
+    let sig = task_ext
         .thread_data()
         .pending
         .lock()
         .dequeue_signal(mask)
-        .or_else(|| task_ext.process_data().pending.lock().dequeue_signal(mask))
+        .or_else(|| proc_data.pending.lock().dequeue_signal(mask))?;
+    if sig.signo() as u32 >= SIGRTMIN {
+        let _ = proc_data
+            .queued_signals
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+    Some(sig)
+}
+
+/// Switch `tf` to the alternate signal stack before the handler of `action`
+/// is set up, if it has `SA_ONSTACK` and the stack is not already in use.
+///
+/// Return the interrupted stack pointer if switched.
+fn switch_signal_stack(tf: &mut TrapFrame, action: &SignalAction) -> Option<usize> {
+    if !action.flags.contains(SignalActionFlags::ONSTACK)
+        || !matches!(action.disposition, SignalDisposition::Handler(_))
+    {
+        return None;
+    }
+    let stack = current().task_ext().thread_data().signal_stack.lock();
+    if stack.size == 0 || stack.contains(tf.sp()) {
+        return None;
+    }
+    let sp = tf.sp();
+    tf.set_sp(stack.top());
+    Some(sp)
+}
+
+/// Save the stack pointer `sp` interrupted by switching to the alternate
+/// signal stack to the `ucontext_t` of the handler frame set up in `tf`, in
+/// place of the top of the alternate stack the frame was built from, so that
+/// the handler sees it and `sigreturn` restores it.
+fn save_interrupted_sp(tf: &TrapFrame, sp: usize) {
+    let ucontext = tf.arg2();
+    let stack = *current().task_ext().thread_data().signal_stack.lock();
+    // The interrupted stack pointer is not on the stack.
+    let uc_stack = stack_t {
+        ss_sp: stack.base as _,
+        ss_flags: 0,
+        ss_size: stack.size as _,
+    };
+    // The frame has just been written, so it is mapped.
+    if let Ok(saved) = UserPtr::<stack_t>::from(ucontext + UC_STACK_OFFSET).get_as_mut() {
+        *saved = uc_stack;
+    }
+    if let Ok(saved) = UserPtr::<usize>::from(ucontext + UC_SP_OFFSET).get_as_mut() {
+        *saved = sp;
+    }
+}
+
+/// Get the signals pending for the current thread or its process.
+fn pending_signals() -> SignalSet {
+    let curr = current();
+    let task_ext = curr.task_ext();
+    let thr_pending = task_ext.thread_data().pending.lock().pending;
+    thr_pending | task_ext.process_data().pending.lock().pending
 }
+
+/// Check whether the current thread has a pending signal not blocked, which
+/// interrupts the blocking syscalls.
+pub fn has_pending_signal() -> bool {
+    let mut pending = pending_signals();
+    pending.remove_from(&current().task_ext().thread_data().blocked.lock());
+    pending != SignalSet::default()
+}
+
+/// Save the trap frame of the current syscall, which returned `ERESTART` as
+/// it was interrupted by a signal, to restart it in [`check_signals`].
+pub fn save_restart_context(tf: &TrapFrame) {
+    *current().task_ext().thread_data().restart_context.lock() = Some(*tf);
+}
+
+/// Make the interrupted syscall of the context `restart` be executed again
+/// when returning to user space from `tf`, with the `sleep_deadline` of an
+/// interrupted `nanosleep`.
+fn restart_syscall(tf: &mut TrapFrame, restart: TrapFrame, sleep_deadline: Option<TimeValue>) {
+    let ip = tf.ip();
+    *tf = restart;
+    // The syscall instruction has been stepped over.
+    tf.set_ip(ip - SYSCALL_INSN_LEN);
+    *current().task_ext().thread_data().sleep_deadline.lock() = sleep_deadline;
+}
+
+/// Deliver the pending signals not blocked, and return whether a handler is
+/// set up to run in `tf`, which restores `restore_blocked` (or the current
+/// blocked set) on return.
 fn check_signals(tf: &mut TrapFrame, restore_blocked: Option<SignalSet>) -> bool {
     let curr = current();
     let task_ext = curr.task_ext();
@@ -43,13 +167,39 @@ fn check_signals(tf: &mut TrapFrame, restore_blocked: Option<SignalSet>) -> bool
     let restore_blocked = restore_blocked.unwrap_or_else(|| *blocked);
     drop(blocked);
 
+    let mut restart = task_ext.thread_data().restart_context.lock().take();
+    let sleep_deadline = task_ext.thread_data().sleep_deadline.lock().take();
+
     let (signo, os_action, reset) = loop {
         let Some(sig) = dequeue_signal(&mask) else {
+            // The interrupted syscall is restarted if no handler runs.
+            if let Some(restart) = restart {
+                restart_syscall(tf, restart, sleep_deadline);
+            }
             return false;
         };
         let signo = sig.signo();
         let action = &actions[signo as usize];
-        if let Some(os_action) = handle_signal(tf, restore_blocked, sig, action) {
+        if matches!(action.disposition, SignalDisposition::Handler(_)) {
+            // The frame of the handler saves the context to return to, so the
+            // syscall must be restarted before it is set up. `nanosleep` is
+            // never restarted after a handler.
+            if let Some(restart) = restart.take() {
+                if action.flags.contains(SignalActionFlags::RESTART) && sleep_deadline.is_none() {
+                    restart_syscall(tf, restart, None);
+                }
+            }
+        }
+        let interrupted_sp = switch_signal_stack(tf, action);
+        let os_action = handle_signal(tf, restore_blocked, sig, action);
+        if let Some(sp) = interrupted_sp {
+            if matches!(os_action, Some(SignalOSAction::Handler { .. })) {
+                save_interrupted_sp(tf, sp);
+            } else {
+                tf.set_sp(sp);
+            }
+        }
+        if let Some(os_action) = os_action {
             break (
                 signo,
                 os_action,
@@ -57,14 +207,17 @@ fn check_signals(tf: &mut TrapFrame, restore_blocked: Option<SignalSet>) -> bool
             );
         }
     };
+    if let Some(restart) = restart {
+        restart_syscall(tf, restart, sleep_deadline);
+    }
 
     match os_action {
         SignalOSAction::Terminate => {
-            do_exit(128 + signo as i32, true);
+            do_exit(signo as i32, true);
         }
         SignalOSAction::CoreDump => {
             // TODO: implement core dump
-            do_exit(128 + signo as i32, true);
+            do_exit(signo as i32 | WCOREFLAG, true);
         }
         SignalOSAction::Stop => {
             // TODO: implement stop
@@ -78,9 +231,10 @@ fn check_signals(tf: &mut TrapFrame, restore_blocked: Option<SignalSet>) -> bool
                 actions[signo as usize] = SignalAction::default();
             }
             task_ext.thread_data().blocked.lock().add_from(&add_blocked);
+            return true;
         }
     }
-    true
+    false
 }
 
 #[register_trap_handler(POST_TRAP)]
@@ -89,6 +243,7 @@ fn post_trap_callback(tf: &mut TrapFrame, from_user: bool) {
         return;
     }
 
+    apply_sched_changes();
     check_signals(tf, None);
 }
 
@@ -99,6 +254,10 @@ fn check_sigset_size(size: usize) -> LinuxResult<()> {
     Ok(())
 }
 
+/// Change the signals blocked by the current thread according to `how`,
+/// storing the previous blocked set to `oldset`.
+///
+/// `SIGKILL` and `SIGSTOP` cannot be blocked and are silently ignored.
 pub fn sys_rt_sigprocmask(
     how: i32,
     set: UserConstPtr<SignalSet>,
@@ -115,13 +274,13 @@ pub fn sys_rt_sigprocmask(
     }
 
     if let Some(set) = nullable!(set.get_as_ref())? {
-        match how {
-            // SIG_BLOCK
-            0 => blocked.add_from(set),
-            // SIG_UNBLOCK
-            1 => blocked.remove_from(set),
-            // SIG_SETMASK
-            2 => *blocked = *set,
+        let mut set = *set;
+        set.remove(SIGKILL);
+        set.remove(SIGSTOP);
+        match how as u32 {
+            SIG_BLOCK => blocked.add_from(&set),
+            SIG_UNBLOCK => blocked.remove_from(&set),
+            SIG_SETMASK => *blocked = set,
             _ => return Err(LinuxError::EINVAL),
         }
     }
@@ -129,6 +288,8 @@ pub fn sys_rt_sigprocmask(
     Ok(0)
 }
 
+/// Set the action of the signal `signum` to `act`, storing the previous
+/// action to `oldact`.
 pub fn sys_rt_sigaction(
     signum: i32,
     act: UserConstPtr<k_sigaction>,
@@ -138,7 +299,7 @@ pub fn sys_rt_sigaction(
     check_sigset_size(sigsetsize)?;
 
     let signum = signum as u32;
-    if !(1..32).contains(&signum) {
+    if !(1..=NSIG as u32).contains(&signum) {
         return Err(LinuxError::EINVAL);
     }
     if signum == SIGKILL || signum == SIGSTOP {
@@ -159,25 +320,82 @@ pub fn sys_rt_sigaction(
     Ok(0)
 }
 
+/// Store the signals pending for the current thread or its process but
+/// blocked from delivery to `set`.
 pub fn sys_rt_sigpending(set: UserPtr<SignalSet>, sigsetsize: usize) -> LinuxResult<isize> {
     check_sigset_size(sigsetsize)?;
 
-    let curr = current();
-    let thr_pending = curr.task_ext().thread_data().pending.lock();
-    let proc_pending = curr.task_ext().process_data().pending.lock();
-
-    *set.get_as_mut()? = thr_pending.pending | proc_pending.pending;
+    let mut pending = pending_signals();
+    pending.remove_from(&!*current().task_ext().thread_data().blocked.lock());
+    *set.get_as_mut()? = pending;
 
     Ok(0)
 }
 
+/// Return from a signal handler, restoring the context and the blocked
+/// signals saved in the signal frame on the user stack when the handler was
+/// entered.
+///
+/// The frame is set up by [`handle_signal`] with the `ucontext_t` (and the
+/// `siginfo_t` for `SA_SIGINFO` handlers), and the handler returns to
+/// `sa_restorer` or the signal trampoline, which calls this.
 pub fn sys_rt_sigreturn(tf: &mut TrapFrame) -> LinuxResult<isize> {
     let curr = current();
     let mut blocked = curr.task_ext().thread_data().blocked.lock();
     axsignal::restore(tf, &mut blocked);
+    // The saved mask is in user memory and may have been changed by the
+    // handler.
+    blocked.remove(SIGKILL);
+    blocked.remove(SIGSTOP);
+    // The return value of the interrupted syscall, if any, is restored too.
     Ok(tf.retval() as isize)
 }
 
+/// Check whether the signal `signo` is ignored with `action`, explicitly or
+/// by default.
+fn is_ignored(signo: u32, action: &SignalAction) -> bool {
+    match action.disposition {
+        SignalDisposition::Ignore => true,
+        SignalDisposition::Default => matches!(signo, SIGCHLD | SIGCONT | SIGURG | SIGWINCH),
+        SignalDisposition::Handler(_) => false,
+    }
+}
+
+/// Check whether a signal neither blocked nor in `set` is pending, which
+/// interrupts `sigtimedwait`, discarding the pending signals that are
+/// ignored, which do not.
+fn has_interrupting_signal(set: &SignalSet) -> bool {
+    let curr = current();
+    let task_ext = curr.task_ext();
+    let mut pending = pending_signals();
+    pending.remove_from(&task_ext.thread_data().blocked.lock());
+    pending.remove_from(set);
+    let actions = task_ext.process_data().signal_actions.lock();
+    let mut interrupted = false;
+    for signo in 1..=NSIG as u32 {
+        let mut sig = SignalSet::default();
+        sig.add(signo);
+        let mut pending_sig = pending;
+        pending_sig.remove_from(&!sig);
+        if pending_sig == SignalSet::default() {
+            continue;
+        }
+        if is_ignored(signo, &actions[signo as usize]) {
+            while dequeue_signal(&sig).is_some() {}
+        } else {
+            interrupted = true;
+        }
+    }
+    interrupted
+}
+
+/// Wait for a signal in `set` to be pending and dequeue it, storing its
+/// information to `info`.
+///
+/// Return `EAGAIN` if none is pending before `timeout`, or `EINTR` if the
+/// wait is interrupted by another signal not blocked, which has a handler or
+/// terminates or stops the process by default. The ignored signals are
+/// discarded.
 pub fn sys_rt_sigtimedwait(
     set: UserConstPtr<SignalSet>,
     info: UserPtr<siginfo>,
@@ -187,56 +405,52 @@ pub fn sys_rt_sigtimedwait(
     check_sigset_size(sigsetsize)?;
 
     let curr = current();
-    let proc_data = curr.task_ext().process_data();
-    let thr_data = curr.task_ext().thread_data();
     let mut set = *set.get_as_ref()?;
-    // Non-blocked signals cannot be waited
-    set.remove_from(&!*thr_data.blocked.lock());
+    // SIGKILL and SIGSTOP cannot be waited
+    set.remove(SIGKILL);
+    set.remove(SIGSTOP);
 
     let timeout = nullable!(timeout.get_as_ref())?
-        .map(|spec| Duration::new(spec.tv_sec as u64, spec.tv_nsec as u32));
-
-    if let Some(siginfo) = thr_data.pending.lock().dequeue_signal(&set) {
-        if let Some(info) = nullable!(info.get_as_mut())? {
-            siginfo.to_ctype(info);
-        }
-        return Ok(0);
-    }
-
-    let wq = &proc_data.signal_wq;
-    let deadline = timeout.map(|dur| axhal::time::wall_time() + dur);
-
+        .map(|spec| {
+            if spec.tv_sec < 0 || !(0..1_000_000_000).contains(&spec.tv_nsec) {
+                return Err(LinuxError::EINVAL);
+            }
+            Ok(Duration::new(spec.tv_sec as u64, spec.tv_nsec as u32))
+        })
+        .transpose()?;
+    let deadline = timeout.map(|dur| axhal::time::monotonic_time() + dur);
+
+    let wq = &curr.task_ext().process_data().signal_wq;
+    let woken = || {
+        let mut waited = pending_signals();
+        waited.remove_from(&!set);
+        waited != SignalSet::default() || has_pending_signal()
+    };
     // There might be false wakeups, so we need a loop
     loop {
-        match &deadline {
-            Some(deadline) => {
-                match deadline.checked_sub(axhal::time::wall_time()) {
-                    Some(dur) => {
-                        if wq.wait_timeout(dur) {
-                            // timed out
-                            break;
-                        }
-                    }
-                    None => {
-                        // deadline passed
-                        break;
-                    }
-                }
+        if let Some(sig) = dequeue_signal(&set) {
+            if let Some(info) = nullable!(info.get_as_mut())? {
+                sig.to_ctype(info);
             }
-            _ => wq.wait(),
+            return Ok(sig.signo() as _);
+        }
+        if has_interrupting_signal(&set) {
+            return Err(LinuxError::EINTR);
         }
 
-        if let Some(signal) = dequeue_signal(&set) {
-            if let Some(info) = nullable!(info.get_as_mut())? {
-                signal.to_ctype(info);
+        match deadline {
+            Some(deadline) => {
+                let Some(dur) = deadline
+                    .checked_sub(axhal::time::monotonic_time())
+                    .filter(|dur| !dur.is_zero())
+                else {
+                    return Err(LinuxError::EAGAIN);
+                };
+                wq.wait_timeout_until(dur, woken);
             }
-            return Ok(0);
+            None => wq.wait_until(woken),
         }
     }
-
-    // TODO: EINTR
-
-    Err(LinuxError::EAGAIN)
 }
 
 pub fn sys_rt_sigsuspend(
@@ -255,36 +469,204 @@ pub fn sys_rt_sigsuspend(
 
     let old_blocked = mem::replace(&mut *thr_data.blocked.lock(), *set);
 
+    wait_for_handler(tf, Some(old_blocked))
+}
+
+/// Wait until a signal is delivered to a handler, which returns `EINTR` to
+/// the syscall, or terminates the process.
+///
+/// `restore_blocked` is as in [`check_signals`].
+fn wait_for_handler(tf: &mut TrapFrame, restore_blocked: Option<SignalSet>) -> LinuxResult<isize> {
+    let curr = current();
     tf.set_retval((-LinuxError::EINTR.code() as isize) as usize);
 
     loop {
-        if check_signals(tf, Some(old_blocked)) {
+        if check_signals(tf, restore_blocked) {
             break;
         }
-        curr.task_ext().process_data().signal_wq.wait();
+        curr.task_ext()
+            .process_data()
+            .signal_wq
+            .wait_until(has_pending_signal);
+    }
+
+    // Keep the registers set up for the handler.
+    Ok(tf.retval() as isize)
+}
+
+/// Wait until a signal is delivered to a handler or terminates the process.
+#[cfg(target_arch = "x86_64")]
+pub fn sys_pause(tf: &mut TrapFrame) -> LinuxResult<isize> {
+    wait_for_handler(tf, None)
+}
+
+/// Run the blocking syscall `f` with the signals blocked by the current
+/// thread replaced by `mask` if given, as `epoll_pwait` and the like do.
+///
+/// If `f` is interrupted by a signal, it is delivered while `mask` is still
+/// in effect, and the original blocked set is restored when its handler
+/// returns.
+pub fn with_sigmask(
+    tf: &mut TrapFrame,
+    mask: Option<SignalSet>,
+    f: impl FnOnce() -> LinuxResult<isize>,
+) -> LinuxResult<isize> {
+    let Some(mut mask) = mask else {
+        return f();
+    };
+    mask.remove(SIGKILL);
+    mask.remove(SIGSTOP);
+
+    let curr = current();
+    let thr_data = curr.task_ext().thread_data();
+    let old_blocked = mem::replace(&mut *thr_data.blocked.lock(), mask);
+    let res = f();
+    if matches!(res, Err(LinuxError::EINTR | LinuxError::ERESTART)) {
+        tf.set_retval((-LinuxError::EINTR.code() as isize) as usize);
+        if check_signals(tf, Some(old_blocked)) {
+            // Keep the registers set up for the handler.
+            return Ok(tf.retval() as isize);
+        }
+    }
+    *thr_data.blocked.lock() = old_blocked;
+    res
+}
+
+/// Set the alternate signal stack of the current thread to `ss`, storing the
+/// previous one to `old_ss`.
+pub fn sys_sigaltstack(
+    tf: &TrapFrame,
+    ss: UserConstPtr<stack_t>,
+    old_ss: UserPtr<stack_t>,
+) -> LinuxResult<isize> {
+    let curr = current();
+    let mut stack = curr.task_ext().thread_data().signal_stack.lock();
+    let on_stack = stack.contains(tf.sp());
+    let old = stack_t {
+        ss_sp: stack.base as _,
+        ss_flags: if on_stack {
+            SS_ONSTACK
+        } else if stack.size == 0 {
+            SS_DISABLE
+        } else {
+            0
+        } as _,
+        ss_size: stack.size as _,
+    };
+
+    if let Some(ss) = nullable!(ss.get_as_ref())? {
+        if on_stack {
+            return Err(LinuxError::EPERM);
+        }
+        // `SS_AUTODISARM` is not supported.
+        match ss.ss_flags as u32 {
+            SS_DISABLE => *stack = SignalStack::default(),
+            0 | SS_ONSTACK => {
+                if ss.ss_size < MINSIGSTKSZ as _ {
+                    return Err(LinuxError::ENOMEM);
+                }
+                *stack = SignalStack {
+                    base: ss.ss_sp as _,
+                    size: ss.ss_size as _,
+                };
+            }
+            _ => return Err(LinuxError::EINVAL),
+        }
+    }
+
+    if let Some(old_ss) = nullable!(old_ss.get_as_mut())? {
+        *old_ss = old;
     }
 
     Ok(0)
 }
 
-pub fn send_signal_thread(thr: &Thread, sig: SignalInfo) {
+/// Create a signalfd reading the signals in `mask`, or replace the mask of
+/// the signalfd `fd` if it is not -1.
+pub fn sys_signalfd4(
+    fd: i32,
+    mask: UserConstPtr<SignalSet>,
+    sizemask: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    debug!("sys_signalfd4 <= fd: {}, flags: {:#x}", fd, flags);
+    check_sigset_size(sizemask)?;
+    if flags & !(SFD_CLOEXEC | SFD_NONBLOCK) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let mut mask = *mask.get_as_ref()?;
+    // SIGKILL and SIGSTOP cannot be read from a signalfd
+    mask.remove(SIGKILL);
+    mask.remove(SIGSTOP);
+
+    if fd != -1 {
+        SignalFd::from_fd(fd)?.set_mask(mask);
+        return Ok(fd as _);
+    }
+
+    let signalfd = SignalFd::new(mask, flags & SFD_NONBLOCK != 0);
+    Ok(add_file_like_from(Arc::new(signalfd), 0, flags & SFD_CLOEXEC != 0)? as _)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_signalfd(fd: i32, mask: UserConstPtr<SignalSet>, sizemask: usize) -> LinuxResult<isize> {
+    sys_signalfd4(fd, mask, sizemask, 0)
+}
+
+/// Count one more signal queued to the process of `proc_data` if `sig` is
+/// a real-time signal, failing if it has reached `RLIMIT_SIGPENDING`.
+///
+/// The standard signals are a single pending bit each and never fail.
+fn reserve_queued_signal(proc_data: &ProcessData, sig: &SignalInfo) -> bool {
+    if (sig.signo() as u32) < SIGRTMIN {
+        return true;
+    }
+    let limit = proc_data.rlim.read()[RLIMIT_SIGPENDING].current;
+    proc_data
+        .queued_signals
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            ((n as u64) < limit).then_some(n + 1)
+        })
+        .is_ok()
+}
+
+/// Send the signal `sig` to the thread `thr`.
+///
+/// Return `false` if it is a real-time signal that cannot be queued.
+pub fn send_signal_thread(thr: &Thread, sig: SignalInfo) -> bool {
     info!("Send signal {} to thread {}", sig.signo(), thr.tid());
     let Some(thr_data) = thr.data::<ThreadData>() else {
-        return;
+        return false;
     };
     let Some(proc_data) = thr.process().data::<ProcessData>() else {
-        return;
+        return false;
     };
+    if !reserve_queued_signal(proc_data, &sig) {
+        return false;
+    }
     thr_data.pending.lock().send_signal(sig);
     proc_data.signal_wq.notify_all(false);
+    proc_data.child_exit_wq.notify_all(false);
+    true
 }
-pub fn send_signal_process(proc: &Process, sig: SignalInfo) {
+
+/// Send the signal `sig` to the process `proc`.
+///
+/// Return `false` if it is a real-time signal that cannot be queued.
+pub fn send_signal_process(proc: &Process, sig: SignalInfo) -> bool {
     info!("Send signal {} to process {}", sig.signo(), proc.pid());
     let Some(proc_data) = proc.data::<ProcessData>() else {
-        return;
+        return false;
     };
+    if !reserve_queued_signal(proc_data, &sig) {
+        return false;
+    }
     proc_data.pending.lock().send_signal(sig);
-    proc_data.signal_wq.notify_one(false);
+    // Any thread may take the signal.
+    proc_data.signal_wq.notify_all(false);
+    proc_data.child_exit_wq.notify_all(false);
+    true
 }
 pub fn send_signal_process_group(pg: &ProcessGroup, sig: SignalInfo) -> usize {
     info!("Send signal {} to process group {}", sig.signo(), pg.pgid());
@@ -295,8 +677,10 @@ pub fn send_signal_process_group(pg: &ProcessGroup, sig: SignalInfo) -> usize {
     processes.len()
 }
 
+/// Make the information of the signal `signo` sent by a syscall, or `None`
+/// for the null signal 0, which only checks the target.
 fn make_siginfo(signo: u32, code: u32) -> LinuxResult<Option<SignalInfo>> {
-    if !(1..32).contains(&signo) {
+    if signo > NSIG as u32 {
         return Err(LinuxError::EINVAL);
     }
     if signo == 0 {
@@ -305,53 +689,99 @@ fn make_siginfo(signo: u32, code: u32) -> LinuxResult<Option<SignalInfo>> {
     Ok(Some(SignalInfo::new(signo, code)))
 }
 
+/// Send the signal `sig` to the processes selected by `pid`: the process
+/// `pid` if positive, the process group of the caller if 0, every process
+/// but the init process and the caller if -1, or the process group `-pid`.
+///
+/// The signal 0 is not sent, but the processes must still exist.
 pub fn sys_kill(pid: i32, sig: u32) -> LinuxResult<isize> {
-    let Some(sig) = make_siginfo(sig, SI_USER)? else {
-        // TODO: should also check permissions
-        return Ok(0);
-    };
+    debug!("sys_kill <= pid: {}, sig: {}", pid, sig);
+    // TODO: should also check permissions
+    let sig = make_siginfo(sig, SI_USER)?;
 
     let curr = current();
-    let mut result = 0usize;
-    match pid {
-        1.. => {
-            let proc = get_process(pid as Pid)?;
-            send_signal_process(&proc, sig);
-            result += 1;
-        }
-        0 => {
-            let pg = curr.task_ext().thread.process().group();
-            result += send_signal_process_group(&pg, sig);
-        }
+    let targets = match pid {
+        1.. => vec![get_process(pid as Pid)?],
+        0 => curr.task_ext().thread.process().group().processes(),
         -1 => {
-            for proc in processes() {
-                if proc.is_init() {
-                    // init process
-                    continue;
-                }
-                send_signal_process(&proc, sig.clone());
-                result += 1;
-            }
+            let caller = curr.task_ext().thread.process().pid();
+            processes()
+                .into_iter()
+                .filter(|proc| !proc.is_init() && proc.pid() != caller)
+                .collect()
         }
-        ..-1 => {
-            let pg = get_process_group((-pid) as Pid)?;
-            result += send_signal_process_group(&pg, sig);
+        ..-1 => get_process_group((-pid) as Pid)?.processes(),
+    };
+    if targets.is_empty() {
+        return Err(LinuxError::ESRCH);
+    }
+
+    if let Some(sig) = sig {
+        let single = targets.len() == 1;
+        for proc in targets {
+            if !send_signal_process(&proc, sig.clone()) && single {
+                return Err(LinuxError::EAGAIN);
+            }
         }
     }
+    Ok(0)
+}
+
+/// Send the signal `sig` to the thread `tid`.
+pub fn sys_tkill(tid: Pid, sig: u32) -> LinuxResult<isize> {
+    debug!("sys_tkill <= tid: {}, sig: {}", tid, sig);
+    let sig = make_siginfo(sig, SI_TKILL as u32)?;
 
-    Ok(result as isize)
+    let thr = get_thread(tid)?;
+    if let Some(sig) = sig {
+        if !send_signal_thread(&thr, sig) {
+            return Err(LinuxError::EAGAIN);
+        }
+    }
+    Ok(0)
 }
 
+/// Send the signal `sig` to the thread `tid` of the process `tgid`.
 pub fn sys_tgkill(tgid: Pid, tid: Pid, sig: u32) -> LinuxResult<isize> {
-    let Some(sig) = make_siginfo(sig, SI_TKILL as u32)? else {
-        // TODO: should also check permissions
-        return Ok(0);
-    };
+    debug!("sys_tgkill <= tgid: {}, tid: {}, sig: {}", tgid, tid, sig);
+    let sig = make_siginfo(sig, SI_TKILL as u32)?;
 
     let thr = get_thread(tid)?;
     if thr.process().pid() != tgid {
         return Err(LinuxError::ESRCH);
     }
-    send_signal_thread(&thr, sig);
+    if let Some(sig) = sig {
+        if !send_signal_thread(&thr, sig) {
+            return Err(LinuxError::EAGAIN);
+        }
+    }
+    Ok(0)
+}
+
+/// Send the signal `sig` with the data in `info` to the process `pid`.
+pub fn sys_rt_sigqueueinfo(pid: Pid, sig: u32, info: UserConstPtr<siginfo>) -> LinuxResult<isize> {
+    debug!("sys_rt_sigqueueinfo <= pid: {}, sig: {}", pid, sig);
+    let mut info = *info.get_as_ref()?;
+    if sig > NSIG as u32 {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let proc = get_process(pid)?;
+    // SAFETY: all the variants of the union start with these fields.
+    let fields = unsafe { &mut info.__bindgen_anon_1.__bindgen_anon_1 };
+    // Other processes cannot pretend to be the kernel or `kill`.
+    if (fields.si_code >= 0 || fields.si_code == SI_TKILL)
+        && proc.pid() != current().task_ext().thread.process().pid()
+    {
+        return Err(LinuxError::EPERM);
+    }
+    if sig == 0 {
+        return Ok(0);
+    }
+    fields.si_signo = sig as _;
+
+    if !send_signal_process(&proc, SignalInfo(info)) {
+        return Err(LinuxError::EAGAIN);
+    }
     Ok(0)
 }
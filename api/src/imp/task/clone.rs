@@ -1,3 +1,5 @@
+use core::sync::atomic::Ordering;
+
 use alloc::sync::Arc;
 use axerrno::{LinuxError, LinuxResult};
 use axfs::{CURRENT_DIR, CURRENT_DIR_PATH};
@@ -7,17 +9,26 @@ use axsync::Mutex;
 use axtask::{TaskExtRef, current};
 use bitflags::bitflags;
 use linux_raw_sys::general::{
-    CLONE_CHILD_CLEARTID, CLONE_CHILD_SETTID, CLONE_FILES, CLONE_FS, CLONE_IO, CLONE_NEWCGROUP,
-    CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID, CLONE_NEWUSER, CLONE_NEWUTS,
-    CLONE_PARENT, CLONE_PARENT_SETTID, CLONE_PTRACE, CLONE_SETTLS, CLONE_SIGHAND, CLONE_SYSVSEM,
-    CLONE_THREAD, CLONE_UNTRACED, CLONE_VFORK, CLONE_VM, SIGCHLD,
+    CLONE_ARGS_SIZE_VER0, CLONE_CHILD_CLEARTID, CLONE_CHILD_SETTID, CLONE_FILES, CLONE_FS,
+    CLONE_IO, CLONE_NEWCGROUP, CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID,
+    CLONE_NEWUSER, CLONE_NEWUTS, CLONE_PARENT, CLONE_PARENT_SETTID, CLONE_PTRACE, CLONE_SETTLS,
+    CLONE_SIGHAND, CLONE_SYSVSEM, CLONE_THREAD, CLONE_UNTRACED, CLONE_VFORK, CLONE_VM,
+    SCHED_NORMAL, SCHED_RESET_ON_FORK, SIGCHLD, clone_args,
 };
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use starry_core::{
     mm::copy_from_kernel,
     task::{ProcessData, TaskExt, ThreadData, add_thread_to_table, new_user_task},
 };
 
-use crate::fd::FD_TABLE;
+use crate::{
+    CWD_MOUNT,
+    fd::FD_TABLE,
+    fork_cow_pages, fork_file_mappings, fork_shm_attachments, fork_vmas,
+    path::ROOT_DIR,
+    ptr::{UserConstPtr, UserPtr},
+    share_cow_pages, unshare_cow_pages,
+};
 
 bitflags! {
     /// Options for use with [`sys_clone`].
@@ -85,9 +96,9 @@ bitflags! {
 pub fn sys_clone(
     flags: u32,
     stack: usize,
-    _ptid: usize,
-    _tls: usize,
-    _ctid: usize,
+    ptid: usize,
+    tls: usize,
+    ctid: usize,
 ) -> LinuxResult<isize> {
     const FLAG_MASK: u32 = 0xff;
     let _exit_signal = flags & FLAG_MASK;
@@ -98,16 +109,104 @@ pub fn sys_clone(
         flags, _exit_signal, stack
     );
 
+    clone_task(flags, stack, ptid, tls, ctid)
+}
+
+/// Create a new task with `clone3`, described by the `size` bytes of `args`.
+pub fn sys_clone3(args: UserConstPtr<clone_args>, size: usize) -> LinuxResult<isize> {
+    if size < CLONE_ARGS_SIZE_VER0 as usize {
+        return Err(LinuxError::EINVAL);
+    }
+    if size > PAGE_SIZE_4K {
+        return Err(LinuxError::E2BIG);
+    }
+    let bytes = UserConstPtr::<u8>::from(args.address().as_usize()).get_as_slice(size)?;
+    let known = size.min(size_of::<clone_args>());
+    // The fields unknown to the kernel must be zero.
+    if bytes[known..].iter().any(|&b| b != 0) {
+        return Err(LinuxError::E2BIG);
+    }
+    // SAFETY: all the fields are integers.
+    let mut args: clone_args = unsafe { core::mem::zeroed() };
+    // SAFETY: `known` is within both the struct and the bytes.
+    unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), &mut args as *mut _ as *mut u8, known)
+    };
+
+    info!(
+        "sys_clone3 <= flags: {:#x}, exit_signal: {}, stack: {:#x}, stack_size: {:#x}",
+        args.flags, args.exit_signal, args.stack, args.stack_size
+    );
+    // The signal is in the flags of `clone` instead.
+    if args.flags & 0xff != 0 || args.exit_signal > 0xff || args.set_tid_size != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let flags = u32::try_from(args.flags)
+        .ok()
+        .and_then(CloneFlags::from_bits)
+        .ok_or(LinuxError::EINVAL)?;
+    // The stack grows down from the end of the area.
+    let stack = match (args.stack, args.stack_size) {
+        (0, 0) => 0,
+        (0, _) | (_, 0) => return Err(LinuxError::EINVAL),
+        (stack, size) => stack.checked_add(size).ok_or(LinuxError::EINVAL)? as usize,
+    };
+
+    clone_task(
+        flags,
+        stack,
+        args.parent_tid as _,
+        args.tls as _,
+        args.child_tid as _,
+    )
+}
+
+/// Create a new task as a copy of the current one.
+///
+/// It runs on `stack` if not 0, and has the thread pointer `tls` with
+/// `CLONE_SETTLS`. Its thread ID is stored to `ptid` of the parent with
+/// `CLONE_PARENT_SETTID`, and to `ctid` of the child with
+/// `CLONE_CHILD_SETTID`, where it is cleared on exit with
+/// `CLONE_CHILD_CLEARTID`.
+fn clone_task(
+    flags: CloneFlags,
+    stack: usize,
+    ptid: usize,
+    tls: usize,
+    ctid: usize,
+) -> LinuxResult<isize> {
+    // Threads share the signal handlers, which are only shared in the same
+    // address space.
+    if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::SIGHAND)
+        || flags.contains(CloneFlags::SIGHAND) && !flags.contains(CloneFlags::VM)
+        || flags.contains(CloneFlags::NEWNS | CloneFlags::FS)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
     let curr = current();
     let mut new_task = new_user_task(curr.name());
 
-    // TODO: check SETTLS
     #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
-    new_task
-        .ctx_mut()
-        .set_tls(axhal::arch::read_thread_pointer().into());
+    new_task.ctx_mut().set_tls(
+        if flags.contains(CloneFlags::SETTLS) {
+            tls
+        } else {
+            axhal::arch::read_thread_pointer()
+        }
+        .into(),
+    );
 
     let trap_frame = read_trapframe_from_kstack(curr.get_kernel_stack_top().unwrap());
+    // The thread pointer is a general register.
+    #[cfg(any(target_arch = "riscv64", target_arch = "loongarch64"))]
+    let trap_frame = {
+        let mut trap_frame = trap_frame;
+        if flags.contains(CloneFlags::SETTLS) {
+            trap_frame.regs.tp = tls;
+        }
+        trap_frame
+    };
     let mut new_uctx = UspaceContext::from(&trap_frame);
     if stack != 0 {
         new_uctx.set_sp(stack);
@@ -115,10 +214,11 @@ pub fn sys_clone(
     new_uctx.set_retval(0);
 
     let tid = new_task.id().as_u64() as Pid;
+    if flags.contains(CloneFlags::PARENT_SETTID) {
+        *UserPtr::<Pid>::from(ptid).get_as_mut()? = tid;
+    }
+
     let process = if flags.contains(CloneFlags::THREAD) {
-        if !flags.contains(CloneFlags::VM | CloneFlags::SIGHAND) {
-            return Err(LinuxError::EINVAL);
-        }
         curr.task_ext().thread.process()
     } else {
         // create a new process
@@ -133,22 +233,43 @@ pub fn sys_clone(
             curr.task_ext().thread.process().fork(tid)
         };
 
+        // The child of `vfork` shares the address space as well, and gets a
+        // new one on `execve`.
         let aspace = if flags.contains(CloneFlags::VM) {
             curr.task_ext().process_data().aspace.clone()
         } else {
-            let mut aspace = curr.task_ext().process_data().aspace.lock();
-            let mut aspace = aspace.clone_or_err()?;
+            let parent_aspace = &curr.task_ext().process_data().aspace;
+            let mut parent = parent_aspace.lock();
+            // The private pages are shared copy-on-write instead of copied.
+            share_cow_pages(parent_aspace, &mut parent)?;
+            let mut aspace = parent.clone_or_err()?;
+            drop(parent);
             copy_from_kernel(&mut aspace)?;
-            Arc::new(Mutex::new(aspace))
+            let aspace = Arc::new(Mutex::new(aspace));
+            fork_cow_pages(parent_aspace, &aspace);
+            fork_file_mappings(parent_aspace, &aspace);
+            fork_shm_attachments(parent_aspace, &aspace);
+            fork_vmas(parent_aspace, &aspace);
+            aspace
         };
         new_task
             .ctx_mut()
             .set_page_table_root(aspace.lock().page_table_root());
 
-        let process_data = ProcessData::new(
-            curr.task_ext().process_data().exe_path.read().clone(),
-            aspace,
-        );
+        let parent_data = curr.task_ext().process_data();
+        let mut process_data = ProcessData::new(parent_data.exe_path.read().clone(), aspace);
+        if flags.contains(CloneFlags::FS) {
+            process_data.share_umask(parent_data);
+        } else {
+            process_data.replace_umask(parent_data.umask());
+        }
+        if flags.contains(CloneFlags::SIGHAND) {
+            process_data.signal_actions = parent_data.signal_actions.clone();
+        } else {
+            *process_data.signal_actions.lock() = parent_data.signal_actions.lock().clone();
+        }
+        process_data.set_heap_bottom(parent_data.get_heap_bottom());
+        process_data.set_heap_top(parent_data.get_heap_top());
 
         if flags.contains(CloneFlags::FILES) {
             FD_TABLE
@@ -167,6 +288,12 @@ pub fn sys_clone(
             CURRENT_DIR_PATH
                 .deref_from(&process_data.ns)
                 .init_shared(CURRENT_DIR_PATH.share());
+            CWD_MOUNT
+                .deref_from(&process_data.ns)
+                .init_shared(CWD_MOUNT.share());
+            ROOT_DIR
+                .deref_from(&process_data.ns)
+                .init_shared(ROOT_DIR.share());
         } else {
             CURRENT_DIR
                 .deref_from(&process_data.ns)
@@ -174,14 +301,75 @@ pub fn sys_clone(
             CURRENT_DIR_PATH
                 .deref_from(&process_data.ns)
                 .init_new(CURRENT_DIR_PATH.copy_inner());
+            CWD_MOUNT
+                .deref_from(&process_data.ns)
+                .init_new(CWD_MOUNT.copy_inner());
+            ROOT_DIR
+                .deref_from(&process_data.ns)
+                .init_new(ROOT_DIR.copy_inner());
         }
         &builder.data(process_data).build()
     };
 
-    let thread = process.new_thread(tid).data(ThreadData::new()).build();
+    let thread_data = ThreadData::new();
+    *thread_data.blocked.lock() = *curr.task_ext().thread_data().blocked.lock();
+    if !flags.contains(CloneFlags::VM) {
+        // A thread sharing the memory cannot use the same alternate stack.
+        *thread_data.signal_stack.lock() = *curr.task_ext().thread_data().signal_stack.lock();
+    }
+    if flags.contains(CloneFlags::CHILD_CLEARTID) {
+        thread_data.set_clear_child_tid(ctid);
+    }
+    let affinity = *curr.task_ext().thread_data().affinity.lock();
+    *thread_data.affinity.lock() = affinity;
+    new_task.set_cpumask(affinity);
+    let curr_data = curr.task_ext().thread_data();
+    let mut nice = curr_data.nice.load(Ordering::Acquire);
+    let mut policy = curr_data.sched_policy.load(Ordering::Acquire);
+    let mut priority = curr_data.sched_priority.load(Ordering::Acquire);
+    if policy & SCHED_RESET_ON_FORK != 0 {
+        // The child starts in `SCHED_OTHER` without a raised priority.
+        (nice, policy, priority) = (nice.max(0), SCHED_NORMAL, 0);
+    }
+    thread_data.nice.store(nice, Ordering::Release);
+    thread_data.sched_policy.store(policy, Ordering::Release);
+    thread_data
+        .sched_priority
+        .store(priority, Ordering::Release);
+    // The priority of a task can only be set by itself.
+    thread_data
+        .nice_changed
+        .store(nice != 0 || policy != SCHED_NORMAL, Ordering::Release);
+    let thread = process.new_thread(tid).data(thread_data).build();
     add_thread_to_table(&thread);
+    if flags.contains(CloneFlags::CHILD_SETTID) {
+        let proc_data: &ProcessData = thread.process().data().unwrap();
+        let mut aspace = proc_data.aspace.lock();
+        let vaddr = VirtAddr::from(ctid);
+        unshare_cow_pages(&proc_data.aspace, &mut aspace, vaddr, size_of::<u32>());
+        // Failing to store it does not fail the creation, as on Linux.
+        if let Err(err) = aspace
+            .populate_area(vaddr.align_down_4k(), PAGE_SIZE_4K)
+            .and_then(|_| aspace.write(vaddr, &tid.to_ne_bytes()))
+        {
+            warn!("failed to store the child tid to {:#x}: {:?}", ctid, err);
+        }
+    }
+    let child = thread.process().clone();
+    let child_data: &ProcessData = child.data().unwrap();
+    if flags.contains(CloneFlags::VFORK) {
+        child_data.vfork_pending.store(true, Ordering::Release);
+    }
     new_task.init_task_ext(TaskExt::new(new_uctx, thread));
-    axtask::spawn_task(new_task);
+    let new_task = axtask::spawn_task(new_task);
+    *new_task.task_ext().thread_data().task.lock() = Arc::downgrade(&new_task);
+
+    if flags.contains(CloneFlags::VFORK) {
+        curr.task_ext()
+            .process_data()
+            .child_exit_wq
+            .wait_until(|| !child_data.vfork_pending.load(Ordering::Acquire) || child.is_zombie());
+    }
 
     Ok(tid as _)
 }
@@ -195,3 +383,10 @@ fn read_trapframe_from_kstack(kstack_top: usize) -> TrapFrame {
 pub fn sys_fork() -> LinuxResult<isize> {
     sys_clone(SIGCHLD, 0, 0, 0, 0)
 }
+
+/// Create a child process and suspend the current one until the child calls
+/// `execve` or exits.
+#[cfg(target_arch = "x86_64")]
+pub fn sys_vfork() -> LinuxResult<isize> {
+    sys_clone(CLONE_VFORK | CLONE_VM | SIGCHLD, 0, 0, 0, 0)
+}
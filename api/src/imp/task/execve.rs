@@ -1,19 +1,72 @@
-use core::ffi::c_char;
+use core::{
+    ffi::{c_char, c_int},
+    sync::atomic::Ordering,
+};
 
-use alloc::{string::ToString, vec::Vec};
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use axerrno::{LinuxError, LinuxResult};
-use axhal::arch::UspaceContext;
-use axtask::{TaskExtRef, current};
-use starry_core::mm::{load_user_app, map_trampoline};
+use axhal::{arch::UspaceContext, paging::MappingFlags};
+use axsignal::ctypes::{SignalAction, SignalDisposition};
+use axsync::Mutex;
+use axtask::{TaskExtRef, TaskInner, current};
+use linux_raw_sys::general::{
+    AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW, S_IFMT, S_IFREG, SIGKILL,
+};
+use memory_addr::{VirtAddr, VirtAddrRange};
+use starry_core::{
+    mm::{
+        UserApp, copy_from_kernel, load_user_app, map_trampoline, new_user_aspace_empty,
+        read_user_app,
+    },
+    task::{ProcessData, SignalStack},
+};
 
-use crate::ptr::UserConstPtr;
+use super::do_exit;
+use crate::{
+    add_vma, clear_user_mappings,
+    fd::FD_TABLE,
+    path::{FilePath, SYMLINK_MANAGER, handle_file_path, resolve_symlinks},
+    ptr::UserConstPtr,
+    stat_at_path,
+};
 
 pub fn sys_execve(
     path: UserConstPtr<c_char>,
     argv: UserConstPtr<UserConstPtr<c_char>>,
     envp: UserConstPtr<UserConstPtr<c_char>>,
 ) -> LinuxResult<isize> {
-    let path = path.get_as_str()?.to_string();
+    sys_execveat(AT_FDCWD, path, argv, envp, 0)
+}
+
+/// Execute the program at `path` relative to `dirfd`, or the file `dirfd`
+/// refers to if `path` is empty and `AT_EMPTY_PATH` is given.
+///
+/// A symbolic link is not followed if `AT_SYMLINK_NOFOLLOW` is given.
+pub fn sys_execveat(
+    dirfd: c_int,
+    path: UserConstPtr<c_char>,
+    argv: UserConstPtr<UserConstPtr<c_char>>,
+    envp: UserConstPtr<UserConstPtr<c_char>>,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let path = path.get_as_str()?;
+    if flags & !(AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if path.is_empty() && flags & AT_EMPTY_PATH == 0 {
+        return Err(LinuxError::ENOENT);
+    }
+    let no_follow = flags & AT_SYMLINK_NOFOLLOW != 0;
+    let path = resolve_symlinks(&handle_file_path(dirfd, path)?, !no_follow)?;
+    if no_follow && SYMLINK_MANAGER.is_symlink(path.as_str()) {
+        return Err(LinuxError::ELOOP);
+    }
+    check_executable(&path)?;
+    let path = path.as_str().to_string();
 
     let args = argv
         .get_as_null_terminated()?
@@ -36,17 +89,44 @@ pub fn sys_execve(
 
     // TODO: handle multi-thread case
 
-    let mut aspace = curr_ext.process_data().aspace.lock();
-    aspace.unmap_user_areas()?;
-    map_trampoline(&mut aspace)?;
-    axhal::arch::flush_tlb(None);
+    // The old program is kept if the new one cannot be read.
+    let app = read_user_app(&path, &args).inspect_err(|err| {
+        error!("Failed to read app {}: {:?}", path, err);
+    })?;
+
+    let shared = Arc::strong_count(&curr_ext.process_data().aspace) > 1;
+    let (entry_point, user_stack_base, brk, areas) = if shared {
+        // The address space is shared with other processes, such as the
+        // parent suspended by `vfork`, so the program is loaded to a new one.
+        load_to_new_aspace(&app, &envs).inspect_err(|err| {
+            error!("Failed to load app {}: {:?}", path, err);
+        })?
+    } else {
+        let mut aspace = curr_ext.process_data().aspace.lock();
+        clear_user_mappings(&curr_ext.process_data().aspace, &mut aspace)?;
+        map_trampoline(&mut aspace)?;
+        axhal::arch::flush_tlb(None);
 
-    let (entry_point, user_stack_base) =
-        load_user_app(&mut aspace, &args, &envs).map_err(|_| {
-            error!("Failed to load app {}", path);
-            LinuxError::ENOENT
-        })?;
-    drop(aspace);
+        match load_user_app(&mut aspace, &app, &envs) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                // There is no program to return to.
+                error!("Failed to load app {}: {:?}", path, err);
+                drop(aspace);
+                do_exit(SIGKILL as _, true);
+            }
+        }
+    };
+    for (range, flags) in areas {
+        add_vma(
+            &curr_ext.process_data().aspace,
+            range.start,
+            range.size(),
+            flags,
+        );
+    }
+    curr_ext.process_data().set_heap_bottom(brk.as_usize());
+    curr_ext.process_data().set_heap_top(brk.as_usize());
 
     let name = path
         .rsplit_once('/')
@@ -54,8 +134,94 @@ pub fn sys_execve(
     curr.set_name(name);
     *curr_ext.process_data().exe_path.write() = path;
 
-    // TODO: fd close-on-exec
+    FD_TABLE.close_on_exec();
+    curr_ext
+        .process_data()
+        .execed
+        .store(true, Ordering::Relaxed);
+    // Resume the parent suspended by `vfork`.
+    if curr_ext
+        .process_data()
+        .vfork_pending
+        .swap(false, Ordering::AcqRel)
+    {
+        if let Some(data) = curr_ext
+            .thread
+            .process()
+            .parent()
+            .and_then(|parent| parent.data::<ProcessData>())
+        {
+            data.child_exit_wq.notify_all(false);
+        }
+    }
+    // The handlers are gone with the old program, while ignored signals stay
+    // ignored.
+    for action in curr_ext.process_data().signal_actions.lock().iter_mut() {
+        if matches!(action.disposition, SignalDisposition::Handler(_)) {
+            *action = SignalAction::default();
+        }
+    }
+    *curr_ext.thread_data().signal_stack.lock() = SignalStack::default();
 
     let uctx = UspaceContext::new(entry_point.as_usize(), user_stack_base, 0);
     unsafe { uctx.enter_uspace(curr.kernel_stack_top().expect("No kernel stack top")) }
 }
+
+/// Check that the program at `path` is a regular file with some execute
+/// permission bits set, as even root may not execute it otherwise.
+fn check_executable(path: &FilePath) -> LinuxResult {
+    // The programs in `/bin` are provided by busybox.
+    if path.as_str().starts_with("/bin/") {
+        return Ok(());
+    }
+    let kstat = stat_at_path(path, false)?;
+    if kstat.mode & S_IFMT != S_IFREG || kstat.mode & 0o111 == 0 {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(())
+}
+
+/// Load `app` to a new address space, which replaces the one shared by the
+/// current process with its parent, and switch to it.
+///
+/// The old program is kept if `app` cannot be loaded.
+#[allow(clippy::type_complexity)]
+fn load_to_new_aspace(
+    app: &UserApp,
+    envs: &[String],
+) -> LinuxResult<(
+    VirtAddr,
+    VirtAddr,
+    VirtAddr,
+    Vec<(VirtAddrRange, MappingFlags)>,
+)> {
+    let mut aspace = new_user_aspace_empty()?;
+    map_trampoline(&mut aspace)?;
+    let loaded = load_user_app(&mut aspace, app, envs)?;
+    // The kernel mappings are copied last, as they must be cleared before
+    // the address space is dropped.
+    copy_from_kernel(&mut aspace)?;
+    let root = aspace.page_table_root();
+
+    let curr = current();
+    if !curr
+        .task_ext()
+        .process_data()
+        .aspace
+        .replace(Arc::new(Mutex::new(aspace)))
+    {
+        unreachable!("the address space of a vfork child is replaced twice");
+    }
+    // SAFETY: the context of the running task is only used when it is
+    // switched out, which saves the page table root set here.
+    unsafe {
+        let task = &**curr as *const TaskInner as *mut TaskInner;
+        (*task).ctx_mut().set_page_table_root(root);
+        #[cfg(any(target_arch = "aarch64", target_arch = "loongarch64"))]
+        axhal::arch::write_page_table_root0(root);
+        #[cfg(not(any(target_arch = "aarch64", target_arch = "loongarch64")))]
+        axhal::arch::write_page_table_root(root);
+    }
+    axhal::arch::flush_tlb(None);
+    Ok(loaded)
+}
@@ -1,27 +1,47 @@
+use core::sync::atomic::Ordering;
+
+use alloc::sync::Arc;
 use axsignal::ctypes::SignalInfo;
 use axtask::{TaskExtRef, current};
-use linux_raw_sys::general::{SI_KERNEL, SIGCHLD, SIGKILL};
-use starry_core::task::ProcessData;
+use linux_raw_sys::general::{SI_KERNEL, SI_USER, SIGCHLD, SIGKILL};
+use starry_core::task::{ProcessData, time_stat_exit};
 
-use crate::{fd::FD_TABLE, send_signal_process, send_signal_thread};
+use super::{futex_exit_pi, futex_wake, remove_rt_thread};
+use crate::{
+    clear_user_mappings, fd::FD_TABLE, ptr::UserPtr, send_signal_process, send_signal_thread,
+};
 
 pub fn do_exit(exit_code: i32, group_exit: bool) -> ! {
     let curr = current();
-    let clear_child_tid = curr.task_ext().thread_data().clear_child_tid() as *mut i32;
-    if !clear_child_tid.is_null() {
-        // TODO: check whether the address is valid
-        unsafe {
-            // TODO: Encapsulate all operations that access user-mode memory into a unified function
-            *(clear_child_tid) = 0;
+    let clear_child_tid = curr.task_ext().thread_data().clear_child_tid();
+    if clear_child_tid != 0 {
+        // Tell the threads joining this one that it has exited. An invalid
+        // address is ignored, as on Linux.
+        if let Ok(tid) = UserPtr::<i32>::from(clear_child_tid).get_as_mut() {
+            *tid = 0;
+            let _ = futex_wake(clear_child_tid, 1);
         }
-        // TODO: wake up threads, which are blocked by futex, and waiting for the address pointed by clear_child_tid
     }
+    futex_exit_pi();
+    remove_rt_thread();
 
     let thread = &curr.task_ext().thread;
     info!("{:?} exit with code: {}", thread, exit_code);
     let process = thread.process();
+    // Before the parent may reap the process.
+    time_stat_exit();
     if thread.exit(exit_code) {
+        // The children are given to another parent on exit.
+        let children = process.children();
         process.exit();
+        for child in children {
+            let signo = child
+                .data::<ProcessData>()
+                .map_or(0, |data| data.pdeath_signal.load(Ordering::Relaxed));
+            if signo != 0 {
+                send_signal_process(&child, SignalInfo::new(signo as _, SI_USER));
+            }
+        }
         if let Some(parent) = process.parent() {
             send_signal_process(&parent, SignalInfo::new(SIGCHLD, SI_KERNEL));
             if let Some(data) = parent.data::<ProcessData>() {
@@ -32,8 +52,19 @@ pub fn do_exit(exit_code: i32, group_exit: bool) -> ! {
         // TODO: clear namespace resources
         // FIXME: axns should drop all the resources
         FD_TABLE.clear();
+
+        // The mappings hold files, locks and dirty shared pages, which are
+        // released now unless another process shares the address space.
+        let aspace = &curr.task_ext().process_data().aspace;
+        if Arc::strong_count(aspace) == 1 {
+            if let Err(e) = clear_user_mappings(aspace, &mut aspace.lock()) {
+                warn!("failed to clear the mappings of {}: {:?}", process.pid(), e);
+            }
+        }
     }
-    if group_exit {
+    // Only the first thread exiting the group kills the others, so that the
+    // status of the process is its own.
+    if group_exit && !process.is_group_exited() {
         process.group_exit();
         let sig = SignalInfo::new(SIGKILL, SI_KERNEL);
         for thr in process.threads() {
@@ -43,10 +74,13 @@ pub fn do_exit(exit_code: i32, group_exit: bool) -> ! {
     axtask::exit(exit_code)
 }
 
+/// Terminate the calling thread only.
 pub fn sys_exit(exit_code: i32) -> ! {
     do_exit(exit_code << 8, false)
 }
 
+/// Terminate all the threads of the calling process, which stop at their
+/// next return to user space.
 pub fn sys_exit_group(exit_code: i32) -> ! {
     do_exit(exit_code << 8, true)
 }
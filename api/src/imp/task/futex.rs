@@ -0,0 +1,534 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use alloc::{sync::Arc, vec::Vec};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::{
+    mem::phys_to_virt,
+    paging::MappingFlags,
+    time::{TimeValue, monotonic_time},
+};
+use axprocess::Process;
+use axsync::Mutex;
+use axtask::{TaskExtRef, current};
+use linux_raw_sys::general::{
+    FUTEX_BITSET_MATCH_ANY, FUTEX_CLOCK_REALTIME, FUTEX_CMD_MASK, FUTEX_CMP_REQUEUE, FUTEX_LOCK_PI,
+    FUTEX_OWNER_DIED, FUTEX_PRIVATE_FLAG, FUTEX_REQUEUE, FUTEX_TID_MASK, FUTEX_TRYLOCK_PI,
+    FUTEX_UNLOCK_PI, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAITERS, FUTEX_WAKE, FUTEX_WAKE_BITSET,
+    timespec,
+};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr, VirtAddrRange};
+use starry_core::task::{ProcessData, ThreadData, get_thread};
+
+use super::{task_priority, timespec_duration, update_priority};
+use crate::{
+    has_pending_signal, is_cow_page,
+    ptr::{UserConstPtr, UserPtr},
+    time::realtime,
+};
+
+/// The identity of a futex.
+///
+/// A futex is identified by its address in an address space, and also by
+/// its physical address unless it is private (`FUTEX_PRIVATE_FLAG`) or in a
+/// copy-on-write page, so that futexes in memory shared by processes work.
+/// Copy-on-write pages are private to each process even while their frames
+/// are shared.
+#[derive(Clone, Copy)]
+struct FutexKey {
+    aspace: usize,
+    addr: usize,
+    paddr: Option<PhysAddr>,
+}
+
+impl FutexKey {
+    /// Get the key of the futex at `addr` of the current process, which must
+    /// be an aligned and readable address.
+    fn new(addr: usize, private: bool) -> LinuxResult<Self> {
+        if addr % size_of::<u32>() != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let curr = current();
+        let aspace = &curr.task_ext().process_data().aspace;
+        let vaddr = VirtAddr::from(addr);
+        let mut guard = aspace.lock();
+        if !guard.check_region_access(
+            VirtAddrRange::from_start_size(vaddr, size_of::<u32>()),
+            MappingFlags::READ,
+        ) {
+            return Err(LinuxError::EFAULT);
+        }
+        let paddr = if private || is_cow_page(aspace, vaddr) {
+            None
+        } else {
+            guard.populate_area(vaddr.align_down_4k(), PAGE_SIZE_4K)?;
+            let (paddr, ..) = guard
+                .page_table()
+                .query(vaddr.align_down_4k())
+                .map_err(|_| LinuxError::EFAULT)?;
+            Some(paddr + vaddr.align_offset_4k())
+        };
+        Ok(Self {
+            aspace: Arc::as_ptr(aspace) as usize,
+            addr,
+            paddr,
+        })
+    }
+
+    /// Check whether the keys identify the same futex.
+    ///
+    /// The virtual address is also compared, as the physical address is
+    /// only known for the futexes that are not private.
+    fn matches(&self, other: &Self) -> bool {
+        (self.aspace == other.aspace && self.addr == other.addr)
+            || (self.paddr.is_some() && self.paddr == other.paddr)
+    }
+}
+
+/// A thread waiting on a futex.
+struct FutexWaiter {
+    key: FutexKey,
+    /// Set when the waiter is woken, which is checked by the waiting thread.
+    woken: Arc<AtomicBool>,
+    /// The process of the waiting thread, which sleeps on its signal wait
+    /// queue.
+    process: Arc<Process>,
+    /// The waiter is only woken by the wakeups whose bitsets intersect this.
+    bitset: u32,
+    /// Whether the waiter is taking a PI futex.
+    pi: bool,
+    /// The TID of the waiting thread.
+    tid: u32,
+    /// The owner of the PI futex the waiter is taking, or 0.
+    owner: u32,
+    /// The priority of the waiting thread as a nice value, which the owner
+    /// of the PI futex is boosted to.
+    priority: i32,
+}
+
+impl FutexWaiter {
+    fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(data) = self.process.data::<ProcessData>() {
+            data.signal_wq.notify_all(false);
+        }
+    }
+}
+
+/// The threads waiting on futexes, in the order they started waiting.
+static FUTEX_WAITERS: Mutex<Vec<FutexWaiter>> = Mutex::new(Vec::new());
+
+/// Wake at most `count` threads waiting on the futex `key` with bitsets
+/// intersecting `bitset`, and return the number of them.
+fn wake_key(key: &FutexKey, count: usize, bitset: u32) -> usize {
+    let mut waiters = FUTEX_WAITERS.lock();
+    let mut woken = 0;
+    waiters.retain(|waiter| {
+        if woken == count || waiter.pi || !waiter.key.matches(key) || waiter.bitset & bitset == 0 {
+            return true;
+        }
+        waiter.wake();
+        woken += 1;
+        false
+    });
+    woken
+}
+
+/// Wake at most `wake` threads waiting on the futex `key`, and move at most
+/// `requeue` of the others to wait on the futex `key2`, if the futex at
+/// `uaddr` contains `val` when it is given.
+///
+/// Return the number of threads woken or moved.
+fn requeue_key(
+    key: &FutexKey,
+    key2: FutexKey,
+    wake: usize,
+    requeue: usize,
+    cmp: Option<(UserConstPtr<u32>, u32)>,
+) -> LinuxResult<usize> {
+    let mut waiters = FUTEX_WAITERS.lock();
+    if let Some((uaddr, val)) = cmp {
+        if *uaddr.get_as_ref()? != val {
+            return Err(LinuxError::EAGAIN);
+        }
+    }
+    let (mut woken, mut moved) = (0, 0);
+    waiters.retain_mut(|waiter| {
+        if waiter.pi || !waiter.key.matches(key) {
+            return true;
+        }
+        if woken < wake {
+            waiter.wake();
+            woken += 1;
+            return false;
+        }
+        if moved < requeue {
+            waiter.key = key2;
+            moved += 1;
+        }
+        true
+    });
+    Ok(woken + moved)
+}
+
+/// Wake at most `count` threads waiting on the futex at `addr` of the current
+/// process, and return the number of them.
+pub fn futex_wake(addr: usize, count: usize) -> LinuxResult<usize> {
+    Ok(wake_key(
+        &FutexKey::new(addr, false)?,
+        count,
+        FUTEX_BITSET_MATCH_ANY,
+    ))
+}
+
+/// Sleep until `woken` is set, a signal arrives, or the monotonic time
+/// `deadline`, after which the waiter with `woken` is removed.
+fn wait_woken(woken: &Arc<AtomicBool>, deadline: Option<TimeValue>) -> LinuxResult {
+    let is_woken = || woken.load(Ordering::Acquire);
+    let signal_wq = &current().task_ext().process_data().signal_wq;
+    let result = loop {
+        if is_woken() {
+            return Ok(());
+        }
+        if has_pending_signal() {
+            break Err(LinuxError::EINTR);
+        }
+        match deadline {
+            Some(deadline) => {
+                let Some(remaining) = deadline
+                    .checked_sub(monotonic_time())
+                    .filter(|remaining| !remaining.is_zero())
+                else {
+                    break Err(LinuxError::ETIMEDOUT);
+                };
+                signal_wq.wait_timeout_until(remaining, || is_woken() || has_pending_signal());
+            }
+            None => signal_wq.wait_until(|| is_woken() || has_pending_signal()),
+        }
+    };
+    let mut waiters = FUTEX_WAITERS.lock();
+    waiters.retain(|waiter| !Arc::ptr_eq(&waiter.woken, woken));
+    // A wakeup that came before the waiter is removed is consumed.
+    if is_woken() {
+        return Ok(());
+    }
+    result
+}
+
+/// Wait on the futex `key` at `uaddr` with `bitset` if it contains `val`,
+/// until woken, a signal arrives, or the monotonic time `deadline`.
+fn wait_key(
+    key: FutexKey,
+    uaddr: UserConstPtr<u32>,
+    val: u32,
+    bitset: u32,
+    deadline: Option<TimeValue>,
+) -> LinuxResult<isize> {
+    let woken = Arc::new(AtomicBool::new(false));
+    {
+        // The value is checked with the waiters locked, so that a wakeup
+        // after changing it is not missed.
+        let mut waiters = FUTEX_WAITERS.lock();
+        if *uaddr.get_as_ref()? != val {
+            return Err(LinuxError::EAGAIN);
+        }
+        waiters.push(FutexWaiter {
+            key,
+            woken: woken.clone(),
+            process: current().task_ext().thread.process().clone(),
+            bitset,
+            pi: false,
+            tid: current().id().as_u64() as _,
+            owner: 0,
+            priority: 0,
+        });
+    }
+    wait_woken(&woken, deadline)?;
+    Ok(0)
+}
+
+/// Get the futex word at `uaddr` of the current process, which is also
+/// accessed by user space atomically.
+fn futex_word(uaddr: UserConstPtr<u32>) -> LinuxResult<&'static AtomicU32> {
+    let word = UserPtr::<u32>::from(uaddr.address().as_usize()).get_as_mut()?;
+    // SAFETY: The word is valid and aligned, and only accessed atomically.
+    Ok(unsafe { AtomicU32::from_ptr(word) })
+}
+
+/// Get the monotonic time of the absolute time `timeout` of
+/// `CLOCK_REALTIME`.
+///
+/// Changes of the real time after this are not followed.
+fn realtime_deadline(timeout: UserConstPtr<timespec>) -> LinuxResult<TimeValue> {
+    let deadline = timespec_duration(timeout)?;
+    Ok(monotonic_time() + deadline.saturating_sub(realtime()))
+}
+
+/// Boost the thread `owner` to the highest priority of the threads in
+/// `waiters` taking its PI futexes, or drop its boost if there are none.
+fn update_pi_boost(waiters: &[FutexWaiter], owner: u32) {
+    let Ok(thread) = get_thread(owner as _) else {
+        return;
+    };
+    let Some(thr_data) = thread.data::<ThreadData>() else {
+        return;
+    };
+    let boost = waiters
+        .iter()
+        .filter(|waiter| waiter.pi && waiter.owner == owner)
+        .map(|waiter| waiter.priority)
+        .min()
+        .unwrap_or(i32::MAX);
+    if thr_data.pi_nice.swap(boost, Ordering::AcqRel) != boost
+        && update_priority(&thread, thr_data).is_err()
+    {
+        warn!("failed to boost the priority of thread {}", owner);
+    }
+}
+
+/// Hand the PI futex in `word` to the waiter at `pos` of `waiters`, which
+/// is removed and woken, with `flags` set besides its TID and
+/// `FUTEX_WAITERS`.
+///
+/// The other waiters then wait for the new owner, which is boosted by them.
+fn hand_over_pi(waiters: &mut Vec<FutexWaiter>, pos: usize, word: &AtomicU32, flags: u32) {
+    let next = waiters.remove(pos);
+    word.store(next.tid | FUTEX_WAITERS | flags, Ordering::Release);
+    for waiter in waiters.iter_mut() {
+        if waiter.pi && waiter.key.matches(&next.key) {
+            waiter.owner = next.tid;
+        }
+    }
+    next.wake();
+    update_pi_boost(waiters, next.tid);
+}
+
+/// Lock the PI futex `key` at `uaddr` for the current thread, sleeping until
+/// the monotonic time `deadline` unless `try_lock` is set.
+///
+/// A lock whose owner has exited is taken over with `FUTEX_OWNER_DIED` set.
+/// While the current thread sleeps, the owner is boosted to its priority,
+/// and the lock is handed to it when unlocked.
+fn lock_pi(
+    key: FutexKey,
+    uaddr: UserConstPtr<u32>,
+    deadline: Option<TimeValue>,
+    try_lock: bool,
+) -> LinuxResult<isize> {
+    let curr = current();
+    let tid = curr.id().as_u64() as u32;
+    loop {
+        let woken = Arc::new(AtomicBool::new(false));
+        {
+            let mut waiters = FUTEX_WAITERS.lock();
+            let word = futex_word(uaddr)?;
+            let value = word.load(Ordering::Acquire);
+            let owner = value & FUTEX_TID_MASK;
+            if owner == tid {
+                return Err(LinuxError::EDEADLK);
+            }
+            if owner == 0 || get_thread(owner as _).is_err() {
+                let mut new = tid | (value & FUTEX_OWNER_DIED);
+                if owner != 0 {
+                    new |= FUTEX_OWNER_DIED;
+                }
+                if waiters.iter().any(|waiter| waiter.key.matches(&key)) {
+                    new |= FUTEX_WAITERS;
+                }
+                if word
+                    .compare_exchange(value, new, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    return Ok(0);
+                }
+                continue;
+            }
+            if try_lock {
+                return Err(LinuxError::EAGAIN);
+            }
+            // The owner has to unlock it with `FUTEX_UNLOCK_PI`.
+            if word
+                .compare_exchange(
+                    value,
+                    value | FUTEX_WAITERS,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_err()
+            {
+                continue;
+            }
+            waiters.push(FutexWaiter {
+                key,
+                woken: woken.clone(),
+                process: curr.task_ext().thread.process().clone(),
+                bitset: FUTEX_BITSET_MATCH_ANY,
+                pi: true,
+                tid,
+                owner,
+                priority: task_priority(curr.task_ext().thread_data()) as _,
+            });
+            update_pi_boost(&waiters, owner);
+        }
+        // The lock is handed to the current thread when it is woken.
+        let result = wait_woken(&woken, deadline);
+        if result.is_err() {
+            update_pi_boost(&FUTEX_WAITERS.lock(), owner);
+        }
+        return match result {
+            Ok(()) => Ok(0),
+            // Taking a lock is restarted after a signal, as on Linux.
+            Err(LinuxError::EINTR) => Err(LinuxError::ERESTART),
+            Err(err) => Err(err),
+        };
+    }
+}
+
+/// Unlock the PI futex `key` at `uaddr` owned by the current thread, handing
+/// it to the first waiter if any, so that it cannot be taken by others
+/// before the waiter runs.
+fn unlock_pi(key: FutexKey, uaddr: UserConstPtr<u32>) -> LinuxResult<isize> {
+    let tid = current().id().as_u64() as u32;
+    let mut waiters = FUTEX_WAITERS.lock();
+    let word = futex_word(uaddr)?;
+    if word.load(Ordering::Acquire) & FUTEX_TID_MASK != tid {
+        return Err(LinuxError::EPERM);
+    }
+    match waiters
+        .iter()
+        .position(|waiter| waiter.pi && waiter.key.matches(&key))
+    {
+        Some(pos) => hand_over_pi(&mut waiters, pos, word, 0),
+        None => word.store(0, Ordering::Release),
+    }
+    update_pi_boost(&waiters, tid);
+    Ok(0)
+}
+
+/// Release the PI futexes with waiters owned by the exiting current thread,
+/// handing each to its first waiter with `FUTEX_OWNER_DIED` set.
+pub fn futex_exit_pi() {
+    let curr = current();
+    let tid = curr.id().as_u64() as u32;
+    let aspace = Arc::as_ptr(&curr.task_ext().process_data().aspace) as usize;
+    let mut waiters = FUTEX_WAITERS.lock();
+    let keys = waiters
+        .iter()
+        .filter(|waiter| waiter.pi && waiter.owner == tid)
+        .map(|waiter| waiter.key)
+        .collect::<Vec<_>>();
+    for key in keys {
+        let word = if key.aspace == aspace {
+            match futex_word(key.addr.into()) {
+                Ok(word) => word,
+                Err(_) => continue,
+            }
+        } else if let Some(paddr) = key.paddr {
+            // SAFETY: The shared page is still mapped by the waiting process.
+            unsafe { AtomicU32::from_ptr(phys_to_virt(paddr).as_mut_ptr_of()) }
+        } else {
+            continue;
+        };
+        // A futex with several waiters is handed over once.
+        if word.load(Ordering::Acquire) & FUTEX_TID_MASK != tid {
+            continue;
+        }
+        if let Some(pos) = waiters
+            .iter()
+            .position(|waiter| waiter.pi && waiter.key.matches(&key))
+        {
+            hand_over_pi(&mut waiters, pos, word, FUTEX_OWNER_DIED);
+        }
+    }
+}
+
+/// Operate on the futex at `uaddr`.
+///
+/// `timeout` is a pointer to a relative `timespec` for `FUTEX_WAIT`, which
+/// sleeps if the futex contains `val`, and is ignored by `FUTEX_WAKE`, which
+/// wakes at most `val` waiters. `FUTEX_REQUEUE` and `FUTEX_CMP_REQUEUE` also
+/// move at most `timeout` of the other waiters to the futex at `uaddr2`, the
+/// latter only if the futex contains `val3`.
+///
+/// `FUTEX_WAIT_BITSET` and `FUTEX_WAKE_BITSET` are like `FUTEX_WAIT` and
+/// `FUTEX_WAKE` with the bitset `val3`, which must not be zero, and a waiter
+/// is only woken by a wakeup whose bitset intersects its own. The timeout of
+/// `FUTEX_WAIT_BITSET` is an absolute time of `CLOCK_MONOTONIC`, or of
+/// `CLOCK_REALTIME` if `FUTEX_CLOCK_REALTIME` is in `op`.
+///
+/// `FUTEX_LOCK_PI`, `FUTEX_TRYLOCK_PI` and `FUTEX_UNLOCK_PI` operate on the
+/// futex as a lock, which holds the TID of its owner with `FUTEX_WAITERS`
+/// set if there are waiters, and the timeout of `FUTEX_LOCK_PI` is an
+/// absolute time of `CLOCK_REALTIME`. The owner runs with the highest
+/// priority of the waiters from its next return to user space, and the
+/// unlocked futex is handed to the first waiter.
+pub fn sys_futex(
+    uaddr: UserConstPtr<u32>,
+    op: u32,
+    val: u32,
+    timeout: usize,
+    uaddr2: usize,
+    val3: u32,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_futex <= uaddr: {:?}, op: {:#x}, val: {}, timeout: {:#x}, uaddr2: {:#x}, val3: {:#x}",
+        uaddr.address(),
+        op,
+        val,
+        timeout,
+        uaddr2,
+        val3
+    );
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    let cmd = op & FUTEX_CMD_MASK as u32;
+    if op & FUTEX_CLOCK_REALTIME != 0 && cmd != FUTEX_WAIT_BITSET {
+        return Err(LinuxError::ENOSYS);
+    }
+    if matches!(cmd, FUTEX_WAIT_BITSET | FUTEX_WAKE_BITSET) && val3 == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let key = FutexKey::new(uaddr.address().as_usize(), private)?;
+    match cmd {
+        FUTEX_WAIT => {
+            let timeout = UserConstPtr::<timespec>::from(timeout);
+            let deadline = if timeout.is_null() {
+                None
+            } else {
+                Some(monotonic_time() + timespec_duration(timeout)?)
+            };
+            wait_key(key, uaddr, val, FUTEX_BITSET_MATCH_ANY, deadline)
+        }
+        FUTEX_WAIT_BITSET => {
+            let timeout = UserConstPtr::<timespec>::from(timeout);
+            let deadline = if timeout.is_null() {
+                None
+            } else if op & FUTEX_CLOCK_REALTIME != 0 {
+                Some(realtime_deadline(timeout)?)
+            } else {
+                Some(timespec_duration(timeout)?)
+            };
+            wait_key(key, uaddr, val, val3, deadline)
+        }
+        FUTEX_WAKE => Ok(wake_key(&key, val as usize, FUTEX_BITSET_MATCH_ANY) as _),
+        FUTEX_WAKE_BITSET => Ok(wake_key(&key, val as usize, val3) as _),
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+            if (val as i32) < 0 || (timeout as i32) < 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let key2 = FutexKey::new(uaddr2, private)?;
+            let cmp = (cmd == FUTEX_CMP_REQUEUE).then_some((uaddr, val3));
+            Ok(requeue_key(&key, key2, val as usize, timeout, cmp)? as _)
+        }
+        FUTEX_LOCK_PI => {
+            let timeout = UserConstPtr::<timespec>::from(timeout);
+            let deadline = if timeout.is_null() {
+                None
+            } else {
+                Some(realtime_deadline(timeout)?)
+            };
+            lock_pi(key, uaddr, deadline, false)
+        }
+        FUTEX_TRYLOCK_PI => lock_pi(key, uaddr, None, true),
+        FUTEX_UNLOCK_PI => unlock_pi(key, uaddr),
+        _ => Err(LinuxError::ENOSYS),
+    }
+}
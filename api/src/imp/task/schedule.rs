@@ -1,47 +1,537 @@
+use core::{ops::RangeInclusive, sync::atomic::Ordering};
+
+use alloc::{sync::Arc, vec, vec::Vec};
 use axerrno::{LinuxError, LinuxResult};
-use linux_raw_sys::general::timespec;
+use axhal::time::{TimeValue, monotonic_time};
+use axprocess::{Pid, Process, Thread};
+use axsync::Mutex;
+use axtask::{AxCpuMask, AxTaskRef, TaskExtRef, TaskState, current};
+use linux_raw_sys::general::{
+    __kernel_clockid_t, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_REALTIME, PRIO_MAX, PRIO_MIN,
+    PRIO_PGRP, PRIO_PROCESS, PRIO_USER, SCHED_BATCH, SCHED_FIFO, SCHED_IDLE, SCHED_NORMAL,
+    SCHED_RESET_ON_FORK, SCHED_RR, TIMER_ABSTIME, timespec,
+};
+
+use starry_core::task::{ThreadData, get_process_group, get_thread, processes};
 
 use crate::{
+    has_pending_signal,
     ptr::{UserConstPtr, UserPtr, nullable},
-    time::{timespec_to_timevalue, timevalue_to_timespec},
+    time::{realtime, timespec_to_timevalue, timevalue_to_timespec},
 };
 
+/// Give up the CPU, moving the current task to the end of its run queue,
+/// and a real-time thread to the end of its priority.
 pub fn sys_sched_yield() -> LinuxResult<isize> {
+    let curr = current();
+    let mut queue = RT_QUEUE.lock();
+    if let Some(pos) = queue.iter().position(|t| curr.ptr_eq(&t.task)) {
+        let thread = queue.remove(pos);
+        enqueue_rt(&mut queue, thread.task, thread.priority);
+    }
+    drop(queue);
     axtask::yield_now();
     Ok(0)
 }
 
+/// The time slice of the threads in `SCHED_RR`, which is the default of
+/// Linux.
+const RR_TIMESLICE: TimeValue = TimeValue::from_millis(100);
+
+/// A thread in a real-time scheduling policy.
+struct RtThread {
+    task: AxTaskRef,
+    priority: u32,
+    /// When the thread was moved to the end of its priority, from which its
+    /// time slice in `SCHED_RR` is counted.
+    queued_at: TimeValue,
+}
+
+/// The threads in the real-time policies, ordered by their priorities from
+/// the highest, and by the order they run in each priority.
+///
+/// The CFS scheduler has no real-time classes, so they are emulated here: a
+/// thread returning to user space gives up the CPU as long as a real-time
+/// thread ahead of it is ready to run on the CPU, so that a `SCHED_FIFO`
+/// thread runs until it blocks or yields, and a `SCHED_RR` thread until its
+/// time slice is used up. The real-time threads also have the highest
+/// weight in the scheduler, so that they are picked soon.
+static RT_QUEUE: Mutex<Vec<RtThread>> = Mutex::new(Vec::new());
+
+/// Put the task `task` at the end of the priority `priority` of `queue`.
+fn enqueue_rt(queue: &mut Vec<RtThread>, task: AxTaskRef, priority: u32) {
+    let pos = queue
+        .iter()
+        .position(|t| t.priority < priority)
+        .unwrap_or(queue.len());
+    queue.insert(
+        pos,
+        RtThread {
+            task,
+            priority,
+            queued_at: monotonic_time(),
+        },
+    );
+}
+
+/// Check whether the scheduling policy `policy` is a real-time one.
+fn is_rt_policy(policy: u32) -> bool {
+    matches!(policy & !SCHED_RESET_ON_FORK, SCHED_FIFO | SCHED_RR)
+}
+
+/// Update the place of the current thread with the data `thr_data` in
+/// [`RT_QUEUE`], after its policy or priority is changed.
+fn update_rt_queue(thr_data: &ThreadData) {
+    let curr = current();
+    let policy = thr_data.sched_policy.load(Ordering::Acquire);
+    let priority = thr_data.sched_priority.load(Ordering::Acquire);
+    let mut queue = RT_QUEUE.lock();
+    let pos = queue.iter().position(|t| curr.ptr_eq(&t.task));
+    if let Some(pos) = pos {
+        if is_rt_policy(policy) && queue[pos].priority == priority {
+            return;
+        }
+        queue.remove(pos);
+    }
+    if is_rt_policy(policy) {
+        enqueue_rt(&mut queue, curr.clone(), priority);
+    }
+}
+
+/// Remove the exiting current thread from [`RT_QUEUE`].
+pub(crate) fn remove_rt_thread() {
+    let curr = current();
+    RT_QUEUE.lock().retain(|t| !curr.ptr_eq(&t.task));
+}
+
+/// Give up the CPU while there is a real-time thread ahead of the current
+/// thread ready to run on the CPU, after moving the current thread to the
+/// end of its priority if its time slice in `SCHED_RR` is used up.
+fn yield_to_rt_threads(thr_data: &ThreadData) {
+    let curr = current();
+    loop {
+        let mut queue = RT_QUEUE.lock();
+        if queue.is_empty() {
+            return;
+        }
+        if let Some(pos) = queue.iter().position(|t| curr.ptr_eq(&t.task)) {
+            let policy = thr_data.sched_policy.load(Ordering::Acquire);
+            if policy & !SCHED_RESET_ON_FORK == SCHED_RR
+                && monotonic_time() - queue[pos].queued_at >= RR_TIMESLICE
+            {
+                let thread = queue.remove(pos);
+                enqueue_rt(&mut queue, thread.task, thread.priority);
+            }
+        }
+        let cpu = axhal::cpu::this_cpu_id();
+        let blocked = queue
+            .iter()
+            .take_while(|t| !curr.ptr_eq(&t.task))
+            .any(|t| t.task.state() == TaskState::Ready && t.task.cpu_id() as usize == cpu);
+        drop(queue);
+        if !blocked {
+            return;
+        }
+        axtask::yield_now();
+    }
+}
+
+/// Get the duration of `ts`, which must be valid.
+pub fn timespec_duration(ts: UserConstPtr<timespec>) -> LinuxResult<TimeValue> {
+    let ts = ts.get_as_ref()?;
+    if ts.tv_nsec < 0 || ts.tv_nsec > 999_999_999 || ts.tv_sec < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(timespec_to_timevalue(*ts))
+}
+
+/// Sleep until the monotonic time `deadline`.
+///
+/// If interrupted by a signal, the remaining time is stored to `rem` unless
+/// it is null, and the sleep is restarted with the same deadline if no
+/// handler runs, in which case `deadline` is not called.
+fn sleep_until(deadline: impl FnOnce() -> TimeValue, rem: UserPtr<timespec>) -> LinuxResult<isize> {
+    let curr = current();
+    // FIXME: this blocks for single-core, probably irq is disabled by a spin lock
+    if curr.name() == "busybox" {
+        return Ok(0);
+    }
+
+    let thr_data = curr.task_ext().thread_data();
+    let deadline = thr_data
+        .sleep_deadline
+        .lock()
+        .take()
+        .unwrap_or_else(deadline);
+    let signal_wq = &curr.task_ext().process_data().signal_wq;
+    loop {
+        let Some(remaining) = deadline
+            .checked_sub(monotonic_time())
+            .filter(|remaining| !remaining.is_zero())
+        else {
+            return Ok(0);
+        };
+        if has_pending_signal() {
+            if let Some(rem) = nullable!(rem.get_as_mut())? {
+                *rem = timevalue_to_timespec(remaining);
+            }
+            *thr_data.sleep_deadline.lock() = Some(deadline);
+            return Err(LinuxError::ERESTART);
+        }
+        signal_wq.wait_timeout_until(remaining, has_pending_signal);
+    }
+}
+
 /// Sleep some nanoseconds
 ///
-/// TODO: should be woken by signals, and set errno
+/// If interrupted by a signal, the remaining time is stored to `rem`, and the
+/// sleep is restarted with it if no handler runs.
 pub fn sys_nanosleep(req: UserConstPtr<timespec>, rem: UserPtr<timespec>) -> LinuxResult<isize> {
-    let req = req.get_as_ref()?;
+    let dur = timespec_duration(req)?;
+    debug!("sys_nanosleep <= {:?}", dur);
+    sleep_until(|| monotonic_time() + dur, rem)
+}
 
-    if req.tv_nsec < 0 || req.tv_nsec > 999_999_999 || req.tv_sec < 0 {
+/// Sleep some nanoseconds measured by the clock `clock_id`, or until its
+/// time `req` if `TIMER_ABSTIME` is in `flags`.
+///
+/// The remaining time of a relative sleep interrupted by a signal is stored
+/// to `rem` as in [`sys_nanosleep`].
+pub fn sys_clock_nanosleep(
+    clock_id: __kernel_clockid_t,
+    flags: u32,
+    req: UserConstPtr<timespec>,
+    rem: UserPtr<timespec>,
+) -> LinuxResult<isize> {
+    let clock: fn() -> TimeValue = match clock_id as u32 {
+        CLOCK_REALTIME => realtime,
+        CLOCK_MONOTONIC | CLOCK_BOOTTIME => monotonic_time,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let dur = timespec_duration(req)?;
+    debug!(
+        "sys_clock_nanosleep <= clock_id: {}, flags: {:#x}, {:?}",
+        clock_id, flags, dur
+    );
+    if flags & TIMER_ABSTIME != 0 {
+        // The deadline is fixed when the sleep starts, so changing the real
+        // time does not affect it.
+        sleep_until(
+            || monotonic_time() + dur.saturating_sub(clock()),
+            UserPtr::from(0),
+        )
+    } else {
+        sleep_until(|| monotonic_time() + dur, rem)
+    }
+}
+
+/// Get the size in bytes of the CPU masks of user space, which is the number
+/// of CPUs rounded up to a long.
+fn cpu_mask_size() -> usize {
+    axconfig::plat::CPU_NUM.div_ceil(usize::BITS as usize) * size_of::<usize>()
+}
+
+/// Get the thread `tid`, or the current thread if `tid` is 0.
+fn thread_or_current(tid: Pid) -> LinuxResult<Arc<Thread>> {
+    if tid == 0 {
+        Ok(current().task_ext().thread.clone())
+    } else {
+        get_thread(tid)
+    }
+}
+
+/// Set the CPUs the thread `pid` may run on to the `cpusetsize` bytes at
+/// `mask`, or the current thread if `pid` is 0.
+///
+/// Another thread is moved to the CPUs when it is next put on a run queue.
+pub fn sys_sched_setaffinity(
+    pid: Pid,
+    cpusetsize: usize,
+    mask: UserConstPtr<u8>,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_sched_setaffinity <= pid: {}, cpusetsize: {}",
+        pid, cpusetsize
+    );
+    if cpusetsize < cpu_mask_size() {
+        return Err(LinuxError::EINVAL);
+    }
+    let bytes = mask.get_as_slice(cpu_mask_size())?;
+    let mut affinity = AxCpuMask::new();
+    for cpu in 0..axconfig::plat::CPU_NUM {
+        affinity.set(cpu, bytes[cpu / 8] & (1 << (cpu % 8)) != 0);
+    }
+    if affinity.is_empty() {
         return Err(LinuxError::EINVAL);
     }
 
-    let dur = timespec_to_timevalue(*req);
-    debug!("sys_nanosleep <= {:?}", dur);
+    let thread = thread_or_current(pid)?;
+    let thr_data = thread.data::<ThreadData>().ok_or(LinuxError::ESRCH)?;
+    *thr_data.affinity.lock() = affinity;
+    if Arc::ptr_eq(&thread, &current().task_ext().thread) {
+        axtask::set_current_affinity(affinity);
+    } else if let Some(task) = thr_data.task.lock().upgrade() {
+        task.set_cpumask(affinity);
+    }
+    Ok(0)
+}
 
-    let now = axhal::time::monotonic_time();
+/// Store the CPUs the thread `pid` may run on to the `cpusetsize` bytes at
+/// `mask`, or those of the current thread if `pid` is 0.
+///
+/// Return the size of the mask stored.
+pub fn sys_sched_getaffinity(pid: Pid, cpusetsize: usize, mask: UserPtr<u8>) -> LinuxResult<isize> {
+    debug!(
+        "sys_sched_getaffinity <= pid: {}, cpusetsize: {}",
+        pid, cpusetsize
+    );
+    if cpusetsize < cpu_mask_size() {
+        return Err(LinuxError::EINVAL);
+    }
+    let thread = thread_or_current(pid)?;
+    let thr_data = thread.data::<ThreadData>().ok_or(LinuxError::ESRCH)?;
+    let affinity = *thr_data.affinity.lock();
+    let bytes = mask.get_as_mut_slice(cpu_mask_size())?;
+    bytes.fill(0);
+    for cpu in (0..axconfig::plat::CPU_NUM).filter(|&cpu| affinity.get(cpu)) {
+        bytes[cpu / 8] |= 1 << (cpu % 8);
+    }
+    Ok(cpu_mask_size() as _)
+}
 
-    // FIXME: this blocks for single-core, probably irq is disabled by a spin lock
-    if axtask::current().name() != "busybox" {
-        axtask::sleep(dur);
+/// Apply the nice value and the scheduling policy set for the
+/// current thread by another thread, if they are changed, and let the
+/// real-time threads ahead of it run first.
+pub fn apply_sched_changes() {
+    let curr = current();
+    let thr_data = curr.task_ext().thread_data();
+    if thr_data.nice_changed.swap(false, Ordering::AcqRel) {
+        if !axtask::set_priority(task_priority(thr_data)) {
+            warn!("failed to apply the priority of thread {}", curr.id_name());
+        }
+        update_rt_queue(thr_data);
     }
+    yield_to_rt_threads(thr_data);
+}
 
-    let after = axhal::time::monotonic_time();
-    let actual = after - now;
+/// Get the priority of the task of a thread in the scheduler, which is its
+/// nice value, or that of the waiters of its PI futexes if lower, or the
+/// highest one in the real-time policies.
+pub(crate) fn task_priority(thr_data: &ThreadData) -> isize {
+    if is_rt_policy(thr_data.sched_policy.load(Ordering::Acquire)) {
+        return PRIO_MIN as _;
+    }
+    let nice = thr_data.nice.load(Ordering::Acquire);
+    nice.min(thr_data.pi_nice.load(Ordering::Acquire)) as _
+}
 
-    if let Some(diff) = dur.checked_sub(actual) {
-        if let Some(rem) = nullable!(rem.get_as_mut())? {
-            *rem = timevalue_to_timespec(diff);
+/// Make the scheduler use the priority of `thread` with the data
+/// `thr_data`, after its nice value, boost or scheduling policy is changed.
+pub(crate) fn update_priority(thread: &Arc<Thread>, thr_data: &ThreadData) -> LinuxResult {
+    if Arc::ptr_eq(thread, &current().task_ext().thread) {
+        if !axtask::set_priority(task_priority(thr_data)) {
+            return Err(LinuxError::EINVAL);
         }
-        // FIXME: hack
-        Ok(0)
-        // Err(LinuxError::EINTR)
+        update_rt_queue(thr_data);
     } else {
-        Ok(0)
+        thr_data.nice_changed.store(true, Ordering::Release);
+    }
+    Ok(())
+}
+
+/// The highest nice value, which is the lowest priority.
+const NICE_MAX: i32 = PRIO_MAX as i32 - 1;
+
+/// Get the threads selected by `which` and `who` of `getpriority` and
+/// `setpriority`.
+fn priority_targets(which: u32, who: u32) -> LinuxResult<Vec<Arc<Thread>>> {
+    let threads_of = |processes: Vec<Arc<Process>>| {
+        processes
+            .iter()
+            .flat_map(|process| process.threads())
+            .collect::<Vec<_>>()
+    };
+    let threads = match which {
+        PRIO_PROCESS => vec![thread_or_current(who as _)?],
+        PRIO_PGRP => threads_of(if who == 0 {
+            current().task_ext().thread.process().group().processes()
+        } else {
+            get_process_group(who as _)?.processes()
+        }),
+        // All the processes belong to root, the only user.
+        PRIO_USER if who == 0 => threads_of(processes()),
+        PRIO_USER => Vec::new(),
+        _ => return Err(LinuxError::EINVAL),
+    };
+    if threads.is_empty() {
+        return Err(LinuxError::ESRCH);
+    }
+    Ok(threads)
+}
+
+/// Set the nice value of `thread`, which maps to the weight of the task in
+/// the CFS scheduler.
+fn set_nice(thread: &Arc<Thread>, nice: i32) -> LinuxResult {
+    let Some(thr_data) = thread.data::<ThreadData>() else {
+        return Ok(());
+    };
+    thr_data.nice.store(nice, Ordering::Release);
+    update_priority(thread, thr_data)
+}
+
+/// Get the highest priority of the threads selected by `which` and `who`,
+/// which is `20 - nice` as the result cannot be negative.
+pub fn sys_getpriority(which: u32, who: u32) -> LinuxResult<isize> {
+    debug!("sys_getpriority <= which: {}, who: {}", which, who);
+    let nice = priority_targets(which, who)?
+        .iter()
+        .filter_map(|thread| thread.data::<ThreadData>())
+        .map(|thr_data| thr_data.nice.load(Ordering::Acquire))
+        .min()
+        .ok_or(LinuxError::ESRCH)?;
+    Ok((PRIO_MAX as i32 - nice) as _)
+}
+
+/// Set the nice value of the threads selected by `which` and `who` to
+/// `prio`, clamped to -20..=19.
+///
+/// There is only root, so the priority can always be raised.
+pub fn sys_setpriority(which: u32, who: u32, prio: i32) -> LinuxResult<isize> {
+    debug!(
+        "sys_setpriority <= which: {}, who: {}, prio: {}",
+        which, who, prio
+    );
+    let nice = prio.clamp(PRIO_MIN, NICE_MAX);
+    for thread in priority_targets(which, who)? {
+        set_nice(&thread, nice)?;
+    }
+    Ok(0)
+}
+
+/// Add `inc` to the nice value of the current thread, clamped to -20..=19.
+///
+/// None of the supported architectures has this syscall, for which libc
+/// uses `setpriority` instead.
+pub fn sys_nice(inc: i32) -> LinuxResult<isize> {
+    debug!("sys_nice <= inc: {}", inc);
+    let curr = current();
+    let nice = curr.task_ext().thread_data().nice.load(Ordering::Acquire);
+    let nice = nice.saturating_add(inc).clamp(PRIO_MIN, NICE_MAX);
+    set_nice(&curr.task_ext().thread, nice)?;
+    Ok(0)
+}
+
+/// Run `f` on the thread `pid` of the scheduling syscalls, or the current
+/// thread if `pid` is 0, with its data.
+fn with_sched_target<R>(
+    pid: i32,
+    f: impl FnOnce(&Arc<Thread>, &ThreadData) -> LinuxResult<R>,
+) -> LinuxResult<R> {
+    if pid < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let thread = thread_or_current(pid as _)?;
+    let thr_data = thread.data::<ThreadData>().ok_or(LinuxError::ESRCH)?;
+    f(&thread, thr_data)
+}
+
+/// Get the range of the priorities of the policy `policy`, which is 1 to 99
+/// for the real-time policies and 0 for the others.
+fn priority_range(policy: u32) -> LinuxResult<RangeInclusive<u32>> {
+    match policy {
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE => Ok(0..=0),
+        SCHED_FIFO | SCHED_RR => Ok(1..=99),
+        _ => Err(LinuxError::EINVAL),
     }
 }
+
+/// Check the priority `param` of the policy `policy`.
+fn sched_priority(policy: u32, param: UserConstPtr<i32>) -> LinuxResult<u32> {
+    let priority = *param.get_as_ref()?;
+    let range = priority_range(policy)?;
+    u32::try_from(priority)
+        .ok()
+        .filter(|priority| range.contains(priority))
+        .ok_or(LinuxError::EINVAL)
+}
+
+/// Set the scheduling policy of the thread `pid` to `policy` with the
+/// priority `param`, which points to a `struct sched_param`, or the current
+/// thread if `pid` is 0.
+///
+/// `SCHED_BATCH` and `SCHED_IDLE` are scheduled as `SCHED_OTHER`.
+pub fn sys_sched_setscheduler(
+    pid: i32,
+    policy: u32,
+    param: UserConstPtr<i32>,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_sched_setscheduler <= pid: {}, policy: {:#x}",
+        pid, policy
+    );
+    let priority = sched_priority(policy & !SCHED_RESET_ON_FORK, param)?;
+    with_sched_target(pid, |thread, thr_data| {
+        thr_data.sched_policy.store(policy, Ordering::Release);
+        thr_data.sched_priority.store(priority, Ordering::Release);
+        update_priority(thread, thr_data)?;
+        Ok(0)
+    })
+}
+
+/// Get the scheduling policy of the thread `pid`, or the current thread if
+/// `pid` is 0.
+pub fn sys_sched_getscheduler(pid: i32) -> LinuxResult<isize> {
+    debug!("sys_sched_getscheduler <= pid: {}", pid);
+    with_sched_target(pid, |_, thr_data| {
+        Ok(thr_data.sched_policy.load(Ordering::Acquire) as _)
+    })
+}
+
+/// Set the priority of the thread `pid` in its scheduling policy to `param`,
+/// or that of the current thread if `pid` is 0.
+pub fn sys_sched_setparam(pid: i32, param: UserConstPtr<i32>) -> LinuxResult<isize> {
+    debug!("sys_sched_setparam <= pid: {}", pid);
+    with_sched_target(pid, |thread, thr_data| {
+        let policy = thr_data.sched_policy.load(Ordering::Acquire);
+        let priority = sched_priority(policy & !SCHED_RESET_ON_FORK, param)?;
+        thr_data.sched_priority.store(priority, Ordering::Release);
+        update_priority(thread, thr_data)?;
+        Ok(0)
+    })
+}
+
+/// Store the priority of the thread `pid` in its scheduling policy to
+/// `param`, or that of the current thread if `pid` is 0.
+pub fn sys_sched_getparam(pid: i32, param: UserPtr<i32>) -> LinuxResult<isize> {
+    debug!("sys_sched_getparam <= pid: {}", pid);
+    with_sched_target(pid, |_, thr_data| {
+        *param.get_as_mut()? = thr_data.sched_priority.load(Ordering::Acquire) as _;
+        Ok(0)
+    })
+}
+
+/// Get the highest priority of the policy `policy`.
+pub fn sys_sched_get_priority_max(policy: u32) -> LinuxResult<isize> {
+    Ok(*priority_range(policy)?.end() as _)
+}
+
+/// Get the lowest priority of the policy `policy`.
+pub fn sys_sched_get_priority_min(policy: u32) -> LinuxResult<isize> {
+    Ok(*priority_range(policy)?.start() as _)
+}
+
+/// Store the time slice of the thread `pid` to `interval`, or that of the
+/// current thread if `pid` is 0, which is 0 unless it is in `SCHED_RR`.
+pub fn sys_sched_rr_get_interval(pid: i32, interval: UserPtr<timespec>) -> LinuxResult<isize> {
+    debug!("sys_sched_rr_get_interval <= pid: {}", pid);
+    let slice = with_sched_target(pid, |_, thr_data| {
+        Ok(
+            match thr_data.sched_policy.load(Ordering::Acquire) & !SCHED_RESET_ON_FORK {
+                SCHED_RR => RR_TIMESLICE,
+                _ => TimeValue::ZERO,
+            },
+        )
+    })?;
+    *interval.get_as_mut()? = timevalue_to_timespec(slice);
+    Ok(0)
+}
@@ -1,8 +1,19 @@
-use axerrno::LinuxResult;
+use core::{ffi::c_char, sync::atomic::Ordering};
+
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use axprocess::{Pid, Process};
 use axtask::{TaskExtRef, current};
+use linux_raw_sys::prctl::{PR_GET_NAME, PR_GET_PDEATHSIG, PR_SET_NAME, PR_SET_PDEATHSIG};
 use num_enum::TryFromPrimitive;
+use starry_core::task::{
+    NSIG, ProcessData, add_process_group_to_table, get_process, get_process_group,
+};
+
+use crate::ptr::{UserConstPtr, UserPtr};
 
-use crate::ptr::UserConstPtr;
+/// The size of the name of a thread, including the trailing NUL.
+const TASK_COMM_LEN: usize = 16;
 
 pub fn sys_getpid() -> LinuxResult<isize> {
     Ok(axtask::current().task_ext().thread.process().pid() as _)
@@ -18,6 +29,90 @@ pub fn sys_getppid() -> LinuxResult<isize> {
         .map_or(1, |p| p.pid()) as _)
 }
 
+/// Get the process `pid`, or the current process if `pid` is 0.
+fn process_or_current(pid: Pid) -> LinuxResult<Arc<Process>> {
+    if pid == 0 {
+        Ok(current().task_ext().thread.process().clone())
+    } else {
+        get_process(pid)
+    }
+}
+
+/// Move the process `pid`, which is the caller or a child of it, to the
+/// process group `pgid` in the same session.
+///
+/// Either of them being 0 means the caller or the process itself, and a
+/// `pgid` that is the pid of the process makes it the leader of a new group.
+pub fn sys_setpgid(pid: Pid, pgid: Pid) -> LinuxResult<isize> {
+    debug!("sys_setpgid <= pid: {}, pgid: {}", pid, pgid);
+    if (pgid as i32) < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let curr = current();
+    let caller = curr.task_ext().thread.process();
+    let process = process_or_current(pid)?;
+    if !Arc::ptr_eq(&process, caller) {
+        if !process
+            .parent()
+            .is_some_and(|parent| Arc::ptr_eq(&parent, caller))
+        {
+            return Err(LinuxError::ESRCH);
+        }
+        if !Arc::ptr_eq(&process.group().session(), &caller.group().session()) {
+            return Err(LinuxError::EPERM);
+        }
+        let data: &ProcessData = process.data().unwrap();
+        if data.execed.load(Ordering::Relaxed) {
+            return Err(LinuxError::EACCES);
+        }
+    }
+    // A session leader cannot leave its group.
+    if process.group().session().sid() == process.pid() {
+        return Err(LinuxError::EPERM);
+    }
+
+    let pgid = if pgid == 0 { process.pid() } else { pgid };
+    if process.group().pgid() == pgid {
+        return Ok(0);
+    }
+    if pgid == process.pid() {
+        let group = process.create_group().ok_or(LinuxError::EPERM)?;
+        add_process_group_to_table(&group);
+        return Ok(0);
+    }
+    let group = get_process_group(pgid).map_err(|_| LinuxError::EPERM)?;
+    if !Arc::ptr_eq(&group.session(), &caller.group().session()) || !process.move_to_group(&group) {
+        return Err(LinuxError::EPERM);
+    }
+    Ok(0)
+}
+
+/// Get the process group of the process `pid`, or of the caller if `pid` is
+/// 0.
+pub fn sys_getpgid(pid: Pid) -> LinuxResult<isize> {
+    Ok(process_or_current(pid)?.group().pgid() as _)
+}
+
+/// Make the caller the leader of a new session and of a new process group in
+/// it, and return the new session id.
+///
+/// There are no controlling terminals, so the new session has none.
+pub fn sys_setsid() -> LinuxResult<isize> {
+    let curr = current();
+    let process = curr.task_ext().thread.process();
+    if process.group().pgid() == process.pid() {
+        return Err(LinuxError::EPERM);
+    }
+    let (session, group) = process.create_session().ok_or(LinuxError::EPERM)?;
+    add_process_group_to_table(&group);
+    Ok(session.sid() as _)
+}
+
+/// Get the session of the process `pid`, or of the caller if `pid` is 0.
+pub fn sys_getsid(pid: Pid) -> LinuxResult<isize> {
+    Ok(process_or_current(pid)?.group().session().sid() as _)
+}
+
 pub fn sys_gettid() -> LinuxResult<isize> {
     Ok(axtask::current().id().as_u64() as _)
 }
@@ -54,9 +149,58 @@ pub fn sys_set_tid_address(tid_ptd: UserConstPtr<i32>) -> LinuxResult<isize> {
     Ok(curr.id().as_u64() as isize)
 }
 
+/// Operate on the calling thread or process.
+///
+/// Only the name of the thread and the signal sent on the exit of the parent
+/// are supported, and other options are `EINVAL`.
+pub fn sys_prctl(
+    option: u32,
+    arg2: usize,
+    _arg3: usize,
+    _arg4: usize,
+    _arg5: usize,
+) -> LinuxResult<isize> {
+    debug!("sys_prctl <= option: {}, arg2: {:#x}", option, arg2);
+    let curr = current();
+    match option {
+        PR_SET_NAME => {
+            let name = UserConstPtr::<c_char>::from(arg2).get_as_str()?;
+            let mut len = name.len().min(TASK_COMM_LEN - 1);
+            while !name.is_char_boundary(len) {
+                len -= 1;
+            }
+            curr.set_name(&name[..len]);
+        }
+        PR_GET_NAME => {
+            let buf = UserPtr::<u8>::from(arg2).get_as_mut_slice(TASK_COMM_LEN)?;
+            let name = curr.name().as_bytes();
+            let len = name.len().min(TASK_COMM_LEN - 1);
+            buf[..len].copy_from_slice(&name[..len]);
+            buf[len..].fill(0);
+        }
+        PR_SET_PDEATHSIG => {
+            if arg2 > NSIG {
+                return Err(LinuxError::EINVAL);
+            }
+            curr.task_ext()
+                .process_data()
+                .pdeath_signal
+                .store(arg2 as _, Ordering::Relaxed);
+        }
+        PR_GET_PDEATHSIG => {
+            *UserPtr::<i32>::from(arg2).get_as_mut()? =
+                curr.task_ext()
+                    .process_data()
+                    .pdeath_signal
+                    .load(Ordering::Relaxed) as _;
+        }
+        _ => return Err(LinuxError::EINVAL),
+    }
+    Ok(0)
+}
+
 #[cfg(target_arch = "x86_64")]
-pub fn sys_arch_prctl(code: i32, addr: crate::ptr::UserPtr<u64>) -> LinuxResult<isize> {
-    use axerrno::LinuxError;
+pub fn sys_arch_prctl(code: i32, addr: UserPtr<u64>) -> LinuxResult<isize> {
     match ArchPrctlCode::try_from(code).map_err(|_| LinuxError::EINVAL)? {
         // According to Linux implementation, SetFs & SetGs does not return
         // error at all
@@ -1,13 +1,20 @@
 use alloc::{sync::Arc, vec::Vec};
 use axerrno::{LinuxError, LinuxResult};
+use axhal::time::{NANOS_PER_MICROS, NANOS_PER_SEC, nanos_to_ticks};
 use axprocess::{Pid, Process};
 use axtask::{TaskExtRef, current};
 use bitflags::bitflags;
 use linux_raw_sys::general::{
-    __WALL, __WCLONE, __WNOTHREAD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WUNTRACED,
+    __WALL, __WCLONE, __WNOTHREAD, __kernel_old_timeval, __sifields__bindgen_ty_4, CLD_DUMPED,
+    CLD_EXITED, CLD_KILLED, P_ALL, P_PGID, P_PID, SIGCHLD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT,
+    WUNTRACED, rusage, siginfo,
 };
+use starry_core::task::ProcessData;
 
-use crate::ptr::{UserPtr, nullable};
+use crate::{
+    has_pending_signal,
+    ptr::{UserPtr, nullable},
+};
 
 bitflags! {
     #[derive(Debug)]
@@ -52,26 +59,29 @@ impl WaitPid {
             WaitPid::Pgid(pgid) => child.group().pgid() == *pgid,
         }
     }
-}
 
-pub fn sys_waitpid(pid: i32, exit_code_ptr: UserPtr<i32>, options: u32) -> LinuxResult<isize> {
-    let options = WaitOptions::from_bits_truncate(options);
-    info!("sys_waitpid <= pid: {:?}, options: {:?}", pid, options);
+    fn from_idtype(idtype: u32, id: Pid) -> LinuxResult<Self> {
+        match idtype {
+            P_ALL => Ok(WaitPid::Any),
+            P_PID => Ok(WaitPid::Pid(id)),
+            P_PGID if id == 0 => Ok(WaitPid::Pgid(
+                current().task_ext().thread.process().group().pgid(),
+            )),
+            P_PGID => Ok(WaitPid::Pgid(id)),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+}
 
+/// Wait for a child selected by `pid` to exit if `WEXITED` is set, and reap
+/// it unless `WNOWAIT` is set.
+///
+/// Return `None` if `WNOHANG` is set and no child has exited.
+fn wait_child(pid: WaitPid, options: &WaitOptions) -> LinuxResult<Option<Arc<Process>>> {
     let curr = current();
     let process = curr.task_ext().thread.process();
     let proc_data = curr.task_ext().process_data();
 
-    let pid = if pid == -1 {
-        WaitPid::Any
-    } else if pid == 0 {
-        WaitPid::Pgid(process.group().pgid())
-    } else if pid > 0 {
-        WaitPid::Pid(pid as _)
-    } else {
-        WaitPid::Pgid(-pid as _)
-    };
-
     let children = process
         .children()
         .into_iter()
@@ -81,20 +91,150 @@ pub fn sys_waitpid(pid: i32, exit_code_ptr: UserPtr<i32>, options: u32) -> Linux
         return Err(LinuxError::ECHILD);
     }
 
-    let exit_code = nullable!(exit_code_ptr.get_as_mut())?;
+    let exited = |child: &Arc<Process>| options.contains(WaitOptions::WEXITED) && child.is_zombie();
     loop {
-        if let Some(child) = children.iter().find(|child| child.is_zombie()) {
+        if let Some(child) = children.iter().find(|child| exited(child)) {
             if !options.contains(WaitOptions::WNOWAIT) {
+                let (utime, stime) = child_time(child);
+                let mut children_time = proc_data.children_time.lock();
+                children_time.0 += utime;
+                children_time.1 += stime;
                 child.free();
             }
-            if let Some(exit_code) = exit_code {
-                *exit_code = child.exit_code();
-            }
-            return Ok(child.pid() as _);
+            return Ok(Some(child.clone()));
         } else if options.contains(WaitOptions::WNOHANG) {
-            return Ok(0);
+            return Ok(None);
+        } else if has_pending_signal() {
+            return Err(LinuxError::ERESTART);
         } else {
-            proc_data.child_exit_wq.wait();
+            // Signals also wake up the waiters.
+            proc_data
+                .child_exit_wq
+                .wait_until(|| children.iter().any(exited) || has_pending_signal());
         }
     }
 }
+
+/// Get the user and system time in nanoseconds of the exited `child` and
+/// its waited-for descendants.
+fn child_time(child: &Process) -> (usize, usize) {
+    let Some(data) = child.data::<ProcessData>() else {
+        return (0, 0);
+    };
+    let exited = *data.exited_time.lock();
+    let children = *data.children_time.lock();
+    (exited.0 + children.0, exited.1 + children.1)
+}
+
+fn nanos_to_timeval(nanos: usize) -> __kernel_old_timeval {
+    __kernel_old_timeval {
+        tv_sec: (nanos / NANOS_PER_SEC as usize) as _,
+        tv_usec: (nanos % NANOS_PER_SEC as usize / NANOS_PER_MICROS as usize) as _,
+    }
+}
+
+/// Wait for a child selected by `pid` to exit, storing its exit status to
+/// `wstatus` and its resource usage to `rusage`.
+///
+/// `pid` selects any child if -1, the children in the process group of the
+/// caller if 0, the child `pid` if positive, or the children in the process
+/// group `-pid` otherwise.
+pub fn sys_wait4(
+    pid: i32,
+    wstatus: UserPtr<i32>,
+    options: u32,
+    rusage: UserPtr<rusage>,
+) -> LinuxResult<isize> {
+    let options = WaitOptions::from_bits(options).ok_or(LinuxError::EINVAL)?;
+    info!("sys_wait4 <= pid: {:?}, options: {:?}", pid, options);
+    if options.contains(WaitOptions::WEXITED) || options.contains(WaitOptions::WNOWAIT) {
+        return Err(LinuxError::EINVAL);
+    }
+    // Stopped and continued children cannot be reported, as job control is
+    // not supported.
+    if options.intersects(WaitOptions::WUNTRACED | WaitOptions::WCONTINUED) {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let process = current().task_ext().thread.process().clone();
+    let pid = if pid == -1 {
+        WaitPid::Any
+    } else if pid == 0 {
+        WaitPid::Pgid(process.group().pgid())
+    } else if pid > 0 {
+        WaitPid::Pid(pid as _)
+    } else {
+        WaitPid::Pgid(-pid as _)
+    };
+
+    // Exited children are always waited for.
+    let Some(child) = wait_child(pid, &(options | WaitOptions::WEXITED))? else {
+        return Ok(0);
+    };
+    if let Some(wstatus) = nullable!(wstatus.get_as_mut())? {
+        *wstatus = child.exit_code();
+    }
+    if let Some(rusage) = nullable!(rusage.get_as_mut())? {
+        let (utime, stime) = child_time(&child);
+        // SAFETY: all the fields are integers.
+        *rusage = unsafe { core::mem::zeroed() };
+        rusage.ru_utime = nanos_to_timeval(utime);
+        rusage.ru_stime = nanos_to_timeval(stime);
+    }
+    Ok(child.pid() as _)
+}
+
+/// Wait for a child selected by `idtype` and `id` to exit, storing the
+/// information of its exit to `infop` as for `SIGCHLD`.
+pub fn sys_waitid(
+    idtype: u32,
+    id: Pid,
+    infop: UserPtr<siginfo>,
+    options: u32,
+) -> LinuxResult<isize> {
+    let options = WaitOptions::from_bits(options).ok_or(LinuxError::EINVAL)?;
+    info!(
+        "sys_waitid <= idtype: {}, id: {}, options: {:?}",
+        idtype, id, options
+    );
+    // Stopped (`WSTOPPED`, the same as `WUNTRACED`) and continued children
+    // cannot be reported, as job control is not supported.
+    if !options.contains(WaitOptions::WEXITED)
+        || options.intersects(WaitOptions::WUNTRACED | WaitOptions::WCONTINUED)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let pid = WaitPid::from_idtype(idtype, id)?;
+    let child = wait_child(pid, &options)?;
+
+    let Some(infop) = nullable!(infop.get_as_mut())? else {
+        return Ok(0);
+    };
+    // SAFETY: all the fields are integers.
+    *infop = unsafe { core::mem::zeroed() };
+    let Some(child) = child else {
+        return Ok(0);
+    };
+    let status = child.exit_code();
+    let (code, status) = match status & 0x7f {
+        0 => (CLD_EXITED, (status >> 8) & 0xff),
+        signo if status & 0x80 != 0 => (CLD_DUMPED, signo),
+        signo => (CLD_KILLED, signo),
+    };
+    let (utime, stime) = child_time(&child);
+    // SAFETY: the fields are initialized above.
+    unsafe {
+        let fields = &mut infop.__bindgen_anon_1.__bindgen_anon_1;
+        fields.si_signo = SIGCHLD as _;
+        fields.si_code = code as _;
+        fields._sifields._sigchld = __sifields__bindgen_ty_4 {
+            _pid: child.pid() as _,
+            _uid: 0,
+            _status: status,
+            _utime: nanos_to_ticks(utime as u64) as _,
+            _stime: nanos_to_ticks(stime as u64) as _,
+        };
+    }
+    Ok(0)
+}
@@ -1,46 +1,227 @@
+use alloc::sync::Arc;
 use axerrno::{LinuxError, LinuxResult};
-use axhal::time::{monotonic_time, monotonic_time_nanos, nanos_to_ticks, wall_time};
+use axhal::time::{NANOS_PER_SEC, TimeValue, monotonic_time, monotonic_time_nanos, ticks_to_nanos};
+use axprocess::Process;
+use axsignal::ctypes::SignalInfo;
+use axtask::{TaskExtRef, current};
 use linux_raw_sys::general::{
-    __kernel_clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME, timespec, timeval,
+    __kernel_clockid_t, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_MONOTONIC_COARSE,
+    CLOCK_MONOTONIC_RAW, CLOCK_REALTIME, CLOCK_REALTIME_COARSE, ITIMER_REAL, SI_KERNEL, SIGALRM,
+    itimerval, timespec, timeval, timezone,
+};
+use starry_core::{
+    task::{ProcessData, process_time_stat},
+    time::{RealTimer, Tms},
 };
-use starry_core::{task::time_stat_output, time::Tms};
 
 use crate::{
-    ptr::UserPtr,
-    time::{timevalue_to_timespec, timevalue_to_timeval},
+    ptr::{UserConstPtr, UserPtr, nullable},
+    send_signal_process,
+    time::{
+        realtime, set_realtime, timeval_to_timevalue, timevalue_to_timespec, timevalue_to_timeval,
+    },
 };
 
+/// Get the function reading the clock `clock_id`.
+///
+/// The coarse clocks are as precise as the others.
+fn clock_of(clock_id: __kernel_clockid_t) -> LinuxResult<fn() -> TimeValue> {
+    match clock_id as u32 {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Ok(realtime),
+        // The system never suspends, so the time since boot is monotonic.
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+            Ok(monotonic_time)
+        }
+        _ => {
+            warn!("Called for unsupported clock {}", clock_id);
+            Err(LinuxError::EINVAL)
+        }
+    }
+}
+
+/// Get the time of the clock `clock_id`.
 pub fn sys_clock_gettime(
     clock_id: __kernel_clockid_t,
     ts: UserPtr<timespec>,
 ) -> LinuxResult<isize> {
-    let now = match clock_id as u32 {
-        CLOCK_REALTIME => wall_time(),
-        CLOCK_MONOTONIC => monotonic_time(),
-        _ => {
-            warn!(
-                "Called sys_clock_gettime for unsupported clock {}",
-                clock_id
-            );
-            return Err(LinuxError::EINVAL);
-        }
-    };
+    let now = clock_of(clock_id)?();
     *ts.get_as_mut()? = timevalue_to_timespec(now);
     Ok(0)
 }
 
-pub fn sys_get_time_of_day(ts: UserPtr<timeval>) -> LinuxResult<isize> {
-    *ts.get_as_mut()? = timevalue_to_timeval(monotonic_time());
+/// Get the resolution of the clock `clock_id`, which is a tick of the timer
+/// for all the clocks.
+pub fn sys_clock_getres(
+    clock_id: __kernel_clockid_t,
+    res: UserPtr<timespec>,
+) -> LinuxResult<isize> {
+    clock_of(clock_id)?;
+    if let Some(res) = nullable!(res.get_as_mut())? {
+        *res = timevalue_to_timespec(TimeValue::from_nanos(ticks_to_nanos(1).max(1)));
+    }
     Ok(0)
 }
 
+/// Get the time of `CLOCK_REALTIME`, with the time zone always being UTC.
+pub fn sys_gettimeofday(tv: UserPtr<timeval>, tz: UserPtr<timezone>) -> LinuxResult<isize> {
+    if let Some(tv) = nullable!(tv.get_as_mut())? {
+        *tv = timevalue_to_timeval(realtime());
+    }
+    if let Some(tz) = nullable!(tz.get_as_mut())? {
+        *tz = timezone {
+            tz_minuteswest: 0,
+            tz_dsttime: 0,
+        };
+    }
+    Ok(0)
+}
+
+/// Set the time of `CLOCK_REALTIME`, ignoring the time zone.
+pub fn sys_settimeofday(
+    tv: UserConstPtr<timeval>,
+    _tz: UserConstPtr<timezone>,
+) -> LinuxResult<isize> {
+    if let Some(tv) = nullable!(tv.get_as_ref())? {
+        set_realtime(timeval_duration(*tv)?);
+    }
+    Ok(0)
+}
+
+/// The clock ticks per second of `times`, which is `sysconf(_SC_CLK_TCK)`.
+const CLK_TCK: usize = 100;
+
+fn nanos_to_clock_ticks(nanos: usize) -> usize {
+    nanos / (NANOS_PER_SEC as usize / CLK_TCK)
+}
+
+/// Get the user and system time of the current process and of its
+/// waited-for children in clock ticks, and return the clock ticks since boot.
 pub fn sys_times(tms: UserPtr<Tms>) -> LinuxResult<isize> {
-    let (_, utime_us, _, stime_us) = time_stat_output();
-    *tms.get_as_mut()? = Tms {
-        tms_utime: utime_us,
-        tms_stime: stime_us,
-        tms_cutime: utime_us,
-        tms_cstime: stime_us,
+    if let Some(tms) = nullable!(tms.get_as_mut())? {
+        let (utime_ns, stime_ns) = process_time_stat();
+        let (cutime_ns, cstime_ns) = *current().task_ext().process_data().children_time.lock();
+        *tms = Tms {
+            tms_utime: nanos_to_clock_ticks(utime_ns),
+            tms_stime: nanos_to_clock_ticks(stime_ns),
+            tms_cutime: nanos_to_clock_ticks(cutime_ns),
+            tms_cstime: nanos_to_clock_ticks(cstime_ns),
+        };
+    }
+    Ok(nanos_to_clock_ticks(monotonic_time_nanos() as usize) as _)
+}
+
+/// Get the duration of `tv`, which must be valid.
+fn timeval_duration(tv: timeval) -> LinuxResult<TimeValue> {
+    if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(timeval_to_timevalue(tv))
+}
+
+/// Get the time until the next expiration and the interval of `timer`.
+fn real_timer_setting(timer: &RealTimer, now: TimeValue) -> itimerval {
+    let remaining = timer.deadline.map_or(TimeValue::ZERO, |deadline| {
+        // An armed timer never reports zero, as that means disarmed.
+        deadline
+            .checked_sub(now)
+            .filter(|remaining| !remaining.is_zero())
+            .unwrap_or(TimeValue::from_micros(1))
+    });
+    itimerval {
+        it_interval: timevalue_to_timeval(timer.interval),
+        it_value: timevalue_to_timeval(remaining),
+    }
+}
+
+/// Send `SIGALRM` to `process` on the expirations of its `ITIMER_REAL`
+/// timer, until the timer is set again for a newer `generation`.
+fn serve_real_timer(process: Arc<Process>, generation: u64) {
+    let data: &ProcessData = process.data().unwrap();
+    loop {
+        let now = monotonic_time();
+        let mut timer = data.real_timer.lock();
+        if timer.generation != generation || process.is_zombie() {
+            return;
+        }
+        let Some(deadline) = timer.deadline else {
+            return;
+        };
+        if now < deadline {
+            drop(timer);
+            data.real_timer_wq.wait_timeout(deadline - now);
+            continue;
+        }
+        // The expirations missed are merged into one, as the signal is not
+        // queued anyway.
+        timer.deadline = (!timer.interval.is_zero()).then(|| {
+            let interval = timer.interval.as_nanos();
+            let count = (now - deadline).as_nanos() / interval + 1;
+            deadline + TimeValue::from_nanos((count * interval) as u64)
+        });
+        drop(timer);
+        send_signal_process(&process, SignalInfo::new(SIGALRM, SI_KERNEL));
+    }
+}
+
+/// Get the setting of the interval timer `which` of the current process.
+///
+/// Only `ITIMER_REAL` is supported.
+pub fn sys_getitimer(which: u32, curr_value: UserPtr<itimerval>) -> LinuxResult<isize> {
+    if which != ITIMER_REAL {
+        return Err(LinuxError::EINVAL);
+    }
+    let timer = current().task_ext().process_data().real_timer.lock();
+    let setting = real_timer_setting(&timer, monotonic_time());
+    drop(timer);
+    *curr_value.get_as_mut()? = setting;
+    Ok(0)
+}
+
+/// Set the interval timer `which` of the current process to `new_value`, and
+/// store the previous setting to `old_value` unless it is null.
+///
+/// Only `ITIMER_REAL` is supported, which sends `SIGALRM` on expiration.
+pub fn sys_setitimer(
+    which: u32,
+    new_value: UserConstPtr<itimerval>,
+    old_value: UserPtr<itimerval>,
+) -> LinuxResult<isize> {
+    if which != ITIMER_REAL {
+        return Err(LinuxError::EINVAL);
+    }
+    // A null setting disarms the timer, as on Linux.
+    let (value, interval) = match nullable!(new_value.get_as_ref())? {
+        Some(new_value) => (
+            timeval_duration(new_value.it_value)?,
+            timeval_duration(new_value.it_interval)?,
+        ),
+        None => (TimeValue::ZERO, TimeValue::ZERO),
     };
-    Ok(nanos_to_ticks(monotonic_time_nanos()) as _)
+    debug!(
+        "sys_setitimer <= value: {:?}, interval: {:?}",
+        value, interval
+    );
+
+    let curr = current();
+    let data = curr.task_ext().process_data();
+    let now = monotonic_time();
+    let mut timer = data.real_timer.lock();
+    let old_setting = real_timer_setting(&timer, now);
+    timer.generation += 1;
+    timer.deadline = (!value.is_zero()).then(|| now + value);
+    timer.interval = interval;
+    let generation = timer.generation;
+    let armed = timer.deadline.is_some();
+    drop(timer);
+    // Stop the task serving the old setting.
+    data.real_timer_wq.notify_all(false);
+    if armed {
+        let process = curr.task_ext().thread.process().clone();
+        axtask::spawn(move || serve_real_timer(process, generation));
+    }
+
+    if let Some(old_value) = nullable!(old_value.get_as_mut())? {
+        *old_value = old_setting;
+    }
+    Ok(0)
 }
@@ -1,15 +1,27 @@
-use core::{ffi::c_int, fmt, ops::Deref};
+use core::{
+    ffi::c_int,
+    fmt,
+    ops::Deref,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use alloc::{
     collections::btree_map::BTreeMap,
+    format,
     string::{String, ToString},
+    vec::Vec,
 };
 use axerrno::{AxError, AxResult, LinuxError, LinuxResult};
 use axfs::api::canonicalize;
-use linux_raw_sys::general::AT_FDCWD;
+use axhal::time::TimeValue;
+use axns::{ResArc, def_resource};
+use linux_raw_sys::general::{AT_FDCWD, S_IFMT};
 use spin::RwLock;
 
-use crate::fd::{Directory, File, FileLike};
+use crate::{
+    fd::{Directory, File, FileLike, Kstat, get_file_like},
+    resolve_mounts, visible_path,
+};
 
 /// 一个规范化的文件路径表示
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
@@ -205,7 +217,7 @@ impl HardlinkManager {
         if !dst.exists() {
             return Err(LinkError::NotFound);
         }
-        if !dst.is_dir() {
+        if dst.is_dir() {
             return Err(LinkError::NotFile);
         }
 
@@ -218,13 +230,38 @@ impl HardlinkManager {
     /// 链接数量为零 或 没有链接时， 删除文件
     /// 如果路径对应的链接不存在 或 路径对应的文件不存在，则返回 `None`
     /// 否则返回链接的目标路径
-    pub fn remove_link(&self, src: &FilePath) -> Option<String> {
+    ///
+    /// `name` is the removed name itself, not resolved with [`Self::real_path`].
+    pub fn remove_link(&self, name: &str) -> Option<String> {
         let mut inner = self.inner.write();
-        self.atomic_link_remove(&mut inner, src).or_else(|| {
-            axfs::api::remove_file(src.as_str())
-                .ok()
-                .map(|_| src.to_string())
-        })
+        if let Some(dst) = self.atomic_link_remove(&mut inner, name) {
+            return Some(dst);
+        }
+        // The original name of a file that still has other links: move the
+        // file to one of them, which owns it from now on, so that the
+        // remaining names stay valid.
+        if let Some(count) = inner.ref_counts.remove(name) {
+            let names = inner
+                .links
+                .iter()
+                .filter(|(_, dst)| *dst == name)
+                .map(|(src, _)| src.clone())
+                .collect::<Vec<_>>();
+            let Some((owner, others)) = names.split_first() else {
+                return axfs::api::remove_file(name).ok().map(|_| name.to_string());
+            };
+            axfs::api::rename(name, owner).ok()?;
+            ATTR_MANAGER.rename(name, owner);
+            inner.links.remove(owner);
+            for src in others {
+                inner.links.insert(src.clone(), owner.clone());
+            }
+            if count > 2 {
+                inner.ref_counts.insert(owner.clone(), count - 1);
+            }
+            return Some(name.to_string());
+        }
+        axfs::api::remove_file(name).ok().map(|_| name.to_string())
     }
 
     pub fn real_path(&self, path: &str) -> String {
@@ -258,13 +295,14 @@ impl HardlinkManager {
             self.decrease_ref_count(inner, &old_dst.to_string());
         }
         inner.links.insert(src.to_string(), dst.to_string());
-        *inner.ref_counts.entry(dst.to_string()).or_insert(0) += 1;
+        // 计数包含文件原本的名字
+        *inner.ref_counts.entry(dst.to_string()).or_insert(1) += 1;
     }
 
     /// 移除链接
     /// 如果链接不存在，则返回 `None`，否则返回链接的目标路径
-    fn atomic_link_remove(&self, inner: &mut LinkManagerInner, src: &FilePath) -> Option<String> {
-        inner.links.remove(src.as_str()).inspect(|dst| {
+    fn atomic_link_remove(&self, inner: &mut LinkManagerInner, src: &str) -> Option<String> {
+        inner.links.remove(src).inspect(|dst| {
             self.decrease_ref_count(inner, dst);
         })
     }
@@ -290,17 +328,358 @@ impl HardlinkManager {
     }
 }
 
+/// A global symbolic link manager
+pub static SYMLINK_MANAGER: SymlinkManager = SymlinkManager::new();
+
+/// A manager for symbolic links
+///
+/// The underlying file systems have no notion of symbolic links, so the
+/// targets are recorded here, keyed by the canonical path of the link.
+pub struct SymlinkManager {
+    links: RwLock<BTreeMap<String, String>>,
+}
+
+impl SymlinkManager {
+    const fn new() -> Self {
+        Self {
+            links: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the target of the symbolic link at `path`, or `None` if `path`
+    /// is not a symbolic link.
+    pub fn read_link(&self, path: &str) -> Option<String> {
+        self.links.read().get(path.trim_end_matches('/')).cloned()
+    }
+
+    /// Whether `path` is a symbolic link
+    pub fn is_symlink(&self, path: &str) -> bool {
+        self.links.read().contains_key(path.trim_end_matches('/'))
+    }
+
+    /// Create a symbolic link at `path` pointing to `target`.
+    ///
+    /// The target is stored verbatim and may dangle. A placeholder regular
+    /// file is created in the underlying file system so that the link shows
+    /// up in directory listings.
+    pub fn create_symlink(&self, path: &FilePath, target: &str) -> LinuxResult {
+        if path.exists() || self.is_symlink(path) {
+            return Err(LinuxError::EEXIST);
+        }
+        if !FilePath::new(path.parent()?)?.exists() {
+            return Err(LinuxError::ENOENT);
+        }
+
+        let mut links = self.links.write();
+        axfs::api::write(path.as_str(), b"")?;
+        links.insert(path.trim_end_matches('/').to_string(), target.to_string());
+        Ok(())
+    }
+
+    /// Move the symbolic links at or below `old` to `new`, following a rename
+    /// in the underlying file system.
+    pub fn rename(&self, old: &str, new: &str) {
+        rename_subtree(&mut self.links.write(), old, new);
+    }
+
+    /// Forget the symbolic link at `path`, returning its target.
+    ///
+    /// The placeholder file is left for the caller to remove.
+    pub fn remove_symlink(&self, path: &str) -> Option<String> {
+        self.links.write().remove(path.trim_end_matches('/'))
+    }
+}
+
+/// Move the entries of `map` keyed by `old` or a path below it to `new`.
+fn rename_subtree<V>(map: &mut BTreeMap<String, V>, old: &str, new: &str) {
+    let old = old.trim_end_matches('/');
+    let new = new.trim_end_matches('/');
+    let moved = map
+        .keys()
+        .filter(|path| {
+            path.strip_prefix(old)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    for path in moved {
+        let value = map.remove(&path).unwrap();
+        map.insert(format!("{}{}", new, &path[old.len()..]), value);
+    }
+}
+
+/// Inode attributes that the underlying file systems cannot store
+///
+/// `None` means the value reported by the file system is used.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InodeAttr {
+    /// File type bits, for special files backed by regular files
+    pub file_type: Option<u32>,
+    /// Device number of device special files
+    pub rdev: Option<u64>,
+    /// Permission bits
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub atime: Option<TimeValue>,
+    pub mtime: Option<TimeValue>,
+    pub ctime: Option<TimeValue>,
+    /// Identity of the inode, which follows it across renames and links
+    pub id: Option<u64>,
+}
+
+/// A global inode attribute manager
+pub static ATTR_MANAGER: AttrManager = AttrManager::new();
+
+/// A manager for inode attributes, keyed by the real path of the inode
+pub struct AttrManager {
+    attrs: RwLock<BTreeMap<String, InodeAttr>>,
+}
+
+impl AttrManager {
+    const fn new() -> Self {
+        Self {
+            attrs: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Update the attributes of the inode at `path` with `f`.
+    pub fn update<F: FnOnce(&mut InodeAttr)>(&self, path: &FilePath, f: F) {
+        let key = path.trim_end_matches('/').to_string();
+        f(self.attrs.write().entry(key).or_default());
+    }
+
+    /// Get the identity of the inode at `path`, assigning a new one if it
+    /// has none.
+    pub fn file_id(&self, path: &FilePath) -> u64 {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let key = path.trim_end_matches('/').to_string();
+        let mut attrs = self.attrs.write();
+        let attr = attrs.entry(key).or_default();
+        *attr
+            .id
+            .get_or_insert_with(|| NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Override the fields of `kstat` with the attributes recorded for the
+    /// inode at `path`.
+    pub fn apply(&self, path: &str, kstat: &mut Kstat) {
+        let Ok(path) = FilePath::new(path) else {
+            return;
+        };
+        let Some(attr) = self.attrs.read().get(path.trim_end_matches('/')).copied() else {
+            return;
+        };
+        if let Some(file_type) = attr.file_type {
+            kstat.mode = (kstat.mode & !S_IFMT) | (file_type & S_IFMT);
+        }
+        if let Some(rdev) = attr.rdev {
+            kstat.rdev = rdev;
+        }
+        if let Some(mode) = attr.mode {
+            kstat.mode = (kstat.mode & S_IFMT) | (mode & !S_IFMT);
+        }
+        if let Some(uid) = attr.uid {
+            kstat.uid = uid;
+        }
+        if let Some(gid) = attr.gid {
+            kstat.gid = gid;
+        }
+        if let Some(atime) = attr.atime {
+            kstat.atime = atime;
+        }
+        if let Some(mtime) = attr.mtime {
+            kstat.mtime = mtime;
+        }
+        if let Some(ctime) = attr.ctime {
+            kstat.ctime = ctime;
+        }
+    }
+
+    /// Forget the attributes of the inode at `path`.
+    pub fn remove(&self, path: &str) {
+        self.attrs.write().remove(path.trim_end_matches('/'));
+    }
+
+    /// Move the attributes at or below `old` to `new`, following a rename in
+    /// the underlying file system.
+    pub fn rename(&self, old: &str, new: &str) {
+        rename_subtree(&mut self.attrs.write(), old, new);
+    }
+}
+
+/// The maximum number of symbolic links followed during path resolution
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolve the symbolic links contained in `path`.
+///
+/// All intermediate components are followed. The final component is only
+/// followed if `follow_last` is set, otherwise the returned path refers to the
+/// link itself.
+///
+/// Returns `ELOOP` if more than [`MAX_SYMLINKS`] links are encountered.
+pub fn resolve_symlinks(path: &FilePath, follow_last: bool) -> LinuxResult<FilePath> {
+    let mut path = path.clone();
+    for _ in 0..=MAX_SYMLINKS {
+        match resolve_first_symlink(&path, follow_last)? {
+            Some(next) => path = resolve_mounts(next),
+            None => return Ok(path),
+        }
+    }
+    Err(LinuxError::ELOOP)
+}
+
+/// Substitute the first symbolic link in `path` with its target.
+///
+/// Returns `None` if there is no symbolic link to follow.
+fn resolve_first_symlink(path: &FilePath, follow_last: bool) -> LinuxResult<Option<FilePath>> {
+    let components = path.components().collect::<Vec<_>>();
+    let mut resolved = String::new();
+    for (i, name) in components.iter().enumerate() {
+        if name.is_empty() {
+            continue;
+        }
+        let current = format!("{}/{}", resolved, name);
+        if i + 1 == components.len() && !follow_last {
+            break;
+        }
+        if let Some(target) = SYMLINK_MANAGER.read_link(&current) {
+            // A relative target is relative to the directory containing the link.
+            // `..` leads out of the mount point rather than the directory
+            // holding the mounted file system, and never leaves the root.
+            let mut new_path = if target.starts_with('/') {
+                String::from(resolve_in_root(&target)?.as_str())
+            } else {
+                let base = visible_path(&resolved);
+                match strip_root(&base) {
+                    Some(base) => String::from(
+                        resolve_in_root(&format!("{}/{}", base.trim_end_matches('/'), target))?
+                            .as_str(),
+                    ),
+                    None => format!("{}/{}", base, target),
+                }
+            };
+            for rest in &components[i + 1..] {
+                new_path.push('/');
+                new_path.push_str(rest);
+            }
+            if path.is_dir() {
+                new_path.push('/');
+            }
+            return Ok(Some(FilePath::new(new_path)?));
+        }
+        resolved = current;
+    }
+    Ok(None)
+}
+
+def_resource! {
+    /// The root directory of the process, which is changed by `chroot`.
+    pub static ROOT_DIR: ResArc<RwLock<String>> = ResArc::new();
+}
+
+impl ROOT_DIR {
+    /// Return a copy of the inner root directory.
+    pub fn copy_inner(&self) -> RwLock<String> {
+        RwLock::new(self.read().clone())
+    }
+}
+
+#[ctor_bare::register_ctor]
+fn init_root_dir() {
+    ROOT_DIR.init_new(RwLock::new("/".into()));
+}
+
+/// Prefix the absolute `path` seen by the process with its root directory.
+fn with_root(path: &str) -> String {
+    let root = ROOT_DIR.read();
+    if root.as_str() == "/" {
+        path.into()
+    } else {
+        format!("{}{}", root.trim_end_matches('/'), path)
+    }
+}
+
+/// Strip the root directory of the process from the real `path`, giving the
+/// path seen by the process, or `None` if `path` is outside the root.
+pub fn strip_root(path: &str) -> Option<String> {
+    let root = ROOT_DIR.read();
+    let root = root.trim_end_matches('/');
+    let rest = path.strip_prefix(root)?;
+    if rest.is_empty() {
+        Some("/".into())
+    } else if rest.starts_with('/') {
+        Some(rest.into())
+    } else {
+        None
+    }
+}
+
+/// Get the path seen by the process of the real `path`, through its mount
+/// points and relative to its root directory if `path` is inside of it.
+pub fn user_path(path: &str) -> String {
+    let path = visible_path(path);
+    strip_root(&path).unwrap_or(path)
+}
+
+/// Resolve the absolute `path` seen by the process, where `..` never leaves
+/// its root directory.
+fn resolve_in_root(path: &str) -> AxResult<FilePath> {
+    // The path is normalized before being prefixed, so `..` stops at "/".
+    let canonical = canonicalize(path).map_err(|_| AxError::NotFound)?;
+    let mut real = with_root(canonical.trim());
+    if path.ends_with('/') && !real.ends_with('/') {
+        real.push('/');
+    }
+    FilePath::new(real)
+}
+
 pub fn handle_file_path(dirfd: c_int, path: &str) -> LinuxResult<FilePath> {
-    if path.starts_with('/') {
-        Ok(FilePath::new(path)?)
+    let path = if path.starts_with('/') {
+        resolve_in_root(path)?
+    } else if path.is_empty() && dirfd == AT_FDCWD {
+        // `AT_EMPTY_PATH` with `AT_FDCWD` refers to the current directory.
+        FilePath::new("")?
     } else if path.is_empty() {
-        Ok(FilePath::new(File::from_fd(dirfd)?.path())?)
+        let f = get_file_like(dirfd)?.into_any();
+        if let Some(file) = f.downcast_ref::<File>() {
+            FilePath::new(file.path())?
+        } else if let Some(dir) = f.downcast_ref::<Directory>() {
+            FilePath::new(dir.path())?
+        } else {
+            return Err(LinuxError::EINVAL);
+        }
     } else {
         let base = if dirfd == AT_FDCWD {
             FilePath::new("")?
         } else {
             FilePath::new(Directory::from_fd(dirfd)?.path())?
         };
-        Ok(base.join(path)?)
+        // `..` at a mount point leads to its parent.
+        let base = FilePath::new(visible_path(&base))?;
+        // Resolve the path relative to the root, unless the base directory
+        // is outside of it.
+        match strip_root(&base) {
+            Some(base) => resolve_in_root(&format!("{}/{}", base.trim_end_matches('/'), path))?,
+            None => base.join(path)?,
+        }
+    };
+    Ok(resolve_mounts(path))
+}
+
+/// Resolve `path` relative to `dirfd` as [`handle_file_path`] does, except
+/// for a hard link in the last component, so that the returned path names
+/// the link itself rather than the file it refers to.
+pub fn handle_link_path(dirfd: c_int, path: &str) -> LinuxResult<String> {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((dir, name)) => (dir, name),
+        None => (".", path),
+    };
+    if matches!(name, "" | "." | "..") {
+        return Ok(handle_file_path(dirfd, path)?.as_str().to_string());
     }
+    let dir = handle_file_path(dirfd, dir)?;
+    Ok(format!("{}/{}", dir.trim_end_matches('/'), name))
 }
@@ -6,6 +6,8 @@ use axtask::{TaskExtRef, current};
 use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use starry_core::mm::access_user_memory;
 
+use crate::{dirty_file_pages, unshare_cow_pages};
+
 fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) -> LinuxResult<()> {
     let align = layout.align();
     if start.as_usize() & (align - 1) != 0 {
@@ -13,8 +15,13 @@ fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) ->
     }
 
     let task = current();
-    let mut aspace = task.task_ext().process_data().aspace.lock();
+    let aspace_ref = &task.task_ext().process_data().aspace;
+    let mut aspace = aspace_ref.lock();
 
+    if access_flags.contains(MappingFlags::WRITE) {
+        dirty_file_pages(aspace_ref, &mut aspace, start, layout.size());
+        unshare_cow_pages(aspace_ref, &mut aspace, start, layout.size());
+    }
     if !aspace.check_region_access(
         VirtAddrRange::from_start_size(start, layout.size()),
         access_flags,
@@ -7,10 +7,11 @@ use core::{
     net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
 };
 
+use alloc::{string::String, vec::Vec};
 use axerrno::{LinuxError, LinuxResult};
 use linux_raw_sys::net::{
-    __kernel_sa_family_t, AF_INET, AF_INET6, in_addr, in6_addr, sockaddr, sockaddr_in,
-    sockaddr_in6, socklen_t,
+    __kernel_sa_family_t, AF_INET, AF_INET6, AF_UNIX, in_addr, in6_addr, sockaddr, sockaddr_in,
+    sockaddr_in6, sockaddr_un, socklen_t,
 };
 
 /// A type that can hold any kind of socket address, as a safe abstraction for
@@ -50,7 +51,8 @@ impl SockAddr {
     ///  - `ptr` must be a pointer to memory containing a valid socket address.
     ///  - `len` bytes must be initialized.
     pub unsafe fn read(ptr: *const sockaddr, len: socklen_t) -> LinuxResult<Self> {
-        if size_of::<__kernel_sa_family_t>() < len as usize || len as usize > size_of::<sockaddr>()
+        if (len as usize) < size_of::<__kernel_sa_family_t>()
+            || len as usize > size_of::<sockaddr>()
         {
             return Err(LinuxError::EINVAL);
         }
@@ -194,3 +196,64 @@ impl TryFrom<SockAddr> for SocketAddr {
         }
     }
 }
+
+/// The address of a Unix domain socket.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnixAddr {
+    /// The address of a socket not bound.
+    Unnamed,
+    /// A path in the file system.
+    Path(String),
+    /// A name in the abstract namespace, without the leading NUL.
+    Abstract(Vec<u8>),
+}
+
+impl From<&UnixAddr> for SockAddr {
+    fn from(unix: &UnixAddr) -> Self {
+        let mut addr = sockaddr_un {
+            sun_family: AF_UNIX as _,
+            sun_path: [0; 108],
+        };
+        let (name, len) = match unix {
+            UnixAddr::Unnamed => (&[][..], 0),
+            // With the trailing NUL.
+            UnixAddr::Path(path) => (path.as_bytes(), path.len() + 1),
+            UnixAddr::Abstract(name) => (&name[..], name.len() + 1),
+        };
+        let start = matches!(unix, UnixAddr::Abstract(_)) as usize;
+        let len = len.min(addr.sun_path.len());
+        for (dst, &src) in addr.sun_path[start..len].iter_mut().zip(name) {
+            *dst = src as _;
+        }
+        unsafe {
+            Self::read(
+                &addr as *const sockaddr_un as *const sockaddr,
+                (size_of::<__kernel_sa_family_t>() + len) as socklen_t,
+            )
+            .unwrap()
+        }
+    }
+}
+
+impl TryFrom<SockAddr> for UnixAddr {
+    type Error = LinuxError;
+
+    fn try_from(addr: SockAddr) -> LinuxResult<Self> {
+        if addr.family() != AF_UNIX {
+            return Err(LinuxError::EAFNOSUPPORT);
+        }
+        if size_of::<sockaddr_un>() < addr.addr_len() as usize {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(match &addr.bytes()[size_of::<__kernel_sa_family_t>()..] {
+            [] => UnixAddr::Unnamed,
+            [0, name @ ..] => UnixAddr::Abstract(name.to_vec()),
+            path => {
+                let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+                UnixAddr::Path(
+                    String::from_utf8(path[..len].to_vec()).map_err(|_| LinuxError::EINVAL)?,
+                )
+            }
+        })
+    }
+}
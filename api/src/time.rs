@@ -1,6 +1,26 @@
-use axhal::time::TimeValue;
+use core::sync::atomic::{AtomicI64, Ordering};
+
+use axhal::time::{TimeValue, wall_time};
 use linux_raw_sys::general::{timespec, timeval};
 
+/// The difference in nanoseconds of `CLOCK_REALTIME` from the wall time of
+/// the platform, which is changed by setting the time.
+static REALTIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// Get the time of `CLOCK_REALTIME`.
+pub fn realtime() -> TimeValue {
+    let nanos = (wall_time().as_nanos() as i64)
+        .saturating_add(REALTIME_OFFSET.load(Ordering::Relaxed))
+        .max(0);
+    TimeValue::from_nanos(nanos as u64)
+}
+
+/// Set the time of `CLOCK_REALTIME`, which does not affect `CLOCK_MONOTONIC`.
+pub fn set_realtime(time: TimeValue) {
+    let offset = (time.as_nanos() as i64).saturating_sub(wall_time().as_nanos() as i64);
+    REALTIME_OFFSET.store(offset, Ordering::Relaxed);
+}
+
 pub fn timevalue_to_timespec(tv: TimeValue) -> timespec {
     timespec {
         tv_sec: tv.as_secs() as _,
@@ -0,0 +1,53 @@
+#include <fcntl.h>
+#include <sched.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define COUNT 200
+
+void test_append() {
+  int fd = open("append_test", O_CREAT | O_TRUNC | O_RDWR | O_APPEND, 0644);
+  int pid = fork();
+  char c = pid == 0 ? 'c' : 'p';
+  for (int i = 0; i < COUNT; i++) {
+    write(fd, &c, 1);
+    // Let the other task run between the writes
+    if (i % 10 == 0) {
+      sched_yield();
+    }
+  }
+  if (pid == 0) {
+    _exit(0);
+  }
+  wait(NULL);
+
+  // Writes go to the end of file even if the offset is moved.
+  lseek(fd, 0, SEEK_SET);
+  write(fd, "e", 1);
+  if (lseek(fd, 0, SEEK_CUR) == 2 * COUNT + 1) {
+    puts("test_append ok1");
+  }
+
+  lseek(fd, 0, SEEK_SET);
+  char buf[2 * COUNT + 1];
+  int n = read(fd, buf, sizeof(buf));
+  int parent = 0, child = 0;
+  for (int i = 0; i < n; i++) {
+    if (buf[i] == 'p') {
+      parent++;
+    } else if (buf[i] == 'c') {
+      child++;
+    }
+  }
+  if (n == 2 * COUNT + 1 && parent == COUNT && child == COUNT && buf[n - 1] == 'e') {
+    puts("test_append ok2");
+  }
+  close(fd);
+  unlink("append_test");
+}
+
+int main() {
+  test_append();
+  return 0;
+}
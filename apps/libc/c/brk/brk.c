@@ -0,0 +1,65 @@
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define PAGE 4096
+#define PAGES 4
+
+// Whether reading `p` kills the process with SIGSEGV
+int faults(volatile char *p) {
+  int pid = fork();
+  if (pid == 0) {
+    (void)*p;
+    _exit(0);
+  }
+  int status;
+  waitpid(pid, &status, 0);
+  return WIFSIGNALED(status) && WTERMSIG(status) == SIGSEGV;
+}
+
+void test_brk() {
+  // The libc `brk` does not move the program break, so call it directly.
+  // Nothing is printed until it is restored, as stdio may use the heap.
+  int ok[4] = {0};
+  uintptr_t start = syscall(SYS_brk, 0);
+  char *base = (char *)((start + PAGE - 1) & ~(uintptr_t)(PAGE - 1));
+  int grown = 1;
+  for (int i = 1; i <= PAGES; i++) {
+    uintptr_t end = (uintptr_t)base + i * PAGE;
+    if (syscall(SYS_brk, end) != end) {
+      grown = 0;
+      break;
+    }
+    base[(i - 1) * PAGE] = 'a' + i;
+  }
+  ok[0] = grown;
+  int kept = 1;
+  for (int i = 1; i <= PAGES; i++) {
+    kept = kept && base[(i - 1) * PAGE] == 'a' + i;
+  }
+  ok[1] = kept;
+
+  int shrunk = 1;
+  for (int i = PAGES - 1; i > 0; i--) {
+    uintptr_t end = (uintptr_t)base + i * PAGE;
+    shrunk = shrunk && syscall(SYS_brk, end) == end;
+  }
+  ok[2] = shrunk && base[0] == 'b' && faults(base + PAGE);
+  // The pages mapped again are zeroed
+  uintptr_t end = (uintptr_t)base + 2 * PAGE;
+  ok[3] = syscall(SYS_brk, end) == end && base[PAGE] == 0;
+  syscall(SYS_brk, start);
+  for (int i = 0; i < 4; i++) {
+    if (ok[i]) {
+      printf("test_brk ok%d\n", i + 1);
+    }
+  }
+}
+
+int main() {
+  test_brk();
+  return 0;
+}
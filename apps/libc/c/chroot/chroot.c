@@ -0,0 +1,47 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+void test_chroot() {
+  mkdir("chroot_root", 0755);
+  mkdir("chroot_root/etc", 0755);
+  close(open("chroot_root/etc/marker", O_CREAT | O_WRONLY, 0644));
+  // Links whose targets climb above the new root
+  symlink("/../../etc", "chroot_root/abs");
+  symlink("../../../etc", "chroot_root/rel");
+
+  if (fork() == 0) {
+    if (chroot("chroot_root") == 0 && access("/etc/marker", F_OK) == 0) {
+      puts("test_chroot ok1");
+    }
+    // The current directory is moved into the new root.
+    char cwd[64];
+    if (getcwd(cwd, sizeof(cwd)) && strcmp(cwd, "/") == 0 &&
+        access("etc/marker", F_OK) == 0) {
+      puts("test_chroot ok2");
+    }
+    if (access("/abs/marker", F_OK) == 0) {
+      puts("test_chroot ok3");
+    }
+    if (access("/rel/marker", F_OK) == 0 && access("/../../etc/marker", F_OK) == 0) {
+      puts("test_chroot ok4");
+    }
+    exit(0);
+  }
+  wait(NULL);
+
+  unlink("chroot_root/rel");
+  unlink("chroot_root/abs");
+  unlink("chroot_root/etc/marker");
+  rmdir("chroot_root/etc");
+  rmdir("chroot_root");
+}
+
+int main() {
+  test_chroot();
+  return 0;
+}
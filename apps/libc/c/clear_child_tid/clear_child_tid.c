@@ -0,0 +1,59 @@
+#define _GNU_SOURCE
+#include <linux/futex.h>
+#include <pthread.h>
+#include <sched.h>
+#include <stdio.h>
+#include <sys/syscall.h>
+#include <time.h>
+#include <unistd.h>
+
+static char stack[65536];
+static volatile int child_tid;
+static int main_tid;
+
+// Exit after the parent is blocked on the tid address
+static int child(void *arg) {
+  struct timespec ts = {0, 100000000};
+  nanosleep(&ts, NULL);
+  return 0;
+}
+
+void test_clear_child_tid() {
+  int flags = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD |
+              CLONE_SYSVSEM | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID;
+  int tid = clone(child, stack + sizeof(stack), flags, NULL, &child_tid, NULL,
+                  &child_tid);
+  if (tid > 0 && child_tid == tid) {
+    puts("test_clear_child_tid ok1");
+  }
+  // The exit of the child clears its tid and wakes the futex on it
+  int value;
+  while ((value = child_tid) != 0) {
+    syscall(SYS_futex, &child_tid, FUTEX_WAIT, value, NULL, NULL, 0);
+  }
+  puts("test_clear_child_tid ok2");
+}
+
+void test_set_tid_address() {
+  if (syscall(SYS_set_tid_address, &main_tid) == syscall(SYS_gettid)) {
+    puts("test_set_tid_address ok1");
+  }
+}
+
+static void *thread(void *arg) { return arg; }
+
+void test_pthread_join() {
+  pthread_t t;
+  void *ret = NULL;
+  pthread_create(&t, NULL, thread, &main_tid);
+  if (pthread_join(t, &ret) == 0 && ret == &main_tid) {
+    puts("test_pthread_join ok1");
+  }
+}
+
+int main() {
+  test_clear_child_tid();
+  test_pthread_join();
+  test_set_tid_address();
+  return 0;
+}
@@ -0,0 +1,51 @@
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <sched.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static char stack[65536];
+
+// Open a new file to fds[0] and close fds[1]
+static int child(void *arg) {
+  int *fds = arg;
+  fds[0] = open("/dev/null", O_RDONLY);
+  close(fds[1]);
+  return 0;
+}
+
+// Run `child` in a thread sharing the memory and created with `flags`
+void run_child(int *fds, int flags) {
+  fds[0] = -1;
+  fds[1] = open("/dev/null", O_RDONLY);
+  int pid = clone(child, stack + sizeof(stack), CLONE_VM | flags | SIGCHLD, fds);
+  waitpid(pid, NULL, 0);
+}
+
+int is_open(int fd) { return fd >= 0 && fcntl(fd, F_GETFD) != -1; }
+
+void test_clone_files() {
+  int fds[2];
+  // The file descriptor table is shared
+  run_child(fds, CLONE_FILES);
+  if (is_open(fds[0])) {
+    puts("test_clone_files ok1");
+  }
+  if (!is_open(fds[1])) {
+    puts("test_clone_files ok2");
+  }
+  close(fds[0]);
+
+  // Without CLONE_FILES, the child changes its copy
+  run_child(fds, 0);
+  if (!is_open(fds[0]) && is_open(fds[1])) {
+    puts("test_clone_files ok3");
+  }
+  close(fds[1]);
+}
+
+int main() {
+  test_clone_files();
+  return 0;
+}
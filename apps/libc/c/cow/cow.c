@@ -0,0 +1,44 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static int global = 1;
+
+void test_cow() {
+  char *heap = malloc(8192);
+  memset(heap, 'p', 8192);
+
+  int pid = fork();
+  if (pid == 0) {
+    // The child sees the memory of the parent, then writes its own copy
+    int seen = global == 1 && heap[0] == 'p' && heap[8191] == 'p';
+    global = 2;
+    memset(heap, 'c', 8192);
+    int written = global == 2 && heap[0] == 'c' && heap[8191] == 'c';
+    _exit(seen && written ? 0 : 1);
+  }
+
+  int status;
+  waitpid(pid, &status, 0);
+  if (global == 1 && heap[0] == 'p' && heap[8191] == 'p') {
+    puts("test_cow ok1");
+  }
+  if (WIFEXITED(status) && WEXITSTATUS(status) == 0) {
+    puts("test_cow ok2");
+  }
+
+  // The parent writes to the pages it shared with the exited child
+  global = 3;
+  memset(heap, 'q', 8192);
+  if (global == 3 && heap[0] == 'q' && heap[8191] == 'q') {
+    puts("test_cow ok3");
+  }
+  free(heap);
+}
+
+int main() {
+  test_cow();
+  return 0;
+}
@@ -0,0 +1,60 @@
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <pthread.h>
+#include <stdatomic.h>
+#include <stdio.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static atomic_int started;
+static int pipe_fds[2];
+
+// Write to the pipe until it is full and block there
+static void *writer(void *arg) {
+  atomic_fetch_add(&started, 1);
+  for (;;) {
+    write(pipe_fds[1], "x", 1);
+  }
+  return NULL;
+}
+
+void test_exit_group() {
+  pipe(pipe_fds);
+  int pid = fork();
+  if (pid == 0) {
+    pthread_t threads[2];
+    for (int i = 0; i < 2; i++) {
+      pthread_create(&threads[i], NULL, writer, NULL);
+    }
+    while (atomic_load(&started) < 2) {
+    }
+    syscall(SYS_exit_group, 3);
+    // Unreachable unless exit_group returns
+    for (;;) {
+    }
+  }
+  close(pipe_fds[1]);
+
+  int status;
+  waitpid(pid, &status, 0);
+  if (WIFEXITED(status) && WEXITSTATUS(status) == 3) {
+    puts("test_exit_group ok1");
+  }
+
+  // The pipe reaches its end only if all the writing threads are gone
+  fcntl(pipe_fds[0], F_SETFL, O_NONBLOCK);
+  char buf[4096];
+  ssize_t n;
+  while ((n = read(pipe_fds[0], buf, sizeof(buf))) > 0) {
+  }
+  if (n == 0) {
+    puts("test_exit_group ok2");
+  }
+  close(pipe_fds[0]);
+}
+
+int main() {
+  test_exit_group();
+  return 0;
+}
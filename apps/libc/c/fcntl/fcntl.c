@@ -0,0 +1,37 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <unistd.h>
+
+void test_dupfd() {
+  int fd = fcntl(STDOUT_FILENO, F_DUPFD, 100);
+  if (fd == 100) {
+    puts("test_dupfd ok1");
+  }
+  // The lowest free fd not below the minimum is taken.
+  int fd2 = fcntl(STDOUT_FILENO, F_DUPFD, 100);
+  if (fd2 == 101) {
+    puts("test_dupfd ok2");
+  }
+  if (fcntl(fd, F_GETFD) == 0) {
+    puts("test_dupfd ok3");
+  }
+  close(fd);
+  close(fd2);
+}
+
+void test_dupfd_cloexec() {
+  int fd = fcntl(STDOUT_FILENO, F_DUPFD_CLOEXEC, 100);
+  if (fd == 100) {
+    puts("test_dupfd_cloexec ok1");
+  }
+  if (fcntl(fd, F_GETFD) == FD_CLOEXEC) {
+    puts("test_dupfd_cloexec ok2");
+  }
+  close(fd);
+}
+
+int main() {
+  test_dupfd();
+  test_dupfd_cloexec();
+  return 0;
+}
@@ -0,0 +1,49 @@
+#include <dirent.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+static int entry_type(const char *dir, const char *name) {
+  DIR *d = opendir(dir);
+  if (!d) {
+    return -1;
+  }
+  int type = -1;
+  struct dirent *ent;
+  while ((ent = readdir(d)) != NULL) {
+    if (strcmp(ent->d_name, name) == 0) {
+      type = ent->d_type;
+    }
+  }
+  closedir(d);
+  return type;
+}
+
+void test_d_type() {
+  mkdir("getdents_dir", 0755);
+  close(open("getdents_dir/file", O_CREAT | O_WRONLY, 0644));
+  mkdir("getdents_dir/subdir", 0755);
+  symlink("file", "getdents_dir/link");
+
+  if (entry_type("getdents_dir", "file") == DT_REG) {
+    puts("test_d_type ok1");
+  }
+  if (entry_type("getdents_dir", "subdir") == DT_DIR) {
+    puts("test_d_type ok2");
+  }
+  if (entry_type("getdents_dir", "link") == DT_LNK) {
+    puts("test_d_type ok3");
+  }
+
+  unlink("getdents_dir/link");
+  rmdir("getdents_dir/subdir");
+  unlink("getdents_dir/file");
+  rmdir("getdents_dir");
+}
+
+int main() {
+  test_d_type();
+  return 0;
+}
@@ -0,0 +1,39 @@
+#include <errno.h>
+#include <stdio.h>
+#include <sys/mman.h>
+
+#ifndef MAP_FIXED_NOREPLACE
+#define MAP_FIXED_NOREPLACE 0x100000
+#endif
+
+#define PAGE 4096
+
+void test_noreplace() {
+  char *p = mmap(NULL, 3 * PAGE, PROT_READ | PROT_WRITE,
+                 MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+  p[PAGE] = 'a';
+  // Overlapping the mapping partially or entirely fails
+  char *q = mmap(p + 2 * PAGE, 2 * PAGE, PROT_READ | PROT_WRITE,
+                 MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE, -1, 0);
+  if (q == MAP_FAILED && errno == EEXIST) {
+    puts("test_noreplace ok1");
+  }
+  q = mmap(p + PAGE, PAGE, PROT_READ | PROT_WRITE,
+           MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE, -1, 0);
+  if (q == MAP_FAILED && errno == EEXIST && p[PAGE] == 'a') {
+    puts("test_noreplace ok2");
+  }
+  // A free range is mapped at exactly the address
+  munmap(p + PAGE, 2 * PAGE);
+  q = mmap(p + PAGE, PAGE, PROT_READ | PROT_WRITE,
+           MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE, -1, 0);
+  if (q == p + PAGE && q[0] == 0) {
+    puts("test_noreplace ok3");
+  }
+  munmap(p, 2 * PAGE);
+}
+
+int main() {
+  test_noreplace();
+  return 0;
+}
@@ -0,0 +1,72 @@
+#include <signal.h>
+#include <stdio.h>
+#include <sys/mman.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define PAGE 4096
+
+// Whether reading `p` kills the process with SIGSEGV
+int faults(volatile char *p) {
+  int pid = fork();
+  if (pid == 0) {
+    (void)*p;
+    _exit(0);
+  }
+  int status;
+  waitpid(pid, &status, 0);
+  return (WIFSIGNALED(status) && WTERMSIG(status) == SIGSEGV) ||
+         (WIFEXITED(status) && WEXITSTATUS(status) == SIGSEGV);
+}
+
+char *map_pages(char *addr, int n, char first) {
+  char *p = mmap(addr, n * PAGE, PROT_READ | PROT_WRITE,
+                 MAP_PRIVATE | MAP_ANONYMOUS | (addr ? MAP_FIXED : 0), -1, 0);
+  for (int i = 0; i < n; i++) {
+    p[i * PAGE] = first + i;
+  }
+  return p;
+}
+
+void test_munmap_middle() {
+  char *p = map_pages(NULL, 3, 'a');
+  if (munmap(p + PAGE, PAGE) == 0 && p[0] == 'a' && p[2 * PAGE] == 'c') {
+    puts("test_munmap_middle ok1");
+  }
+  if (faults(p + PAGE)) {
+    puts("test_munmap_middle ok2");
+  }
+  munmap(p, 3 * PAGE);
+}
+
+void test_munmap_start() {
+  char *p = map_pages(NULL, 3, 'a');
+  if (munmap(p, PAGE) == 0 && p[PAGE] == 'b' && p[2 * PAGE] == 'c') {
+    puts("test_munmap_start ok1");
+  }
+  if (faults(p)) {
+    puts("test_munmap_start ok2");
+  }
+  munmap(p, 3 * PAGE);
+}
+
+void test_munmap_across() {
+  // Two adjacent mappings, unmapped from the middle of one to the middle
+  // of the other
+  char *p = map_pages(NULL, 4, 'a');
+  map_pages(p + 2 * PAGE, 2, 'x');
+  if (munmap(p + PAGE, 2 * PAGE) == 0 && p[0] == 'a' && p[3 * PAGE] == 'y') {
+    puts("test_munmap_across ok1");
+  }
+  if (faults(p + PAGE) && faults(p + 2 * PAGE)) {
+    puts("test_munmap_across ok2");
+  }
+  munmap(p, 4 * PAGE);
+}
+
+int main() {
+  test_munmap_middle();
+  test_munmap_start();
+  test_munmap_across();
+  return 0;
+}
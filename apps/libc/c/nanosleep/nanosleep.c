@@ -0,0 +1,47 @@
+#include <errno.h>
+#include <signal.h>
+#include <stdio.h>
+#include <time.h>
+#include <unistd.h>
+
+void test_einval() {
+  struct timespec ts = {0, 1000000000};
+  if (nanosleep(&ts, NULL) < 0 && errno == EINVAL) {
+    puts("test_einval ok1");
+  }
+  ts.tv_nsec = -1;
+  if (nanosleep(&ts, NULL) < 0 && errno == EINVAL) {
+    puts("test_einval ok2");
+  }
+}
+
+static void alarm_handler(int signum) {}
+
+void test_rem() {
+  struct timespec ts = {0, 10000000};
+  struct timespec rem = {42, 42};
+  if (nanosleep(&ts, &rem) == 0 && rem.tv_sec == 42 && rem.tv_nsec == 42) {
+    puts("test_rem ok1");
+  }
+
+  struct sigaction sa = {0};
+  sa.sa_handler = alarm_handler;
+  sigaction(SIGALRM, &sa, NULL);
+  alarm(1);
+  ts.tv_sec = 3;
+  ts.tv_nsec = 0;
+  if (nanosleep(&ts, &rem) < 0 && errno == EINTR) {
+    puts("test_rem ok2");
+  }
+  // Roughly two seconds should be left
+  if (rem.tv_sec >= 1 && rem.tv_sec <= 2 && rem.tv_nsec >= 0 &&
+      rem.tv_nsec < 1000000000) {
+    puts("test_rem ok3");
+  }
+}
+
+int main() {
+  test_einval();
+  test_rem();
+  return 0;
+}
@@ -0,0 +1,66 @@
+#define _GNU_SOURCE
+#include <sched.h>
+#include <stdio.h>
+#include <sys/resource.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+
+// Count the loops run in half a second after setting the nice value to `nice`
+static void spin(int nice, int fd) {
+  setpriority(PRIO_PROCESS, 0, nice);
+  struct timespec start, now;
+  clock_gettime(CLOCK_MONOTONIC, &start);
+  long count = 0;
+  do {
+    for (int i = 0; i < 1000; i++) {
+      __asm__ volatile("" ::: "memory");
+    }
+    count++;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+  } while ((now.tv_sec - start.tv_sec) * 1000000000L + now.tv_nsec -
+               start.tv_nsec <
+           500000000L);
+  write(fd, &count, sizeof(count));
+  _exit(0);
+}
+
+void test_nice() {
+  if (setpriority(PRIO_PROCESS, 0, 5) == 0 &&
+      getpriority(PRIO_PROCESS, 0) == 5) {
+    puts("test_nice ok1");
+  }
+  setpriority(PRIO_PROCESS, 0, 0);
+
+  // Both tasks contend for the same CPU
+  cpu_set_t set;
+  CPU_ZERO(&set);
+  CPU_SET(0, &set);
+  sched_setaffinity(0, sizeof(set), &set);
+
+  int fds[2][2];
+  int pids[2];
+  for (int i = 0; i < 2; i++) {
+    pipe(fds[i]);
+    pids[i] = fork();
+    if (pids[i] == 0) {
+      spin(i == 0 ? 0 : 19, fds[i][1]);
+    }
+  }
+  long counts[2] = {0, 0};
+  for (int i = 0; i < 2; i++) {
+    waitpid(pids[i], NULL, 0);
+    read(fds[i][0], &counts[i], sizeof(counts[i]));
+    close(fds[i][0]);
+    close(fds[i][1]);
+  }
+  // The niced task gets much less CPU time
+  if (counts[1] * 2 < counts[0]) {
+    puts("test_nice ok2");
+  }
+}
+
+int main() {
+  test_nice();
+  return 0;
+}
@@ -0,0 +1,45 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+void test_pread() {
+  int fd = open("pread_test", O_CREAT | O_TRUNC | O_RDWR, 0644);
+  write(fd, "0123456789", 10);
+  lseek(fd, 3, SEEK_SET);
+
+  char buf[4] = {0};
+  if (pread(fd, buf, 3, 7) == 3 && memcmp(buf, "789", 3) == 0) {
+    puts("test_pread ok1");
+  }
+  if (pread(fd, buf, 3, 0) == 3 && memcmp(buf, "012", 3) == 0) {
+    puts("test_pread ok2");
+  }
+  if (pwrite(fd, "ab", 2, 4) == 2 && pread(fd, buf, 3, 3) == 3 &&
+      memcmp(buf, "3ab", 3) == 0) {
+    puts("test_pread ok3");
+  }
+  // None of the above moves the offset.
+  if (lseek(fd, 0, SEEK_CUR) == 3) {
+    puts("test_pread ok4");
+  }
+  if (pread(fd, buf, 3, -1) < 0 && errno == EINVAL) {
+    puts("test_pread ok5");
+  }
+  close(fd);
+  unlink("pread_test");
+
+  int fds[2];
+  pipe(fds);
+  if (pwrite(fds[1], "x", 1, 0) < 0 && errno == ESPIPE) {
+    puts("test_pread ok6");
+  }
+  close(fds[0]);
+  close(fds[1]);
+}
+
+int main() {
+  test_pread();
+  return 0;
+}
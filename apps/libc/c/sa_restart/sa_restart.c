@@ -0,0 +1,55 @@
+#include <errno.h>
+#include <signal.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static volatile int handled;
+
+static void handler(int signum) { handled = 1; }
+
+// Read from a pipe while a child interrupts the read with SIGUSR1 then
+// writes to the pipe, returning the result of the read
+int interrupted_read(int flags, char *c) {
+  struct sigaction sa = {0};
+  sa.sa_handler = handler;
+  sa.sa_flags = flags;
+  sigaction(SIGUSR1, &sa, NULL);
+  handled = 0;
+
+  int fds[2];
+  pipe(fds);
+  int parent = getpid();
+  int pid = fork();
+  if (pid == 0) {
+    sleep(1);
+    kill(parent, SIGUSR1);
+    sleep(1);
+    write(fds[1], "x", 1);
+    _exit(0);
+  }
+  int n = read(fds[0], c, 1);
+  int err = errno;
+  waitpid(pid, NULL, 0);
+  close(fds[0]);
+  close(fds[1]);
+  errno = err;
+  return n;
+}
+
+void test_sa_restart() {
+  char c = 0;
+  // The read is restarted after the handler returns
+  if (interrupted_read(SA_RESTART, &c) == 1 && c == 'x' && handled) {
+    puts("test_sa_restart ok1");
+  }
+  // Without SA_RESTART, it fails with EINTR
+  if (interrupted_read(0, &c) == -1 && errno == EINTR && handled) {
+    puts("test_sa_restart ok2");
+  }
+}
+
+int main() {
+  test_sa_restart();
+  return 0;
+}
@@ -0,0 +1,89 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <pthread.h>
+#include <sched.h>
+#include <stdatomic.h>
+#include <stdio.h>
+#include <time.h>
+#include <unistd.h>
+
+static atomic_int started;
+static atomic_int go;
+static atomic_int ran;
+
+static long elapsed_ms(struct timespec *start) {
+  struct timespec now;
+  clock_gettime(CLOCK_MONOTONIC, &now);
+  return (now.tv_sec - start->tv_sec) * 1000 +
+         (now.tv_nsec - start->tv_nsec) / 1000000;
+}
+
+// Run only once the real-time thread lets the CPU go
+static void *other(void *arg) {
+  atomic_store(&started, 1);
+  while (!atomic_load(&go)) {
+  }
+  atomic_store(&ran, 1);
+  return NULL;
+}
+
+void test_sched_param() {
+  struct sched_param param = {.sched_priority = 0};
+  if (sched_setscheduler(0, SCHED_FIFO, &param) == -1 && errno == EINVAL) {
+    param.sched_priority = 100;
+    if (sched_setscheduler(0, SCHED_RR, &param) == -1 && errno == EINVAL) {
+      puts("test_sched_param ok1");
+    }
+  }
+  if (sched_get_priority_min(SCHED_FIFO) == 1 &&
+      sched_get_priority_max(SCHED_RR) == 99 &&
+      sched_get_priority_max(SCHED_OTHER) == 0) {
+    puts("test_sched_param ok2");
+  }
+}
+
+void test_sched_fifo() {
+  cpu_set_t set;
+  CPU_ZERO(&set);
+  CPU_SET(0, &set);
+  sched_setaffinity(0, sizeof(set), &set);
+  // The new thread is in SCHED_OTHER on the same CPU
+  pthread_t thread;
+  pthread_create(&thread, NULL, other, NULL);
+  while (!atomic_load(&started)) {
+  }
+
+  struct sched_param param = {.sched_priority = 10};
+  if (sched_setscheduler(0, SCHED_FIFO, &param) != 0) {
+    return;
+  }
+  param.sched_priority = 0;
+  if (sched_getscheduler(0) == SCHED_FIFO && sched_getparam(0, &param) == 0 &&
+      param.sched_priority == 10) {
+    puts("test_sched_fifo ok1");
+  }
+
+  // The other thread cannot run while this one does not block
+  atomic_store(&go, 1);
+  struct timespec start;
+  clock_gettime(CLOCK_MONOTONIC, &start);
+  while (elapsed_ms(&start) < 300) {
+  }
+  if (!atomic_load(&ran)) {
+    puts("test_sched_fifo ok2");
+  }
+
+  // It runs once this one blocks
+  pthread_join(thread, NULL);
+  if (atomic_load(&ran)) {
+    puts("test_sched_fifo ok3");
+  }
+  param.sched_priority = 0;
+  sched_setscheduler(0, SCHED_OTHER, &param);
+}
+
+int main() {
+  test_sched_param();
+  test_sched_fifo();
+  return 0;
+}
@@ -0,0 +1,41 @@
+#include <pthread.h>
+#include <sched.h>
+#include <stdatomic.h>
+#include <stdio.h>
+
+#define ROUNDS 1000
+
+static atomic_int turn;
+
+// Take `ROUNDS` turns with the other task, yielding while waiting
+static void take_turns(int me) {
+  for (int i = 0; i < ROUNDS; i++) {
+    while (atomic_load(&turn) != me) {
+      sched_yield();
+    }
+    atomic_store(&turn, !me);
+  }
+}
+
+static void *other(void *arg) {
+  take_turns(1);
+  return NULL;
+}
+
+void test_sched_yield() {
+  if (sched_yield() == 0) {
+    puts("test_sched_yield ok1");
+  }
+
+  // Both busy tasks make progress, as each yields to the other
+  pthread_t t;
+  pthread_create(&t, NULL, other, NULL);
+  take_turns(0);
+  pthread_join(t, NULL);
+  puts("test_sched_yield ok2");
+}
+
+int main() {
+  test_sched_yield();
+  return 0;
+}
@@ -0,0 +1,54 @@
+#define _GNU_SOURCE
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <ucontext.h>
+
+static char altstack[16384];
+static int on_altstack;
+static uintptr_t saved_sp;
+static void *saved_ss_sp;
+
+static uintptr_t context_sp(ucontext_t *uc) {
+#if defined(__x86_64__)
+  return uc->uc_mcontext.gregs[REG_RSP];
+#elif defined(__aarch64__)
+  return uc->uc_mcontext.sp;
+#elif defined(__riscv)
+  return uc->uc_mcontext.__gregs[2];
+#elif defined(__loongarch64)
+  return uc->uc_mcontext.__gregs[3];
+#endif
+}
+
+static void handler(int signum, siginfo_t *info, void *context) {
+  char local;
+  on_altstack = &local >= altstack && &local < altstack + sizeof(altstack);
+  saved_sp = context_sp(context);
+  saved_ss_sp = ((ucontext_t *)context)->uc_stack.ss_sp;
+}
+
+void test_sigaltstack() {
+  stack_t ss = {.ss_sp = altstack, .ss_size = sizeof(altstack)};
+  sigaltstack(&ss, NULL);
+  struct sigaction sa = {0};
+  sa.sa_sigaction = handler;
+  sa.sa_flags = SA_SIGINFO | SA_ONSTACK;
+  sigaction(SIGUSR1, &sa, NULL);
+
+  char local;
+  raise(SIGUSR1);
+  if (on_altstack) {
+    puts("test_sigaltstack ok1");
+  }
+  // The context saves the interrupted stack pointer, not the alternate one
+  uintptr_t sp = (uintptr_t)&local;
+  if (saved_sp < sp && sp - saved_sp < 65536 && saved_ss_sp == altstack) {
+    puts("test_sigaltstack ok2");
+  }
+}
+
+int main() {
+  test_sigaltstack();
+  return 0;
+}
@@ -0,0 +1,41 @@
+#include <signal.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+
+void test_sigtimedwait_ignored() {
+  signal(SIGUSR2, SIG_IGN);
+  sigset_t set;
+  sigemptyset(&set);
+  sigaddset(&set, SIGUSR1);
+  sigprocmask(SIG_BLOCK, &set, NULL);
+
+  int parent = getpid();
+  // SIGCHLD is ignored by default, so the exit of this child does not
+  // interrupt the wait
+  int exited = fork();
+  if (exited == 0) {
+    _exit(0);
+  }
+  int pid = fork();
+  if (pid == 0) {
+    kill(parent, SIGUSR2);
+    sleep(1);
+    kill(parent, SIGUSR1);
+    _exit(0);
+  }
+  struct timespec timeout = {.tv_sec = 5};
+  if (sigtimedwait(&set, NULL, &timeout) == SIGUSR1) {
+    puts("test_sigtimedwait_ignored ok1");
+  }
+  waitpid(exited, NULL, 0);
+  waitpid(pid, NULL, 0);
+  sigprocmask(SIG_UNBLOCK, &set, NULL);
+  signal(SIGUSR2, SIG_DFL);
+}
+
+int main() {
+  test_sigtimedwait_ignored();
+  return 0;
+}
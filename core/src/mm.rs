@@ -1,11 +1,11 @@
 use core::ffi::CStr;
 
 use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
-use axerrno::{AxError, AxResult};
+use axerrno::{AxResult, LinuxError, LinuxResult};
 use axhal::{mem::virt_to_phys, paging::MappingFlags};
 use axmm::{AddrSpace, kernel_aspace};
-use kernel_elf_parser::{AuxvEntry, ELFParser, app_stack_region};
-use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use kernel_elf_parser::{AuxvEntry, AuxvType, ELFParser, app_stack_region};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use xmas_elf::{ElfFile, program::SegmentData};
 
 pub fn new_user_aspace_empty() -> AxResult<AddrSpace> {
@@ -43,6 +43,144 @@ pub fn map_trampoline(aspace: &mut AddrSpace) -> AxResult {
     Ok(())
 }
 
+/// The maximum number of nested interpreters of `#!` scripts, as on Linux.
+const MAX_SCRIPT_DEPTH: usize = 4;
+
+/// A user app read by [`read_user_app`], with the `#!` scripts and the
+/// dynamic linker resolved.
+pub struct UserApp {
+    /// The arguments of the app, including those prepended by the `#!`
+    /// scripts.
+    args: Vec<String>,
+    /// The contents of the elf file.
+    elf: Vec<u8>,
+    /// The contents of the dynamic linker, if the app uses one.
+    interp: Option<Vec<u8>>,
+}
+
+/// Parse the elf file `data`, which must be a well-formed executable.
+fn parse_elf(data: &[u8]) -> LinuxResult<ElfFile<'_>> {
+    let elf = ElfFile::new(data).map_err(|_| LinuxError::ENOEXEC)?;
+    ELFParser::new(
+        &elf,
+        axconfig::plat::USER_INTERP_BASE,
+        elf_bias(&elf, axconfig::plat::USER_SPACE_BASE),
+        axconfig::plat::USER_SPACE_BASE,
+    )
+    .map_err(|_| LinuxError::ENOEXEC)?;
+    Ok(elf)
+}
+
+/// Get the offset of `elf` in the user address space from `uspace_base`, as
+/// only the position independent programs are relocated.
+fn elf_bias(elf: &ElfFile, uspace_base: usize) -> Option<isize> {
+    (elf.header.pt2.type_().as_type() == xmas_elf::header::Type::SharedObject)
+        .then_some(uspace_base as isize)
+}
+
+/// Read the program at `path`, which must be a regular file.
+fn read_program(path: &str) -> LinuxResult<Vec<u8>> {
+    let path = if path.starts_with("/bin/") {
+        "/musl/busybox"
+    } else {
+        path
+    };
+    if !axfs::api::metadata(path)?.is_file() {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(axfs::api::read(path)?)
+}
+
+/// Get the path of the dynamic linker requested by `elf`.
+fn interp_path(elf: &ElfFile) -> LinuxResult<Option<String>> {
+    let Some(interp) = elf
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Interp))
+    else {
+        return Ok(None);
+    };
+    let interp = match interp.get_data(elf) {
+        Ok(SegmentData::Undefined(data)) => data,
+        _ => return Err(LinuxError::ENOEXEC),
+    };
+    let interp_path = axfs::api::canonicalize(
+        CStr::from_bytes_until_nul(interp)
+            .ok()
+            .and_then(|path| path.to_str().ok())
+            .ok_or(LinuxError::ENOEXEC)?,
+    )?;
+
+    if interp_path == "/lib/ld-linux-riscv64-lp64.so.1"
+        || interp_path == "/lib64/ld-linux-loongarch-lp64d.so.1"
+        || interp_path == "/lib64/ld-linux-x86-64.so.2"
+        || interp_path == "/lib/ld-linux-aarch64.so.1"
+    {
+        // TODO: Use soft link
+        return Ok(Some(String::from("/musl/lib/libc.so")));
+    }
+    Ok(Some(interp_path))
+}
+
+/// Read the user app at `path` with the arguments `args`, whose first one is
+/// the name of the app.
+///
+/// `#!` scripts are run by the interpreters they name, with the path of the
+/// script after the interpreter and its optional argument. The dynamic
+/// linker of the app, if any, is read as well.
+///
+/// Nothing is mapped here, so that a failure leaves the caller intact.
+pub fn read_user_app(path: &str, args: &[String]) -> LinuxResult<UserApp> {
+    let mut path = path.to_owned();
+    let mut args = args.to_vec();
+    let mut file_data = read_program(&path)?;
+    let mut depth = 0;
+    while file_data.starts_with(b"#!") {
+        if depth == MAX_SCRIPT_DEPTH {
+            return Err(LinuxError::ELOOP);
+        }
+        depth += 1;
+
+        let head = &file_data[2..file_data.len().min(256)];
+        let pos = head.iter().position(|c| *c == b'\n').unwrap_or(head.len());
+        let line = core::str::from_utf8(&head[..pos]).map_err(|_| LinuxError::ENOEXEC)?;
+        let mut parts = line
+            .trim_ascii()
+            .splitn(2, |c: char| c.is_ascii_whitespace());
+        let interp = parts
+            .next()
+            .filter(|interp| !interp.is_empty())
+            .ok_or(LinuxError::ENOEXEC)?
+            .to_owned();
+
+        // The script replaces the name given by the caller.
+        let mut new_args = vec![interp.clone()];
+        new_args.extend(parts.next().map(|arg| arg.trim_ascii().to_owned()));
+        new_args.push(path);
+        new_args.extend(args.into_iter().skip(1));
+        args = new_args;
+        file_data = read_program(&interp)?;
+        path = interp;
+    }
+    if args.is_empty() {
+        args.push(path);
+    }
+
+    let elf = parse_elf(&file_data)?;
+    let interp = match interp_path(&elf)? {
+        Some(interp_path) => {
+            let interp_data = read_program(&interp_path)?;
+            parse_elf(&interp_data)?;
+            Some(interp_data)
+        }
+        None => None,
+    };
+    Ok(UserApp {
+        args,
+        elf: file_data,
+        interp,
+    })
+}
+
 /// Map the elf file to the user address space.
 ///
 /// # Arguments
@@ -51,16 +189,25 @@ pub fn map_trampoline(aspace: &mut AddrSpace) -> AxResult {
 ///
 /// # Returns
 /// - The entry point of the user app.
-fn map_elf(uspace: &mut AddrSpace, elf: &ElfFile) -> AxResult<(VirtAddr, [AuxvEntry; 16])> {
+/// - The end of the loaded segments.
+/// - The auxiliary vectors of the elf file.
+///
+/// The areas mapped for the segments are appended to `areas`.
+fn map_elf(
+    uspace: &mut AddrSpace,
+    elf: &ElfFile,
+    areas: &mut Vec<(VirtAddrRange, MappingFlags)>,
+) -> LinuxResult<(VirtAddr, VirtAddr, [AuxvEntry; 16])> {
     let uspace_base = uspace.base().as_usize();
     let elf_parser = ELFParser::new(
         elf,
         axconfig::plat::USER_INTERP_BASE,
-        Some(uspace_base as isize),
+        elf_bias(elf, uspace_base),
         uspace_base,
     )
-    .map_err(|_| AxError::InvalidData)?;
+    .map_err(|_| LinuxError::ENOEXEC)?;
 
+    let mut end = VirtAddr::from_usize(uspace_base);
     for segement in elf_parser.ph_load() {
         debug!(
             "Mapping ELF segment: [{:#x?}, {:#x?}) flags: {:#x?}",
@@ -69,7 +216,9 @@ fn map_elf(uspace: &mut AddrSpace, elf: &ElfFile) -> AxResult<(VirtAddr, [AuxvEn
             segement.flags
         );
         let seg_pad = segement.vaddr.align_offset_4k();
-        assert_eq!(seg_pad, segement.offset % PAGE_SIZE_4K);
+        if seg_pad != segement.offset % PAGE_SIZE_4K || segement.filesz > segement.memsz {
+            return Err(LinuxError::ENOEXEC);
+        }
 
         let seg_align_size =
             (segement.memsz as usize + seg_pad + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1);
@@ -79,90 +228,68 @@ fn map_elf(uspace: &mut AddrSpace, elf: &ElfFile) -> AxResult<(VirtAddr, [AuxvEn
             segement.flags,
             true,
         )?;
+        areas.push((
+            VirtAddrRange::from_start_size(segement.vaddr.align_down_4k(), seg_align_size),
+            segement.flags,
+        ));
         let seg_data = elf
             .input
             .get(segement.offset..segement.offset + segement.filesz as usize)
-            .ok_or(AxError::InvalidData)?;
+            .ok_or(LinuxError::ENOEXEC)?;
         uspace.write(segement.vaddr, seg_data)?;
+        end = end.max(segement.vaddr.align_down_4k() + seg_align_size);
         // TDOO: flush the I-cache
     }
 
     Ok((
         elf_parser.entry().into(),
+        end,
         elf_parser.auxv_vector(PAGE_SIZE_4K),
     ))
 }
 
-/// Load the user app to the user address space.
+/// Load the user app read by [`read_user_app`] to the user address space.
 ///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
-/// - `args`: The arguments of the user app. The first argument is the path of the user app.
+/// - `app`: The user app.
 /// - `envs`: The environment variables of the user app.
 ///
 /// # Returns
-/// - The entry point of the user app.
+/// - The entry point of the user app, which is that of its dynamic linker
+///   if it uses one.
 /// - The stack pointer of the user app.
+/// - The initial program break, right after the bss of the user app.
+/// - The areas mapped for the user app, with their access.
 pub fn load_user_app(
     uspace: &mut AddrSpace,
-    args: &[String],
+    app: &UserApp,
     envs: &[String],
-) -> AxResult<(VirtAddr, VirtAddr)> {
-    if args.is_empty() {
-        return Err(AxError::InvalidInput);
-    }
-    let file_data = axfs::api::read(if args[0].starts_with("/bin/") {
-        "/musl/busybox"
-    } else {
-        args[0].as_str()
-    })?;
-    if file_data.starts_with(b"#!") {
-        let head = &file_data[2..file_data.len().min(256)];
-        let pos = head.iter().position(|c| *c == b'\n').unwrap_or(head.len());
-        let line = core::str::from_utf8(&head[..pos]).map_err(|_| AxError::InvalidData)?;
-
-        let new_args: Vec<String> = line
-            .trim()
-            .splitn(2, |c: char| c.is_ascii_whitespace())
-            .map(|s| s.trim_ascii().to_owned())
-            .chain(args.iter().cloned())
-            .collect();
-        return load_user_app(uspace, &new_args, envs);
-    }
-    let elf = ElfFile::new(&file_data).map_err(|_| AxError::InvalidData)?;
-
-    if let Some(interp) = elf
-        .program_iter()
-        .find(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Interp))
-    {
-        let interp = match interp.get_data(&elf) {
-            Ok(SegmentData::Undefined(data)) => data,
-            _ => panic!("Invalid data in Interp Elf Program Header"),
-        };
-
-        let mut interp_path = axfs::api::canonicalize(
-            CStr::from_bytes_with_nul(interp)
-                .map_err(|_| AxError::InvalidData)?
-                .to_str()
-                .map_err(|_| AxError::InvalidData)?,
-        )?;
-
-        if interp_path == "/lib/ld-linux-riscv64-lp64.so.1"
-            || interp_path == "/lib64/ld-linux-loongarch-lp64d.so.1"
-            || interp_path == "/lib64/ld-linux-x86-64.so.2"
-            || interp_path == "/lib/ld-linux-aarch64.so.1"
-        {
-            // TODO: Use soft link
-            interp_path = String::from("/musl/lib/libc.so");
+) -> LinuxResult<(
+    VirtAddr,
+    VirtAddr,
+    VirtAddr,
+    Vec<(VirtAddrRange, MappingFlags)>,
+)> {
+    let mut areas = Vec::new();
+    let elf = parse_elf(&app.elf)?;
+    let (mut entry, brk, mut auxv) = map_elf(uspace, &elf, &mut areas)?;
+    if let Some(interp) = &app.interp {
+        let (interp_entry, _, interp_auxv) = map_elf(uspace, &parse_elf(interp)?, &mut areas)?;
+        entry = interp_entry;
+        // The program is mapped here, so the dynamic linker only needs to
+        // know where itself is.
+        let interp_base = interp_auxv
+            .iter()
+            .find(|aux| aux.get_type() == AuxvType::BASE)
+            .map_or(0, |aux| aux.value());
+        for aux in auxv.iter_mut() {
+            if aux.get_type() == AuxvType::BASE {
+                *aux.value_mut_ref() = interp_base;
+            }
         }
-
-        // Set the first argument to the path of the user app.
-        let mut new_args = vec![interp_path];
-        new_args.extend_from_slice(args);
-        return load_user_app(uspace, &new_args, envs);
     }
 
-    let (entry, mut auxv) = map_elf(uspace, &elf)?;
     // The user stack is divided into two parts:
     // `ustack_start` -> `ustack_pointer`: It is the stack space that users actually read and write.
     // `ustack_pointer` -> `ustack_end`: It is the space that contains the arguments, environment variables and auxv passed to the app.
@@ -175,28 +302,19 @@ pub fn load_user_app(
         ustack_start, ustack_end
     );
 
-    let stack_data = app_stack_region(args, envs, &mut auxv, ustack_start, ustack_size);
-    uspace.map_alloc(
-        ustack_start,
-        ustack_size,
-        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
-        true,
-    )?;
-
-    let heap_start = VirtAddr::from_usize(axconfig::plat::USER_HEAP_BASE);
-    let heap_size = axconfig::plat::USER_HEAP_SIZE;
-    uspace.map_alloc(
-        heap_start,
-        heap_size,
-        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
-        true,
-    )?;
+    let stack_data = app_stack_region(&app.args, envs, &mut auxv, ustack_start, ustack_size);
+    let stack_flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+    uspace.map_alloc(ustack_start, ustack_size, stack_flags, true)?;
+    areas.push((
+        VirtAddrRange::from_start_size(ustack_start, ustack_size),
+        stack_flags,
+    ));
 
     let user_sp = ustack_end - stack_data.len();
 
     uspace.write(user_sp, stack_data.as_slice())?;
 
-    Ok((entry, user_sp))
+    Ok((entry, user_sp, brk, areas))
 }
 
 #[percpu::def_percpu]
@@ -1,6 +1,9 @@
 use core::ops::{Index, IndexMut};
 
-use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_STACK};
+use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_SIGPENDING, RLIMIT_STACK};
+
+/// The default limit of the real-time signals queued to a process.
+const DEFAULT_SIGPENDING: u64 = 4096;
 
 #[derive(Default)]
 pub struct Rlimit {
@@ -28,6 +31,7 @@ impl Default for Rlimits {
     fn default() -> Self {
         let mut result = Self(Default::default());
         result[RLIMIT_STACK] = (axconfig::plat::USER_STACK_SIZE as u64).into();
+        result[RLIMIT_SIGPENDING] = DEFAULT_SIGPENDING.into();
         result
     }
 }
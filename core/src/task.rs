@@ -2,7 +2,7 @@ use core::{
     alloc::Layout,
     cell::{Cell, RefCell},
     hint::black_box,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicUsize, Ordering},
 };
 
 use alloc::{
@@ -12,8 +12,8 @@ use alloc::{
 };
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
-    arch::UspaceContext,
-    time::{NANOS_PER_MICROS, NANOS_PER_SEC, monotonic_time_nanos},
+    arch::{TrapFrame, UspaceContext},
+    time::{NANOS_PER_MICROS, NANOS_PER_SEC, TimeValue, monotonic_time_nanos},
 };
 use axmm::{AddrSpace, kernel_aspace};
 use axns::{AxNamespace, AxNamespaceIf};
@@ -23,12 +23,15 @@ use axsignal::{
     ctypes::{SignalAction, SignalSet},
 };
 use axsync::{Mutex, spin::SpinNoIrq};
-use axtask::{TaskExtRef, TaskInner, WaitQueue, current};
+use axtask::{AxCpuMask, TaskExtRef, TaskInner, WaitQueue, WeakAxTaskRef, current};
 use memory_addr::VirtAddrRange;
 use spin::{Once, RwLock};
 use weak_map::WeakMap;
 
-use crate::{resources::Rlimits, time::TimeStat};
+use crate::{
+    resources::Rlimits,
+    time::{RealTimer, TimeStat},
+};
 
 pub fn new_user_task(name: &str) -> TaskInner {
     TaskInner::new(
@@ -71,10 +74,12 @@ impl TaskExt {
 
     pub(crate) fn time_stat_from_kernel_to_user(&self, current_tick: usize) {
         self.time.borrow_mut().switch_into_user_mode(current_tick);
+        *self.thread_data().cpu_time.lock() = self.time_stat_output();
     }
 
     pub(crate) fn time_stat_from_user_to_kernel(&self, current_tick: usize) {
         self.time.borrow_mut().switch_into_kernel_mode(current_tick);
+        *self.thread_data().cpu_time.lock() = self.time_stat_output();
     }
 
     pub(crate) fn time_stat_output(&self) -> (usize, usize) {
@@ -106,6 +111,40 @@ pub fn time_stat_from_user_to_kernel() {
         .time_stat_from_user_to_kernel(monotonic_time_nanos() as usize);
 }
 
+/// Add the user and system time of the current thread, which is exiting, to
+/// its process.
+pub fn time_stat_exit() {
+    let curr_task = current();
+    let (utime_ns, stime_ns) = curr_task.task_ext().time_stat_output();
+    let mut exited_time = curr_task.task_ext().process_data().exited_time.lock();
+    exited_time.0 += utime_ns;
+    exited_time.1 += stime_ns;
+}
+
+/// Get the user and system time in nanoseconds of the current process,
+/// including its exited threads.
+pub fn process_time_stat() -> (usize, usize) {
+    let curr_task = current();
+    let task_ext = curr_task.task_ext();
+    let exited_time = *task_ext.process_data().exited_time.lock();
+    task_ext
+        .thread
+        .process()
+        .threads()
+        .iter()
+        .fold(exited_time, |(utime_ns, stime_ns), thread| {
+            // The time of the current thread is more recent than the copy.
+            let (thread_utime_ns, thread_stime_ns) = if Arc::ptr_eq(thread, &task_ext.thread) {
+                task_ext.time_stat_output()
+            } else {
+                thread
+                    .data::<ThreadData>()
+                    .map_or((0, 0), |data| *data.cpu_time.lock())
+            };
+            (utime_ns + thread_utime_ns, stime_ns + thread_stime_ns)
+        })
+}
+
 pub fn time_stat_output() -> (usize, usize, usize, usize) {
     let curr_task = current();
     let (utime_ns, stime_ns) = curr_task.task_ext().time_stat_output();
@@ -129,6 +168,38 @@ pub struct ThreadData {
     pub pending: SpinNoIrq<PendingSignals>,
     /// The set of signals currently blocked from delivery.
     pub blocked: Mutex<SignalSet>,
+    /// The alternate signal stack.
+    pub signal_stack: Mutex<SignalStack>,
+    /// The trap frame of the syscall interrupted by a signal, which is
+    /// restarted after the signal is handled if no handler runs or the
+    /// handler has `SA_RESTART`.
+    pub restart_context: Mutex<Option<TrapFrame>>,
+    /// The deadline of the interrupted `nanosleep`, which is restarted with
+    /// the remaining time only if no handler runs.
+    pub sleep_deadline: Mutex<Option<TimeValue>>,
+    /// The user and system time in nanoseconds of the thread as of its last
+    /// switch between user and kernel mode, for the other threads to read.
+    pub cpu_time: SpinNoIrq<(usize, usize)>,
+    /// The CPUs the thread may run on, set by `sched_setaffinity`.
+    pub affinity: SpinNoIrq<AxCpuMask>,
+    /// The task running the thread, set once it is spawned.
+    pub task: SpinNoIrq<WeakAxTaskRef>,
+    /// The nice value of the thread, from -20 to 19.
+    pub nice: AtomicI32,
+    /// The lowest nice value of the threads waiting for the PI futexes owned
+    /// by the thread, whose priority it runs with if higher, or `i32::MAX`
+    /// if there are none.
+    pub pi_nice: AtomicI32,
+    /// Whether `nice`, `pi_nice` or the scheduling policy is changed by
+    /// another thread and not yet applied, which is done by the thread itself
+    /// before returning to user space.
+    pub nice_changed: AtomicBool,
+    /// The scheduling policy of the thread, such as `SCHED_FIFO`, with
+    /// `SCHED_RESET_ON_FORK` if set.
+    pub sched_policy: AtomicU32,
+    /// The priority of the thread in the real-time policies, from 1 to 99,
+    /// or 0 in the others.
+    pub sched_priority: AtomicU32,
 }
 
 impl ThreadData {
@@ -138,6 +209,17 @@ impl ThreadData {
             clear_child_tid: AtomicUsize::new(0),
             pending: SpinNoIrq::new(PendingSignals::new()),
             blocked: Mutex::default(),
+            signal_stack: Mutex::default(),
+            restart_context: Mutex::new(None),
+            sleep_deadline: Mutex::new(None),
+            cpu_time: SpinNoIrq::new((0, 0)),
+            affinity: SpinNoIrq::new(AxCpuMask::full()),
+            task: SpinNoIrq::new(Weak::new()),
+            nice: AtomicI32::new(0),
+            pi_nice: AtomicI32::new(i32::MAX),
+            nice_changed: AtomicBool::new(false),
+            sched_policy: AtomicU32::new(0),
+            sched_priority: AtomicU32::new(0),
         }
     }
 
@@ -151,25 +233,110 @@ impl ThreadData {
     }
 }
 
+/// The alternate stack for the signal handlers with `SA_ONSTACK`, set by
+/// `sigaltstack`.
+#[derive(Clone, Copy, Default)]
+pub struct SignalStack {
+    /// The lowest address of the stack.
+    pub base: usize,
+    /// The size of the stack, 0 if it is disabled.
+    pub size: usize,
+}
+
+impl SignalStack {
+    /// The initial stack pointer of the stack.
+    pub fn top(&self) -> usize {
+        self.base + self.size
+    }
+
+    /// Check whether the stack pointer `sp` is on the stack.
+    pub fn contains(&self, sp: usize) -> bool {
+        self.size != 0 && self.base < sp && sp <= self.top()
+    }
+}
+
+/// The number of signals, including the real-time signals.
+pub const NSIG: usize = 64;
+
+/// The address space of a process, which may be shared with `CLONE_VM`.
+///
+/// The child of `vfork` shares the address space of its parent until it
+/// calls `execve`, which gives it a new one.
+pub struct ProcessAspace {
+    initial: Arc<Mutex<AddrSpace>>,
+    replaced: Once<Arc<Mutex<AddrSpace>>>,
+}
+
+impl ProcessAspace {
+    fn new(aspace: Arc<Mutex<AddrSpace>>) -> Self {
+        Self {
+            initial: aspace,
+            replaced: Once::new(),
+        }
+    }
+
+    /// Replace the address space with `aspace`, returning `false` if it has
+    /// already been replaced.
+    pub fn replace(&self, aspace: Arc<Mutex<AddrSpace>>) -> bool {
+        let mut replaced = false;
+        self.replaced.call_once(|| {
+            replaced = true;
+            aspace
+        });
+        replaced
+    }
+}
+
+impl core::ops::Deref for ProcessAspace {
+    type Target = Arc<Mutex<AddrSpace>>;
+
+    fn deref(&self) -> &Self::Target {
+        self.replaced.get().unwrap_or(&self.initial)
+    }
+}
+
+impl Drop for ProcessAspace {
+    fn drop(&mut self) {
+        if cfg!(target_arch = "aarch64") || cfg!(target_arch = "loongarch64") {
+            return;
+        }
+        // See [`crate::new_user_aspace`]
+        let kernel = kernel_aspace().lock();
+        let range = VirtAddrRange::from_start_size(kernel.base(), kernel.size());
+        for aspace in core::iter::once(&self.initial).chain(self.replaced.get()) {
+            // The address space is freed with its last reference.
+            if Arc::strong_count(aspace) == 1 {
+                aspace.lock().clear_mappings(range);
+            }
+        }
+    }
+}
+
 pub struct ProcessData {
     /// The executable path
     pub exe_path: RwLock<String>,
     /// The virtual memory address space.
-    pub aspace: Arc<Mutex<AddrSpace>>,
+    pub aspace: ProcessAspace,
     /// The resource namespace
     pub ns: AxNamespace,
     /// The user heap bottom
     heap_bottom: AtomicUsize,
     /// The user heap top
     heap_top: AtomicUsize,
+    /// The file mode creation mask, which may be shared with `CLONE_FS`
+    umask: Arc<AtomicU32>,
 
     /// The resource limits
     pub rlim: RwLock<Rlimits>,
 
     /// The process-level shared pending signals
     pub pending: SpinNoIrq<PendingSignals>,
-    /// The signal actions
-    pub signal_actions: Mutex<[SignalAction; 32]>,
+    /// The signal actions, indexed by the signal number, which may be shared
+    /// with `CLONE_SIGHAND`
+    pub signal_actions: Arc<Mutex<[SignalAction; NSIG + 1]>>,
+    /// The number of real-time signals queued to the process and its
+    /// threads, which is limited by `RLIMIT_SIGPENDING`.
+    pub queued_signals: AtomicUsize,
     /// The wait queue for signal. Used by `rt_sigtimedwait`, etc.
     ///
     /// Note that this is shared by all threads in the process, so false wakeups
@@ -177,23 +344,52 @@ pub struct ProcessData {
     pub signal_wq: WaitQueue,
     /// The wait queue for child exits.
     pub child_exit_wq: WaitQueue,
+    /// Whether the process is created by `vfork` and has not called `execve`
+    /// or exited, during which its parent is suspended.
+    pub vfork_pending: AtomicBool,
+    /// Whether the process has called `execve`, after which its parent can
+    /// no longer change its process group.
+    pub execed: AtomicBool,
+    /// The signal sent to the process when its parent exits, or 0 for none.
+    pub pdeath_signal: AtomicU32,
+    /// The `ITIMER_REAL` timer, which sends `SIGALRM` on expiration.
+    pub real_timer: Mutex<RealTimer>,
+    /// The wait queue of the task serving the `ITIMER_REAL` timer, notified
+    /// when the timer is set.
+    pub real_timer_wq: WaitQueue,
+    /// The user and system time in nanoseconds of the exited threads.
+    pub exited_time: Mutex<(usize, usize)>,
+    /// The user and system time in nanoseconds of the waited-for children
+    /// and their waited-for descendants.
+    pub children_time: Mutex<(usize, usize)>,
 }
 
 impl ProcessData {
     pub fn new(exe_path: String, aspace: Arc<Mutex<AddrSpace>>) -> Self {
         Self {
             exe_path: RwLock::new(exe_path),
-            aspace,
+            aspace: ProcessAspace::new(aspace),
             ns: AxNamespace::new_thread_local(),
             heap_bottom: AtomicUsize::new(axconfig::plat::USER_HEAP_BASE),
             heap_top: AtomicUsize::new(axconfig::plat::USER_HEAP_BASE),
+            umask: Arc::new(AtomicU32::new(0o022)),
 
             rlim: RwLock::default(),
 
             pending: SpinNoIrq::new(PendingSignals::new()),
-            signal_actions: Mutex::default(),
+            signal_actions: Arc::new(Mutex::new(core::array::from_fn(|_| {
+                SignalAction::default()
+            }))),
+            queued_signals: AtomicUsize::new(0),
             signal_wq: WaitQueue::new(),
             child_exit_wq: WaitQueue::new(),
+            vfork_pending: AtomicBool::new(false),
+            execed: AtomicBool::new(false),
+            pdeath_signal: AtomicU32::new(0),
+            real_timer: Mutex::default(),
+            real_timer_wq: WaitQueue::new(),
+            exited_time: Mutex::new((0, 0)),
+            children_time: Mutex::new((0, 0)),
         }
     }
 
@@ -212,17 +408,19 @@ impl ProcessData {
     pub fn set_heap_top(&self, top: usize) {
         self.heap_top.store(top, Ordering::Release)
     }
-}
 
-impl Drop for ProcessData {
-    fn drop(&mut self) {
-        if !cfg!(target_arch = "aarch64") && !cfg!(target_arch = "loongarch64") {
-            // See [`crate::new_user_aspace`]
-            let kernel = kernel_aspace().lock();
-            self.aspace
-                .lock()
-                .clear_mappings(VirtAddrRange::from_start_size(kernel.base(), kernel.size()));
-        }
+    pub fn umask(&self) -> u32 {
+        self.umask.load(Ordering::Relaxed)
+    }
+
+    /// Set the file mode creation mask, returning the previous one.
+    pub fn replace_umask(&self, umask: u32) -> u32 {
+        self.umask.swap(umask, Ordering::Relaxed)
+    }
+
+    /// Share the file mode creation mask of `other`.
+    pub fn share_umask(&mut self, other: &ProcessData) {
+        self.umask = other.umask.clone();
     }
 }
 
@@ -280,6 +478,16 @@ pub fn add_thread_to_table(thread: &Arc<Thread>) {
     session_table.insert(session.sid(), &session);
 }
 
+/// Add the newly created process group, as well as its session, to the
+/// tables.
+pub fn add_process_group_to_table(process_group: &Arc<ProcessGroup>) {
+    PROCESS_GROUP_TABLE
+        .write()
+        .insert(process_group.pgid(), process_group);
+    let session = process_group.session();
+    SESSION_TABLE.write().insert(session.sid(), &session);
+}
+
 pub fn processes() -> Vec<Arc<Process>> {
     PROCESS_TABLE.read().values().collect()
 }
@@ -1,12 +1,14 @@
+use axhal::time::TimeValue;
+
 #[repr(C)]
 pub struct Tms {
-    /// 进程用户态执行时间，单位为us
+    /// 进程用户态执行时间，单位为时钟滴答
     pub tms_utime: usize,
-    /// 进程内核态执行时间，单位为us
+    /// 进程内核态执行时间，单位为时钟滴答
     pub tms_stime: usize,
-    /// 子进程用户态执行时间和，单位为us
+    /// 子进程用户态执行时间和，单位为时钟滴答
     pub tms_cutime: usize,
-    /// 子进程内核态执行时间和，单位为us
+    /// 子进程内核态执行时间和，单位为时钟滴答
     pub tms_cstime: usize,
 }
 
@@ -135,3 +137,15 @@ impl TimeStat {
         }
     }
 }
+
+/// The setting of the `ITIMER_REAL` timer of a process.
+#[derive(Default)]
+pub struct RealTimer {
+    /// The next expiration in monotonic time, `None` if disarmed.
+    pub deadline: Option<TimeValue>,
+    /// The period of the timer, zero for one-shot timers.
+    pub interval: TimeValue,
+    /// The number of times the timer has been set, which tells the task
+    /// serving an older setting to stop.
+    pub generation: u64,
+}
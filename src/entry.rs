@@ -3,9 +3,10 @@ use axfs::{CURRENT_DIR, CURRENT_DIR_PATH, api::set_current_dir};
 use axhal::arch::UspaceContext;
 use axprocess::{Pid, init_proc};
 use axsync::Mutex;
-use starry_api::fd::FD_TABLE;
+use axtask::TaskExtRef;
+use starry_api::{CWD_MOUNT, add_vma, fd::FD_TABLE, path::ROOT_DIR};
 use starry_core::{
-    mm::{copy_from_kernel, load_user_app, map_trampoline, new_user_aspace_empty},
+    mm::{copy_from_kernel, load_user_app, map_trampoline, new_user_aspace_empty, read_user_app},
     task::{ProcessData, TaskExt, ThreadData, add_thread_to_table, new_user_task},
 };
 
@@ -22,7 +23,8 @@ pub fn run_user_app(args: &[String], envs: &[String]) -> Option<i32> {
     let (dir, name) = exe_path.rsplit_once('/').unwrap_or(("", &exe_path));
     set_current_dir(dir).expect("Failed to set current dir");
 
-    let (entry_vaddr, ustack_top) = load_user_app(&mut uspace, args, envs)
+    let (entry_vaddr, ustack_top, brk, areas) = read_user_app(&exe_path, args)
+        .and_then(|app| load_user_app(&mut uspace, &app, envs))
         .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
 
     let uctx = UspaceContext::new(entry_vaddr.into(), ustack_top, 2333);
@@ -31,6 +33,11 @@ pub fn run_user_app(args: &[String], envs: &[String]) -> Option<i32> {
     task.ctx_mut().set_page_table_root(uspace.page_table_root());
 
     let process_data = ProcessData::new(exe_path, Arc::new(Mutex::new(uspace)));
+    for (range, flags) in areas {
+        add_vma(&process_data.aspace, range.start, range.size(), flags);
+    }
+    process_data.set_heap_bottom(brk.as_usize());
+    process_data.set_heap_top(brk.as_usize());
 
     FD_TABLE
         .deref_from(&process_data.ns)
@@ -41,6 +48,12 @@ pub fn run_user_app(args: &[String], envs: &[String]) -> Option<i32> {
     CURRENT_DIR_PATH
         .deref_from(&process_data.ns)
         .init_new(CURRENT_DIR_PATH.copy_inner());
+    CWD_MOUNT
+        .deref_from(&process_data.ns)
+        .init_new(CWD_MOUNT.copy_inner());
+    ROOT_DIR
+        .deref_from(&process_data.ns)
+        .init_new(ROOT_DIR.copy_inner());
 
     let tid = task.id().as_u64() as Pid;
     let process = init_proc().fork(tid).data(process_data).build();
@@ -51,6 +64,7 @@ pub fn run_user_app(args: &[String], envs: &[String]) -> Option<i32> {
     task.init_task_ext(TaskExt::new(uctx, thread));
 
     let task = axtask::spawn_task(task);
+    *task.task_ext().thread_data().task.lock() = Arc::downgrade(&task);
 
     // TODO: we need a way to wait on the process but not only the main task
     task.join()
@@ -5,8 +5,10 @@ use axhal::{
 };
 use axsignal::ctypes::SignalInfo;
 use axtask::{TaskExtRef, current};
-use linux_raw_sys::general::{RLIMIT_STACK, SI_KERNEL, SIGSEGV};
-use starry_api::{do_exit, send_signal_process};
+use linux_raw_sys::general::{RLIMIT_STACK, SI_KERNEL, SIGBUS, SIGSEGV};
+use starry_api::{
+    do_exit, handle_cow_fault, handle_file_page_fault, is_beyond_eof, send_signal_process,
+};
 use starry_core::mm::is_accessing_user_memory;
 
 #[register_trap_handler(PAGE_FAULT)]
@@ -34,13 +36,21 @@ fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool)
             );
         }
     }
-    if !curr
-        .task_ext()
-        .process_data()
-        .aspace
-        .lock()
-        .handle_page_fault(vaddr, access_flags)
+    let aspace = &curr.task_ext().process_data().aspace;
+    let handled = aspace.lock().handle_page_fault(vaddr, access_flags);
+    if !handled
+        && !handle_file_page_fault(aspace, vaddr, access_flags)
+        && !handle_cow_fault(aspace, vaddr, access_flags)
     {
+        if is_beyond_eof(aspace, vaddr) {
+            warn!(
+                "{} ({:?}): bus error at {:#x}, exit!",
+                curr.id_name(),
+                curr.task_ext().thread,
+                vaddr
+            );
+            do_exit(SIGBUS as _, true);
+        }
         warn!(
             "{} ({:?}): segmentation fault at {:#x}, exit!",
             curr.id_name(),
@@ -3,7 +3,6 @@ use axhal::{
     arch::TrapFrame,
     trap::{SYSCALL, register_trap_handler},
 };
-use linux_raw_sys::general::SIGSYS;
 use starry_api::*;
 use starry_core::task::{time_stat_from_kernel_to_user, time_stat_from_user_to_kernel};
 use syscalls::Sysno;
@@ -25,12 +24,83 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
             tf.arg2() as _,
             tf.arg3() as _,
         ),
+        Sysno::pwrite64 => sys_pwrite64(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::preadv => sys_preadv(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::pwritev => sys_pwritev(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
         Sysno::sendfile => sys_sendfile(
             tf.arg0() as _,
             tf.arg1() as _,
             tf.arg2().into(),
             tf.arg3() as _,
         ),
+        Sysno::copy_file_range => sys_copy_file_range(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3().into(),
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
+        Sysno::splice => sys_splice(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3().into(),
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::eventfd => sys_eventfd(tf.arg0() as _),
+        Sysno::eventfd2 => sys_eventfd2(tf.arg0() as _, tf.arg1() as _),
+        Sysno::timerfd_create => sys_timerfd_create(tf.arg0() as _, tf.arg1() as _),
+        Sysno::timerfd_settime => sys_timerfd_settime(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2().into(),
+            tf.arg3().into(),
+        ),
+        Sysno::timerfd_gettime => sys_timerfd_gettime(tf.arg0() as _, tf.arg1().into()),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::epoll_create => sys_epoll_create(tf.arg0() as _),
+        Sysno::epoll_create1 => sys_epoll_create1(tf.arg0() as _),
+        Sysno::epoll_ctl => sys_epoll_ctl(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3().into(),
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::epoll_wait => sys_epoll_wait(
+            tf,
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::epoll_pwait => sys_epoll_pwait(
+            tf,
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4().into(),
+            tf.arg5() as _,
+        ),
         #[cfg(target_arch = "x86_64")]
         Sysno::poll => sys_poll(tf.arg0().into(), tf.arg1() as _, tf.arg2() as _),
         Sysno::ppoll => sys_ppoll(
@@ -49,20 +119,72 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
         ),
         Sysno::ioctl => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2().into()),
         Sysno::sched_yield => sys_sched_yield(),
+        Sysno::getpriority => sys_getpriority(tf.arg0() as _, tf.arg1() as _),
+        Sysno::setpriority => sys_setpriority(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::sched_setscheduler => {
+            sys_sched_setscheduler(tf.arg0() as _, tf.arg1() as _, tf.arg2().into())
+        }
+        Sysno::sched_getscheduler => sys_sched_getscheduler(tf.arg0() as _),
+        Sysno::sched_setparam => sys_sched_setparam(tf.arg0() as _, tf.arg1().into()),
+        Sysno::sched_getparam => sys_sched_getparam(tf.arg0() as _, tf.arg1().into()),
+        Sysno::sched_get_priority_max => sys_sched_get_priority_max(tf.arg0() as _),
+        Sysno::sched_get_priority_min => sys_sched_get_priority_min(tf.arg0() as _),
+        Sysno::sched_rr_get_interval => sys_sched_rr_get_interval(tf.arg0() as _, tf.arg1().into()),
+        Sysno::sched_setaffinity => {
+            sys_sched_setaffinity(tf.arg0() as _, tf.arg1() as _, tf.arg2().into())
+        }
+        Sysno::sched_getaffinity => {
+            sys_sched_getaffinity(tf.arg0() as _, tf.arg1() as _, tf.arg2().into())
+        }
         Sysno::nanosleep => sys_nanosleep(tf.arg0().into(), tf.arg1().into()),
+        Sysno::clock_nanosleep => sys_clock_nanosleep(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2().into(),
+            tf.arg3().into(),
+        ),
         Sysno::getpid => sys_getpid(),
         Sysno::getppid => sys_getppid(),
+        Sysno::setpgid => sys_setpgid(tf.arg0() as _, tf.arg1() as _),
+        Sysno::getpgid => sys_getpgid(tf.arg0() as _),
+        Sysno::setsid => sys_setsid(),
+        Sysno::getsid => sys_getsid(tf.arg0() as _),
         Sysno::gettid => sys_gettid(),
         Sysno::exit => sys_exit(tf.arg0() as _),
         Sysno::exit_group => sys_exit_group(tf.arg0() as _),
-        Sysno::gettimeofday => sys_get_time_of_day(tf.arg0().into()),
+        Sysno::gettimeofday => sys_gettimeofday(tf.arg0().into(), tf.arg1().into()),
+        Sysno::settimeofday => sys_settimeofday(tf.arg0().into(), tf.arg1().into()),
         Sysno::getcwd => sys_getcwd(tf.arg0().into(), tf.arg1() as _),
         Sysno::dup => sys_dup(tf.arg0() as _),
         #[cfg(target_arch = "x86_64")]
         Sysno::dup2 => sys_dup2(tf.arg0() as _, tf.arg1() as _),
-        Sysno::dup3 => sys_dup2(tf.arg0() as _, tf.arg1() as _),
+        Sysno::dup3 => sys_dup3(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         Sysno::fcntl => sys_fcntl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::truncate => sys_truncate(tf.arg0().into(), tf.arg1() as _),
+        Sysno::ftruncate => sys_ftruncate(tf.arg0() as _, tf.arg1() as _),
+        Sysno::memfd_create => sys_memfd_create(tf.arg0().into(), tf.arg1() as _),
+        Sysno::fallocate => sys_fallocate(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::fadvise64 => sys_fadvise64(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::flock => sys_flock(tf.arg0() as _, tf.arg1() as _),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::inotify_init => sys_inotify_init(),
+        Sysno::inotify_init1 => sys_inotify_init1(tf.arg0() as _),
+        Sysno::inotify_add_watch => {
+            sys_inotify_add_watch(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _)
+        }
+        Sysno::inotify_rm_watch => sys_inotify_rm_watch(tf.arg0() as _, tf.arg1() as _),
         Sysno::lseek => sys_lseek(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        #[cfg(not(target_arch = "x86_64"))]
         Sysno::clone => sys_clone(
             tf.arg0() as _,
             tf.arg1() as _,
@@ -70,16 +192,51 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
             tf.arg3() as _,
             tf.arg4() as _,
         ),
+        // The child tid comes before the thread pointer on x86_64.
+        #[cfg(target_arch = "x86_64")]
+        Sysno::clone => sys_clone(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg4() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::clone3 => sys_clone3(tf.arg0().into(), tf.arg1() as _),
         #[cfg(target_arch = "x86_64")]
         Sysno::fork => sys_fork(),
-        Sysno::wait4 => sys_waitpid(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
-        Sysno::pipe2 => sys_pipe(tf.arg0().into()),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::vfork => sys_vfork(),
+        Sysno::wait4 => sys_wait4(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3().into(),
+        ),
+        Sysno::waitid => sys_waitid(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2().into(),
+            tf.arg3() as _,
+        ),
+        Sysno::pipe2 => sys_pipe2(tf.arg0().into(), tf.arg1() as _),
         #[cfg(target_arch = "x86_64")]
         Sysno::pipe => sys_pipe(tf.arg0().into()),
         Sysno::close => sys_close(tf.arg0() as _),
         Sysno::chdir => sys_chdir(tf.arg0().into()),
+        Sysno::fchdir => sys_fchdir(tf.arg0() as _),
+        Sysno::chroot => sys_chroot(tf.arg0().into()),
         Sysno::mkdirat => sys_mkdirat(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::mkdir => sys_mkdir(tf.arg0().into(), tf.arg1() as _),
+        Sysno::umask => sys_umask(tf.arg0() as _),
         Sysno::execve => sys_execve(tf.arg0().into(), tf.arg1().into(), tf.arg2().into()),
+        Sysno::execveat => sys_execveat(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2().into(),
+            tf.arg3().into(),
+            tf.arg4() as _,
+        ),
         Sysno::openat => sys_openat(
             tf.arg0() as _,
             tf.arg1().into(),
@@ -98,6 +255,64 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
         ),
         #[cfg(target_arch = "x86_64")]
         Sysno::link => sys_link(tf.arg0().into(), tf.arg1().into()),
+        Sysno::readlinkat => sys_readlinkat(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2().into(),
+            tf.arg3() as _,
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::readlink => sys_readlink(tf.arg0().into(), tf.arg1().into(), tf.arg2() as _),
+        Sysno::symlinkat => sys_symlinkat(tf.arg0().into(), tf.arg1() as _, tf.arg2().into()),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::symlink => sys_symlink(tf.arg0().into(), tf.arg1().into()),
+        Sysno::renameat2 => sys_renameat2(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3().into(),
+            tf.arg4() as _,
+        ),
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        Sysno::renameat => sys_renameat(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3().into(),
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::rename => sys_rename(tf.arg0().into(), tf.arg1().into()),
+        Sysno::fchmod => sys_fchmod(tf.arg0() as _, tf.arg1() as _),
+        // fchmodat has no flags argument, unlike its libc wrapper
+        Sysno::fchmodat => sys_fchmodat(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _, 0),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::chmod => sys_chmod(tf.arg0().into(), tf.arg1() as _),
+        Sysno::fchown => sys_fchown(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::fchownat => sys_fchownat(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::chown => sys_chown(tf.arg0().into(), tf.arg1() as _, tf.arg2() as _),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::lchown => sys_lchown(tf.arg0().into(), tf.arg1() as _, tf.arg2() as _),
+        Sysno::utimensat => sys_utimensat(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2().into(),
+            tf.arg3() as _,
+        ),
+        Sysno::mknodat => sys_mknodat(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::mknod => sys_mknod(tf.arg0().into(), tf.arg1() as _, tf.arg2() as _),
         Sysno::unlinkat => sys_unlinkat(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
         #[cfg(target_arch = "x86_64")]
         Sysno::unlink => sys_unlink(tf.arg0().into()),
@@ -105,6 +320,8 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
         #[cfg(target_arch = "x86_64")]
         Sysno::stat => sys_stat(tf.arg0().into(), tf.arg1().into()),
         Sysno::fstat => sys_fstat(tf.arg0() as _, tf.arg1().into()),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::lstat => sys_lstat(tf.arg0().into(), tf.arg1().into()),
         Sysno::mount => sys_mount(
             tf.arg0().into(),
             tf.arg1().into(),
@@ -134,9 +351,53 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
             tf.arg3() as _,
             tf.arg4().into(),
         ),
+        Sysno::faccessat => sys_faccessat(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        Sysno::faccessat2 => sys_faccessat2(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::access => sys_access(tf.arg0().into(), tf.arg1() as _),
         Sysno::statfs => sys_statfs(tf.arg0().into(), tf.arg1().into()),
+        Sysno::fstatfs => sys_fstatfs(tf.arg0() as _, tf.arg1().into()),
         Sysno::munmap => sys_munmap(tf.arg0(), tf.arg1() as _),
         Sysno::mprotect => sys_mprotect(tf.arg0(), tf.arg1() as _, tf.arg2() as _),
+        Sysno::mremap => sys_mremap(
+            tf.arg0(),
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4(),
+        ),
+        Sysno::madvise => sys_madvise(tf.arg0(), tf.arg1() as _, tf.arg2() as _),
+        Sysno::msync => sys_msync(tf.arg0(), tf.arg1() as _, tf.arg2() as _),
+        Sysno::mlock => sys_mlock(tf.arg0(), tf.arg1() as _),
+        Sysno::munlock => sys_munlock(tf.arg0(), tf.arg1() as _),
+        Sysno::mlockall => sys_mlockall(tf.arg0() as _),
+        Sysno::munlockall => sys_munlockall(),
+        Sysno::mincore => sys_mincore(tf.arg0(), tf.arg1() as _, tf.arg2().into()),
+        Sysno::process_vm_readv => sys_process_vm_readv(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3().into(),
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
+        Sysno::process_vm_writev => sys_process_vm_writev(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3().into(),
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
+        Sysno::shmget => sys_shmget(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::shmat => sys_shmat(tf.arg0() as _, tf.arg1(), tf.arg2() as _),
+        Sysno::shmdt => sys_shmdt(tf.arg0()),
+        Sysno::shmctl => sys_shmctl(tf.arg0() as _, tf.arg1() as _, tf.arg2().into()),
         Sysno::times => sys_times(tf.arg0().into()),
         Sysno::brk => sys_brk(tf.arg0() as _),
         Sysno::prlimit64 => sys_prlimit64(
@@ -147,8 +408,12 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
         ),
         #[cfg(target_arch = "x86_64")]
         Sysno::arch_prctl => sys_arch_prctl(tf.arg0() as _, tf.arg1().into()),
+        Sysno::prctl => sys_prctl(tf.arg0() as _, tf.arg1(), tf.arg2(), tf.arg3(), tf.arg4()),
         Sysno::set_tid_address => sys_set_tid_address(tf.arg0().into()),
         Sysno::clock_gettime => sys_clock_gettime(tf.arg0() as _, tf.arg1().into()),
+        Sysno::clock_getres => sys_clock_getres(tf.arg0() as _, tf.arg1().into()),
+        Sysno::getitimer => sys_getitimer(tf.arg0() as _, tf.arg1().into()),
+        Sysno::setitimer => sys_setitimer(tf.arg0() as _, tf.arg1().into(), tf.arg2().into()),
         Sysno::getuid => sys_getuid(),
         Sysno::geteuid => sys_geteuid(),
         Sysno::getgid => sys_getgid(),
@@ -171,21 +436,85 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
             tf.arg2().into(),
             tf.arg3() as _,
         ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::pause => sys_pause(tf),
         Sysno::rt_sigsuspend => sys_rt_sigsuspend(tf, tf.arg0().into(), tf.arg1() as _),
         Sysno::rt_sigpending => sys_rt_sigpending(tf.arg0().into(), tf.arg1() as _),
         Sysno::rt_sigreturn => sys_rt_sigreturn(tf),
+        Sysno::sigaltstack => sys_sigaltstack(tf, tf.arg0().into(), tf.arg1().into()),
+        Sysno::signalfd4 => sys_signalfd4(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::signalfd => sys_signalfd(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
         Sysno::kill => sys_kill(tf.arg0() as _, tf.arg1() as _),
-        Sysno::tgkill => sys_tgkill(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        Sysno::futex => {
-            warn!("preventing pthread from blocking testing");
-            do_exit(SIGSYS as _, true);
+        Sysno::rt_sigqueueinfo => {
+            sys_rt_sigqueueinfo(tf.arg0() as _, tf.arg1() as _, tf.arg2().into())
         }
+        Sysno::tkill => sys_tkill(tf.arg0() as _, tf.arg1() as _),
+        Sysno::tgkill => sys_tgkill(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::futex => sys_futex(
+            tf.arg0().into(),
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3(),
+            tf.arg4(),
+            tf.arg5() as _,
+        ),
+        Sysno::socket => sys_socket(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::socketpair => sys_socketpair(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3().into(),
+        ),
+        Sysno::bind => sys_bind(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        Sysno::listen => sys_listen(tf.arg0() as _, tf.arg1() as _),
+        Sysno::connect => sys_connect(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        Sysno::accept => sys_accept(tf.arg0() as _, tf.arg1().into(), tf.arg2().into()),
+        Sysno::accept4 => sys_accept4(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2().into(),
+            tf.arg3() as _,
+        ),
+        Sysno::sendmsg => sys_sendmsg(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        Sysno::recvmsg => sys_recvmsg(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        Sysno::sendto => sys_sendto(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4().into(),
+            tf.arg5() as _,
+        ),
+        Sysno::recvfrom => sys_recvfrom(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4().into(),
+            tf.arg5().into(),
+        ),
+        Sysno::getsockname => sys_getsockname(tf.arg0() as _, tf.arg1().into(), tf.arg2().into()),
+        Sysno::getpeername => sys_getpeername(tf.arg0() as _, tf.arg1().into(), tf.arg2().into()),
+        Sysno::shutdown => sys_shutdown(tf.arg0() as _, tf.arg1() as _),
         sysno => {
             warn!("Unimplemented syscall: {}", sysno);
             Err(LinuxError::ENOSYS)
         }
     };
-    let result = result.unwrap_or_else(|err| -err.code() as isize);
+    let result = match result {
+        Err(LinuxError::ERESTART) => {
+            // Interrupted by a signal, report `EINTR` unless restarted.
+            save_restart_context(tf);
+            -LinuxError::EINTR.code() as isize
+        }
+        result => result.unwrap_or_else(|err| -err.code() as isize),
+    };
     time_stat_from_kernel_to_user();
     info!(
         "Syscall {:?} return {:?}",